@@ -0,0 +1,123 @@
+//! Times CSV imports and dynamic row reads over a synthetic wide, string-heavy table on a real server.
+//! Requires the `test-util` feature and Docker; run with `cargo bench --features test-util`. Without Docker the
+//! benchmark is skipped.
+
+use mssql_rs::{CsvImportOptions, QueryOptions, SqlServerPool, TestServer, TrimFixedChar};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The number of `nvarchar` columns, each followed by an `nchar` column.
+const COLUMNS: usize = 24;
+/// The number of rows in the table.
+const ROWS: usize = 2_000;
+/// The number of timed runs of each case, after one warm-up run.
+const RUNS: usize = 5;
+
+#[tokio::main]
+async fn main() {
+    let (_server, pool) = match TestServer::start().await {
+        Ok(started) => started,
+        Err(e) => {
+            println!("skipped, no test server: {e}");
+            return;
+        }
+    };
+    let columns: Vec<String> = (0..COLUMNS)
+        .flat_map(|c| {
+            [
+                format!("wide_{c} nvarchar(100)"),
+                format!("fixed_{c} nchar(20)"),
+            ]
+        })
+        .collect();
+    pool.execute_batch(&[&format!("CREATE TABLE dbo.wide ({});", columns.join(", "))])
+        .await
+        .unwrap();
+
+    let csv = wide_csv();
+    report("csv_import", RUNS, async || {
+        pool.execute_batch(&["TRUNCATE TABLE dbo.wide;"])
+            .await
+            .unwrap();
+        let start = Instant::now();
+        let stats = pool
+            .csv_import(csv.as_bytes(), "dbo.wide", &CsvImportOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(stats.rows_loaded, ROWS as u64);
+        start.elapsed()
+    })
+    .await;
+
+    let plain = QueryOptions::default();
+    report("row_query_dynamic", RUNS, async || {
+        read_all(&pool, &plain).await
+    })
+    .await;
+    let trimmed = QueryOptions {
+        transformers: vec![Arc::new(TrimFixedChar)],
+        ..QueryOptions::default()
+    };
+    report("row_query_dynamic with TrimFixedChar", RUNS, async || {
+        read_all(&pool, &trimmed).await
+    })
+    .await;
+}
+
+/// A CSV with a header and [`ROWS`] records of non-ASCII text, some of it quoted.
+fn wide_csv() -> String {
+    let header: Vec<String> = (0..COLUMNS)
+        .flat_map(|c| [format!("wide_{c}"), format!("fixed_{c}")])
+        .collect();
+    let mut csv = header.join(",");
+    csv.push('\n');
+    for row in 0..ROWS {
+        let fields: Vec<String> = (0..COLUMNS)
+            .flat_map(|c| {
+                let wide = match (row + c) % 4 {
+                    0 => format!("\"row {row}, column {c}\""),
+                    _ => format!("Zürich–Kraków {row} {c} ✓ {}", "x".repeat(c * 2)),
+                };
+                [wide, format!("k{c}")]
+            })
+            .collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Read every value of every row of the table through [`mssql_rs::DynamicRow`].
+async fn read_all(pool: &SqlServerPool, options: &QueryOptions) -> Duration {
+    let start = Instant::now();
+    let rows = pool
+        .row_query_dynamic("SELECT * FROM dbo.wide;", &[], options)
+        .await
+        .unwrap();
+    let mut chars = 0;
+    for row in &rows {
+        for index in 0..row.columns().len() {
+            if let Some(mssql_rs::tiberius::ColumnData::String(Some(s))) = row.value_at(index) {
+                chars += s.len();
+            }
+        }
+    }
+    assert_eq!(rows.len(), ROWS);
+    assert!(chars > 0);
+    start.elapsed()
+}
+
+/// Run `case` once to warm up, then `runs` times, and print the fastest and median times.
+async fn report(name: &str, runs: usize, mut case: impl AsyncFnMut() -> Duration) {
+    case().await;
+    let mut times = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        times.push(case().await);
+    }
+    times.sort();
+    println!(
+        "{name}: fastest {:?}, median {:?} over {runs} runs",
+        times[0],
+        times[runs / 2]
+    );
+}
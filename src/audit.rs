@@ -0,0 +1,171 @@
+use crate::{connection::Client, error::Error, param::SqlParam, sql::quote_identifier};
+use futures_util::Future;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tiberius::Query;
+
+tokio::task_local! {
+    static ACTOR: String;
+}
+
+/// Run `f` with `actor` as the actor recorded in [`AuditConfig::updated_by`] columns, unless a call sets
+/// [`QueryOptions::actor`](crate::QueryOptions::actor).
+///
+/// The actor applies to every upsert awaited within `f`, e.g. a whole request handler,
+/// but not to tasks it spawns.
+///
+/// ```no_run
+/// # use mssql_rs::{SqlParam, SqlServerPool};
+/// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+/// mssql_rs::with_actor("alice", async {
+///     sql_server
+///         .upsert("dbo.people", &[("id", SqlParam::from(1))], &[("name", SqlParam::from("Alice"))])
+///         .await
+/// })
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn with_actor<F: Future>(actor: impl Into<String>, f: F) -> F::Output {
+    ACTOR.scope(actor.into(), f).await
+}
+
+/// The actor set by [`with_actor`] for the current task, if any.
+pub(crate) fn current_actor() -> Option<String> {
+    ACTOR.try_with(Clone::clone).ok()
+}
+
+/// The names of the audit columns set automatically by [`SqlServerPool::upsert`](crate::SqlServerPool::upsert),
+/// see [`SqlServerPoolBuilder::audit_columns`](crate::SqlServerPoolBuilder::audit_columns).
+///
+/// Each column is only set on tables that have it, and never when the caller passes a value for it.
+#[derive(Debug, Clone, Default)]
+pub struct AuditConfig {
+    /// Set to `SYSUTCDATETIME()` when a row is inserted.
+    pub created_at: Option<String>,
+    /// Set to `SYSUTCDATETIME()` when a row is inserted or updated.
+    pub updated_at: Option<String>,
+    /// Set to the actor when a row is inserted or updated. The actor is [`QueryOptions::actor`](crate::QueryOptions::actor)
+    /// if set, or else the one set by [`with_actor`]. Without an actor, the column isn't set.
+    pub updated_by: Option<String>,
+}
+
+/// The configured audit columns a table has.
+#[derive(Debug, Clone, Default)]
+struct TableAuditColumns {
+    created_at: Option<String>,
+    updated_at: Option<String>,
+    updated_by: Option<String>,
+}
+
+/// A timestamp column set by an upsert's `MERGE` statement.
+#[derive(Debug, Clone)]
+pub(crate) struct AuditTimestamp {
+    /// The quoted column name.
+    pub(crate) column: String,
+    /// Whether the column is also set when a row is updated, rather than only when inserted.
+    pub(crate) on_update: bool,
+}
+
+/// The audit columns to add to a single upsert.
+#[derive(Debug, Default)]
+pub(crate) struct AuditAssignments {
+    pub(crate) timestamps: Vec<AuditTimestamp>,
+    /// The unquoted actor column, with the actor.
+    pub(crate) actor: Option<(String, SqlParam)>,
+}
+
+/// Works out which audit columns each table has, caching the result per table.
+#[derive(Debug)]
+pub(crate) struct Auditor {
+    config: AuditConfig,
+    tables: Mutex<HashMap<String, TableAuditColumns>>,
+}
+
+impl Auditor {
+    pub(crate) fn new(config: AuditConfig) -> Self {
+        Self {
+            config,
+            tables: Mutex::default(),
+        }
+    }
+
+    /// The audit columns to set for an upsert into the quoted `table`, skipping those in `explicit`.
+    pub(crate) async fn assignments(
+        &self,
+        conn: &mut Client,
+        table: &str,
+        explicit: &[&str],
+        actor: Option<String>,
+    ) -> Result<AuditAssignments, Error> {
+        let cached = self
+            .tables
+            .lock()
+            .expect("audit column cache poisoned")
+            .get(table)
+            .cloned();
+        let columns = match cached {
+            Some(columns) => columns,
+            None => {
+                let columns = self.introspect(conn, table).await?;
+                self.tables
+                    .lock()
+                    .expect("audit column cache poisoned")
+                    .insert(table.to_owned(), columns.clone());
+                columns
+            }
+        };
+
+        let unset = |column: &Option<String>| {
+            column
+                .clone()
+                .filter(|c| !explicit.iter().any(|e| e.eq_ignore_ascii_case(c)))
+        };
+
+        let mut assignments = AuditAssignments::default();
+        if let Some(column) = unset(&columns.created_at) {
+            assignments.timestamps.push(AuditTimestamp {
+                column: quote_identifier(&column),
+                on_update: false,
+            });
+        }
+        if let Some(column) = unset(&columns.updated_at) {
+            assignments.timestamps.push(AuditTimestamp {
+                column: quote_identifier(&column),
+                on_update: true,
+            });
+        }
+        if let (Some(column), Some(actor)) = (unset(&columns.updated_by), actor) {
+            assignments.actor = Some((column, SqlParam::from(actor)));
+        }
+        Ok(assignments)
+    }
+
+    /// Read which of the configured audit columns `table` has.
+    async fn introspect(&self, conn: &mut Client, table: &str) -> Result<TableAuditColumns, Error> {
+        let mut query = Query::new(
+            "SELECT name FROM sys.columns WHERE object_id = OBJECT_ID(@P1) AND is_computed = 0;",
+        );
+        query.bind(table);
+        let names: Vec<String> = query
+            .query(conn)
+            .await?
+            .into_first_result()
+            .await?
+            .iter()
+            .filter_map(|row| row.get::<&str, _>(0).map(str::to_owned))
+            .collect();
+
+        // Use the configured spelling, as it is only compared case-insensitively.
+        let has = |column: &Option<String>| {
+            column
+                .clone()
+                .filter(|c| names.iter().any(|n| n.eq_ignore_ascii_case(c)))
+        };
+        Ok(TableAuditColumns {
+            created_at: has(&self.config.created_at),
+            updated_at: has(&self.config.updated_at),
+            updated_by: has(&self.config.updated_by),
+        })
+    }
+}
@@ -0,0 +1,57 @@
+//! Print the structs `mssql_rs::codegen` generates for tables of a schema snapshot.
+//!
+//! ```text
+//! mssql-codegen [--derive TRAIT]... [--type SQL_TYPE=RUST_TYPE]... SNAPSHOT TABLE...
+//! ```
+//!
+//! `--derive` replaces the default derives, and may be repeated.
+
+use mssql_rs::codegen::{generate, CodegenOptions, SchemaSnapshot};
+use std::process::ExitCode;
+
+const USAGE: &str =
+    "usage: mssql-codegen [--derive TRAIT]... [--type SQL_TYPE=RUST_TYPE]... SNAPSHOT TABLE...";
+
+fn run() -> Result<String, String> {
+    let mut options = CodegenOptions::default();
+    let mut derives = Vec::new();
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--derive" => derives.push(args.next().ok_or(USAGE)?),
+            "--type" => {
+                let mapping = args.next().ok_or(USAGE)?;
+                let (sql, rust) = mapping.split_once('=').ok_or(USAGE)?;
+                options.types.push((sql.to_owned(), rust.to_owned()));
+            }
+            "--help" | "-h" => return Err(USAGE.to_owned()),
+            _ => positional.push(arg),
+        }
+    }
+    if !derives.is_empty() {
+        options.derives = derives;
+    }
+    let (path, tables) = positional.split_first().ok_or(USAGE)?;
+    if tables.is_empty() {
+        return Err(USAGE.to_owned());
+    }
+
+    let json = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let snapshot = SchemaSnapshot::from_json(&json).map_err(|e| format!("{path}: {e}"))?;
+    let tables: Vec<&str> = tables.iter().map(String::as_str).collect();
+    generate(&snapshot, &tables, &options).map_err(|e| e.to_string())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => {
+            print!("{code}");
+            ExitCode::SUCCESS
+        }
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::future::Future;
+use tokio_util::sync::CancellationToken;
+
+/// Run the future `f` builds with a fresh token, cancelling the token if the returned future is dropped before it
+/// completes, e.g. when a client disconnects and the server drops the request's response future.
+///
+/// This bridges a request's lifetime to
+/// [`SqlServerPool::row_query_cancellable`](crate::SqlServerPool::row_query_cancellable) without depending on a web
+/// framework. In a Tower middleware, wrap the inner service's call and pass the token to the handler in the request's
+/// extensions:
+///
+/// ```no_run
+/// # use mssql_rs::{cancel_on_drop, CancellationToken, SqlServerPool, TryFromRow};
+/// # struct Order;
+/// # impl TryFromRow for Order {
+/// #     fn try_from(_: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Order) }
+/// # }
+/// # struct Request { token: Option<CancellationToken> }
+/// # async fn handler(sql_server: SqlServerPool, request: Request) -> mssql_rs::Result<Vec<Order>> {
+/// #     let cancel = request.token.unwrap();
+/// #     sql_server.row_query_cancellable("SELECT * FROM orders;", &[], &cancel).await
+/// # }
+/// # async fn example(sql_server: SqlServerPool, mut request: Request) -> mssql_rs::Result<()> {
+/// // In `Service::call`, or an axum `middleware::from_fn` function:
+/// let response = cancel_on_drop(|cancel| {
+///     request.token = Some(cancel); // e.g. `request.extensions_mut().insert(cancel)`
+///     handler(sql_server, request)
+/// });
+/// // If `response` is dropped before it completes, the handler's query stops with `Error::Cancelled`.
+/// let orders = response.await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The token is left uncancelled once the future completes, so work the request started in the background carries
+/// on.
+pub fn cancel_on_drop<F, Fut>(f: F) -> impl Future<Output = Fut::Output>
+where
+    F: FnOnce(CancellationToken) -> Fut,
+    Fut: Future,
+{
+    let cancel = CancellationToken::new();
+    let guard = cancel.clone().drop_guard();
+    let future = f(cancel);
+    async move {
+        let output = future.await;
+        guard.disarm();
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn dropping_the_future_early_cancels_the_token() {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let future = cancel_on_drop(|cancel| {
+            tx.send(cancel).unwrap();
+            std::future::pending::<()>()
+        });
+        let cancel = rx.await.unwrap();
+        let _ = tokio::time::timeout(Duration::from_millis(10), future).await;
+        assert!(cancel.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn completing_the_future_leaves_the_token_alone() {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let output = cancel_on_drop(|cancel| {
+            tx.send(cancel).unwrap();
+            async { 42 }
+        })
+        .await;
+        assert_eq!(output, 42);
+        assert!(!rx.await.unwrap().is_cancelled());
+    }
+}
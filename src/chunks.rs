@@ -0,0 +1,101 @@
+/// How buffered query methods accumulate rows, see [`QueryOptions::accumulation`](crate::QueryOptions::accumulation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accumulation {
+    /// Collect rows into one contiguous Vec, which grows by doubling.
+    /// For very large results, the reallocation can briefly hold up to twice the memory of the rows themselves.
+    Contiguous,
+    /// Collect rows into fixed-size chunks of this many rows, so growing never copies or overshoots
+    /// by more than one chunk.
+    Chunked(usize),
+}
+
+/// The number of rows after which [`Chunks`] switch to chunked accumulation when no strategy is set.
+pub(crate) const DEFAULT_CHUNK_ROWS: usize = 64 * 1024;
+
+/// Rows accumulated in one or more chunks, returned by [`SqlServerPool::row_query_chunked`](crate::SqlServerPool::row_query_chunked).
+///
+/// Iterate over the rows directly, or use [`Chunks::into_vec`] when a contiguous Vec is needed
+/// (which needs memory for both while copying).
+#[derive(Debug, Clone)]
+pub struct Chunks<T> {
+    chunks: Vec<Vec<T>>,
+    chunk_rows: usize,
+    len: usize,
+}
+
+impl<T> Chunks<T> {
+    /// Create an accumulator for the given strategy. Without one, rows are collected contiguously
+    /// up to [`DEFAULT_CHUNK_ROWS`] and in chunks of that size past it.
+    pub(crate) fn new(accumulation: Option<Accumulation>) -> Self {
+        let chunk_rows = match accumulation {
+            Some(Accumulation::Contiguous) => usize::MAX,
+            Some(Accumulation::Chunked(rows)) => rows.max(1),
+            None => DEFAULT_CHUNK_ROWS,
+        };
+
+        Self {
+            chunks: Vec::new(),
+            chunk_rows,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, value: T) {
+        match self.chunks.last_mut() {
+            Some(chunk) if chunk.len() < self.chunk_rows => chunk.push(value),
+            _ => {
+                // The first chunk grows as needed, so small results don't allocate a whole chunk up front.
+                let capacity = if self.chunks.is_empty() {
+                    0
+                } else {
+                    self.chunk_rows
+                };
+                let mut chunk = Vec::with_capacity(capacity);
+                chunk.push(value);
+                self.chunks.push(chunk);
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Returns the total number of rows.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the chunks of rows.
+    pub fn chunks(&self) -> &[Vec<T>] {
+        &self.chunks
+    }
+
+    /// Iterate over the rows by reference.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.iter().flatten()
+    }
+
+    /// Flatten the chunks into a single Vec.
+    pub fn into_vec(self) -> Vec<T> {
+        if self.chunks.len() == 1 {
+            return self.chunks.into_iter().next().unwrap_or_default();
+        }
+
+        let mut buf = Vec::with_capacity(self.len);
+        for chunk in self.chunks {
+            buf.extend(chunk);
+        }
+        buf
+    }
+}
+
+impl<T> IntoIterator for Chunks<T> {
+    type Item = T;
+    type IntoIter = std::iter::Flatten<std::vec::IntoIter<Vec<T>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.into_iter().flatten()
+    }
+}
@@ -0,0 +1,178 @@
+use futures_util::future::BoxFuture;
+use futures_util::Future;
+use std::fmt;
+#[cfg(feature = "test-util")]
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+#[cfg(feature = "test-util")]
+use tokio::sync::oneshot;
+
+/// The source of time for the pool's own timeouts, retry delays and background intervals.
+///
+/// Production pools use tokio's timers. With the `test-util` feature, tests can set a [`MockClock`] with
+/// [`SqlServerPoolBuilder::clock`](crate::SqlServerPoolBuilder::clock) and advance it by hand instead of sleeping.
+/// bb8's checkout timeout and idle reaper run on their own timers, so they always use real time.
+pub trait Clock: Send + Sync {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+
+    /// Returns a future completing once `duration` has passed.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+impl fmt::Debug for dyn Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+impl dyn Clock + '_ {
+    /// Run `future`, returning `None` if it doesn't complete within `duration`.
+    pub(crate) async fn timeout<F: Future>(
+        &self,
+        duration: Duration,
+        future: F,
+    ) -> Option<F::Output> {
+        tokio::select! {
+            biased;
+            output = future => Some(output),
+            _ = self.sleep(duration) => None,
+        }
+    }
+
+    /// Returns the time passed since `start`, an instant returned by [`Clock::now`].
+    pub(crate) fn elapsed(&self, start: Instant) -> Duration {
+        self.now().saturating_duration_since(start)
+    }
+}
+
+/// The default clock, using tokio's timers.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A [`Clock`] that only moves when advanced, for testing timeouts without waiting. Requires the `test-util` feature.
+///
+/// Sleeps complete once [`MockClock::advance`] moves the clock past their deadline, so a test can check what
+/// happens on a query timeout or between retries immediately and deterministically.
+///
+/// ```no_run
+/// # use mssql_rs::{MockClock, QueryOptions, SqlServerPoolBuilder};
+/// # use std::{sync::Arc, time::Duration};
+/// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+/// let clock = Arc::new(MockClock::new());
+/// let sql_server = SqlServerPoolBuilder::new().clock(clock.clone()).build(cfg).await?;
+///
+/// let options = QueryOptions {
+///     timeout: Some(Duration::from_secs(30)),
+///     ..Default::default()
+/// };
+/// let query =
+///     sql_server.json_query_with_options::<serde_json::Value>("WAITFOR DELAY '00:01:00'", &[], &options);
+/// let advance = async {
+///     while clock.pending_sleeps() == 0 {
+///         tokio::task::yield_now().await;
+///     }
+///     clock.advance(Duration::from_secs(30));
+/// };
+/// let (result, ()) = tokio::join!(query, advance);
+/// assert!(result.is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "test-util")]
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    state: Mutex<MockState>,
+}
+
+#[cfg(feature = "test-util")]
+#[derive(Debug, Default)]
+struct MockState {
+    elapsed: Duration,
+    /// The pending sleeps, with the elapsed time each completes at.
+    sleepers: Vec<(Duration, oneshot::Sender<()>)>,
+}
+
+#[cfg(feature = "test-util")]
+impl MockClock {
+    /// Create a clock starting at the current instant.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Move the clock forward by `duration`, completing the sleeps that are due.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.lock();
+        state.elapsed += duration;
+        let now = state.elapsed;
+        let (due, pending) = std::mem::take(&mut state.sleepers)
+            .into_iter()
+            .partition(|(deadline, _)| *deadline <= now);
+        state.sleepers = pending;
+        drop(state);
+
+        for (_, wake) in due {
+            // The sleep may have been dropped, e.g. when the future it guarded completed first.
+            let _ = wake.send(());
+        }
+    }
+
+    /// Returns how far the clock has been advanced.
+    pub fn elapsed(&self) -> Duration {
+        self.lock().elapsed
+    }
+
+    /// Returns the number of sleeps waiting for the clock to advance, so a test can wait until the code under test
+    /// is blocked on the clock before advancing it.
+    pub fn pending_sleeps(&self) -> usize {
+        let mut state = self.lock();
+        state.sleepers.retain(|(_, wake)| !wake.is_closed());
+        state.sleepers.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().expect("mock clock state poisoned")
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.lock().elapsed
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        if duration.is_zero() {
+            return Box::pin(std::future::ready(()));
+        }
+        let (wake, woken) = oneshot::channel();
+        let mut state = self.lock();
+        let deadline = state.elapsed + duration;
+        state.sleepers.push((deadline, wake));
+        Box::pin(async move {
+            // A dropped clock never advances, so finish rather than hang.
+            let _ = woken.await;
+        })
+    }
+}
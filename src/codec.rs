@@ -0,0 +1,256 @@
+use crate::error::Error;
+use crate::param::SqlParam;
+use crate::sql::lexer::{tokenize, TokenKind};
+use crate::sql::split_object_name;
+use crate::value::{is_null, SqlValue};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tiberius::Column;
+
+/// Application-side encryption of a column's values, e.g. for PII that Always Encrypted can't cover.
+///
+/// Register a codec for a table's column with [`SqlServerPoolBuilder::column_codec`](crate::SqlServerPoolBuilder::column_codec).
+/// Values bound to that column by [`SqlServerPool::upsert`](crate::SqlServerPool::upsert),
+/// [`SqlServerPool::insert_or_get`](crate::SqlServerPool::insert_or_get) and
+/// [`SqlServerPool::write_behind`](crate::SqlServerPool::write_behind) are encrypted, and values read from it by
+/// [`SqlServerPool::row_query_dynamic`](crate::SqlServerPool::row_query_dynamic) with
+/// [`QueryOptions::codec_table`](crate::QueryOptions::codec_table) set are decrypted.
+///
+/// Other writes fail closed with [`Error::InvalidArgument`] rather than store plaintext:
+/// [`SqlServerPool::sync_table`](crate::SqlServerPool::sync_table),
+/// [`SqlServerPool::csv_import`](crate::SqlServerPool::csv_import) and
+/// [`SqlServerPool::with_identity_insert`](crate::SqlServerPool::with_identity_insert) refuse tables with codecs, and
+/// the pool's query methods refuse an `INSERT`, `UPDATE` or `MERGE` naming both a codec's table and its column.
+/// Queries run directly on a [`PooledConnection`](crate::PooledConnection) aren't checked.
+///
+/// The trait is agnostic to the cipher. Implementations must fail closed: if a key is missing or a value can't be
+/// processed, return an error rather than the input, so plaintext is never written in place of ciphertext.
+/// A codec used for upsert key columns must be deterministic, so the same plaintext matches the stored row.
+pub trait ColumnCodec: Send + Sync {
+    /// Encrypt a value before it is bound. NULL values are passed through without calling the codec.
+    fn encrypt(&self, plaintext: SqlValue) -> Result<SqlValue, Error>;
+
+    /// Decrypt a value after it is read. NULL values are passed through without calling the codec.
+    fn decrypt(&self, ciphertext: SqlValue) -> Result<SqlValue, Error>;
+}
+
+/// The codecs registered for a pool, keyed by unqualified table name and column name, case-insensitively.
+///
+/// Keying on the unqualified table name means a codec applies to a table of that name in any schema,
+/// which errs on the side of encrypting.
+#[derive(Clone, Default)]
+pub(crate) struct ColumnCodecs {
+    codecs: HashMap<(String, String), Arc<dyn ColumnCodec>>,
+}
+
+impl std::fmt::Debug for ColumnCodecs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.codecs.keys()).finish()
+    }
+}
+
+impl ColumnCodecs {
+    pub(crate) fn insert(
+        &mut self,
+        table: &str,
+        column: &str,
+        codec: Arc<dyn ColumnCodec>,
+    ) -> Result<(), Error> {
+        self.codecs
+            .insert((table_key(table)?, column.to_lowercase()), codec);
+        Ok(())
+    }
+
+    /// Fail with [`Error::InvalidArgument`] if `table` has codecs, for `method`s that write to a table without
+    /// applying them.
+    pub(crate) fn reject_table(&self, table: &str, method: &str) -> Result<(), Error> {
+        if self.codecs.is_empty() {
+            return Ok(());
+        }
+        let key = table_key(table)?;
+        match self.codecs.keys().find(|(table, _)| *table == key) {
+            Some((_, column)) => Err(Error::InvalidArgument(format!(
+                "{method} can't encrypt column {column} of {table}, which has a column codec"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Fail with [`Error::InvalidArgument`] if `statement` is an `INSERT`, `UPDATE` or `MERGE` naming both a codec's
+    /// table and its column, as the values it writes there weren't encrypted.
+    ///
+    /// Names are matched as words, so this errs on the side of refusing, e.g. a statement that only reads the
+    /// column while writing elsewhere.
+    pub(crate) fn check_statement(&self, statement: &str) -> Result<(), Error> {
+        if self.codecs.is_empty() {
+            return Ok(());
+        }
+        let mut writes = false;
+        let mut names = std::collections::HashSet::new();
+        for token in tokenize(statement) {
+            match token.kind {
+                TokenKind::Word => {
+                    writes |= ["INSERT", "UPDATE", "MERGE"]
+                        .iter()
+                        .any(|keyword| token.text.eq_ignore_ascii_case(keyword));
+                    names.insert(token.text.to_lowercase());
+                }
+                TokenKind::QuotedIdentifier if token.text.len() >= 2 => {
+                    let quote = token.text.chars().last().unwrap_or_default();
+                    let name = &token.text[1..token.text.len() - 1];
+                    names.insert(
+                        name.replace(&format!("{quote}{quote}"), &quote.to_string())
+                            .to_lowercase(),
+                    );
+                }
+                _ => {}
+            }
+        }
+        if !writes {
+            return Ok(());
+        }
+        match self
+            .codecs
+            .keys()
+            .find(|(table, column)| names.contains(table) && names.contains(column))
+        {
+            Some((table, column)) => Err(Error::InvalidArgument(format!(
+                "the statement may write column {column} of {table} without its column codec; \
+                 use upsert, insert_or_get or write_behind, which encrypt it"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn get(&self, table_key: &str, column: &str) -> Option<&dyn ColumnCodec> {
+        if self.codecs.is_empty() {
+            return None;
+        }
+        self.codecs
+            .get(&(table_key.to_owned(), column.to_lowercase()))
+            .map(|codec| &**codec)
+    }
+
+    /// Encrypt the parameters bound to `table`'s encrypted columns.
+    /// Returns `None` if none of the columns are encrypted, so the caller can bind the originals.
+    pub(crate) fn encrypt_params<'a>(
+        &self,
+        table: &str,
+        params: &[(&'a str, SqlParam)],
+    ) -> Result<Option<Vec<(&'a str, SqlParam)>>, Error> {
+        if self.codecs.is_empty() {
+            return Ok(None);
+        }
+        let table = table_key(table)?;
+        if !params
+            .iter()
+            .any(|(column, _)| self.get(&table, column).is_some())
+        {
+            return Ok(None);
+        }
+
+        params
+            .iter()
+            .map(|(column, param)| match self.get(&table, column) {
+                Some(codec) if *param != SqlParam::Null => {
+                    let ciphertext = codec.encrypt(param.into())?;
+                    Ok((*column, SqlParam::try_from(ciphertext)?))
+                }
+                _ => Ok((*column, param.clone())),
+            })
+            .collect::<Result<_, Error>>()
+            .map(Some)
+    }
+
+    /// Decrypt the values of a row read from `table`'s encrypted columns.
+    pub(crate) fn decrypt_values(
+        &self,
+        table: &str,
+        columns: &[Column],
+        values: &mut [SqlValue],
+    ) -> Result<(), Error> {
+        if self.codecs.is_empty() {
+            return Ok(());
+        }
+        let table = table_key(table)?;
+        for (column, value) in columns.iter().zip(values) {
+            if let Some(codec) = self.get(&table, column.name()) {
+                if !is_null(value) {
+                    let ciphertext = std::mem::replace(value, SqlValue::I32(None));
+                    *value = codec.decrypt(ciphertext)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The unqualified, lowercased name of a table.
+pub(crate) fn table_key(table: &str) -> Result<String, Error> {
+    let parts = split_object_name(table)?;
+    let name = parts
+        .last()
+        .ok_or_else(|| Error::InvalidIdentifier(table.to_owned()))?;
+    Ok(name.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A codec that must never be called.
+    struct Unused;
+
+    impl ColumnCodec for Unused {
+        fn encrypt(&self, _: SqlValue) -> Result<SqlValue, Error> {
+            unreachable!()
+        }
+
+        fn decrypt(&self, _: SqlValue) -> Result<SqlValue, Error> {
+            unreachable!()
+        }
+    }
+
+    fn codecs() -> ColumnCodecs {
+        let mut codecs = ColumnCodecs::default();
+        codecs
+            .insert("dbo.People", "SSN", Arc::new(Unused))
+            .unwrap();
+        codecs
+    }
+
+    #[test]
+    fn tables_with_codecs_are_refused() {
+        let codecs = codecs();
+        assert!(matches!(
+            codecs.reject_table("[sales].[people]", "csv_import"),
+            Err(Error::InvalidArgument(message)) if message.contains("csv_import")
+        ));
+        assert!(codecs.reject_table("dbo.orders", "csv_import").is_ok());
+        assert!(ColumnCodecs::default()
+            .reject_table("dbo.people", "csv_import")
+            .is_ok());
+    }
+
+    #[test]
+    fn writes_naming_a_codec_column_are_refused() {
+        let codecs = codecs();
+        for statement in [
+            "INSERT INTO dbo.people (name, ssn) VALUES (@P1, @P2)",
+            "update [People] set [SSN] = @P1 where id = @P2",
+            "MERGE people USING (SELECT @P1 AS ssn) AS s ON 1 = 0 WHEN NOT MATCHED THEN INSERT (ssn) VALUES (ssn);",
+        ] {
+            assert!(
+                codecs.check_statement(statement).is_err(),
+                "{statement}"
+            );
+        }
+        for statement in [
+            "SELECT ssn FROM dbo.people",
+            "UPDATE dbo.people SET last_login = SYSUTCDATETIME() WHERE id = @P1",
+            "INSERT INTO dbo.audit (note) VALUES ('people ssn changed')",
+        ] {
+            assert!(codecs.check_statement(statement).is_ok(), "{statement}");
+        }
+    }
+}
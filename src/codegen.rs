@@ -0,0 +1,394 @@
+//! Generate Rust structs for tables from a schema snapshot, so row types can't drift from the tables they read.
+//!
+//! Take a [`SchemaSnapshot`] of the tables with [`SqlServerPool::schema_snapshot`](crate::SqlServerPool::schema_snapshot)
+//! and commit it as JSON with [`SchemaSnapshot::to_json`]. Then generate the structs from the file, e.g. in a
+//! `build.rs` that writes them to `OUT_DIR`, so builds never need a database:
+//!
+//! ```no_run
+//! // build.rs
+//! use mssql_rs::codegen::{generate, CodegenOptions, SchemaSnapshot};
+//!
+//! fn main() {
+//!     println!("cargo:rerun-if-changed=schema.json");
+//!     let snapshot = SchemaSnapshot::from_json(&std::fs::read_to_string("schema.json").unwrap()).unwrap();
+//!     let code = generate(&snapshot, &["dbo.people"], &CodegenOptions::default()).unwrap();
+//!     let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("tables.rs");
+//!     std::fs::write(out, code).unwrap();
+//! }
+//! ```
+//!
+//! The `mssql-codegen` binary does the same from the command line. Both require the `codegen` feature.
+//!
+//! Each table becomes a struct named after it in `PascalCase`, with a field per column in `snake_case`, in column
+//! order. Nullable columns are `Option`s, and each field's doc comment carries its SQL type. A [`TryFromRow`]
+//! implementation reads the columns by name, failing on a NULL in a field that isn't an `Option`. The output only
+//! depends on the snapshot and options, so regenerating it diffs cleanly.
+//!
+//! SQL types map to:
+//!
+//! | SQL type | Rust type |
+//! |---|---|
+//! | `bit` | `bool` |
+//! | `tinyint`, `smallint`, `int`, `bigint` | `u8`, `i16`, `i32`, `i64` |
+//! | `real`, `float` | `f32`, `f64` |
+//! | `money`, `smallmoney` | `f64` |
+//! | `decimal`, `numeric` | [`Numeric`](tiberius::numeric::Numeric) |
+//! | `char`, `varchar`, `text`, `nchar`, `nvarchar`, `ntext`, `sysname` | `String` |
+//! | `binary`, `varbinary`, `image`, `rowversion`, `timestamp` | `Vec<u8>` |
+//! | `uniqueidentifier` | [`Uuid`](tiberius::Uuid) |
+//! | `date`, `datetime`, `smalldatetime`, `datetime2`, `datetimeoffset` | [`SqlDateTime`](crate::SqlDateTime) |
+//!
+//! Other types, e.g. `time` and `xml`, need a mapping in [`CodegenOptions::types`] naming a type that implements
+//! [`FromSqlValue`](crate::FromSqlValue). The same option replaces a default, e.g. `datetime2` with
+//! `chrono::NaiveDateTime` with tiberius's `chrono` feature, or `decimal` with `rust_decimal::Decimal`.
+//!
+//! [`TryFromRow`]: crate::TryFromRow
+
+use crate::{error::Error, row::RowExt, sql::split_object_name};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use tiberius::Row;
+
+/// Reads the columns of the table named by `@P1`, in order, for
+/// [`SqlServerPool::schema_snapshot`](crate::SqlServerPool::schema_snapshot).
+pub(crate) const SCHEMA_QUERY: &str = "SELECT c.name, TYPE_NAME(c.system_type_id) AS type_name, c.max_length, c.precision, c.scale, c.is_nullable \
+     FROM sys.columns c \
+     WHERE c.object_id = OBJECT_ID(@P1) \
+     ORDER BY c.column_id;";
+
+/// The columns of some tables, as read by
+/// [`SqlServerPool::schema_snapshot`](crate::SqlServerPool::schema_snapshot), see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaSnapshot {
+    pub tables: Vec<TableSchema>,
+}
+
+/// A table of a [`SchemaSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableSchema {
+    /// The table's name as it was given to
+    /// [`SqlServerPool::schema_snapshot`](crate::SqlServerPool::schema_snapshot), e.g. `dbo.people`.
+    pub name: String,
+    pub columns: Vec<ColumnSchema>,
+}
+
+/// A column of a [`TableSchema`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnSchema {
+    pub name: String,
+    /// The declared type, e.g. `nvarchar(100)` or `decimal(18, 2)`.
+    pub sql_type: String,
+    pub nullable: bool,
+}
+
+impl SchemaSnapshot {
+    /// Parse a snapshot written by [`SchemaSnapshot::to_json`].
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// The snapshot as pretty-printed JSON, with a trailing newline, to commit alongside the code.
+    pub fn to_json(&self) -> String {
+        let mut json = serde_json::to_string_pretty(self).expect("a snapshot always serializes");
+        json.push('\n');
+        json
+    }
+
+    /// The first table called `name`, with its parts compared ignoring case and quoting, so `[dbo].[People]` finds
+    /// `dbo.people`. Parts left out of either name match anything, so `people` finds `dbo.people` too.
+    pub fn table(&self, name: &str) -> Result<Option<&TableSchema>, Error> {
+        let key = table_key(name)?;
+        for table in &self.tables {
+            let other = table_key(&table.name)?;
+            if key
+                .iter()
+                .rev()
+                .zip(other.iter().rev())
+                .all(|(a, b)| a == b)
+            {
+                return Ok(Some(table));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A column read with [`SCHEMA_QUERY`], with its declared type rebuilt from the length, precision and scale.
+pub(crate) fn column_schema(row: &Row) -> Result<ColumnSchema, Error> {
+    let name: String = row.get_named("name")?.unwrap_or_default();
+    let type_name: String = row.get_named("type_name")?.unwrap_or_default();
+    let max_length: i16 = row.get_named("max_length")?.unwrap_or_default();
+    let precision: u8 = row.get_named("precision")?.unwrap_or_default();
+    let scale: u8 = row.get_named("scale")?.unwrap_or_default();
+    let length = |bytes_per_char: i16| {
+        if max_length == -1 {
+            "max".to_owned()
+        } else {
+            (max_length / bytes_per_char).to_string()
+        }
+    };
+    let sql_type = match type_name.as_str() {
+        "char" | "varchar" | "binary" | "varbinary" => format!("{type_name}({})", length(1)),
+        "nchar" | "nvarchar" => format!("{type_name}({})", length(2)),
+        "decimal" | "numeric" => format!("{type_name}({precision}, {scale})"),
+        "datetime2" | "datetimeoffset" | "time" => format!("{type_name}({scale})"),
+        _ => type_name,
+    };
+    Ok(ColumnSchema {
+        name,
+        sql_type,
+        nullable: row.get_named("is_nullable")?.unwrap_or_default(),
+    })
+}
+
+fn table_key(name: &str) -> Result<Vec<String>, Error> {
+    Ok(split_object_name(name)?
+        .iter()
+        .map(|part| part.to_lowercase())
+        .collect())
+}
+
+/// Options for [`generate`].
+#[derive(Debug, Clone)]
+pub struct CodegenOptions {
+    /// The derives of every struct. Defaults to `Debug`, `Clone` and `PartialEq`. Add `serde::Serialize` to bind the
+    /// structs as [`ToSqlParams`](crate::ToSqlParams), which serde renames keep matching the column names, as long
+    /// as the field types are `Serialize`.
+    pub derives: Vec<String>,
+    /// Rust types for SQL types, by base type name ignoring case, e.g. `("datetime2", "chrono::NaiveDateTime")`.
+    /// These come before the default mappings.
+    pub types: Vec<(String, String)>,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        Self {
+            derives: vec!["Debug".into(), "Clone".into(), "PartialEq".into()],
+            types: Vec::new(),
+        }
+    }
+}
+
+/// Generate a struct and its [`TryFromRow`](crate::TryFromRow) implementation for each of `tables`, in order, see
+/// the [module docs](self).
+///
+/// Fails with [`Error::InvalidArgument`] if a table isn't in the snapshot or a column's type has no mapping.
+///
+/// ```
+/// use mssql_rs::codegen::{generate, CodegenOptions, SchemaSnapshot};
+///
+/// let snapshot = SchemaSnapshot::from_json(
+///     r#"{"tables": [{"name": "dbo.people", "columns": [
+///         {"name": "id", "sql_type": "int", "nullable": false},
+///         {"name": "DisplayName", "sql_type": "nvarchar(100)", "nullable": true}
+///     ]}]}"#,
+/// )?;
+/// let code = generate(&snapshot, &["dbo.people"], &CodegenOptions::default())?;
+/// assert_eq!(
+///     code,
+///     r#"// Generated by mssql_rs::codegen from a schema snapshot. Do not edit.
+///
+/// /// A row of `dbo.people`.
+/// #[derive(Debug, Clone, PartialEq)]
+/// pub struct People {
+///     /// `int NOT NULL`
+///     pub id: i32,
+///     /// `nvarchar(100) NULL`
+///     pub display_name: Option<String>,
+/// }
+///
+/// impl mssql_rs::TryFromRow for People {
+///     fn try_from(row: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {
+///         use mssql_rs::RowExt;
+///         Ok(Self {
+///             id: row.get_named("id")?.ok_or_else(|| null_column("id"))?,
+///             display_name: row.get_named("DisplayName")?,
+///         })
+///     }
+/// }
+///
+/// fn null_column(name: &str) -> mssql_rs::Error {
+///     mssql_rs::tiberius::error::Error::Conversion(format!("column {name} is NULL").into()).into()
+/// }
+/// "#
+/// );
+/// # Ok::<(), mssql_rs::Error>(())
+/// ```
+pub fn generate(
+    snapshot: &SchemaSnapshot,
+    tables: &[&str],
+    options: &CodegenOptions,
+) -> Result<String, Error> {
+    let mut code =
+        String::from("// Generated by mssql_rs::codegen from a schema snapshot. Do not edit.\n");
+    let serialize = options
+        .derives
+        .iter()
+        .any(|derive| derive.rsplit("::").next() == Some("Serialize"));
+    let mut needs_null_column = false;
+
+    for name in tables {
+        let table = snapshot.table(name)?.ok_or_else(|| {
+            Error::InvalidArgument(format!("table {name} is not in the schema snapshot"))
+        })?;
+        let object = split_object_name(&table.name)?
+            .pop()
+            .expect("object names have at least one part");
+        let struct_name = pascal_case(&object);
+
+        let mut fields = Vec::with_capacity(table.columns.len());
+        for column in &table.columns {
+            let rust_type = rust_type(&column.sql_type, options).ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "no Rust type for column {} of {}: {}, add one to CodegenOptions::types",
+                    column.name, table.name, column.sql_type
+                ))
+            })?;
+            fields.push((column, field_name(&column.name), rust_type));
+        }
+
+        let _ = write!(code, "\n/// A row of `{}`.\n", table.name);
+        if !options.derives.is_empty() {
+            let _ = writeln!(code, "#[derive({})]", options.derives.join(", "));
+        }
+        let _ = writeln!(code, "pub struct {struct_name} {{");
+        for (column, field, rust_type) in &fields {
+            let null = if column.nullable { "NULL" } else { "NOT NULL" };
+            let _ = writeln!(code, "    /// `{} {null}`", column.sql_type);
+            if serialize && field.trim_start_matches("r#") != column.name {
+                let _ = writeln!(code, "    #[serde(rename = {:?})]", column.name);
+            }
+            if column.nullable {
+                let _ = writeln!(code, "    pub {field}: Option<{rust_type}>,");
+            } else {
+                let _ = writeln!(code, "    pub {field}: {rust_type},");
+            }
+        }
+        code.push_str("}\n\n");
+
+        let _ = writeln!(code, "impl mssql_rs::TryFromRow for {struct_name} {{");
+        code.push_str(
+            "    fn try_from(row: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {\n        use mssql_rs::RowExt;\n        Ok(Self {\n",
+        );
+        for (column, field, _) in &fields {
+            if column.nullable {
+                let _ = writeln!(
+                    code,
+                    "            {field}: row.get_named({:?})?,",
+                    column.name
+                );
+            } else {
+                needs_null_column = true;
+                let _ = writeln!(
+                    code,
+                    "            {field}: row.get_named({0:?})?.ok_or_else(|| null_column({0:?}))?,",
+                    column.name
+                );
+            }
+        }
+        code.push_str("        })\n    }\n}\n");
+    }
+
+    if needs_null_column {
+        code.push_str(
+            "\nfn null_column(name: &str) -> mssql_rs::Error {\n    mssql_rs::tiberius::error::Error::Conversion(format!(\"column {name} is NULL\").into()).into()\n}\n",
+        );
+    }
+    Ok(code)
+}
+
+/// The Rust type of a column of `sql_type`, e.g. `nvarchar(100)`.
+fn rust_type(sql_type: &str, options: &CodegenOptions) -> Option<String> {
+    let base = sql_type
+        .split('(')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if let Some((_, rust_type)) = options
+        .types
+        .iter()
+        .find(|(sql, _)| sql.eq_ignore_ascii_case(&base))
+    {
+        return Some(rust_type.clone());
+    }
+    let rust_type = match base.as_str() {
+        "bit" => "bool",
+        "tinyint" => "u8",
+        "smallint" => "i16",
+        "int" => "i32",
+        "bigint" => "i64",
+        "real" => "f32",
+        "float" | "money" | "smallmoney" => "f64",
+        "decimal" | "numeric" => "mssql_rs::tiberius::numeric::Numeric",
+        "char" | "varchar" | "text" | "nchar" | "nvarchar" | "ntext" | "sysname" => "String",
+        "binary" | "varbinary" | "image" | "rowversion" | "timestamp" => "Vec<u8>",
+        "uniqueidentifier" => "mssql_rs::tiberius::Uuid",
+        "date" | "datetime" | "smalldatetime" | "datetime2" | "datetimeoffset" => {
+            "mssql_rs::SqlDateTime"
+        }
+        _ => return None,
+    };
+    Some(rust_type.to_owned())
+}
+
+/// Split a name into words at non-alphanumeric characters and lowercase-to-uppercase transitions.
+fn words(name: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        if !c.is_alphanumeric() {
+            previous = None;
+            continue;
+        }
+        let boundary = match previous {
+            None => true,
+            Some(p) => p.is_lowercase() && c.is_uppercase(),
+        };
+        if boundary {
+            words.push(String::new());
+        }
+        words
+            .last_mut()
+            .expect("a word was started")
+            .extend(c.to_lowercase());
+        previous = Some(c);
+    }
+    words
+}
+
+fn pascal_case(name: &str) -> String {
+    let name: String = words(name)
+        .iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect();
+    match name.chars().next() {
+        Some(first) if !first.is_ascii_digit() => name,
+        _ => format!("Table{name}"),
+    }
+}
+
+/// Rust keywords a field name can't be, so they are written as raw identifiers.
+const KEYWORDS: [&str; 38] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "gen",
+];
+
+fn field_name(column: &str) -> String {
+    let name = words(column).join("_");
+    match name.chars().next() {
+        None => "column".to_owned(),
+        Some(first) if first.is_ascii_digit() => format!("column_{name}"),
+        _ if name == "self" || name == "super" || name == "crate" => format!("{name}_"),
+        _ if KEYWORDS.contains(&name.as_str()) => format!("r#{name}"),
+        _ => name,
+    }
+}
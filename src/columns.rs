@@ -0,0 +1,170 @@
+use crate::error::Error;
+use tiberius::Column;
+
+/// A column of a result, as far as matching it by name goes.
+pub(crate) trait NamedColumn {
+    fn column_name(&self) -> &str;
+}
+
+impl NamedColumn for Column {
+    fn column_name(&self) -> &str {
+        self.name()
+    }
+}
+
+/// How a column name given to a by-name accessor, e.g. [`RowExt::get_named`](crate::RowExt::get_named), is matched
+/// against the columns of a result.
+///
+/// SQL Server compares identifiers case-insensitively under most collations, so by default `UserID` finds a column
+/// selected as `userid`. Either way, a name matching more than one column, e.g. `id` in a join selecting both
+/// `users.id` and `orders.id`, fails with [`Error::AmbiguousColumn`] rather than picking one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColumnMatching {
+    /// Match names ignoring case, including outside ASCII.
+    #[default]
+    CaseInsensitive,
+    /// Match names exactly, as the server does under a case-sensitive collation.
+    Exact,
+}
+
+impl ColumnMatching {
+    /// Whether the column called `column` matches `name`.
+    ///
+    /// ```
+    /// use mssql_rs::ColumnMatching;
+    ///
+    /// assert!(ColumnMatching::CaseInsensitive.matches("UserID", "userid"));
+    /// assert!(ColumnMatching::CaseInsensitive.matches("Größe", "GRÖßE"));
+    /// assert!(ColumnMatching::CaseInsensitive.matches("ÄPFEL", "äpfel"));
+    /// assert!(!ColumnMatching::Exact.matches("UserID", "userid"));
+    /// assert!(ColumnMatching::Exact.matches("Größe", "Größe"));
+    /// ```
+    pub fn matches(self, column: &str, name: &str) -> bool {
+        match self {
+            ColumnMatching::CaseInsensitive => column
+                .chars()
+                .flat_map(char::to_lowercase)
+                .eq(name.chars().flat_map(char::to_lowercase)),
+            ColumnMatching::Exact => column == name,
+        }
+    }
+
+    /// Find the index of the one column of `columns` matching `name`.
+    ///
+    /// Every by-name accessor resolves columns through this, so they all agree on what matches.
+    pub(crate) fn resolve<C: NamedColumn>(self, columns: &[C], name: &str) -> Result<usize, Error> {
+        self.find(columns, name)?.ok_or_else(|| {
+            tiberius::error::Error::Conversion(
+                format!("Could not find column with name {name}").into(),
+            )
+            .into()
+        })
+    }
+
+    /// Find the index of the one column of `columns` matching `name`, or `None` if none does.
+    pub(crate) fn find<C: NamedColumn>(
+        self,
+        columns: &[C],
+        name: &str,
+    ) -> Result<Option<usize>, Error> {
+        let mut matching = columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| self.matches(column.column_name(), name))
+            .map(|(index, _)| index);
+        let Some(index) = matching.next() else {
+            return Ok(None);
+        };
+        let others = matching.count();
+        if others > 0 {
+            return Err(Error::AmbiguousColumn {
+                name: name.to_owned(),
+                count: others + 1,
+            });
+        }
+        Ok(Some(index))
+    }
+
+    /// Fail with [`Error::UnexpectedColumns`] if `columns` has any column not matching one of `expected`.
+    pub(crate) fn deny_unknown<C: NamedColumn>(
+        self,
+        columns: &[C],
+        expected: &[&str],
+    ) -> Result<(), Error> {
+        let unexpected: Vec<String> = columns
+            .iter()
+            .filter(|column| {
+                !expected
+                    .iter()
+                    .any(|name| self.matches(column.column_name(), name))
+            })
+            .map(|column| column.column_name().to_owned())
+            .collect();
+        if unexpected.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedColumns(unexpected))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl NamedColumn for &str {
+        fn column_name(&self) -> &str {
+            self
+        }
+    }
+
+    const COLUMNS: [&str; 4] = ["id", "UserName", "Größe", "note"];
+
+    #[test]
+    fn find_matches_one_column() {
+        let find = |matching: ColumnMatching, name| matching.find(&COLUMNS, name).unwrap();
+        assert_eq!(find(ColumnMatching::CaseInsensitive, "username"), Some(1));
+        assert_eq!(find(ColumnMatching::CaseInsensitive, "GRÖSSE"), None);
+        assert_eq!(find(ColumnMatching::CaseInsensitive, "größe"), Some(2));
+        assert_eq!(find(ColumnMatching::Exact, "username"), None);
+        assert_eq!(find(ColumnMatching::Exact, "UserName"), Some(1));
+        assert_eq!(find(ColumnMatching::CaseInsensitive, "missing"), None);
+        assert!(ColumnMatching::default()
+            .resolve(&COLUMNS, "missing")
+            .is_err());
+        assert_eq!(
+            ColumnMatching::default().resolve(&COLUMNS, "NOTE").unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn several_matches_are_ambiguous() {
+        let joined = ["id", "name", "ID"];
+        assert!(matches!(
+            ColumnMatching::CaseInsensitive.find(&joined, "Id"),
+            Err(Error::AmbiguousColumn { name, count: 2 }) if name == "Id"
+        ));
+        assert_eq!(ColumnMatching::Exact.find(&joined, "ID").unwrap(), Some(2));
+    }
+
+    #[test]
+    fn deny_unknown_names_extra_columns_in_order() {
+        let matching = ColumnMatching::default();
+        assert!(matching
+            .deny_unknown(&COLUMNS, &["NOTE", "größe", "ID", "username"])
+            .is_ok());
+        // Reordered and missing columns are fine, only extra ones fail.
+        assert!(matching
+            .deny_unknown(&COLUMNS[..2], &["username", "id", "note"])
+            .is_ok());
+        assert!(matches!(
+            matching.deny_unknown(&COLUMNS, &["id"]),
+            Err(Error::UnexpectedColumns(extra)) if extra == ["UserName", "Größe", "note"]
+        ));
+        assert!(matches!(
+            ColumnMatching::Exact.deny_unknown(&COLUMNS, &["id", "username", "Größe", "note"]),
+            Err(Error::UnexpectedColumns(extra)) if extra == ["UserName"]
+        ));
+    }
+}
@@ -0,0 +1,291 @@
+use crate::error::Error;
+use crate::fault::Faults;
+use crate::manager::ConnectionManager;
+use crate::metered::ByteCount;
+use crate::observer::{BrokenReason, ConnectionEvent};
+use crate::query::{json_query_on, query_rows_on};
+use crate::session_options::LockSettings;
+use crate::sql;
+use crate::temp_proc::TempProc;
+use crate::version::ServerVersion;
+use crate::TryFromRow;
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+use tokio::sync::OwnedSemaphorePermit;
+
+/// The underlying tiberius client type held by the pool.
+#[cfg(not(feature = "protocol-debug"))]
+pub type Client = tiberius::Client<
+    tokio_util::compat::Compat<crate::metered::MeteredStream<tokio::net::TcpStream>>,
+>;
+
+/// The underlying tiberius client type held by the pool.
+#[cfg(feature = "protocol-debug")]
+pub type Client = tiberius::Client<
+    tokio_util::compat::Compat<
+        crate::trace::TracedStream<crate::metered::MeteredStream<tokio::net::TcpStream>>,
+    >,
+>;
+
+/// The priority class of a connection request, see [`SqlServerPool::get_with_priority`](crate::SqlServerPool::get_with_priority).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// Acquire directly from the pool. This is how the query methods acquire connections.
+    #[default]
+    High,
+    /// Acquire only while fewer than `pool_max_size - high_priority_reserve` low priority connections are checked out,
+    /// so that low priority work can't starve high priority work.
+    Low,
+}
+
+/// A connection checked out from a [`SqlServerPool`](crate::SqlServerPool).
+///
+/// Derefs to the underlying [`tiberius::Client`] for direct access. The connection is returned to the pool when dropped.
+///
+/// Every query run through the same `PooledConnection` runs in the same session, so it can hold a sticky session's
+/// state, e.g. temp tables. Register the SQL dropping that state with [`PooledConnection::on_release`].
+///
+/// Temp objects never outlive the `PooledConnection` they were created on: tiberius doesn't reset sessions on reuse,
+/// so a temp table left behind would otherwise surface in an unrelated query, e.g. as "There is already an object
+/// named '#staging' in the database". Queries run through the crate's methods, on the pool or on a connection, are
+/// scanned for `CREATE TABLE #t`, `SELECT ... INTO #t`, `CREATE PROCEDURE #p` and the matching `DROP`s. A connection
+/// returned with temp objects it created and didn't drop is discarded rather than reused, unless cleanup was
+/// registered with [`PooledConnection::on_release`] and ran with [`PooledConnection::release`]. Queries run directly
+/// on the [`tiberius::Client`] aren't scanned, so register cleanup for the temp objects they create. The sanctioned
+/// ways to use temp objects are [`SqlServerPool::with_temp_table`](crate::SqlServerPool::with_temp_table),
+/// [`PooledConnection::prepare_temp_proc`] and `on_release` cleanup.
+pub struct PooledConnection<'a> {
+    inner: bb8::PooledConnection<'a, ConnectionManager>,
+    _permit: Option<OwnedSemaphorePermit>,
+    _in_flight: Option<OwnedSemaphorePermit>,
+    faults: Faults,
+    cleanup: Vec<String>,
+    /// The temp objects queries created on this connection and haven't dropped, see [`sql::temp_objects`].
+    temp_objects: HashSet<String>,
+}
+
+impl<'a> PooledConnection<'a> {
+    pub(crate) fn new(
+        inner: bb8::PooledConnection<'a, ConnectionManager>,
+        permit: Option<OwnedSemaphorePermit>,
+        in_flight: Option<OwnedSemaphorePermit>,
+        faults: Faults,
+    ) -> Self {
+        inner.ages.set_checked_out(inner.id, true);
+        inner.lifecycle(ConnectionEvent::CheckedOut);
+        Self {
+            inner,
+            _permit: permit,
+            _in_flight: in_flight,
+            faults,
+            cleanup: Vec::new(),
+            temp_objects: HashSet::new(),
+        }
+    }
+
+    /// Returns the version of the server this connection is connected to.
+    pub fn server_version(&self) -> ServerVersion {
+        self.inner.server_version
+    }
+
+    /// Returns how long ago this connection was opened.
+    pub fn age(&self) -> Duration {
+        self.inner.created_at.elapsed()
+    }
+
+    /// Returns the bytes this connection has read and written since it was opened.
+    ///
+    /// Take the count before and after a query and subtract with [`ByteCount::since`] to attribute network usage,
+    /// e.g. egress costs, to the query.
+    pub fn bytes(&self) -> ByteCount {
+        self.inner.bytes.get()
+    }
+
+    /// Run a JSON query on this connection, see [`SqlServerPool::json_query`](crate::SqlServerPool::json_query).
+    pub async fn json_query<T>(&mut self, query: &str, params: &[String]) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        json_query_on(self, query, params).await
+    }
+
+    /// Run a SQL query on this connection, see [`SqlServerPool::row_query`](crate::SqlServerPool::row_query).
+    pub async fn row_query<T>(&mut self, query: &str, params: &[String]) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        query_rows_on(self, query, params).await
+    }
+
+    /// Create a temporary stored procedure on this connection, so a hot dynamically built query is compiled once
+    /// rather than on every execution. See [`TempProc`].
+    ///
+    /// `param_decls` are the names and types of the procedure's parameters, which `body_sql` refers to by name.
+    /// `name_hint` is included in the procedure's name to help identify it, e.g. in traces.
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlParam, SqlServerPool, Priority, TryFromRow};
+    /// # struct Total;
+    /// # impl TryFromRow for Total {
+    /// #     fn try_from(row: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Total) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool, tenant_query: String) -> mssql_rs::Result<()> {
+    /// let mut conn = sql_server.get_with_priority(Priority::High).await?;
+    /// let mut report = conn
+    ///     .prepare_temp_proc("tenant_report", &tenant_query, &[("tenant", "int")])
+    ///     .await?;
+    ///
+    /// for tenant in [1, 2, 3] {
+    ///     let totals: Vec<Total> = report.exec(&[SqlParam::from(tenant)]).await?;
+    /// }
+    /// report.drop_proc().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn prepare_temp_proc<'c>(
+        &'c mut self,
+        name_hint: &str,
+        body_sql: &str,
+        param_decls: &[(&str, &str)],
+    ) -> Result<TempProc<'c, 'a>, Error> {
+        TempProc::create(self, name_hint, body_sql, param_decls).await
+    }
+
+    /// Register cleanup SQL to run when the connection is released with [`PooledConnection::release`], e.g.
+    /// `DROP TABLE IF EXISTS #staging`, so temp objects don't linger on the pooled connection.
+    ///
+    /// Cleanup runs in the reverse order it was registered. If the connection is dropped without being released, the
+    /// cleanup can't run, so the connection is discarded instead of returned to the pool, which drops every temp object.
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, Priority, TryFromRow};
+    /// # struct Total;
+    /// # impl TryFromRow for Total {
+    /// #     fn try_from(row: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Total) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let mut conn = sql_server.get_with_priority(Priority::High).await?;
+    /// conn.simple_query("CREATE TABLE #staging (id int, total money)").await?.into_results().await?;
+    /// conn.on_release("DROP TABLE IF EXISTS #staging");
+    ///
+    /// conn.simple_query("INSERT INTO #staging SELECT id, total FROM orders").await?.into_results().await?;
+    /// let totals: Vec<Total> = conn.row_query("SELECT * FROM #staging", &[]).await?;
+    /// conn.release().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_release(&mut self, cleanup_sql: impl Into<String>) {
+        self.cleanup.push(cleanup_sql.into());
+    }
+
+    /// Run the cleanup registered with [`PooledConnection::on_release`], then return the connection to the pool.
+    ///
+    /// If any cleanup fails, the rest is skipped and the connection is discarded instead.
+    pub async fn release(mut self) -> Result<(), Error> {
+        while let Some(cleanup) = self.cleanup.last() {
+            let cleanup = cleanup.clone();
+            self.simple_query(cleanup).await?.into_results().await?;
+            self.cleanup.pop();
+        }
+        // The cleanup is trusted to drop what the connection's queries created.
+        self.temp_objects.clear();
+        Ok(())
+    }
+
+    /// Mark the connection as broken because a request may not complete, so that it is discarded instead of
+    /// returned to the pool.
+    pub(crate) fn mark_broken(&mut self) {
+        self.mark_broken_because(BrokenReason::Interrupted);
+    }
+
+    /// Mark the connection as broken for `reason`, so that it is discarded instead of returned to the pool.
+    ///
+    /// A connection already broken keeps its reason, as the first cause is the one worth reporting.
+    pub(crate) fn mark_broken_because(&mut self, reason: BrokenReason) {
+        if !self.inner.broken {
+            self.inner.broken_reason = reason;
+        }
+        self.set_broken(true);
+    }
+
+    /// Set whether the connection is discarded instead of returned to the pool.
+    pub(crate) fn set_broken(&mut self, broken: bool) {
+        self.inner.broken = broken;
+    }
+
+    /// Change the session's locking settings to `settings`, unless it already has them. On failure the connection is
+    /// discarded, as its settings are unknown.
+    pub(crate) async fn apply_lock_settings(
+        &mut self,
+        settings: LockSettings,
+    ) -> Result<(), Error> {
+        if self.inner.lock_settings == settings {
+            return Ok(());
+        }
+        self.mark_broken_because(BrokenReason::SessionState);
+        self.inner
+            .client
+            .simple_query(settings.batch())
+            .await?
+            .into_results()
+            .await?;
+        self.inner.lock_settings = settings;
+        self.set_broken(false);
+        Ok(())
+    }
+
+    /// Note the temp objects `sql` creates and drops, so the connection isn't reused with any left behind.
+    ///
+    /// Call it just before `sql` is sent, after any check that can reject the query without reaching the server,
+    /// so a query that never ran doesn't cost the connection.
+    pub(crate) fn track_temp_objects(&mut self, sql: &str) {
+        let (created, dropped) = sql::temp_objects(sql);
+        self.temp_objects.extend(created);
+        for name in dropped {
+            self.temp_objects.remove(&name);
+        }
+    }
+
+    /// Returns the pool's fault injector.
+    pub(crate) fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    /// Returns how many rows of a result are read between yields to the runtime, see
+    /// [`SqlServerPoolBuilder::yield_every_rows`](crate::SqlServerPoolBuilder::yield_every_rows).
+    pub(crate) fn yield_every_rows(&self) -> usize {
+        self.inner.yield_every_rows
+    }
+
+    /// Returns true if the connection will be discarded instead of returned to the pool.
+    pub(crate) fn is_broken(&self) -> bool {
+        self.inner.broken
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        // Discard the connection rather than return it with temp objects that should have been cleaned up.
+        if !self.cleanup.is_empty() || !self.temp_objects.is_empty() {
+            self.mark_broken_because(BrokenReason::SessionState);
+        }
+        self.inner.ages.set_checked_out(self.inner.id, false);
+        self.inner.idle_since = Instant::now();
+    }
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner.client
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner.client
+    }
+}
@@ -0,0 +1,114 @@
+use crate::error::Error;
+use futures_util::future::BoxFuture;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A source of [`Credentials`], consulted every time the pool opens a new physical connection.
+///
+/// Existing connections are unaffected when the returned credentials change,
+/// so secrets can be rotated without rebuilding the pool.
+pub type CredentialsProvider =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<Credentials, Error>> + Send + Sync>;
+
+/// SQL Server authentication credentials.
+#[derive(Clone)]
+pub struct Credentials {
+    user: String,
+    password: String,
+}
+
+impl Credentials {
+    /// Create credentials for SQL Server authentication.
+    pub fn new(user: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            user: user.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Create a provider that reads the user and password from files (e.g. mounted secrets).
+    ///
+    /// The files are read again for every new connection, so rotated secrets are picked up
+    /// without restarting. Trailing whitespace (such as a final newline) is trimmed from both values.
+    pub fn from_files(
+        user_path: impl Into<PathBuf>,
+        password_path: impl Into<PathBuf>,
+    ) -> CredentialsProvider {
+        let user_path = user_path.into();
+        let password_path = password_path.into();
+
+        Arc::new(move || {
+            let user_path = user_path.clone();
+            let password_path = password_path.clone();
+
+            Box::pin(async move {
+                let user = tokio::fs::read_to_string(&user_path).await?;
+                let password = tokio::fs::read_to_string(&password_path).await?;
+
+                Ok(Credentials::new(user.trim_end(), password.trim_end()))
+            })
+        })
+    }
+
+    pub(crate) fn auth_method(&self) -> tiberius::AuthMethod {
+        tiberius::AuthMethod::sql_server(&self.user, &self.password)
+    }
+}
+
+/// The password is redacted.
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("user", &self.user)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mssql_rs-{}-{name}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn from_files_picks_up_rotated_secrets() {
+        let user_path = secret_path("rotation-user");
+        let password_path = secret_path("rotation-password");
+        std::fs::write(&user_path, "app\n").unwrap();
+        std::fs::write(&password_path, "first\n").unwrap();
+
+        let provider = Credentials::from_files(&user_path, &password_path);
+
+        let credentials = provider().await.unwrap();
+        assert_eq!(credentials.user, "app");
+        assert_eq!(credentials.password, "first");
+
+        std::fs::write(&password_path, "second  \r\n").unwrap();
+
+        let credentials = provider().await.unwrap();
+        assert_eq!(credentials.user, "app");
+        assert_eq!(credentials.password, "second");
+
+        std::fs::remove_file(&user_path).unwrap();
+        std::fs::remove_file(&password_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn from_files_fails_when_a_secret_is_missing() {
+        let provider =
+            Credentials::from_files(secret_path("missing-user"), secret_path("missing-password"));
+
+        assert!(provider().await.is_err());
+    }
+
+    #[test]
+    fn debug_redacts_the_password() {
+        let debug = format!("{:?}", Credentials::new("app", "hunter2"));
+
+        assert!(debug.contains("app"));
+        assert!(!debug.contains("hunter2"));
+    }
+}
@@ -0,0 +1,696 @@
+use crate::error::Error;
+use tiberius::numeric::Numeric;
+use tiberius::time::{Date, DateTime, DateTime2, Time};
+use tiberius::{ColumnData, Uuid};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// What [`SqlServerPool::csv_import`](crate::SqlServerPool::csv_import) does with a row it can't load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BadRowPolicy {
+    /// Stop at the first bad row with [`Error::CsvRow`]. Nothing is loaded.
+    #[default]
+    FailFast,
+    /// Skip bad rows, reporting them in [`ImportStats`], and load the rest.
+    Skip,
+}
+
+/// Options for [`SqlServerPool::csv_import`](crate::SqlServerPool::csv_import).
+#[derive(Debug, Clone)]
+pub struct CsvImportOptions {
+    /// The field delimiter. Defaults to `,`.
+    pub delimiter: u8,
+    /// Whether the first record names the columns. Defaults to true.
+    ///
+    /// With a header, fields are matched to columns by name, case-insensitively, and table columns missing from
+    /// the header are loaded as NULL. Without one, the fields must be the table's insertable columns, in order.
+    pub has_header: bool,
+    /// What to do with rows that can't be loaded. Defaults to [`BadRowPolicy::FailFast`].
+    pub on_bad_row: BadRowPolicy,
+    /// The maximum number of rejected rows listed in [`ImportStats::rejected`]. Later ones are only counted.
+    /// Defaults to 100.
+    pub max_rejected_rows: usize,
+}
+
+impl Default for CsvImportOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_header: true,
+            on_bad_row: BadRowPolicy::FailFast,
+            max_rejected_rows: 100,
+        }
+    }
+}
+
+/// The outcome of [`SqlServerPool::csv_import`](crate::SqlServerPool::csv_import).
+#[derive(Debug, Clone, Default)]
+pub struct ImportStats {
+    /// The number of rows loaded into the table.
+    pub rows_loaded: u64,
+    /// The number of rows skipped, see [`BadRowPolicy::Skip`].
+    pub rows_rejected: u64,
+    /// The first [`CsvImportOptions::max_rejected_rows`] rows skipped.
+    pub rejected: Vec<RejectedRow>,
+}
+
+/// A CSV row that couldn't be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedRow {
+    /// The line the row starts on, counting from 1.
+    pub line: u64,
+    /// Why the row was rejected, e.g. a value that doesn't convert to its column's type.
+    pub reason: String,
+}
+
+/// Lists a table's insertable columns, in the order bulk inserts expect them. Identity, computed and rowversion
+/// (system type 189) columns are excluded, as the server generates their values.
+pub(crate) const IMPORT_COLUMNS_QUERY: &str = "SELECT c.name, TYPE_NAME(c.system_type_id), c.max_length, c.precision, c.scale \
+     FROM sys.columns c \
+     WHERE c.object_id = OBJECT_ID(@P1) AND c.is_identity = 0 AND c.is_computed = 0 AND c.system_type_id <> 189 \
+     ORDER BY c.column_id;";
+
+/// A column of the table being imported into, read with [`IMPORT_COLUMNS_QUERY`].
+#[derive(Debug, Clone)]
+pub(crate) struct ImportColumn {
+    pub(crate) name: String,
+    kind: ImportType,
+}
+
+/// The column types a CSV field can be converted to.
+#[derive(Debug, Clone, Copy)]
+enum ImportType {
+    Bit,
+    TinyInt,
+    SmallInt,
+    Int,
+    BigInt,
+    Real,
+    Float,
+    Decimal {
+        precision: u8,
+        scale: u8,
+    },
+    /// A string type, with its maximum length in characters, if limited.
+    String {
+        max_chars: Option<usize>,
+    },
+    Guid,
+    /// A binary type, with its maximum length in bytes, if limited.
+    Binary {
+        max_bytes: Option<usize>,
+    },
+    Date,
+    DateTime,
+    DateTime2 {
+        scale: u8,
+    },
+}
+
+impl ImportColumn {
+    /// Describe a column from its system type name and sizes, failing for types CSV fields can't be converted to.
+    pub(crate) fn new(
+        name: String,
+        type_name: &str,
+        max_length: i16,
+        precision: u8,
+        scale: u8,
+    ) -> Result<Self, Error> {
+        // -1 marks a (max) type.
+        let max_bytes = usize::try_from(max_length).ok();
+        let kind = match type_name {
+            "bit" => ImportType::Bit,
+            "tinyint" => ImportType::TinyInt,
+            "smallint" => ImportType::SmallInt,
+            "int" => ImportType::Int,
+            "bigint" => ImportType::BigInt,
+            "real" => ImportType::Real,
+            "float" => ImportType::Float,
+            "decimal" | "numeric" => ImportType::Decimal { precision, scale },
+            "char" | "varchar" => ImportType::String {
+                max_chars: max_bytes,
+            },
+            "nchar" | "nvarchar" => ImportType::String {
+                max_chars: max_bytes.map(|bytes| bytes / 2),
+            },
+            "uniqueidentifier" => ImportType::Guid,
+            "binary" | "varbinary" => ImportType::Binary { max_bytes },
+            "date" => ImportType::Date,
+            "datetime" => ImportType::DateTime,
+            "datetime2" => ImportType::DateTime2 { scale },
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "column {name} has type {type_name}, which CSV import doesn't support"
+                )))
+            }
+        };
+        Ok(Self { name, kind })
+    }
+
+    /// A NULL of the column's type.
+    pub(crate) fn null(&self) -> ColumnData<'static> {
+        match self.kind {
+            ImportType::Bit => ColumnData::Bit(None),
+            ImportType::TinyInt => ColumnData::U8(None),
+            ImportType::SmallInt => ColumnData::I16(None),
+            ImportType::Int => ColumnData::I32(None),
+            ImportType::BigInt => ColumnData::I64(None),
+            ImportType::Real => ColumnData::F32(None),
+            ImportType::Float => ColumnData::F64(None),
+            ImportType::Decimal { .. } => ColumnData::Numeric(None),
+            ImportType::String { .. } => ColumnData::String(None),
+            ImportType::Guid => ColumnData::Guid(None),
+            ImportType::Binary { .. } => ColumnData::Binary(None),
+            ImportType::Date => ColumnData::Date(None),
+            ImportType::DateTime => ColumnData::DateTime(None),
+            ImportType::DateTime2 { .. } => ColumnData::DateTime2(None),
+        }
+    }
+
+    /// Convert a field to the column's type. An unquoted empty field (`None`) is NULL.
+    pub(crate) fn convert(&self, field: Option<String>) -> Result<ColumnData<'static>, String> {
+        let Some(field) = field else {
+            return Ok(self.null());
+        };
+        let mismatch = || format!("column {}: cannot convert {field:?}", self.name);
+        let text = field.trim();
+
+        let value = match self.kind {
+            ImportType::Bit => match text {
+                "1" => ColumnData::Bit(Some(true)),
+                "0" => ColumnData::Bit(Some(false)),
+                _ if text.eq_ignore_ascii_case("true") => ColumnData::Bit(Some(true)),
+                _ if text.eq_ignore_ascii_case("false") => ColumnData::Bit(Some(false)),
+                _ => return Err(mismatch()),
+            },
+            ImportType::TinyInt => ColumnData::U8(Some(text.parse().map_err(|_| mismatch())?)),
+            ImportType::SmallInt => ColumnData::I16(Some(text.parse().map_err(|_| mismatch())?)),
+            ImportType::Int => ColumnData::I32(Some(text.parse().map_err(|_| mismatch())?)),
+            ImportType::BigInt => ColumnData::I64(Some(text.parse().map_err(|_| mismatch())?)),
+            ImportType::Real => ColumnData::F32(Some(text.parse().map_err(|_| mismatch())?)),
+            ImportType::Float => ColumnData::F64(Some(text.parse().map_err(|_| mismatch())?)),
+            ImportType::Decimal { precision, scale } => {
+                let value = parse_decimal(text, precision, scale).ok_or_else(mismatch)?;
+                ColumnData::Numeric(Some(Numeric::new_with_scale(value, scale)))
+            }
+            ImportType::String { max_chars } => {
+                // A field no longer in bytes than the limit can't be longer in characters, so skip the count.
+                if max_chars.is_some_and(|max| field.len() > max && field.chars().count() > max) {
+                    return Err(format!("column {}: value is too long", self.name));
+                }
+                ColumnData::String(Some(field.into()))
+            }
+            ImportType::Guid => {
+                ColumnData::Guid(Some(Uuid::parse_str(text).map_err(|_| mismatch())?))
+            }
+            ImportType::Binary { max_bytes } => {
+                let bytes = parse_hex(text).ok_or_else(mismatch)?;
+                if max_bytes.is_some_and(|max| bytes.len() > max) {
+                    return Err(format!("column {}: value is too long", self.name));
+                }
+                ColumnData::Binary(Some(bytes.into()))
+            }
+            ImportType::Date => {
+                let (days, nanos) = parse_datetime(text).ok_or_else(mismatch)?;
+                if nanos != 0 {
+                    return Err(mismatch());
+                }
+                ColumnData::Date(Some(Date::new(days)))
+            }
+            ImportType::DateTime => {
+                let (days, nanos) = parse_datetime(text).ok_or_else(mismatch)?;
+                // datetime counts days from 1900-01-01 and time in 1/300 second ticks, rounding to the nearest tick.
+                let mut days = i64::from(days) - DAYS_TO_1900;
+                let mut ticks = (nanos * 3 + 5_000_000) / 10_000_000;
+                if ticks == TICKS_PER_DAY {
+                    days += 1;
+                    ticks = 0;
+                }
+                let days = i32::try_from(days)
+                    .ok()
+                    .filter(|&days| days >= DATETIME_MIN_DAYS)
+                    .ok_or_else(mismatch)?;
+                ColumnData::DateTime(Some(DateTime::new(days, ticks as u32)))
+            }
+            ImportType::DateTime2 { scale } => {
+                let (days, nanos) = parse_datetime(text).ok_or_else(mismatch)?;
+                // Reject digits the column can't hold, rather than silently dropping them.
+                let unit = 10u64.pow(9 - u32::from(scale.min(7)));
+                if nanos % unit != 0 {
+                    return Err(format!(
+                        "column {}: {field:?} has more fractional digits than the column's scale of {scale}",
+                        self.name
+                    ));
+                }
+                let time = Time::new(nanos / unit, scale);
+                ColumnData::DateTime2(Some(DateTime2::new(Date::new(days), time)))
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Days from 0001-01-01 to 1900-01-01.
+const DAYS_TO_1900: i64 = 693_595;
+
+/// The earliest datetime, 1753-01-01, in days from 1900-01-01.
+const DATETIME_MIN_DAYS: i32 = -53_690;
+
+/// The number of 1/300 second datetime ticks in a day.
+const TICKS_PER_DAY: u64 = 24 * 60 * 60 * 300;
+
+/// Parse a decimal number into an integer of `scale` digits after the point, with at most `precision` digits.
+fn parse_decimal(text: &str, precision: u8, scale: u8) -> Option<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+    if int_part.is_empty() && frac_part.is_empty()
+        || !int_part
+            .bytes()
+            .chain(frac_part.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    // Extra fractional digits are allowed only if they are zeros, so no value is rounded.
+    let scale = usize::from(scale);
+    let (kept, dropped) = frac_part.split_at(frac_part.len().min(scale));
+    if dropped.bytes().any(|b| b != b'0') {
+        return None;
+    }
+    let int_part = int_part.trim_start_matches('0');
+    if int_part.len() + scale > usize::from(precision) {
+        return None;
+    }
+
+    let mut value: i128 = 0;
+    for b in int_part.bytes().chain(kept.bytes()) {
+        value = value * 10 + i128::from(b - b'0');
+    }
+    value *= 10i128.pow((scale - kept.len()) as u32);
+    Some(if negative { -value } else { value })
+}
+
+/// Parse hex digits, with or without a `0x` prefix.
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parse `YYYY-MM-DD`, optionally followed by `T` or a space and `HH:MM[:SS[.fffffffff]]`,
+/// into days from 0001-01-01 and nanoseconds since midnight.
+fn parse_datetime(text: &str) -> Option<(u32, u64)> {
+    let (date, time) = match text.find(['T', ' ']) {
+        Some(i) => (&text[..i], Some(&text[i + 1..])),
+        None => (text, None),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parse_digits(parts.next()?, 4)?;
+    let month: u32 = parse_digits(parts.next()?, 2)?;
+    let day: u32 = parse_digits(parts.next()?, 2)?;
+    if year == 0 || !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let days = u32::try_from(days_from_civil(year, month, day) - days_from_civil(1, 1, 1)).ok()?;
+
+    let nanos = match time {
+        None => 0,
+        Some(time) => {
+            let (hms, fraction) = time.split_once('.').unwrap_or((time, ""));
+            let mut parts = hms.splitn(3, ':');
+            let hours: u64 = parse_digits(parts.next()?, 2)?;
+            let minutes: u64 = parse_digits(parts.next()?, 2)?;
+            let seconds: u64 = parts.next().map_or(Some(0), |s| parse_digits(s, 2))?;
+            if hours > 23 || minutes > 59 || seconds > 59 || fraction.len() > 9 {
+                return None;
+            }
+            let fraction_nanos = if fraction.is_empty() {
+                0
+            } else {
+                parse_digits::<u64>(fraction, fraction.len())?
+                    * 10u64.pow(9 - fraction.len() as u32)
+            };
+            ((hours * 60 + minutes) * 60 + seconds) * 1_000_000_000 + fraction_nanos
+        }
+    };
+    Some((days, nanos))
+}
+
+/// Parse exactly `len` ASCII digits.
+fn parse_digits<T: std::str::FromStr>(text: &str, len: usize) -> Option<T> {
+    if text.len() != len || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// A streaming reader of CSV records, reading one record at a time.
+///
+/// Follows RFC 4180: fields may be quoted with `"`, doubling any `"` inside, and quoted fields may span lines.
+pub(crate) struct CsvReader<R> {
+    reader: BufReader<R>,
+    delimiter: u8,
+    line: u64,
+    buf: Vec<u8>,
+}
+
+/// A record read by [`CsvReader`]: the line it starts on, and its fields or why they couldn't be parsed.
+pub(crate) type CsvRecord = (u64, Result<Vec<Option<String>>, String>);
+
+impl<R: AsyncRead + Unpin> CsvReader<R> {
+    pub(crate) fn new(reader: R, delimiter: u8) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            delimiter,
+            line: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Read the next record, skipping blank lines. Returns `None` at the end of the input.
+    pub(crate) async fn next_record(&mut self) -> Result<Option<CsvRecord>, Error> {
+        loop {
+            self.buf.clear();
+            let start = self.line + 1;
+
+            // Read lines until the quotes balance, as a quoted field may contain newlines.
+            let mut quotes = 0;
+            loop {
+                let from = self.buf.len();
+                if self.reader.read_until(b'\n', &mut self.buf).await? == 0 {
+                    break;
+                }
+                self.line += 1;
+                quotes += self.buf[from..].iter().filter(|&&b| b == b'"').count();
+                if quotes % 2 == 0 {
+                    break;
+                }
+            }
+
+            if self.buf.is_empty() {
+                return Ok(None);
+            }
+            if quotes % 2 != 0 {
+                return Ok(Some((start, Err("unterminated quoted field".into()))));
+            }
+
+            let record = strip_newline(&self.buf);
+            if record.is_empty() {
+                continue;
+            }
+            let record = if start == 1 {
+                record.strip_prefix("\u{feff}".as_bytes()).unwrap_or(record)
+            } else {
+                record
+            };
+            return Ok(Some((start, parse_record(record, self.delimiter))));
+        }
+    }
+}
+
+fn strip_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Split a record with balanced quotes into fields. Unquoted empty fields are `None`.
+fn parse_record(record: &[u8], delimiter: u8) -> Result<Vec<Option<String>>, String> {
+    // Most records quote nothing, so each field is copied once, straight out of the record.
+    if !record.contains(&b'"') {
+        return record
+            .split(|&b| b == delimiter)
+            .enumerate()
+            .map(|(index, field)| field_text(field, false, index))
+            .collect();
+    }
+
+    let mut fields = Vec::new();
+    let mut field = Vec::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+
+    let mut bytes = record.iter().copied().peekable();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'"' if in_quotes && bytes.peek() == Some(&b'"') => {
+                bytes.next();
+                field.push(b'"');
+            }
+            b'"' if in_quotes => in_quotes = false,
+            b'"' if field.is_empty() && !quoted => {
+                quoted = true;
+                in_quotes = true;
+            }
+            b'"' => return Err(format!("unexpected quote in field {}", fields.len() + 1)),
+            b if b == delimiter && !in_quotes => {
+                fields.push(finish_field(&mut field, quoted, fields.len())?);
+                quoted = false;
+            }
+            _ if quoted && !in_quotes => {
+                return Err(format!(
+                    "unexpected data after the closing quote of field {}",
+                    fields.len() + 1
+                ))
+            }
+            b => field.push(b),
+        }
+    }
+    fields.push(finish_field(&mut field, quoted, fields.len())?);
+    Ok(fields)
+}
+
+fn finish_field(field: &mut Vec<u8>, quoted: bool, index: usize) -> Result<Option<String>, String> {
+    let text = field_text(field, quoted, index);
+    field.clear();
+    text
+}
+
+fn field_text(bytes: &[u8], quoted: bool, index: usize) -> Result<Option<String>, String> {
+    if bytes.is_empty() && !quoted {
+        return Ok(None);
+    }
+    std::str::from_utf8(bytes)
+        .map(|text| Some(text.to_owned()))
+        .map_err(|_| format!("field {} is not valid UTF-8", index + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn records(input: &str, delimiter: u8) -> Vec<CsvRecord> {
+        let mut reader = CsvReader::new(input.as_bytes(), delimiter);
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record().await.unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn fields(fields: &[Option<&str>]) -> Result<Vec<Option<String>>, String> {
+        Ok(fields.iter().map(|f| f.map(str::to_owned)).collect())
+    }
+
+    fn column(type_name: &str, max_length: i16, precision: u8, scale: u8) -> ImportColumn {
+        ImportColumn::new("c".into(), type_name, max_length, precision, scale).unwrap()
+    }
+
+    fn convert(column: &ImportColumn, field: &str) -> Result<ColumnData<'static>, String> {
+        column.convert(Some(field.to_owned()))
+    }
+
+    #[test]
+    fn records_split_into_fields() {
+        assert_eq!(
+            parse_record(b"a,b,,d", b','),
+            fields(&[Some("a"), Some("b"), None, Some("d")])
+        );
+        assert_eq!(parse_record(b"", b','), fields(&[None]));
+        assert_eq!(
+            parse_record(b"a;\"b;c\"", b';'),
+            fields(&[Some("a"), Some("b;c")])
+        );
+        // A quoted empty field is an empty string, not NULL.
+        assert_eq!(parse_record(b"\"\",", b','), fields(&[Some(""), None]));
+        assert_eq!(
+            parse_record(b"\"say \"\"hi\"\"\",x", b','),
+            fields(&[Some("say \"hi\""), Some("x")])
+        );
+        // Records without quotes are sliced directly, and must split the same as quoted ones.
+        assert_eq!(
+            parse_record("Zürich,,Kraków,".as_bytes(), b','),
+            parse_record("\"Zürich\",,\"Kraków\",".as_bytes(), b',')
+        );
+    }
+
+    #[test]
+    fn stray_quotes_and_invalid_utf8_are_rejected() {
+        assert_eq!(
+            parse_record(b"a,b\"c", b','),
+            Err("unexpected quote in field 2".into())
+        );
+        assert_eq!(
+            parse_record(b"\"a\"b", b','),
+            Err("unexpected data after the closing quote of field 1".into())
+        );
+        assert_eq!(
+            parse_record(b"x,\"a\" ", b','),
+            Err("unexpected data after the closing quote of field 2".into())
+        );
+        assert_eq!(
+            parse_record(b"ok,\xff", b','),
+            Err("field 2 is not valid UTF-8".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn quoted_fields_span_lines() {
+        let input =
+            "\u{feff}id,note\r\n1,\"line one\r\nline two\"\r\n\r\n2,plain\n3,\"unterminated\nline";
+        assert_eq!(
+            records(input, b',').await,
+            [
+                (1, fields(&[Some("id"), Some("note")])),
+                (2, fields(&[Some("1"), Some("line one\r\nline two")])),
+                (5, fields(&[Some("2"), Some("plain")])),
+                (6, Err("unterminated quoted field".into())),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_input_has_no_records() {
+        assert!(records("", b',').await.is_empty());
+        assert!(records("\n\r\n", b',').await.is_empty());
+    }
+
+    #[test]
+    fn numbers_and_bits_convert() {
+        assert!(matches!(
+            convert(&column("int", 4, 10, 0), " 42 "),
+            Ok(ColumnData::I32(Some(42)))
+        ));
+        assert!(matches!(
+            convert(&column("bit", 1, 1, 0), "TRUE"),
+            Ok(ColumnData::Bit(Some(true)))
+        ));
+        assert!(matches!(
+            convert(&column("tinyint", 1, 3, 0), "255"),
+            Ok(ColumnData::U8(Some(255)))
+        ));
+        assert!(convert(&column("tinyint", 1, 3, 0), "256").is_err());
+        assert!(convert(&column("bit", 1, 1, 0), "yes").is_err());
+        assert!(matches!(
+            column("int", 4, 10, 0).convert(None),
+            Ok(ColumnData::I32(None))
+        ));
+    }
+
+    #[test]
+    fn decimals_must_fit_without_rounding() {
+        let money = column("decimal", 9, 10, 2);
+        let value = |field| match convert(&money, field) {
+            Ok(ColumnData::Numeric(Some(n))) => (n.value(), n.scale()),
+            other => panic!("{field}: {other:?}"),
+        };
+        assert_eq!(value("12.5"), (1250, 2));
+        assert_eq!(value("-0.01"), (-1, 2));
+        assert_eq!(value("+7"), (700, 2));
+        assert_eq!(value("1.500"), (150, 2));
+        assert_eq!(value("99999999.99"), (9_999_999_999, 2));
+        for field in ["1.234", "100000000", "", "1e3", "1.2.3", "."] {
+            assert!(convert(&money, field).is_err(), "{field:?}");
+        }
+    }
+
+    #[test]
+    fn strings_and_binary_respect_their_length() {
+        // nvarchar lengths are in bytes, two per character.
+        let name = column("nvarchar", 10, 0, 0);
+        assert!(convert(&name, "héllo").is_ok());
+        assert_eq!(
+            convert(&name, "toolong").unwrap_err(),
+            "column c: value is too long"
+        );
+        assert!(convert(&column("nvarchar", -1, 0, 0), &"x".repeat(10_000)).is_ok());
+
+        let bytes = column("varbinary", 2, 0, 0);
+        assert!(
+            matches!(convert(&bytes, "0xCAFE"), Ok(ColumnData::Binary(Some(b))) if *b == [0xca, 0xfe])
+        );
+        assert!(convert(&bytes, "cafe01").is_err());
+        assert!(convert(&bytes, "abc").is_err());
+    }
+
+    #[test]
+    fn dates_and_times_convert() {
+        match convert(&column("date", 3, 10, 0), "2024-02-29") {
+            Ok(ColumnData::Date(Some(date))) => assert_eq!(date.days(), 738_944),
+            other => panic!("{other:?}"),
+        }
+        assert!(convert(&column("date", 3, 10, 0), "2023-02-29").is_err());
+        assert!(convert(&column("date", 3, 10, 0), "2024-01-01T10:00").is_err());
+
+        // datetime rounds to the nearest 1/300 second tick.
+        let datetime = column("datetime", 8, 23, 3);
+        let ticks = |field| match convert(&datetime, field) {
+            Ok(ColumnData::DateTime(Some(dt))) => (dt.days(), dt.seconds_fragments()),
+            other => panic!("{field}: {other:?}"),
+        };
+        assert_eq!(ticks("1900-01-01 00:00:00.002"), (0, 1));
+        assert_eq!(ticks("1900-01-01T00:00:00.005"), (0, 2));
+        assert_eq!(ticks("1900-01-01 00:00:01"), (0, 300));
+        assert_eq!(ticks("1899-12-31 23:59:59.999"), (0, 0));
+        assert_eq!(ticks("1753-01-01"), (-53_690, 0));
+        assert!(convert(&datetime, "1752-12-31").is_err());
+
+        let datetime2 = column("datetime2", 8, 27, 3);
+        match convert(&datetime2, "0001-01-01 00:00:00.125") {
+            Ok(ColumnData::DateTime2(Some(dt))) => {
+                assert_eq!((dt.date().days(), dt.time().increments()), (0, 125))
+            }
+            other => panic!("{other:?}"),
+        }
+        assert!(convert(&datetime2, "2024-01-01 00:00:00.1234").is_err());
+        assert!(convert(&datetime2, "2024-01-01 24:00").is_err());
+    }
+
+    #[test]
+    fn unsupported_types_fail_up_front() {
+        assert!(ImportColumn::new("c".into(), "xml", -1, 0, 0).is_err());
+        assert!(ImportColumn::new("c".into(), "sql_variant", 8016, 0, 0).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! The [`SqlDateTime`] type for reading date and time columns without a date crate.
+
+use crate::error::Error;
+use crate::row::FromSqlValue;
+use std::fmt;
+use tiberius::ColumnData;
+
+/// Days from 0001-01-01 to 1900-01-01, the epoch of `datetime` and `smalldatetime`.
+const DAYS_TO_1900: i64 = 693_595;
+
+/// Days from 0001-01-01 to 1970-01-01.
+const DAYS_TO_1970: i64 = 719_162;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+const NANOS_PER_DAY: u64 = 86_400 * NANOS_PER_SECOND;
+
+/// A `date`, `datetime`, `smalldatetime`, `datetime2` or `datetimeoffset` value, read using only tiberius's own
+/// types, so neither its `chrono` nor its `time` feature is needed.
+///
+/// The types store time of day with different precision, and a value is read exactly as stored:
+///
+/// | SQL type | Range | Precision |
+/// |---|---|---|
+/// | `date` | 0001-01-01 to 9999-12-31 | one day |
+/// | `smalldatetime` | 1900-01-01 to 2079-06-06 | one minute, seconds are rounded when written |
+/// | `datetime` | 1753-01-01 to 9999-12-31 | 1/300 second, written values round to .000, .003 or .007 |
+/// | `datetime2(n)`, `datetimeoffset(n)` | 0001-01-01 to 9999-12-31 | 10<sup>-n</sup> seconds, 100ns at the default of 7 |
+///
+/// A `datetime` tick of 1/300 second isn't a whole number of nanoseconds, so its time is rounded to the nearest
+/// nanosecond, e.g. `00:00:00.003` (one tick) reads as 3,333,333ns. Compare `datetime` values read from the
+/// server with each other rather than with values computed in Rust to the millisecond. Moving a column from
+/// `datetime` to `datetime2` changes what values it can hold, so values written before and after compare unequal.
+///
+/// A `datetimeoffset` reads as its local time, as SQL Server displays it, with the
+/// [offset](SqlDateTime::offset_minutes) from UTC. The other types carry no offset.
+///
+/// ```no_run
+/// # use mssql_rs::{RowExt, SqlDateTime};
+/// # fn example(row: &tiberius::Row) -> mssql_rs::Result<()> {
+/// let created: Option<SqlDateTime> = row.get_named("created_at")?;
+/// if let Some(created) = created {
+///     println!("{created}, {} ns into the day", created.nanos_of_day());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SqlDateTime {
+    /// Days from 0001-01-01.
+    days: i64,
+    nanos: u64,
+    offset_minutes: Option<i16>,
+}
+
+impl SqlDateTime {
+    /// Days from 0001-01-01, e.g. 0 for 0001-01-01 and 693,595 for 1900-01-01.
+    pub fn days_from_ce(&self) -> i64 {
+        self.days
+    }
+
+    /// Nanoseconds since midnight.
+    pub fn nanos_of_day(&self) -> u64 {
+        self.nanos
+    }
+
+    /// The year, month and day.
+    pub fn date(&self) -> (i32, u8, u8) {
+        civil_from_days(self.days - DAYS_TO_1970)
+    }
+
+    /// The hour, minute, second and nanosecond.
+    pub fn time(&self) -> (u8, u8, u8, u32) {
+        let seconds = self.nanos / NANOS_PER_SECOND;
+        (
+            (seconds / 3600) as u8,
+            (seconds / 60 % 60) as u8,
+            (seconds % 60) as u8,
+            (self.nanos % NANOS_PER_SECOND) as u32,
+        )
+    }
+
+    /// The offset from UTC of a `datetimeoffset` value, in minutes, e.g. 60 for `+01:00`.
+    pub fn offset_minutes(&self) -> Option<i16> {
+        self.offset_minutes
+    }
+}
+
+/// Formats as ISO 8601, with as many fractional digits as the nanoseconds need and the offset if there is one,
+/// e.g. `2024-03-01T12:30:00.003333333` or `2024-03-01T12:30:00+01:00`.
+impl fmt::Display for SqlDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.date();
+        let (hour, minute, second, nanos) = self.time();
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}"
+        )?;
+        if nanos != 0 {
+            let fraction = format!("{nanos:09}");
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        if let Some(offset) = self.offset_minutes {
+            let sign = if offset < 0 { '-' } else { '+' };
+            let offset = offset.unsigned_abs();
+            write!(f, "{sign}{:02}:{:02}", offset / 60, offset % 60)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromSqlValue for SqlDateTime {
+    fn from_sql_value(value: &ColumnData<'static>) -> Result<Option<Self>, Error> {
+        let local = |days: i64, nanos: u64| Self {
+            days,
+            nanos,
+            offset_minutes: None,
+        };
+        let value = match value {
+            ColumnData::Date(None)
+            | ColumnData::DateTime(None)
+            | ColumnData::SmallDateTime(None)
+            | ColumnData::DateTime2(None)
+            | ColumnData::DateTimeOffset(None) => return Ok(None),
+            ColumnData::Date(Some(date)) => local(date.days().into(), 0),
+            // datetime counts time in 1/300 second ticks, rounded here to the nearest nanosecond.
+            ColumnData::DateTime(Some(dt)) => local(
+                DAYS_TO_1900 + i64::from(dt.days()),
+                (u64::from(dt.seconds_fragments()) * NANOS_PER_SECOND + 150) / 300,
+            ),
+            // smalldatetime counts time in minutes.
+            ColumnData::SmallDateTime(Some(dt)) => local(
+                DAYS_TO_1900 + i64::from(dt.days()),
+                u64::from(dt.seconds_fragments()) * 60 * NANOS_PER_SECOND,
+            ),
+            ColumnData::DateTime2(Some(dt)) => {
+                local(dt.date().days().into(), time_nanos(dt.time())?)
+            }
+            // The server sends the UTC time and the offset.
+            ColumnData::DateTimeOffset(Some(dto)) => {
+                let utc = dto.datetime2();
+                let offset = dto.offset();
+                let nanos = time_nanos(utc.time())? as i128
+                    + i128::from(offset) * 60 * i128::from(NANOS_PER_SECOND);
+                let day_nanos = i128::from(NANOS_PER_DAY);
+                Self {
+                    days: i64::from(utc.date().days()) + nanos.div_euclid(day_nanos) as i64,
+                    nanos: nanos.rem_euclid(day_nanos) as u64,
+                    offset_minutes: Some(offset),
+                }
+            }
+            _ => return Err(conversion(value)),
+        };
+        Ok(Some(value))
+    }
+}
+
+/// Nanoseconds since midnight of a `time` value, which counts increments of 10^-scale seconds.
+fn time_nanos(time: tiberius::time::Time) -> Result<u64, Error> {
+    let scale = u32::from(time.scale());
+    let nanos = if scale <= 9 {
+        time.increments().checked_mul(10u64.pow(9 - scale))
+    } else {
+        None
+    };
+    nanos.ok_or_else(|| {
+        tiberius::error::Error::Conversion(
+            format!(
+                "time increments {} at scale {scale} are out of range",
+                time.increments()
+            )
+            .into(),
+        )
+        .into()
+    })
+}
+
+fn conversion(value: &ColumnData<'static>) -> Error {
+    tiberius::error::Error::Conversion(
+        format!("cannot interpret {value:?} as a date and time value").into(),
+    )
+    .into()
+}
+
+/// The proleptic Gregorian year, month and day of a count of days from 1970-01-01.
+///
+/// This is Howard Hinnant's `civil_from_days`, see <https://howardhinnant.github.io/date_algorithms.html>.
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year as i32, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiberius::time::{Date, DateTime, DateTime2, DateTimeOffset, SmallDateTime, Time};
+
+    fn read(value: ColumnData<'static>) -> SqlDateTime {
+        SqlDateTime::from_sql_value(&value).unwrap().unwrap()
+    }
+
+    /// 2024-03-01 in days from 1900-01-01.
+    const MARCH_2024: i32 = 45_350;
+
+    #[test]
+    fn datetime_ticks_are_a_three_hundredth_of_a_second() {
+        let at = |ticks| read(ColumnData::DateTime(Some(DateTime::new(MARCH_2024, ticks))));
+        let millis = |value: SqlDateTime| (value.nanos_of_day() + 500_000) / 1_000_000;
+
+        // Written as .000, .003 and .007, the values SQL Server rounds datetime milliseconds to.
+        assert_eq!(at(0).nanos_of_day(), 0);
+        assert_eq!(at(1).nanos_of_day(), 3_333_333);
+        assert_eq!(at(2).nanos_of_day(), 6_666_667);
+        assert_eq!(at(3).nanos_of_day(), 10_000_000);
+        assert_eq!([millis(at(1)), millis(at(2)), millis(at(3))], [3, 7, 10]);
+
+        // 23:59:59.997, the last tick of a day.
+        let last = at(86_400 * 300 - 1);
+        assert_eq!(last.time(), (23, 59, 59, 996_666_667));
+        assert_eq!(last.to_string(), "2024-03-01T23:59:59.996666667");
+        assert_eq!(
+            at(12 * 3600 * 300 + 30 * 60 * 300 + 1).to_string(),
+            "2024-03-01T12:30:00.003333333"
+        );
+    }
+
+    #[test]
+    fn datetime_before_1900() {
+        // 1753-01-01, the earliest datetime.
+        let value = read(ColumnData::DateTime(Some(DateTime::new(-53_690, 0))));
+        assert_eq!(value.date(), (1753, 1, 1));
+        assert_eq!(value.to_string(), "1753-01-01T00:00:00");
+    }
+
+    #[test]
+    fn smalldatetime_counts_minutes() {
+        let value = read(ColumnData::SmallDateTime(Some(SmallDateTime::new(
+            MARCH_2024 as u16,
+            12 * 60 + 31,
+        ))));
+        assert_eq!(value.date(), (2024, 3, 1));
+        assert_eq!(value.time(), (12, 31, 0, 0));
+        assert_eq!(value.offset_minutes(), None);
+    }
+
+    #[test]
+    fn datetime2_and_date_keep_their_precision() {
+        let date = Date::new(DAYS_TO_1900 as u32 + MARCH_2024 as u32);
+        assert_eq!(
+            read(ColumnData::Date(Some(date))).to_string(),
+            "2024-03-01T00:00:00"
+        );
+
+        let at = |increments, scale| {
+            read(ColumnData::DateTime2(Some(DateTime2::new(
+                date,
+                Time::new(increments, scale),
+            ))))
+        };
+        assert_eq!(at(1, 7).nanos_of_day(), 100);
+        assert_eq!(
+            at(863_999_999_999, 7).to_string(),
+            "2024-03-01T23:59:59.9999999"
+        );
+        assert_eq!(at(450_005, 1).to_string(), "2024-03-01T12:30:00.5");
+        assert_eq!(at(1, 0).time(), (0, 0, 1, 0));
+
+        let first = read(ColumnData::DateTime2(Some(DateTime2::new(
+            Date::new(0),
+            Time::new(0, 7),
+        ))));
+        assert_eq!((first.days_from_ce(), first.date()), (0, (1, 1, 1)));
+        let last = read(ColumnData::Date(Some(Date::new(3_652_058))));
+        assert_eq!(last.date(), (9999, 12, 31));
+    }
+
+    #[test]
+    fn datetimeoffset_reads_as_local_time() {
+        let utc = DateTime2::new(
+            Date::new(DAYS_TO_1900 as u32 + MARCH_2024 as u32),
+            Time::new(23 * 3600, 0),
+        );
+        let ahead = read(ColumnData::DateTimeOffset(Some(DateTimeOffset::new(
+            utc, 90,
+        ))));
+        assert_eq!(ahead.to_string(), "2024-03-02T00:30:00+01:30");
+        assert_eq!(ahead.offset_minutes(), Some(90));
+
+        let behind = read(ColumnData::DateTimeOffset(Some(DateTimeOffset::new(
+            utc, -300,
+        ))));
+        assert_eq!(behind.to_string(), "2024-03-01T18:00:00-05:00");
+    }
+
+    #[test]
+    fn nulls_and_other_types() {
+        assert_eq!(
+            SqlDateTime::from_sql_value(&ColumnData::DateTime(None)).unwrap(),
+            None
+        );
+        assert_eq!(
+            SqlDateTime::from_sql_value(&ColumnData::DateTimeOffset(None)).unwrap(),
+            None
+        );
+        assert!(
+            SqlDateTime::from_sql_value(&ColumnData::String(Some("2024-03-01".into()))).is_err()
+        );
+        assert!(SqlDateTime::from_sql_value(&ColumnData::Time(Some(Time::new(0, 7)))).is_err());
+    }
+}
@@ -1,6 +1,10 @@
+use crate::version::ServerVersion;
+use std::time::Duration;
+
 pub type Result<T, E = Error> = ::std::result::Result<T, E>;
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error(transparent)]
     Tiberius(#[from] tiberius::error::Error),
@@ -12,6 +16,324 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("No results found")]
     EmptyResult,
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("{failed} of {total} pooled connections failed validation: {source}")]
+    ValidationFailed {
+        failed: usize,
+        total: usize,
+        source: Box<Error>,
+    },
+    #[error("Server version {found} is not supported, the minimum is {minimum}")]
+    UnsupportedServerVersion {
+        found: ServerVersion,
+        minimum: ServerVersion,
+    },
+    #[error("Query has {placeholders} parameter placeholders, but {bound} parameters were bound")]
+    ParameterCountMismatch { placeholders: usize, bound: usize },
+    #[error("Grouped rows must be ordered by the parent key, but a key reappeared")]
+    UngroupedRows,
+    #[error("Row {index} has the same key as an earlier row")]
+    DuplicateRowKey { index: usize },
+    #[error("Invalid identifier: {0}")]
+    InvalidIdentifier(String),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("{feature} requires {required} or later, but the server is {found}")]
+    FeatureUnsupported {
+        feature: &'static str,
+        required: ServerVersion,
+        found: ServerVersion,
+    },
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Too many queries are waiting for a connection")]
+    Overloaded,
+    #[error("Query did not complete within {timeout:?}")]
+    QueryTimeout { timeout: std::time::Duration },
+    /// The query's cancellation token was cancelled, see
+    /// [`SqlServerPool::row_query_cancellable`](crate::SqlServerPool::row_query_cancellable).
+    #[error("Query was cancelled")]
+    Cancelled,
+    /// The consumer of a streamed result dropped the stream, so the query stopped reading, see
+    /// [`SqlServerPool::json_stream`](crate::SqlServerPool::json_stream). Streams end quietly rather than yield it.
+    #[error("Query was abandoned by its consumer")]
+    Abandoned,
+    /// Malformed JSON in a streamed `FOR JSON` result, see [`SqlServerPool::json_stream`](crate::SqlServerPool::json_stream).
+    #[error("Invalid JSON near byte {offset}: {source}")]
+    JsonStream {
+        offset: usize,
+        source: serde_json::Error,
+    },
+    /// A by-name accessor was given a name matching several columns, see [`ColumnMatching`](crate::ColumnMatching).
+    #[error(
+        "Column {name} matches {count} columns of the result, select them under distinct aliases"
+    )]
+    AmbiguousColumn { name: String, count: usize },
+    /// A result had columns a conversion didn't expect, see [`RowExt::deny_unknown_columns`](crate::RowExt::deny_unknown_columns).
+    #[error("Result has unexpected columns: {}", .0.join(", "))]
+    UnexpectedColumns(Vec<String>),
+    /// A query exceeded its rate, see [`SqlServerPoolBuilder::query_rate_limit`](crate::SqlServerPoolBuilder::query_rate_limit).
+    #[error("Query rate limit exceeded, retry after {retry_after:?}")]
+    RateLimited { retry_after: std::time::Duration },
+    #[error("Query is {len} bytes long, which exceeds the limit of {limit}")]
+    QueryTooLong { len: usize, limit: usize },
+    #[error("Query is empty or only whitespace and comments")]
+    EmptySql,
+    #[error("Query contains a NUL character at byte {offset}")]
+    NulInSql { offset: usize },
+    #[error("Query returned more than {limit} rows")]
+    TooManyRows { limit: usize },
+    #[error("CSV line {line}: {reason}")]
+    CsvRow { line: u64, reason: String },
+    #[error("{keyword} is not allowed in a read-only transaction")]
+    ReadOnlyViolation { keyword: String },
+    #[error(
+        "Snapshot isolation is not allowed in database {database}, enable it with \
+         ALTER DATABASE [{database}] SET ALLOW_SNAPSHOT_ISOLATION ON, \
+         or set SqlServerPoolBuilder::snapshot_fallback"
+    )]
+    SnapshotIsolationDisabled { database: String },
+    /// A statement of [`SqlServerPool::execute_batch`](crate::SqlServerPool::execute_batch) failed.
+    /// Its kind and server error number are those of `source`.
+    #[error("Statement {index} failed: {source}\n{statement}")]
+    StatementFailed {
+        index: usize,
+        statement: String,
+        source: Box<Error>,
+    },
+    /// The server denied changing a database setting, see
+    /// [`SqlServerPool::set_database_scoped_config`](crate::SqlServerPool::set_database_scoped_config).
+    /// Its kind and server error number are those of `source`.
+    #[error("Setting {setting} requires {permission} permission on the database: {source}")]
+    PermissionDenied {
+        setting: &'static str,
+        permission: &'static str,
+        source: Box<Error>,
+    },
+    /// An error with added context, see [`ResultExt`]. Its kind and server error number are those of `source`.
+    #[error("{context}: {source}")]
+    Context { context: String, source: Box<Error> },
+}
+
+/// A broad classification of an [`Error`], for handling errors without matching on every variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A query returned no results where one was required.
+    NotFound,
+    /// No connection became available in time.
+    Timeout,
+    /// The caller cancelled the query.
+    Cancelled,
+    /// The connection to the server failed, e.g. a network, TLS or protocol error.
+    Connection,
+    /// The server rejected the login.
+    Authentication,
+    /// The statement violated a constraint, e.g. a duplicate key or a foreign key.
+    Constraint,
+    /// The server chose the statement as a deadlock victim. Retrying usually succeeds.
+    Deadlock,
+    /// Any other error reported by the server.
+    Server,
+    /// A value could not be converted or (de)serialized.
+    Conversion,
+    /// An argument to a crate method was invalid, e.g. a malformed identifier or a parameter count mismatch.
+    InvalidInput,
+    /// The pool's configuration or the server's version doesn't support the operation.
+    Configuration,
+    /// The pool shed the query because too many were already waiting, or the server throttled it. Retrying after a
+    /// backoff may succeed, see [`Error::throttle_retry_after`].
+    Overloaded,
+}
+
+/// Server errors for constraint violations: check (547), unique index (2601) and primary key or unique constraint (2627).
+const CONSTRAINT_ERRORS: [u32; 3] = [547, 2601, 2627];
+
+/// The server error for a deadlock victim.
+const DEADLOCK_ERROR: u32 = 1205;
+
+/// Azure SQL errors for throttled requests: too many requests (10928) or sessions (10929), and a busy service
+/// (40501). Their messages may suggest when to retry.
+const THROTTLING_ERRORS: [u32; 3] = [10928, 10929, 40501];
+
+/// The server error for a failed login.
+const LOGIN_FAILED_ERROR: u32 = 18456;
+
+/// An error that occurred partway through a query, along with the rows converted before it.
+///
+/// Returned by [`SqlServerPool::row_query_partial`](crate::SqlServerPool::row_query_partial).
+#[derive(thiserror::Error, Debug)]
+#[error("Query failed after {} rows: {source}", rows.len())]
+pub struct PartialError<T> {
+    pub rows: Vec<T>,
+    #[source]
+    pub source: Error,
+}
+
+impl Error {
+    /// Returns the server error number, if this is an error reported by the server.
+    pub(crate) fn server_code(&self) -> Option<u32> {
+        match self {
+            Error::Tiberius(e) => e.code(),
+            Error::Context { source, .. }
+            | Error::StatementFailed { source, .. }
+            | Error::PermissionDenied { source, .. } => source.server_code(),
+            _ => None,
+        }
+    }
+
+    /// The delay the server suggested waiting before a retry, if it throttled the request and said how long.
+    ///
+    /// Azure SQL throttles with errors 10928, 10929 and 40501, whose messages may include a delay, e.g. "The service
+    /// is currently busy. Retry the request after 10 seconds." The delay is taken from the sentence starting with
+    /// "retry", in milliseconds, seconds or minutes. [`Error::RateLimited`] returns its `retry_after`.
+    pub fn throttle_retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Tiberius(tiberius::error::Error::Server(e))
+                if THROTTLING_ERRORS.contains(&e.code()) =>
+            {
+                parse_retry_after(e.message())
+            }
+            Error::RateLimited { retry_after } => Some(*retry_after),
+            Error::ValidationFailed { source, .. }
+            | Error::Context { source, .. }
+            | Error::StatementFailed { source, .. }
+            | Error::PermissionDenied { source, .. } => source.throttle_retry_after(),
+            _ => None,
+        }
+    }
+
+    /// Classify the error.
+    pub fn kind(&self) -> ErrorKind {
+        use tiberius::error::Error as Tds;
+
+        match self {
+            Error::Tiberius(Tds::Server(e)) => match e.code() {
+                LOGIN_FAILED_ERROR => ErrorKind::Authentication,
+                DEADLOCK_ERROR => ErrorKind::Deadlock,
+                code if THROTTLING_ERRORS.contains(&code) => ErrorKind::Overloaded,
+                code if CONSTRAINT_ERRORS.contains(&code) => ErrorKind::Constraint,
+                _ => ErrorKind::Server,
+            },
+            Error::Tiberius(
+                Tds::Conversion(_) | Tds::Utf8 | Tds::Utf16 | Tds::ParseInt(_) | Tds::BulkInput(_),
+            ) => ErrorKind::Conversion,
+            Error::Tiberius(_) | Error::Io(_) => ErrorKind::Connection,
+            Error::ConnectionTimeout | Error::QueryTimeout { .. } => ErrorKind::Timeout,
+            Error::Cancelled | Error::Abandoned => ErrorKind::Cancelled,
+            Error::Overloaded | Error::RateLimited { .. } => ErrorKind::Overloaded,
+            Error::SerdeJson(_)
+            | Error::JsonStream { .. }
+            | Error::AmbiguousColumn { .. }
+            | Error::UnexpectedColumns(_) => ErrorKind::Conversion,
+            Error::EmptyResult => ErrorKind::NotFound,
+            Error::InvalidQuery(_)
+            | Error::ParameterCountMismatch { .. }
+            | Error::UngroupedRows
+            | Error::DuplicateRowKey { .. }
+            | Error::InvalidIdentifier(_)
+            | Error::InvalidArgument(_)
+            | Error::ReadOnlyViolation { .. }
+            | Error::QueryTooLong { .. }
+            | Error::EmptySql
+            | Error::NulInSql { .. }
+            | Error::TooManyRows { .. }
+            | Error::CsvRow { .. } => ErrorKind::InvalidInput,
+            Error::ValidationFailed { source, .. }
+            | Error::Context { source, .. }
+            | Error::StatementFailed { source, .. }
+            | Error::PermissionDenied { source, .. } => source.kind(),
+            Error::UnsupportedServerVersion { .. }
+            | Error::FeatureUnsupported { .. }
+            | Error::InvalidConfig(_)
+            | Error::SnapshotIsolationDisabled { .. } => ErrorKind::Configuration,
+        }
+    }
+
+    /// Map the error to an HTTP status code, for web handlers that report database errors.
+    ///
+    /// No results map to 404 Not Found, constraint violations to 409 Conflict, and transient failures
+    /// (timeouts, connection failures, deadlocks and overload) to 503 Service Unavailable, as retrying may succeed.
+    /// Cancelled queries map to 499 Client Closed Request, as cancellation usually follows the client going away.
+    /// Everything else, including failed logins and invalid arguments, is a fault on the server side: 500.
+    #[cfg(feature = "http")]
+    pub fn http_status(&self) -> u16 {
+        match self.kind() {
+            ErrorKind::NotFound => 404,
+            ErrorKind::Constraint => 409,
+            ErrorKind::Cancelled => 499,
+            ErrorKind::Timeout
+            | ErrorKind::Connection
+            | ErrorKind::Deadlock
+            | ErrorKind::Overloaded => 503,
+            ErrorKind::Authentication
+            | ErrorKind::Server
+            | ErrorKind::Conversion
+            | ErrorKind::InvalidInput
+            | ErrorKind::Configuration => 500,
+        }
+    }
+
+    /// Split the error into its kind, server error number (if reported by the server), and message.
+    pub fn into_parts(self) -> (ErrorKind, Option<u32>, String) {
+        (self.kind(), self.server_code(), self.to_string())
+    }
+
+    /// Wrap the error with context, keeping its kind and server error number.
+    pub fn context(self, context: impl Into<String>) -> Self {
+        Error::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+}
+
+/// Adds context to the errors of a [`Result`], without losing the error's [`kind`](Error::kind).
+///
+/// # Example
+///
+/// An application error embedding ours, matching on the kind rather than the message:
+///
+/// ```
+/// use mssql_rs::{Error, ErrorKind, ResultExt};
+///
+/// #[derive(Debug, thiserror::Error)]
+/// enum AppError {
+///     #[error("user not found")]
+///     UserNotFound,
+///     #[error(transparent)]
+///     Database(#[from] mssql_rs::Error),
+/// }
+///
+/// fn load_user(result: mssql_rs::Result<String>) -> Result<String, AppError> {
+///     match result.with_table_context("dbo.users") {
+///         Err(e) if e.kind() == ErrorKind::NotFound => Err(AppError::UserNotFound),
+///         result => Ok(result?),
+///     }
+/// }
+///
+/// assert!(matches!(load_user(Err(Error::EmptyResult)), Err(AppError::UserNotFound)));
+/// ```
+pub trait ResultExt<T> {
+    /// Add the query being run to the error.
+    fn with_query_context(self, sql: &str) -> Result<T>;
+
+    /// Add the table being accessed to the error.
+    fn with_table_context(self, table: &str) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for ::std::result::Result<T, E> {
+    fn with_query_context(self, sql: &str) -> Result<T> {
+        self.map_err(|e| e.into().context(format!("Query `{sql}` failed")))
+    }
+
+    fn with_table_context(self, table: &str) -> Result<T> {
+        self.map_err(|e| {
+            e.into()
+                .context(format!("Accessing table `{table}` failed"))
+        })
+    }
 }
 
 impl From<bb8::RunError<Error>> for Error {
@@ -22,3 +344,126 @@ impl From<bb8::RunError<Error>> for Error {
         }
     }
 }
+
+/// The longest delay taken from a server message, so a garbled number can't stall a retry indefinitely.
+const MAX_SUGGESTED_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Find the delay in the sentence of `message` starting with "retry", e.g. "Retry the request after 10 seconds",
+/// "retry in 500 ms" or "please retry after 1.5s".
+fn parse_retry_after(message: &str) -> Option<Duration> {
+    let message = message.to_ascii_lowercase();
+    message.match_indices("retry").find_map(|(start, _)| {
+        let rest = &message[start..];
+        let sentence = rest.find(". ").map_or(rest, |end| &rest[..end]);
+        let mut words = sentence
+            .split(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | ':' | '(' | ')'))
+            .filter(|word| !word.is_empty());
+        while let Some(word) = words.next() {
+            let digits = word
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(word.len());
+            let Ok(amount) = word[..digits].parse::<f64>() else {
+                continue;
+            };
+            let unit = match &word[digits..] {
+                "" => words.next().unwrap_or(""),
+                unit => unit,
+            };
+            let scale = match unit.trim_end_matches('.') {
+                "ms" | "msec" | "millisecond" | "milliseconds" => 0.001,
+                "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+                "min" | "mins" | "minute" | "minutes" => 60.0,
+                _ => return None,
+            };
+            return Duration::try_from_secs_f64(amount * scale)
+                .ok()
+                .map(|delay| delay.min(MAX_SUGGESTED_DELAY));
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Error 40501, as Azure SQL sends it.
+    const SERVICE_BUSY: &str =
+        "The service is currently busy. Retry the request after 10 seconds. \
+        Incident ID: 5D6C6E2B-8A2F-4C1E-9F3B-0A1B2C3D4E5F. Code: 4325376.";
+
+    /// Error 10928, which suggests no delay.
+    const REQUEST_LIMIT: &str =
+        "Resource ID: 1. The request limit for the database is 90 and has been reached. \
+        See 'http://go.microsoft.com/fwlink/?LinkId=267637' for assistance.";
+
+    /// Error 10929, which asks to try again later without saying when.
+    const TOO_BUSY: &str = "Resource ID: 1. The request minimum guarantee is 30, maximum limit is 90, and the \
+        current usage for the database is 92. However, the server is currently too busy to support requests greater \
+        than 30 for this database. See 'http://go.microsoft.com/fwlink/?LinkId=267637' for assistance. Otherwise, \
+        please try again later.";
+
+    #[test]
+    fn reads_the_delay_from_throttling_messages() {
+        assert_eq!(
+            parse_retry_after(SERVICE_BUSY),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(parse_retry_after(REQUEST_LIMIT), None);
+        assert_eq!(parse_retry_after(TOO_BUSY), None);
+    }
+
+    #[test]
+    fn reads_each_unit_and_spelling() {
+        let cases = [
+            ("Please retry in 500 ms.", Duration::from_millis(500)),
+            ("please retry after 1.5s", Duration::from_millis(1500)),
+            ("RETRY AFTER 2 MINUTES.", Duration::from_secs(120)),
+            ("Busy; retry (after 3 secs).", Duration::from_secs(3)),
+            ("Retry after 250msec", Duration::from_millis(250)),
+        ];
+        for (message, delay) in cases {
+            assert_eq!(parse_retry_after(message), Some(delay), "{message}");
+        }
+    }
+
+    #[test]
+    fn ignores_numbers_outside_a_retry_sentence_and_unknown_units() {
+        assert_eq!(
+            parse_retry_after("Incident 42 in 3 seconds. Retry later."),
+            None
+        );
+        assert_eq!(
+            parse_retry_after("Retry the request after 10 fortnights."),
+            None
+        );
+        assert_eq!(
+            parse_retry_after("Retry after 10. Then wait 5 seconds."),
+            None
+        );
+        assert_eq!(parse_retry_after("No delay here."), None);
+        assert_eq!(
+            parse_retry_after("Retry after 1 fortnight. Or retry after 4 seconds."),
+            Some(Duration::from_secs(4))
+        );
+    }
+
+    #[test]
+    fn caps_the_suggested_delay() {
+        assert_eq!(
+            parse_retry_after("Retry the request after 100000 minutes."),
+            Some(MAX_SUGGESTED_DELAY)
+        );
+        assert_eq!(parse_retry_after("Retry after 1e309 seconds."), None);
+    }
+
+    #[test]
+    fn rate_limits_report_their_own_delay_through_context() {
+        let retry_after = Duration::from_millis(750);
+        let error = Err::<(), _>(Error::RateLimited { retry_after })
+            .with_query_context("SELECT 1;")
+            .unwrap_err();
+        assert_eq!(error.throttle_retry_after(), Some(retry_after));
+        assert_eq!(Error::EmptyResult.throttle_retry_after(), None);
+    }
+}
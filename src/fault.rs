@@ -0,0 +1,235 @@
+#[cfg(feature = "test-util")]
+use crate::clock::Clock;
+use crate::error::Error;
+#[cfg(feature = "test-util")]
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Where a [`FaultInjector`] is consulted.
+#[cfg(feature = "test-util")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultPoint {
+    /// Before a connection is checked out of the pool.
+    Checkout,
+    /// Before a query is sent.
+    Query,
+    /// Before each row of a result is handled.
+    Row,
+}
+
+/// What a [`FaultInjector`] does at a [`FaultPoint`].
+#[cfg(feature = "test-util")]
+#[derive(Debug)]
+pub enum Fault {
+    /// Wait, then carry on as usual.
+    Delay(Duration),
+    /// Fail with the error. The connection stays usable, as after an error reported by the server.
+    Fail(Error),
+    /// Discard the connection and fail with a connection aborted error, as if the network dropped.
+    /// At [`FaultPoint::Checkout`] there is no connection yet, so this only fails.
+    Kill,
+}
+
+/// Injects latency and faults into the pool's query paths, for resilience testing. Requires the `test-util` feature.
+///
+/// Register an injector with [`SqlServerPoolBuilder::fault_injector`](crate::SqlServerPoolBuilder::fault_injector).
+/// It is consulted before checkout in every query method, and before each query and each row in those built on the
+/// shared query helpers, e.g. [`SqlServerPool::row_query`](crate::SqlServerPool::row_query) and
+/// [`SqlServerPool::json_query`](crate::SqlServerPool::json_query). Without the feature the hooks are compiled out.
+#[cfg(feature = "test-util")]
+pub trait FaultInjector: Send + Sync {
+    /// Decide what happens at `point`, or `None` to carry on as usual.
+    fn inject(&self, point: FaultPoint) -> Option<Fault>;
+}
+
+#[cfg(feature = "test-util")]
+impl fmt::Debug for dyn FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FaultInjector")
+    }
+}
+
+/// A fault injected by [`ScriptedInjector`]. Unlike [`Fault`], it can be produced any number of times.
+#[cfg(feature = "test-util")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptedFault {
+    /// See [`Fault::Delay`].
+    Delay(Duration),
+    /// Fail with [`Error::ConnectionTimeout`], a transient error.
+    ConnectionTimeout,
+    /// Fail with [`Error::Overloaded`], a transient error.
+    Overloaded,
+    /// Fail with an I/O error of the given kind.
+    Io(std::io::ErrorKind),
+    /// See [`Fault::Kill`].
+    Kill,
+}
+
+#[cfg(feature = "test-util")]
+impl ScriptedFault {
+    fn to_fault(&self) -> Fault {
+        match self {
+            ScriptedFault::Delay(delay) => Fault::Delay(*delay),
+            ScriptedFault::ConnectionTimeout => Fault::Fail(Error::ConnectionTimeout),
+            ScriptedFault::Overloaded => Fault::Fail(Error::Overloaded),
+            ScriptedFault::Io(kind) => {
+                Fault::Fail(std::io::Error::new(*kind, "injected by fault injector").into())
+            }
+            ScriptedFault::Kill => Fault::Kill,
+        }
+    }
+}
+
+/// A rule of a [`ScriptedInjector`].
+#[cfg(feature = "test-util")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultRule {
+    pub point: FaultPoint,
+    /// The chance of injecting the fault each time the point is reached, from 0.0 to 1.0.
+    pub probability: f64,
+    pub fault: ScriptedFault,
+    /// How long after the injector is created the rule applies, or `None` for as long as it exists.
+    pub active_for: Option<Duration>,
+}
+
+/// A [`FaultInjector`] that injects faults at random according to a list of rules. Requires the `test-util` feature.
+///
+/// Rules are checked in order, and the first one that applies and fires decides the fault. Randomness comes from a
+/// seeded generator, so a test can replay the same sequence of faults with [`ScriptedInjector::with_seed`].
+///
+/// ```no_run
+/// # use mssql_rs::{FaultPoint, FaultRule, ScriptedFault, ScriptedInjector, SqlServerPoolBuilder};
+/// # use std::{sync::Arc, time::Duration};
+/// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+/// // Fail 10% of checkouts with a timeout for the first 30 seconds.
+/// let injector = ScriptedInjector::new(vec![FaultRule {
+///     point: FaultPoint::Checkout,
+///     probability: 0.1,
+///     fault: ScriptedFault::ConnectionTimeout,
+///     active_for: Some(Duration::from_secs(30)),
+/// }]);
+///
+/// let sql_server = SqlServerPoolBuilder::new()
+///     .fault_injector(Arc::new(injector))
+///     .build(cfg)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "test-util")]
+#[derive(Debug)]
+pub struct ScriptedInjector {
+    rules: Vec<FaultRule>,
+    started: Instant,
+    state: Mutex<u64>,
+}
+
+#[cfg(feature = "test-util")]
+impl ScriptedInjector {
+    /// Create an injector seeded from the clock.
+    pub fn new(rules: Vec<FaultRule>) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self::with_seed(rules, seed)
+    }
+
+    /// Create an injector with a fixed seed, so the same sequence of checks injects the same faults.
+    pub fn with_seed(rules: Vec<FaultRule>, seed: u64) -> Self {
+        Self {
+            rules,
+            started: Instant::now(),
+            // xorshift never leaves a state of zero.
+            state: Mutex::new(seed.max(1)),
+        }
+    }
+
+    /// Returns a random number in `[0, 1)`, with xorshift64*.
+    fn next_random(&self) -> f64 {
+        let mut state = self.state.lock().expect("fault injector state poisoned");
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        let value = state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl FaultInjector for ScriptedInjector {
+    fn inject(&self, point: FaultPoint) -> Option<Fault> {
+        let elapsed = self.started.elapsed();
+        self.rules
+            .iter()
+            .filter(|rule| rule.point == point)
+            .filter(|rule| rule.active_for.is_none_or(|active| elapsed < active))
+            .find(|rule| self.next_random() < rule.probability)
+            .map(|rule| rule.fault.to_fault())
+    }
+}
+
+/// The pool's fault injector, if any. Without the `test-util` feature this is empty and injects nothing.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "test-util"), derive(Default))]
+pub(crate) struct Faults {
+    #[cfg(feature = "test-util")]
+    injector: Option<Arc<dyn FaultInjector>>,
+    #[cfg(feature = "test-util")]
+    clock: Arc<dyn Clock>,
+}
+
+/// A fault injected by [`Faults::inject`], with whether the connection should be discarded.
+pub(crate) struct Injected {
+    pub(crate) error: Error,
+    pub(crate) kill: bool,
+}
+
+impl Faults {
+    #[cfg(feature = "test-util")]
+    pub(crate) fn new(injector: Option<Arc<dyn FaultInjector>>, clock: Arc<dyn Clock>) -> Self {
+        Self { injector, clock }
+    }
+
+    /// Consult the injector at `point`, sleeping on the pool's clock for any delay.
+    #[cfg(feature = "test-util")]
+    pub(crate) async fn inject(&self, point: FaultPoint) -> Result<(), Injected> {
+        let Some(injector) = &self.injector else {
+            return Ok(());
+        };
+        match injector.inject(point) {
+            None => Ok(()),
+            Some(Fault::Delay(delay)) => {
+                self.clock.sleep(delay).await;
+                Ok(())
+            }
+            Some(Fault::Fail(error)) => Err(Injected { error, kill: false }),
+            Some(Fault::Kill) => Err(Injected {
+                error: std::io::Error::new(
+                    std::io::ErrorKind::ConnectionAborted,
+                    "connection killed by fault injector",
+                )
+                .into(),
+                kill: true,
+            }),
+        }
+    }
+
+    /// Injects nothing, as fault injection requires the `test-util` feature.
+    #[cfg(not(feature = "test-util"))]
+    #[inline(always)]
+    pub(crate) async fn inject(&self, _point: FaultPoint) -> Result<(), Injected> {
+        Ok(())
+    }
+}
+
+/// Where a fault can be injected. Without the `test-util` feature this only exists for the no-op hooks.
+#[cfg(not(feature = "test-util"))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FaultPoint {
+    Checkout,
+    Query,
+    Row,
+}
@@ -0,0 +1,257 @@
+use crate::error::Error;
+use serde::de::{DeserializeOwned, Error as _};
+
+/// Where a [`JsonArrayParser`] is in the array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the opening `[`.
+    Start,
+    /// After the opening `[`, where the array may end.
+    First,
+    /// After a `,`, where another element must follow.
+    Next,
+    /// Inside an element.
+    Element,
+    /// After the closing `]`.
+    End,
+}
+
+/// Parses a JSON array arriving in chunks, e.g. the rows of a `FOR JSON` result, one element at a time.
+///
+/// Only the bytes of the current element are held, so memory use is bounded by the largest element rather than
+/// the whole array. Element boundaries are found by tracking nesting and strings, and each element is then
+/// deserialized on its own.
+#[derive(Debug)]
+pub(crate) struct JsonArrayParser {
+    state: State,
+    element: Vec<u8>,
+    /// The offset of the current element's first byte.
+    element_start: usize,
+    /// The number of bytes consumed so far.
+    offset: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl JsonArrayParser {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: State::Start,
+            element: Vec::new(),
+            element_start: 0,
+            offset: 0,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        }
+    }
+
+    /// Consume the next chunk, pushing each element it completes onto `elements`.
+    pub(crate) fn feed<T>(&mut self, chunk: &str, elements: &mut Vec<T>) -> Result<(), Error>
+    where
+        T: DeserializeOwned,
+    {
+        for &byte in chunk.as_bytes() {
+            match self.state {
+                State::Start if byte.is_ascii_whitespace() => {}
+                State::Start if byte == b'[' => self.state = State::First,
+                State::Start => return Err(self.error("expected `[` at the start of the array")),
+                State::First | State::Next if byte.is_ascii_whitespace() => {}
+                State::First if byte == b']' => self.state = State::End,
+                State::Next if byte == b']' => {
+                    return Err(self.error("expected an element after `,`"));
+                }
+                State::First | State::Next => {
+                    self.state = State::Element;
+                    self.element.clear();
+                    self.element_start = self.offset;
+                    self.element_byte(byte, elements)?;
+                }
+                State::Element => self.element_byte(byte, elements)?,
+                State::End if byte.is_ascii_whitespace() => {}
+                State::End => return Err(self.error("unexpected data after the end of the array")),
+            }
+            self.offset += 1;
+        }
+        Ok(())
+    }
+
+    /// Check that the array was complete. An empty input, e.g. an empty `FOR JSON` result, has no elements.
+    pub(crate) fn finish(&self) -> Result<(), Error> {
+        match self.state {
+            State::Start | State::End => Ok(()),
+            _ => Err(self.error("the array ended early")),
+        }
+    }
+
+    fn element_byte<T>(&mut self, byte: u8, elements: &mut Vec<T>) -> Result<(), Error>
+    where
+        T: DeserializeOwned,
+    {
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if byte == b'\\' {
+                self.escaped = true;
+            } else if byte == b'"' {
+                self.in_string = false;
+            }
+            self.element.push(byte);
+            return Ok(());
+        }
+
+        match byte {
+            b',' | b']' if self.depth == 0 => {
+                elements.push(self.parse_element()?);
+                self.state = if byte == b',' {
+                    State::Next
+                } else {
+                    State::End
+                };
+                return Ok(());
+            }
+            b'"' => self.in_string = true,
+            b'{' | b'[' => self.depth += 1,
+            b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        self.element.push(byte);
+        Ok(())
+    }
+
+    fn parse_element<T>(&self) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_slice(&self.element).map_err(|source| {
+            // FOR JSON output has no line breaks, so the column locates the error within the element.
+            let within = if source.line() == 1 {
+                source.column().saturating_sub(1)
+            } else {
+                0
+            };
+            Error::JsonStream {
+                offset: self.element_start + within,
+                source,
+            }
+        })
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        Error::JsonStream {
+            offset: self.offset,
+            source: serde_json::Error::custom(reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// Feed `chunks` to a new parser, returning the elements and the result of finishing.
+    fn parse<'a>(chunks: impl IntoIterator<Item = &'a str>) -> Result<Vec<Value>, Error> {
+        let mut parser = JsonArrayParser::new();
+        let mut elements = Vec::new();
+        for chunk in chunks {
+            parser.feed(chunk, &mut elements)?;
+        }
+        parser.finish()?;
+        Ok(elements)
+    }
+
+    /// Every way of splitting `json` into two chunks, and one character per chunk.
+    fn splits(json: &str) -> Vec<Vec<&str>> {
+        let mut splits: Vec<_> = json
+            .char_indices()
+            .map(|(i, _)| vec![&json[..i], &json[i..]])
+            .collect();
+        splits.push(
+            json.char_indices()
+                .map(|(i, c)| &json[i..i + c.len_utf8()])
+                .collect(),
+        );
+        splits
+    }
+
+    fn offset(error: Error) -> usize {
+        match error {
+            Error::JsonStream { offset, .. } => offset,
+            error => panic!("unexpected error {error:?}"),
+        }
+    }
+
+    #[test]
+    fn elements_split_across_chunks() {
+        let json = r#" [ {"id":1,"tags":["a","b]"]}, {"id":2,"note":"say \"hi\", {then} [leave]\\"} ,3,"x",null,[[]] ] "#;
+        let expected = vec![
+            json!({"id": 1, "tags": ["a", "b]"]}),
+            json!({"id": 2, "note": "say \"hi\", {then} [leave]\\"}),
+            json!(3),
+            json!("x"),
+            json!(null),
+            json!([[]]),
+        ];
+        for chunks in splits(json) {
+            assert_eq!(
+                parse(chunks.iter().copied()).unwrap(),
+                expected,
+                "{chunks:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn multi_byte_characters() {
+        let json = r#"[{"name":"café é 😀"},"日本"]"#;
+        for chunks in splits(json) {
+            assert_eq!(
+                parse(chunks.iter().copied()).unwrap(),
+                [json!({"name": "café é 😀"}), json!("日本")]
+            );
+        }
+    }
+
+    #[test]
+    fn empty_input_and_empty_array() {
+        assert!(parse([]).unwrap().is_empty());
+        assert!(parse(["", "  "]).unwrap().is_empty());
+        assert!(parse(["[", "]"]).unwrap().is_empty());
+        assert!(parse([" [ ] \n"]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_arrays_fail_at_their_offset() {
+        assert_eq!(offset(parse([r#"{"id":1}"#]).unwrap_err()), 0);
+        assert_eq!(offset(parse(["[1,", "]"]).unwrap_err()), 3);
+        assert_eq!(offset(parse(["[1] ", "[2]"]).unwrap_err()), 4);
+        assert_eq!(offset(parse(["[1,2"]).unwrap_err()), 4);
+        assert_eq!(offset(parse([r#"[{"id":1}"#]).unwrap_err()), 9);
+        // The offset of an invalid element points into it.
+        assert_eq!(offset(parse([r#"[1, {"id" 2}]"#]).unwrap_err()), 10);
+    }
+
+    #[test]
+    fn elements_deserialize_into_the_target_type() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Row {
+            id: i32,
+        }
+
+        let mut parser = JsonArrayParser::new();
+        let mut rows: Vec<Row> = Vec::new();
+        parser.feed(r#"[{"id":1},{"id""#, &mut rows).unwrap();
+        assert_eq!(rows, [Row { id: 1 }]);
+        parser.feed(r#":2}]"#, &mut rows).unwrap();
+        parser.finish().unwrap();
+        assert_eq!(rows, [Row { id: 1 }, Row { id: 2 }]);
+
+        let mut parser = JsonArrayParser::new();
+        let error = parser
+            .feed::<Row>(r#"[{"id":"one"}]"#, &mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(error, Error::JsonStream { .. }));
+    }
+}
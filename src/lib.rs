@@ -1,10 +1,99 @@
+mod audit;
+mod cancel;
+mod chunks;
+mod clock;
+mod codec;
+#[cfg(feature = "codegen")]
+pub mod codegen;
+mod columns;
+mod connection;
+mod credentials;
+mod csv;
+mod datetime;
 mod error;
+mod fault;
+mod json_array;
+mod limiter;
 mod manager;
+mod metered;
+mod named_params;
+mod observer;
+mod options;
+mod param;
 mod pool;
+mod pool_set;
+mod query;
+mod rate_limit;
+mod resumable;
+mod row;
+pub mod row_version;
+mod scoped_config;
+mod session_options;
+mod snapshot;
+pub mod sql;
+mod switchable;
+mod sync;
+mod temp_proc;
+mod temp_table;
+#[cfg(feature = "test-util")]
+mod test_util;
+#[cfg(feature = "protocol-debug")]
+mod trace;
+mod transform;
+mod truncation;
+mod typed;
+mod validator;
+mod value;
+mod version;
+mod write_behind;
 
-pub use error::{Error, Result};
-pub use pool::{SqlServerPool, SqlServerPoolBuilder};
+pub use audit::{with_actor, AuditConfig};
+pub use cancel::cancel_on_drop;
+pub use chunks::{Accumulation, Chunks};
+#[cfg(feature = "test-util")]
+pub use clock::{Clock, MockClock};
+pub use codec::ColumnCodec;
+pub use columns::ColumnMatching;
+pub use connection::{Client, PooledConnection, Priority};
+pub use credentials::{Credentials, CredentialsProvider};
+pub use csv::{BadRowPolicy, CsvImportOptions, ImportStats, RejectedRow};
+pub use datetime::SqlDateTime;
+pub use error::{Error, ErrorKind, PartialError, Result, ResultExt};
+#[cfg(feature = "test-util")]
+pub use fault::{Fault, FaultInjector, FaultPoint, FaultRule, ScriptedFault, ScriptedInjector};
+pub use metered::{ByteCount, MeteredStream};
+pub use named_params::ToSqlParams;
+pub use observer::{
+    BrokenReason, ConnectionEvent, ConnectionInfo, ConnectionObserver, OnBroken, RecycleReason,
+};
+pub use options::QueryOptions;
+pub use param::SqlParam;
+pub use pool::{PoolStatus, QueryTimings, SqlServerPool, SqlServerPoolBuilder, UpsertAction};
+pub use pool_set::{HostTls, PoolSet, PoolSetBuilder, PoolSetSnapshot, PoolSnapshot, ProbeResult};
+pub use query::query_rows_on;
+pub use rate_limit::{FingerprintPattern, Rate};
+pub use resumable::ResumeOptions;
+pub use row::{FromSqlValue, RowExt};
+pub use row_version::RowVersion;
+pub use scoped_config::ScopedConfigKey;
+pub use session_options::{DeadlockPriority, SessionOptionsPreset};
+pub use snapshot::SnapshotReader;
+pub use switchable::{SwitchStatus, SwitchablePool};
+pub use sync::{SyncOptions, SyncStats};
+pub use temp_proc::TempProc;
+pub use temp_table::TempColumn;
+#[cfg(feature = "test-util")]
+pub use test_util::{TestServer, TestTransaction};
 pub use tiberius;
+pub use tokio_util::sync::CancellationToken;
+#[cfg(feature = "protocol-debug")]
+pub use trace::{ProtocolTrace, TraceDirection, TracePacket, TracedStream};
+pub use transform::{EmptyToNull, LocalToUtc, RowTransformer, TrimFixedChar};
+pub use truncation::TruncationPolicy;
+pub use typed::TypedPool;
+pub use value::{DynamicRow, SqlValue};
+pub use version::ServerVersion;
+pub use write_behind::{OverflowPolicy, WriteBehindOptions, WriteBehindQueue};
 
 /// A trait for types that can be created from a [`tiberius::Row`].
 ///
@@ -0,0 +1,120 @@
+use crate::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits the number of checkouts in flight across a pool, see
+/// [`SqlServerPoolBuilder::max_in_flight`](crate::SqlServerPoolBuilder::max_in_flight).
+#[derive(Debug)]
+pub(crate) struct InFlightLimiter {
+    permits: Arc<Semaphore>,
+    max_in_flight: usize,
+    max_queue_depth: Option<usize>,
+    queued: AtomicUsize,
+}
+
+impl InFlightLimiter {
+    pub(crate) fn new(max_in_flight: usize, max_queue_depth: Option<usize>) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_in_flight)),
+            max_in_flight,
+            max_queue_depth,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait for a slot, or fail with [`Error::Overloaded`] if the queue is already full.
+    pub(crate) async fn acquire(&self) -> Result<OwnedSemaphorePermit, Error> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        let queued = self.queued.fetch_add(1, Ordering::AcqRel);
+        let _queued = QueuedGuard(&self.queued);
+        if self.max_queue_depth.is_some_and(|depth| queued >= depth) {
+            return Err(Error::Overloaded);
+        }
+
+        Ok(self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("in-flight semaphore is never closed"))
+    }
+
+    /// The number of checkouts holding a slot.
+    pub(crate) fn in_flight(&self) -> usize {
+        self.max_in_flight - self.permits.available_permits()
+    }
+
+    /// The number of checkouts waiting for a slot.
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::Acquire)
+    }
+}
+
+/// Leaves the queue when a waiter gets a slot, fails or is cancelled.
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn fails_fast_once_slots_and_queue_are_full() {
+        let limiter = Arc::new(InFlightLimiter::new(2, Some(1)));
+        let held = [
+            limiter.acquire().await.unwrap(),
+            limiter.acquire().await.unwrap(),
+        ];
+        assert_eq!(limiter.in_flight(), 2);
+
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire().await.map(drop) }
+        });
+        while limiter.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // The next caller is turned away without waiting for a slot.
+        let overloaded = tokio::time::timeout(Duration::from_secs(1), limiter.acquire()).await;
+        assert!(matches!(overloaded, Ok(Err(Error::Overloaded))));
+        assert_eq!(limiter.queued(), 1);
+
+        // A freed slot goes to the queued waiter.
+        drop(held);
+        waiter.await.unwrap().unwrap();
+        assert_eq!((limiter.in_flight(), limiter.queued()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiters_leave_the_queue() {
+        let limiter = InFlightLimiter::new(1, Some(1));
+        let _held = limiter.acquire().await.unwrap();
+
+        let waiting = tokio::time::timeout(Duration::from_millis(10), limiter.acquire()).await;
+        assert!(waiting.is_err());
+        assert_eq!(limiter.queued(), 0);
+
+        // The freed place in the queue is taken again rather than overloading.
+        let requeued = tokio::time::timeout(Duration::from_millis(10), limiter.acquire()).await;
+        assert!(requeued.is_err());
+    }
+
+    #[tokio::test]
+    async fn an_unbounded_queue_never_overloads() {
+        let limiter = InFlightLimiter::new(1, None);
+        let _held = limiter.acquire().await.unwrap();
+        let queued = tokio::time::timeout(Duration::from_millis(10), limiter.acquire()).await;
+        assert!(queued.is_err(), "waits for a slot instead of failing");
+    }
+}
@@ -1,46 +1,354 @@
+use crate::clock::{Clock, TokioClock};
+use crate::connection::Client;
+use crate::credentials::CredentialsProvider;
 use crate::error::Error;
+use crate::metered::{ByteCounters, MeteredStream};
+use crate::observer::{
+    BrokenReason, ConnectionEvent, ConnectionInfo, ConnectionObserver, OnBroken, RecycleReason,
+};
+use crate::session_options::LockSettings;
+#[cfg(feature = "protocol-debug")]
+use crate::trace::{ProtocolTrace, TracedStream};
+use crate::version::ServerVersion;
 use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tiberius::Config;
 use tiberius::SqlBrowser;
-use tiberius::{Client, Config};
 use tokio::net::TcpStream;
-use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
+use tokio_util::compat::TokioAsyncWriteCompatExt;
+
+/// The query used to check that a connection is still usable.
+pub(crate) const VALIDATION_QUERY: &str = "SELECT 1";
+
+/// The query used to detect the server version of a new connection.
+const VERSION_QUERY: &str = "SELECT CAST(SERVERPROPERTY('ProductVersion') AS nvarchar(128)), CAST(SERVERPROPERTY('EngineEdition') AS int)";
+
+/// Server errors returned while an Azure SQL database is resuming, e.g. a serverless database after auto-pause.
+const RESUMING_ERRORS: [u32; 1] = [40613];
+
+/// How many rows of a result are read between yields to the runtime, unless
+/// [`SqlServerPoolBuilder::yield_every_rows`](crate::SqlServerPoolBuilder::yield_every_rows) says otherwise.
+pub(crate) const DEFAULT_YIELD_EVERY_ROWS: usize = 1024;
+
+/// How long bb8 keeps a connection idle before closing it, as the pool doesn't change bb8's default.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The first and longest delays between connection attempts while a database is resuming.
+const RESUME_RETRY_DELAYS: (Duration, Duration) = (Duration::from_secs(1), Duration::from_secs(8));
+
+/// A pooled connection, along with the state the manager tracks for it.
+pub(crate) struct ManagedConnection {
+    pub(crate) client: Client,
+    pub(crate) broken: bool,
+    pub(crate) server_version: ServerVersion,
+    pub(crate) created_at: Instant,
+    pub(crate) id: u64,
+    pub(crate) ages: Arc<ConnectionAges>,
+    /// The bytes this connection has read and written.
+    pub(crate) bytes: Arc<ByteCounters>,
+    /// The locking settings the session last ran with.
+    pub(crate) lock_settings: LockSettings,
+    /// How many rows of a result are read between yields to the runtime.
+    pub(crate) yield_every_rows: usize,
+    /// Why the connection was marked broken, if it is.
+    pub(crate) broken_reason: BrokenReason,
+    /// When the connection was last returned to the pool.
+    pub(crate) idle_since: Instant,
+    max_lifetime: Option<Duration>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+}
+
+impl ManagedConnection {
+    /// Report `event` to the observer, if there is one.
+    pub(crate) fn lifecycle(&self, event: ConnectionEvent<'_>) {
+        if let Some(observer) = &self.observer {
+            let info = ConnectionInfo {
+                id: self.id,
+                age: self.created_at.elapsed(),
+            };
+            observer.on_lifecycle(info, event);
+        }
+    }
+}
+
+impl Drop for ManagedConnection {
+    fn drop(&mut self) {
+        self.ages.remove(self.id);
+        if !self.broken {
+            // bb8 doesn't say why it closes a connection, so infer recycling from the limits it enforces.
+            let reason = if self
+                .max_lifetime
+                .is_some_and(|lifetime| self.created_at.elapsed() >= lifetime)
+            {
+                Some(RecycleReason::MaxLifetime)
+            } else if self.idle_since.elapsed() >= IDLE_TIMEOUT {
+                Some(RecycleReason::IdleTimeout)
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                self.lifecycle(ConnectionEvent::Recycled { reason });
+            }
+        }
+        self.lifecycle(ConnectionEvent::Closed);
+    }
+}
+
+/// When each open connection of a pool was created, and whether it is checked out.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionAges {
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, (Instant, bool)>>,
+}
+
+impl ConnectionAges {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, (Instant, bool)>> {
+        self.open.lock().expect("connection ages poisoned")
+    }
+
+    /// Record a new connection, returning its id.
+    fn insert(&self, created_at: Instant) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.lock().insert(id, (created_at, false));
+        id
+    }
+
+    fn remove(&self, id: u64) {
+        self.lock().remove(&id);
+    }
+
+    pub(crate) fn set_checked_out(&self, id: u64, checked_out: bool) {
+        if let Some(entry) = self.lock().get_mut(&id) {
+            entry.1 = checked_out;
+        }
+    }
+
+    /// The ages of the connections that aren't checked out, oldest first.
+    pub(crate) fn idle(&self) -> Vec<Duration> {
+        let mut ages: Vec<Duration> = self
+            .lock()
+            .values()
+            .filter(|(_, checked_out)| !checked_out)
+            .map(|(created_at, _)| created_at.elapsed())
+            .collect();
+        ages.sort_unstable_by(|a, b| b.cmp(a));
+        ages
+    }
+}
 
 pub(crate) struct ConnectionManager {
     config: Config,
     use_sql_browser: bool,
+    tcp_nodelay: bool,
+    credentials_provider: Option<CredentialsProvider>,
+    minimum_server_version: ServerVersion,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    #[cfg(feature = "protocol-debug")]
+    protocol_trace: Option<ProtocolTrace>,
+    resume_timeout: Option<Duration>,
+    resuming: Arc<AtomicBool>,
+    ages: Arc<ConnectionAges>,
+    clock: Arc<dyn Clock>,
+    session_options: Option<String>,
+    bytes: Arc<ByteCounters>,
+    yield_every_rows: usize,
+    on_broken: OnBroken,
+    broken: Arc<AtomicU64>,
+    max_lifetime: Option<Duration>,
+}
+
+impl ConnectionManager {
+    /// Connect, waiting up to the resume timeout for a resuming database instead of failing.
+    async fn connect_resuming(&self) -> Result<(Client, ServerVersion, Arc<ByteCounters>), Error> {
+        let Some(resume_timeout) = self.resume_timeout else {
+            return self.connect_client().await;
+        };
+
+        let start = self.clock.now();
+        let mut delay = RESUME_RETRY_DELAYS.0;
+        let mut waited = false;
+        let result = loop {
+            match self.connect_client().await {
+                Err(e)
+                    if e.server_code()
+                        .is_some_and(|code| RESUMING_ERRORS.contains(&code))
+                        && self.clock.elapsed(start) + delay < resume_timeout =>
+                {
+                    waited = true;
+                    self.resuming.store(true, Ordering::Relaxed);
+                    self.clock.sleep(delay).await;
+                    delay = (delay * 2).min(RESUME_RETRY_DELAYS.1);
+                }
+                result => break result,
+            }
+        };
+
+        if waited {
+            self.resuming.store(false, Ordering::Relaxed);
+            if let (Ok(_), Some(observer)) = (&result, &self.observer) {
+                observer.on_resume(self.clock.elapsed(start));
+            }
+        }
+        result
+    }
+
+    async fn connect_client(&self) -> Result<(Client, ServerVersion, Arc<ByteCounters>), Error> {
+        let mut config = self.config.clone();
+        if let Some(provider) = &self.credentials_provider {
+            let credentials = provider().await?;
+            config.authentication(credentials.auth_method());
+        }
+
+        let tcp = if self.use_sql_browser {
+            TcpStream::connect_named(&config).await?
+        } else {
+            TcpStream::connect(&config.get_addr()).await?
+        };
+
+        tcp.set_nodelay(self.tcp_nodelay)?;
+
+        let bytes = Arc::new(ByteCounters::default());
+        let tcp = MeteredStream::new(tcp, bytes.clone(), self.bytes.clone());
+
+        #[cfg(feature = "protocol-debug")]
+        let tcp = TracedStream::new(tcp, self.protocol_trace.clone());
+
+        let mut client = Client::connect(config, tcp.compat_write()).await?;
+
+        let server_version = server_version(&mut client).await?;
+        if !server_version.at_least(self.minimum_server_version) {
+            return Err(Error::UnsupportedServerVersion {
+                found: server_version,
+                minimum: self.minimum_server_version,
+            });
+        }
+
+        if let Some(session_options) = &self.session_options {
+            client
+                .simple_query(session_options.as_str())
+                .await?
+                .into_results()
+                .await?;
+        }
+
+        Ok((client, server_version, bytes))
+    }
 }
 
 #[async_trait]
 impl bb8::ManageConnection for ConnectionManager {
-    type Connection = Client<Compat<TcpStream>>;
+    type Connection = ManagedConnection;
     type Error = Error;
 
     async fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        let tcp = if self.use_sql_browser {
-            TcpStream::connect_named(&self.config).await?
-        } else {
-            TcpStream::connect(&self.config.get_addr()).await?
+        let start = Instant::now();
+        let (client, server_version, bytes) = match self.connect_resuming().await {
+            Ok(connected) => connected,
+            Err(e) => {
+                if let Some(observer) = &self.observer {
+                    observer.on_connect_error(&e);
+                }
+                return Err(e);
+            }
         };
 
-        tcp.set_nodelay(true)?;
-
-        Client::connect(self.config.clone(), tcp.compat_write())
-            .await
-            .map_err(Into::into)
+        let created_at = Instant::now();
+        let conn = ManagedConnection {
+            client,
+            broken: false,
+            server_version,
+            created_at,
+            id: self.ages.insert(created_at),
+            ages: self.ages.clone(),
+            bytes,
+            lock_settings: LockSettings::default(),
+            yield_every_rows: self.yield_every_rows,
+            broken_reason: BrokenReason::default(),
+            idle_since: created_at,
+            max_lifetime: self.max_lifetime,
+            observer: self.observer.clone(),
+        };
+        conn.lifecycle(ConnectionEvent::Created {
+            connect_time: created_at - start,
+            endpoint: &self.config.get_addr(),
+        });
+        Ok(conn)
     }
 
     async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
-        conn.simple_query("SELECT 1").await?;
-        Ok(())
+        let result = conn
+            .client
+            .simple_query(VALIDATION_QUERY)
+            .await
+            .map(drop)
+            .map_err(Error::from);
+
+        match &result {
+            Ok(()) => conn.lifecycle(ConnectionEvent::Validated),
+            Err(error) => conn.lifecycle(ConnectionEvent::ValidationFailed { error }),
+        }
+
+        result
     }
 
-    fn has_broken(&self, _: &mut Self::Connection) -> bool {
-        false
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        if conn.broken {
+            let broken = self.broken.fetch_add(1, Ordering::Relaxed) + 1;
+            conn.lifecycle(ConnectionEvent::Broken {
+                reason: conn.broken_reason,
+            });
+            if let Some(observer) = &self.observer {
+                if self.on_broken == OnBroken::DiscardAndAlert {
+                    observer.on_broken_alert(broken);
+                }
+            }
+        }
+        conn.broken
     }
 }
 
+/// Query the version of the server a client is connected to.
+async fn server_version(client: &mut Client) -> Result<ServerVersion, Error> {
+    let row = client
+        .simple_query(VERSION_QUERY)
+        .await?
+        .into_row()
+        .await?
+        .ok_or(Error::EmptyResult)?;
+
+    let product_version: &str = row.try_get(0)?.unwrap_or_default();
+    let engine_edition: Option<i32> = row.try_get(1)?;
+
+    ServerVersion::parse(product_version, engine_edition).ok_or_else(|| {
+        tiberius::error::Error::Protocol(
+            format!("Unrecognised server version: {product_version}").into(),
+        )
+        .into()
+    })
+}
+
+#[derive(Clone)]
 pub(crate) struct ConnectionManagerBuilder {
     use_sql_browser: bool,
+    tcp_nodelay: bool,
+    credentials_provider: Option<CredentialsProvider>,
+    minimum_server_version: ServerVersion,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    #[cfg(feature = "protocol-debug")]
+    protocol_trace: Option<ProtocolTrace>,
+    resume_timeout: Option<Duration>,
+    resuming: Arc<AtomicBool>,
+    ages: Arc<ConnectionAges>,
+    clock: Arc<dyn Clock>,
+    session_options: Option<String>,
+    bytes: Arc<ByteCounters>,
+    yield_every_rows: usize,
+    on_broken: OnBroken,
+    broken: Arc<AtomicU64>,
+    max_lifetime: Option<Duration>,
 }
 
 impl ConnectionManagerBuilder {
@@ -53,10 +361,106 @@ impl ConnectionManagerBuilder {
         self
     }
 
+    pub fn tcp_nodelay(&mut self, yes: bool) -> &mut Self {
+        self.tcp_nodelay = yes;
+        self
+    }
+
+    pub fn credentials_provider(&mut self, provider: Option<CredentialsProvider>) -> &mut Self {
+        self.credentials_provider = provider;
+        self
+    }
+
+    pub fn minimum_server_version(&mut self, version: ServerVersion) -> &mut Self {
+        self.minimum_server_version = version;
+        self
+    }
+
+    pub fn observer(&mut self, observer: Option<Arc<dyn ConnectionObserver>>) -> &mut Self {
+        self.observer = observer;
+        self
+    }
+
+    #[cfg(feature = "protocol-debug")]
+    pub fn protocol_trace(&mut self, trace: Option<ProtocolTrace>) -> &mut Self {
+        self.protocol_trace = trace;
+        self
+    }
+
+    /// Set how long to wait for a resuming database, and the flag raised while waiting.
+    pub fn resume_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+        resuming: Arc<AtomicBool>,
+    ) -> &mut Self {
+        self.resume_timeout = timeout;
+        self.resuming = resuming;
+        self
+    }
+
+    /// Set where the managed connections' ages are recorded.
+    pub fn ages(&mut self, ages: Arc<ConnectionAges>) -> &mut Self {
+        self.ages = ages;
+        self
+    }
+
+    /// Set the clock used to wait for a resuming database.
+    pub fn clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Set the batch of `SET` statements run on each new connection.
+    pub fn session_options(&mut self, batch: Option<String>) -> &mut Self {
+        self.session_options = batch;
+        self
+    }
+
+    /// Set where the bytes read and written by all managed connections are counted.
+    pub fn bytes(&mut self, bytes: Arc<ByteCounters>) -> &mut Self {
+        self.bytes = bytes;
+        self
+    }
+
+    /// Set how many rows of a result managed connections read between yields to the runtime, or 0 to never yield.
+    pub fn yield_every_rows(&mut self, rows: usize) -> &mut Self {
+        self.yield_every_rows = rows;
+        self
+    }
+
+    /// Set what is done with connections found broken. Managers built from clones of this builder share one count
+    /// of broken connections.
+    pub fn on_broken(&mut self, policy: OnBroken) -> &mut Self {
+        self.on_broken = policy;
+        self
+    }
+
+    /// Set the pool's maximum connection lifetime, to tell connections recycled for their age apart.
+    pub fn max_lifetime(&mut self, max_lifetime: Option<Duration>) -> &mut Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
     pub fn build(&self, config: Config) -> Result<ConnectionManager, Error> {
         Ok(ConnectionManager {
             config,
             use_sql_browser: self.use_sql_browser,
+            tcp_nodelay: self.tcp_nodelay,
+            credentials_provider: self.credentials_provider.clone(),
+            minimum_server_version: self.minimum_server_version,
+            observer: self.observer.clone(),
+            #[cfg(feature = "protocol-debug")]
+            protocol_trace: self.protocol_trace.clone(),
+            resume_timeout: self.resume_timeout,
+            resuming: self.resuming.clone(),
+            ages: self.ages.clone(),
+            clock: self.clock.clone(),
+            session_options: self.session_options.clone(),
+            bytes: self.bytes.clone(),
+            yield_every_rows: self.yield_every_rows,
+            on_broken: self.on_broken,
+            broken: self.broken.clone(),
+            max_lifetime: self.max_lifetime,
         })
     }
 }
@@ -65,6 +469,22 @@ impl Default for ConnectionManagerBuilder {
     fn default() -> Self {
         ConnectionManagerBuilder {
             use_sql_browser: true,
+            tcp_nodelay: true,
+            credentials_provider: None,
+            minimum_server_version: ServerVersion::SQL_SERVER_2008,
+            observer: None,
+            #[cfg(feature = "protocol-debug")]
+            protocol_trace: None,
+            resume_timeout: None,
+            resuming: Arc::default(),
+            ages: Arc::default(),
+            clock: Arc::new(TokioClock),
+            session_options: None,
+            bytes: Arc::default(),
+            yield_every_rows: DEFAULT_YIELD_EVERY_ROWS,
+            on_broken: OnBroken::default(),
+            broken: Arc::default(),
+            max_lifetime: None,
         }
     }
 }
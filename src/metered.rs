@@ -0,0 +1,108 @@
+use serde::Serialize;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The number of bytes read from and written to the network, as counted by a [`MeteredStream`].
+///
+/// Counts are taken on the TCP stream, below TLS, so they include TDS and TLS framing and match what the network
+/// carries. TDS has no compression, so they are also the size of the data the server sends and receives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ByteCount {
+    pub read: u64,
+    pub written: u64,
+}
+
+impl ByteCount {
+    /// The bytes transferred since `earlier`, a count taken from the same counter.
+    pub fn since(self, earlier: ByteCount) -> ByteCount {
+        ByteCount {
+            read: self.read.saturating_sub(earlier.read),
+            written: self.written.saturating_sub(earlier.written),
+        }
+    }
+}
+
+/// Running byte counts, shared between a stream and whoever reads them.
+#[derive(Debug, Default)]
+pub(crate) struct ByteCounters {
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+impl ByteCounters {
+    pub(crate) fn get(&self) -> ByteCount {
+        ByteCount {
+            read: self.read.load(Ordering::Relaxed),
+            written: self.written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A stream that counts the bytes passing through it, the transport of every [`Client`](crate::Client).
+///
+/// Each connection has its own counts, see [`PooledConnection::bytes`](crate::PooledConnection::bytes), and the
+/// pool's total is reported by [`SqlServerPool::status`](crate::SqlServerPool::status).
+pub struct MeteredStream<S> {
+    inner: S,
+    connection: Arc<ByteCounters>,
+    pool: Arc<ByteCounters>,
+}
+
+impl<S> MeteredStream<S> {
+    pub(crate) fn new(inner: S, connection: Arc<ByteCounters>, pool: Arc<ByteCounters>) -> Self {
+        Self {
+            inner,
+            connection,
+            pool,
+        }
+    }
+
+    fn count(&self, bytes: usize, counter: fn(&ByteCounters) -> &AtomicU64) {
+        let bytes = bytes as u64;
+        counter(&self.connection).fetch_add(bytes, Ordering::Relaxed);
+        counter(&self.pool).fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MeteredStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &poll {
+            this.count(buf.filled().len() - before, |counters| &counters.read);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MeteredStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = &poll {
+            this.count(*written, |counters| &counters.written);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
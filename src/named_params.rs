@@ -0,0 +1,497 @@
+use crate::{
+    error::Error,
+    param::SqlParam,
+    sql::lexer::{tokenize, TokenKind},
+};
+use serde::ser::{self, Impossible, Serialize};
+use std::fmt;
+
+/// A value whose fields can be bound by name to the `@field` placeholders of a query, see
+/// [`SqlServerPool::row_query_named`](crate::SqlServerPool::row_query_named).
+///
+/// This is the writing counterpart of [`TryFromRow`](crate::TryFromRow). Every [`Serialize`] type implements it, so
+/// `#[derive(serde::Serialize)]` on a struct is enough. Each field becomes a parameter named as serde names it, so
+/// `#[serde(rename = "...")]` renames the placeholder too. Fields must be scalars: integers, floats, `bool`, `char`,
+/// strings, bytes, unit enum variants (bound as their name) and `Option`s of those, with `None` bound as NULL.
+/// Newtypes bind their inner value. Maps with string keys work too.
+///
+/// ```
+/// use mssql_rs::{SqlParam, ToSqlParams};
+///
+/// #[derive(serde::Serialize)]
+/// struct NewUser<'a> {
+///     name: &'a str,
+///     age: Option<u8>,
+/// }
+///
+/// let params = NewUser { name: "Alice", age: None }.to_sql_params()?;
+/// assert_eq!(
+///     params,
+///     [
+///         ("name".to_owned(), SqlParam::from("Alice")),
+///         ("age".to_owned(), SqlParam::Null),
+///     ]
+/// );
+/// # Ok::<(), mssql_rs::Error>(())
+/// ```
+pub trait ToSqlParams {
+    /// The parameters of the value, as names and values in field order.
+    fn to_sql_params(&self) -> Result<Vec<(String, SqlParam)>, Error>;
+}
+
+impl<T: Serialize + ?Sized> ToSqlParams for T {
+    fn to_sql_params(&self) -> Result<Vec<(String, SqlParam)>, Error> {
+        self.serialize(ParamsSerializer)
+            .map_err(|ParamError(message)| Error::InvalidArgument(message))
+    }
+}
+
+/// Rewrite the `@name` placeholders of `query` to the positional `@P{n}` placeholders tiberius binds, returning the
+/// rewritten query and the parameters in placeholder order.
+///
+/// Names are matched ignoring case, as the server matches variable names, and a name used several times is bound
+/// once. Variables that aren't parameters, e.g. ones the query declares, are left as they are. String literals,
+/// quoted identifiers and comments are skipped, and positional placeholders are rejected, as mixing the two styles
+/// would bind the wrong values.
+pub(crate) fn bind_named(
+    query: &str,
+    params: Vec<(String, SqlParam)>,
+) -> Result<(String, Vec<SqlParam>), Error> {
+    for (i, (name, _)) in params.iter().enumerate() {
+        if params[..i]
+            .iter()
+            .any(|(earlier, _)| earlier.to_lowercase() == name.to_lowercase())
+        {
+            return Err(Error::InvalidArgument(format!(
+                "parameter {name} is given more than once"
+            )));
+        }
+    }
+
+    let mut params: Vec<(String, Option<SqlParam>)> = params
+        .into_iter()
+        .map(|(name, param)| (name, Some(param)))
+        .collect();
+    // The positions of the bound parameters in `params`, in placeholder order.
+    let mut order: Vec<usize> = Vec::new();
+    let mut rewritten = String::with_capacity(query.len());
+    for token in tokenize(query) {
+        match token.kind {
+            TokenKind::Parameter(_) => {
+                return Err(Error::InvalidArgument(format!(
+                    "named parameter queries can't use positional placeholders like {}",
+                    token.text
+                )));
+            }
+            TokenKind::Variable if !token.text.starts_with("@@") => {
+                let name = &token.text[1..];
+                let position = params
+                    .iter()
+                    .position(|(param, _)| param.to_lowercase() == name.to_lowercase());
+                match position {
+                    Some(position) => {
+                        let n = match order.iter().position(|&bound| bound == position) {
+                            Some(n) => n,
+                            None => {
+                                order.push(position);
+                                order.len() - 1
+                            }
+                        };
+                        rewritten.push_str(&format!("@P{}", n + 1));
+                    }
+                    None => rewritten.push_str(token.text),
+                }
+            }
+            _ => rewritten.push_str(token.text),
+        }
+    }
+
+    let bound = order
+        .into_iter()
+        .map(|position| {
+            params[position]
+                .1
+                .take()
+                .expect("each parameter is bound once")
+        })
+        .collect();
+    Ok((rewritten, bound))
+}
+
+/// The error of a value that can't be bound as parameters. Converted to [`Error::InvalidArgument`].
+#[derive(Debug)]
+struct ParamError(String);
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+impl ser::Error for ParamError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ParamError(msg.to_string())
+    }
+}
+
+fn not_named() -> ParamError {
+    ParamError("parameters must be a struct or a map with string keys".to_owned())
+}
+
+/// Serializes a struct or map into named parameters.
+struct ParamsSerializer;
+
+impl ser::Serializer for ParamsSerializer {
+    type Ok = Vec<(String, SqlParam)>;
+    type Error = ParamError;
+    type SerializeSeq = Impossible<Self::Ok, ParamError>;
+    type SerializeTuple = Impossible<Self::Ok, ParamError>;
+    type SerializeTupleStruct = Impossible<Self::Ok, ParamError>;
+    type SerializeTupleVariant = Impossible<Self::Ok, ParamError>;
+    type SerializeMap = ParamsMap;
+    type SerializeStruct = ParamsStruct;
+    type SerializeStructVariant = Impossible<Self::Ok, ParamError>;
+
+    fn serialize_bool(self, _: bool) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_i8(self, _: i8) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_i16(self, _: i16) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_i32(self, _: i32) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_i64(self, _: i64) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_u8(self, _: u8) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_u16(self, _: u16) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_u32(self, _: u32) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_u64(self, _: u64) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_f32(self, _: f32) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_f64(self, _: f64) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_char(self, _: char) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_str(self, _: &str) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_bytes(self, _: &[u8]) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_none(self) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, ParamError> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, ParamError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Self::Ok, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, ParamError> {
+        Err(not_named())
+    }
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, ParamError> {
+        Ok(ParamsMap {
+            params: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, ParamError> {
+        Ok(ParamsStruct {
+            params: Vec::with_capacity(len),
+        })
+    }
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, ParamError> {
+        Err(not_named())
+    }
+}
+
+/// Serialize the value of the field called `name` as a parameter, naming the field in errors.
+fn field_param<T: Serialize + ?Sized>(name: &str, value: &T) -> Result<SqlParam, ParamError> {
+    value
+        .serialize(ParamSerializer)
+        .map_err(|ParamError(message)| ParamError(format!("field {name}: {message}")))
+}
+
+struct ParamsStruct {
+    params: Vec<(String, SqlParam)>,
+}
+
+impl ser::SerializeStruct for ParamsStruct {
+    type Ok = Vec<(String, SqlParam)>;
+    type Error = ParamError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ParamError> {
+        let param = field_param(key, value)?;
+        self.params.push((key.to_owned(), param));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, ParamError> {
+        Ok(self.params)
+    }
+}
+
+struct ParamsMap {
+    params: Vec<(String, SqlParam)>,
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for ParamsMap {
+    type Ok = Vec<(String, SqlParam)>;
+    type Error = ParamError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ParamError> {
+        match key.serialize(ParamSerializer)? {
+            SqlParam::String(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(not_named()),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ParamError> {
+        let key = self.key.take().expect("serialize_key is called first");
+        let param = field_param(&key, value)?;
+        self.params.push((key, param));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, ParamError> {
+        Ok(self.params)
+    }
+}
+
+fn not_scalar() -> ParamError {
+    ParamError("only scalar values can be bound as parameters".to_owned())
+}
+
+fn out_of_range(value: impl fmt::Display) -> ParamError {
+    ParamError(format!("{value} is out of range for bigint"))
+}
+
+/// Serializes a scalar value into a parameter.
+struct ParamSerializer;
+
+impl ser::Serializer for ParamSerializer {
+    type Ok = SqlParam;
+    type Error = ParamError;
+    type SerializeSeq = Impossible<SqlParam, ParamError>;
+    type SerializeTuple = Impossible<SqlParam, ParamError>;
+    type SerializeTupleStruct = Impossible<SqlParam, ParamError>;
+    type SerializeTupleVariant = Impossible<SqlParam, ParamError>;
+    type SerializeMap = Impossible<SqlParam, ParamError>;
+    type SerializeStruct = Impossible<SqlParam, ParamError>;
+    type SerializeStructVariant = Impossible<SqlParam, ParamError>;
+
+    fn serialize_bool(self, v: bool) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::Bool(v))
+    }
+    fn serialize_i8(self, v: i8) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::I16(v.into()))
+    }
+    fn serialize_i16(self, v: i16) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::I16(v))
+    }
+    fn serialize_i32(self, v: i32) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::I32(v))
+    }
+    fn serialize_i64(self, v: i64) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::I64(v))
+    }
+    fn serialize_i128(self, v: i128) -> Result<SqlParam, ParamError> {
+        i64::try_from(v)
+            .map(SqlParam::I64)
+            .map_err(|_| out_of_range(v))
+    }
+    fn serialize_u8(self, v: u8) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::U8(v))
+    }
+    fn serialize_u16(self, v: u16) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::I32(v.into()))
+    }
+    fn serialize_u32(self, v: u32) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::I64(v.into()))
+    }
+    fn serialize_u64(self, v: u64) -> Result<SqlParam, ParamError> {
+        i64::try_from(v)
+            .map(SqlParam::I64)
+            .map_err(|_| out_of_range(v))
+    }
+    fn serialize_u128(self, v: u128) -> Result<SqlParam, ParamError> {
+        i64::try_from(v)
+            .map(SqlParam::I64)
+            .map_err(|_| out_of_range(v))
+    }
+    fn serialize_f32(self, v: f32) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::F32(v))
+    }
+    fn serialize_f64(self, v: f64) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::F64(v))
+    }
+    fn serialize_char(self, v: char) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::String(v.to_string()))
+    }
+    fn serialize_str(self, v: &str) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::String(v.to_owned()))
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::Binary(v.to_vec()))
+    }
+    fn serialize_none(self) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::Null)
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<SqlParam, ParamError> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::Null)
+    }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::Null)
+    }
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<SqlParam, ParamError> {
+        Ok(SqlParam::String(variant.to_owned()))
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<SqlParam, ParamError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<SqlParam, ParamError> {
+        Err(not_scalar())
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, ParamError> {
+        Err(not_scalar())
+    }
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, ParamError> {
+        Err(not_scalar())
+    }
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, ParamError> {
+        Err(not_scalar())
+    }
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, ParamError> {
+        Err(not_scalar())
+    }
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, ParamError> {
+        Err(not_scalar())
+    }
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, ParamError> {
+        Err(not_scalar())
+    }
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, ParamError> {
+        Err(not_scalar())
+    }
+}
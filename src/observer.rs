@@ -0,0 +1,215 @@
+use crate::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Callbacks for connection and pool lifecycle events, e.g. for connection dashboards.
+///
+/// Register an observer with [`SqlServerPoolBuilder::observer`](crate::SqlServerPoolBuilder::observer).
+/// Every method has an empty default, so implement only the events of interest.
+/// A connection's events are reported once, to [`ConnectionObserver::on_lifecycle`], whose default forwards them to
+/// the callbacks without a [`ConnectionInfo`]: `on_connect`, `on_validate`, `on_broken` and `on_close`.
+/// Callbacks run inline on the pool's connection paths, so they should be cheap and must not block.
+pub trait ConnectionObserver: Send + Sync {
+    /// A new connection was established.
+    fn on_connect(&self) {}
+
+    /// A connection's life reached `event`, see [`ConnectionEvent`]. Unlike the other callbacks, this identifies the
+    /// connection, so churn can be traced, e.g. connections recycled as soon as they are opened.
+    ///
+    /// The default forwards the event to [`ConnectionObserver::on_connect`], [`ConnectionObserver::on_validate`],
+    /// [`ConnectionObserver::on_broken`] or [`ConnectionObserver::on_close`]. Overriding it replaces those, so an
+    /// observer implements either this or them.
+    fn on_lifecycle(&self, _connection: ConnectionInfo, event: ConnectionEvent<'_>) {
+        match event {
+            ConnectionEvent::Created { .. } => self.on_connect(),
+            ConnectionEvent::Validated => self.on_validate(Ok(())),
+            ConnectionEvent::ValidationFailed { error } => self.on_validate(Err(error)),
+            ConnectionEvent::Broken { .. } => self.on_broken(),
+            ConnectionEvent::Closed => self.on_close(),
+            ConnectionEvent::CheckedOut | ConnectionEvent::Recycled { .. } => {}
+        }
+    }
+
+    /// A new connection could not be established.
+    fn on_connect_error(&self, _error: &Error) {}
+
+    /// A new connection was established after waiting for the database to resume,
+    /// see [`SqlServerPoolBuilder::resume_timeout`](crate::SqlServerPoolBuilder::resume_timeout).
+    fn on_resume(&self, _waited: Duration) {}
+
+    /// A connection was validated, with the error if validation failed.
+    fn on_validate(&self, _result: Result<(), &Error>) {}
+
+    /// A connection was found broken on return to the pool, and will be discarded.
+    fn on_broken(&self) {}
+
+    /// A connection was found broken on return to a pool alerting on them, see [`OnBroken::DiscardAndAlert`], with
+    /// the number of connections the pool has found broken so far, including this one.
+    fn on_broken_alert(&self, _broken: u64) {}
+
+    /// A connection was closed, for any reason.
+    fn on_close(&self) {}
+
+    /// A [`SwitchablePool`](crate::SwitchablePool) replacement was built and warmed.
+    fn on_switch_prepared(&self) {}
+
+    /// A [`SwitchablePool`](crate::SwitchablePool) switched to its replacement, and the old pool began draining.
+    fn on_switch(&self) {}
+
+    /// A [`SwitchablePool`](crate::SwitchablePool) finished draining the old pool,
+    /// with the number of connections still in use when the grace period ended.
+    fn on_drained(&self, _in_use: usize) {}
+
+    /// A value bound to the quoted `table`'s `column` was truncated to fit,
+    /// see [`TruncationPolicy::TruncateAndWarn`](crate::TruncationPolicy::TruncateAndWarn).
+    fn on_truncated(&self, _table: &str, _column: &str) {}
+
+    /// A retry waited `delay` because the server suggested waiting `suggested`, capped by the retry policy, see
+    /// [`Error::throttle_retry_after`].
+    fn on_throttled(&self, _suggested: Duration, _delay: Duration) {}
+}
+
+/// The connection a [`ConnectionObserver::on_lifecycle`] event is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The connection's id, unique within its pool and stable for its life.
+    pub id: u64,
+    /// How long ago the connection was opened.
+    pub age: Duration,
+}
+
+/// An event in the life of a connection, see [`ConnectionObserver::on_lifecycle`].
+///
+/// Each connection is `Created`, is `CheckedOut` and `Validated` any number of times, may fail validation, be found
+/// `Broken` or be `Recycled`, and is finally `Closed`.
+#[derive(Debug, Clone, Copy)]
+pub enum ConnectionEvent<'a> {
+    /// The connection was opened to `endpoint`, e.g. `db.example.com:1433`, taking `connect_time` including the login
+    /// and any wait for a resuming database.
+    Created {
+        connect_time: Duration,
+        endpoint: &'a str,
+    },
+    /// The connection was checked out of the pool, to run queries until it is returned.
+    CheckedOut,
+    /// The validation query succeeded, e.g. before a checkout, see
+    /// [`SqlServerPoolBuilder::validate_on_checkout`](crate::SqlServerPoolBuilder::validate_on_checkout).
+    Validated,
+    /// The validation query failed, so the connection will be closed.
+    ValidationFailed { error: &'a Error },
+    /// The connection was found broken on return to the pool, and will be closed.
+    Broken { reason: BrokenReason },
+    /// The connection was closed to be replaced, having been open or idle too long.
+    Recycled { reason: RecycleReason },
+    /// The connection was closed, for any reason.
+    Closed,
+}
+
+/// Why a connection was found broken, see [`ConnectionEvent::Broken`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BrokenReason {
+    /// A request didn't complete, e.g. it was cancelled, timed out or failed partway, so the connection could be
+    /// left mid-exchange.
+    #[default]
+    Interrupted,
+    /// The rest of a result was left unread, e.g. past [`SqlServerPoolBuilder::max_rows`](crate::SqlServerPoolBuilder::max_rows)
+    /// or after a stream was dropped, rather than making the next query drain it.
+    Abandoned,
+    /// Session state couldn't be restored, e.g. a transaction left open, a setting not reset or a temporary object
+    /// not dropped.
+    SessionState,
+    /// The validation query failed.
+    ValidationFailed,
+}
+
+/// Why a connection was recycled, see [`ConnectionEvent::Recycled`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecycleReason {
+    /// It reached [`SqlServerPoolBuilder::max_lifetime`](crate::SqlServerPoolBuilder::max_lifetime).
+    MaxLifetime,
+    /// It was idle for longer than the pool's idle timeout of 10 minutes.
+    IdleTimeout,
+}
+
+/// What a pool does with a connection found broken on return, e.g. after a query was cut short or the network
+/// failed mid-result, see [`SqlServerPoolBuilder::on_broken`](crate::SqlServerPoolBuilder::on_broken).
+///
+/// Either way the connection is discarded and replaced. Connections broken over and over usually mean a server or
+/// network problem rather than anything the application did, which alerting makes visible.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnBroken {
+    /// Discard the connection, only reporting [`ConnectionObserver::on_broken`].
+    #[default]
+    Discard,
+    /// Discard the connection and alert the observer with [`ConnectionObserver::on_broken_alert`].
+    DiscardAndAlert,
+}
+
+impl fmt::Debug for dyn ConnectionObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ConnectionObserver")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records the callbacks without a connection.
+    #[derive(Default)]
+    struct Calls(Mutex<Vec<&'static str>>);
+
+    impl ConnectionObserver for Calls {
+        fn on_connect(&self) {
+            self.0.lock().unwrap().push("connect");
+        }
+
+        fn on_validate(&self, result: Result<(), &Error>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(if result.is_ok() { "valid" } else { "invalid" });
+        }
+
+        fn on_broken(&self) {
+            self.0.lock().unwrap().push("broken");
+        }
+
+        fn on_close(&self) {
+            self.0.lock().unwrap().push("close");
+        }
+    }
+
+    #[test]
+    fn lifecycle_events_reach_each_callback_once() {
+        let calls = Calls::default();
+        let connection = ConnectionInfo {
+            id: 1,
+            age: Duration::ZERO,
+        };
+        let error = Error::ConnectionTimeout;
+        for event in [
+            ConnectionEvent::Created {
+                connect_time: Duration::ZERO,
+                endpoint: "localhost:1433",
+            },
+            ConnectionEvent::CheckedOut,
+            ConnectionEvent::Validated,
+            ConnectionEvent::ValidationFailed { error: &error },
+            ConnectionEvent::Broken {
+                reason: BrokenReason::Interrupted,
+            },
+            ConnectionEvent::Recycled {
+                reason: RecycleReason::IdleTimeout,
+            },
+            ConnectionEvent::Closed,
+        ] {
+            calls.on_lifecycle(connection, event);
+        }
+        assert_eq!(
+            *calls.0.lock().unwrap(),
+            ["connect", "valid", "invalid", "broken", "close"]
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use crate::chunks::Accumulation;
+use crate::session_options::DeadlockPriority;
+use crate::transform::RowTransformer;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Per-query options, overriding the pool's defaults for a single call.
+///
+/// Unset options fall back to the pool's configuration. Every method taking options, e.g.
+/// [`SqlServerPool::row_query_with_options`](crate::SqlServerPool::row_query_with_options), honours
+/// [`QueryOptions::timeout`], [`QueryOptions::deadlock_priority`] and [`QueryOptions::lock_timeout`] the same way,
+/// while the other options only apply to the methods they name.
+///
+/// ```
+/// let options = mssql_rs::QueryOptions {
+///     stable_param_types: Some(true),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Override [`SqlServerPoolBuilder::stable_param_types`](crate::SqlServerPoolBuilder::stable_param_types).
+    pub stable_param_types: Option<bool>,
+    /// How [`SqlServerPool::row_query_chunked`](crate::SqlServerPool::row_query_chunked) accumulates rows.
+    /// Unset, rows are collected contiguously for small results and in chunks of 64K rows past that.
+    pub accumulation: Option<Accumulation>,
+    /// Transformers applied by [`SqlServerPool::row_query_dynamic`](crate::SqlServerPool::row_query_dynamic),
+    /// after those of [`SqlServerPoolBuilder::row_transformer`](crate::SqlServerPoolBuilder::row_transformer).
+    pub transformers: Vec<Arc<dyn RowTransformer>>,
+    /// The table whose [`ColumnCodec`](crate::ColumnCodec)s decrypt the columns read by
+    /// [`SqlServerPool::row_query_dynamic`](crate::SqlServerPool::row_query_dynamic), matched by column name.
+    /// Unset, no columns are decrypted.
+    pub codec_table: Option<String>,
+    /// Override [`SqlServerPoolBuilder::fetch_buffer_rows`](crate::SqlServerPoolBuilder::fetch_buffer_rows).
+    pub fetch_buffer_rows: Option<usize>,
+    /// The actor recorded in [`AuditConfig::updated_by`](crate::AuditConfig::updated_by) columns by
+    /// [`SqlServerPool::upsert_with_options`](crate::SqlServerPool::upsert_with_options).
+    /// Unset, the actor set by [`with_actor`](crate::with_actor) is used.
+    pub actor: Option<String>,
+    /// How long the call may take, including waiting for a connection, before it fails with
+    /// [`Error::QueryTimeout`](crate::Error::QueryTimeout). A connection whose query is cut short is discarded
+    /// rather than returned to the pool. Unset, there is no limit beyond the pool's connection timeout.
+    pub timeout: Option<Duration>,
+    /// Override [`SqlServerPoolBuilder::deadlock_priority`](crate::SqlServerPoolBuilder::deadlock_priority) for the
+    /// connections the call checks out.
+    pub deadlock_priority: Option<DeadlockPriority>,
+    /// Override [`SqlServerPoolBuilder::lock_timeout`](crate::SqlServerPoolBuilder::lock_timeout) for the
+    /// connections the call checks out.
+    pub lock_timeout: Option<Duration>,
+    /// Override [`SqlServerPoolBuilder::max_rows`](crate::SqlServerPoolBuilder::max_rows) for
+    /// [`SqlServerPool::row_query_with_options`](crate::SqlServerPool::row_query_with_options).
+    pub max_rows: Option<usize>,
+}
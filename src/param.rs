@@ -0,0 +1,280 @@
+use crate::error::Error;
+use std::borrow::Cow;
+use tiberius::numeric::Numeric;
+use tiberius::{ColumnData, IntoSql, Query, ToSql};
+
+/// An owned, typed query parameter.
+///
+/// Used by the helpers that generate SQL (e.g. [`SqlServerPool::upsert`](crate::SqlServerPool::upsert)),
+/// where parameters come in name/value pairs of differing types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlParam {
+    Null,
+    Bool(bool),
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    /// Bound as `real`, so the value isn't widened to `float` on the way.
+    F32(f32),
+    F64(f64),
+    String(String),
+    Binary(Vec<u8>),
+    /// A `decimal`/`numeric`, exactly, without `rust_decimal`. Bound as `numeric(p, s)` with the value's own scale
+    /// and the precision of its digits, see [`SqlParam::decimal_with`] to bind a column's declared type instead.
+    Numeric(Numeric),
+}
+
+impl SqlParam {
+    /// Create a string parameter that matches `s` literally in a `LIKE` pattern, with its wildcards escaped by
+    /// [`escape_like`](crate::sql::escape_like), e.g. for a user's search term in `LIKE '%' + @P1 + '%'`.
+    pub fn like_literal(s: &str) -> Self {
+        SqlParam::String(crate::sql::escape_like(s))
+    }
+
+    /// Create a string parameter from `value`, truncated to fit an `nvarchar(max_chars)` column, and whether it was
+    /// truncated.
+    ///
+    /// Like the column's length, `max_chars` counts UTF-16 code units, so a character outside the Basic Multilingual
+    /// Plane, e.g. an emoji, takes two. The value is only cut between characters, never within one, so it may end up
+    /// one unit shorter than `max_chars`. Combining marks are characters of their own, so one may be cut from the
+    /// character it follows.
+    ///
+    /// ```
+    /// # use mssql_rs::SqlParam;
+    /// let (param, truncated) = SqlParam::nvarchar_truncated("naïve 🦀", 7);
+    /// assert_eq!(param, SqlParam::String("naïve ".to_owned()));
+    /// assert!(truncated);
+    /// ```
+    pub fn nvarchar_truncated(value: &str, max_chars: usize) -> (Self, bool) {
+        let mut units = 0;
+        for (end, c) in value.char_indices() {
+            units += c.len_utf16();
+            if units > max_chars {
+                return (SqlParam::String(value[..end].to_owned()), true);
+            }
+        }
+        (SqlParam::String(value.to_owned()), false)
+    }
+
+    /// Create a decimal parameter with a fixed precision and scale.
+    ///
+    /// The value is rescaled to `scale`, and an error is returned if it would lose digits or exceed `precision`.
+    /// Note that tiberius declares a numeric parameter's precision from its value, so for a declared type
+    /// that doesn't vary between calls, combine this with
+    /// [`stable_param_types`](crate::SqlServerPoolBuilder::stable_param_types).
+    pub fn decimal_with(value: Numeric, precision: u8, scale: u8) -> Result<Self, Error> {
+        let invalid = || {
+            Error::InvalidArgument(format!(
+                "{} does not fit decimal({precision}, {scale})",
+                numeric_to_string(value)
+            ))
+        };
+
+        if precision == 0 || precision > 38 || scale > precision {
+            return Err(invalid());
+        }
+
+        let rescaled = if scale >= value.scale() {
+            10i128
+                .checked_pow((scale - value.scale()) as u32)
+                .and_then(|factor| value.value().checked_mul(factor))
+                .ok_or_else(invalid)?
+        } else {
+            let factor = 10i128.pow((value.scale() - scale) as u32);
+            if value.value() % factor != 0 {
+                return Err(invalid());
+            }
+            value.value() / factor
+        };
+
+        if rescaled.unsigned_abs() >= 10u128.pow(precision as u32) {
+            return Err(invalid());
+        }
+
+        Ok(SqlParam::Numeric(Numeric::new_with_scale(rescaled, scale)))
+    }
+}
+
+/// Bind a parameter to a query.
+///
+/// With `stable_types`, numerics are sent as text, so the declared parameter type is the same for every value
+/// (tiberius otherwise declares `numeric(p, s)` from each value's own precision).
+/// Strings and binaries need no normalisation, as tiberius already declares them as
+/// `nvarchar(4000)`/`varbinary(8000)`, or `max` past those lengths.
+pub(crate) fn bind_param<'a>(query: &mut Query<'a>, param: &'a SqlParam, stable_types: bool) {
+    match param {
+        SqlParam::Numeric(n) if stable_types => query.bind(numeric_to_string(*n)),
+        param => query.bind(param),
+    }
+}
+
+/// Format a numeric as decimal text, e.g. `-1.05`.
+fn numeric_to_string(n: Numeric) -> String {
+    let scale = n.scale() as usize;
+    let digits = format!("{:0>width$}", n.value().unsigned_abs(), width = scale + 1);
+    let (int_part, dec_part) = digits.split_at(digits.len() - scale);
+    let sign = if n.value() < 0 { "-" } else { "" };
+
+    if scale == 0 {
+        format!("{sign}{int_part}")
+    } else {
+        format!("{sign}{int_part}.{dec_part}")
+    }
+}
+
+impl ToSql for SqlParam {
+    fn to_sql(&self) -> ColumnData<'_> {
+        match self {
+            SqlParam::Null => ColumnData::String(None),
+            SqlParam::Bool(v) => ColumnData::Bit(Some(*v)),
+            SqlParam::U8(v) => ColumnData::U8(Some(*v)),
+            SqlParam::I16(v) => ColumnData::I16(Some(*v)),
+            SqlParam::I32(v) => ColumnData::I32(Some(*v)),
+            SqlParam::I64(v) => ColumnData::I64(Some(*v)),
+            SqlParam::F32(v) => ColumnData::F32(Some(*v)),
+            SqlParam::F64(v) => ColumnData::F64(Some(*v)),
+            SqlParam::String(v) => ColumnData::String(Some(Cow::Borrowed(v))),
+            SqlParam::Binary(v) => ColumnData::Binary(Some(Cow::Borrowed(v))),
+            SqlParam::Numeric(v) => ColumnData::Numeric(Some(*v)),
+        }
+    }
+}
+
+impl From<&SqlParam> for ColumnData<'static> {
+    fn from(param: &SqlParam) -> Self {
+        match param.to_sql() {
+            ColumnData::String(v) => ColumnData::String(v.map(|v| Cow::Owned(v.into_owned()))),
+            ColumnData::Binary(v) => ColumnData::Binary(v.map(|v| Cow::Owned(v.into_owned()))),
+            ColumnData::U8(v) => ColumnData::U8(v),
+            ColumnData::I16(v) => ColumnData::I16(v),
+            ColumnData::I32(v) => ColumnData::I32(v),
+            ColumnData::I64(v) => ColumnData::I64(v),
+            ColumnData::F32(v) => ColumnData::F32(v),
+            ColumnData::F64(v) => ColumnData::F64(v),
+            ColumnData::Bit(v) => ColumnData::Bit(v),
+            ColumnData::Numeric(v) => ColumnData::Numeric(v),
+            _ => unreachable!("SqlParam only converts to the types above"),
+        }
+    }
+}
+
+/// Fails with [`Error::InvalidArgument`] for types that have no `SqlParam` variant.
+impl TryFrom<ColumnData<'static>> for SqlParam {
+    type Error = Error;
+
+    fn try_from(value: ColumnData<'static>) -> Result<Self, Self::Error> {
+        Ok(match value {
+            ColumnData::Bit(Some(v)) => SqlParam::Bool(v),
+            ColumnData::U8(Some(v)) => SqlParam::U8(v),
+            ColumnData::I16(Some(v)) => SqlParam::I16(v),
+            ColumnData::I32(Some(v)) => SqlParam::I32(v),
+            ColumnData::I64(Some(v)) => SqlParam::I64(v),
+            ColumnData::F32(Some(v)) => SqlParam::F32(v),
+            ColumnData::F64(Some(v)) => SqlParam::F64(v),
+            ColumnData::String(Some(v)) => SqlParam::String(v.into_owned()),
+            ColumnData::Binary(Some(v)) => SqlParam::Binary(v.into_owned()),
+            ColumnData::Numeric(Some(v)) => SqlParam::Numeric(v),
+            ColumnData::Bit(None)
+            | ColumnData::U8(None)
+            | ColumnData::I16(None)
+            | ColumnData::I32(None)
+            | ColumnData::I64(None)
+            | ColumnData::F32(None)
+            | ColumnData::F64(None)
+            | ColumnData::String(None)
+            | ColumnData::Binary(None)
+            | ColumnData::Numeric(None) => SqlParam::Null,
+            value => {
+                return Err(Error::InvalidArgument(format!(
+                    "{value:?} can't be bound as a parameter"
+                )))
+            }
+        })
+    }
+}
+
+impl<'a> IntoSql<'a> for &'a SqlParam {
+    fn into_sql(self) -> ColumnData<'a> {
+        self.to_sql()
+    }
+}
+
+macro_rules! from_value {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for SqlParam {
+                fn from(value: $ty) -> Self {
+                    SqlParam::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+from_value!(
+    bool => Bool,
+    u8 => U8,
+    i16 => I16,
+    i32 => I32,
+    i64 => I64,
+    f32 => F32,
+    f64 => F64,
+    String => String,
+    &str => String,
+    Vec<u8> => Binary,
+    &[u8] => Binary,
+    Numeric => Numeric,
+);
+
+impl<T> From<Option<T>> for SqlParam
+where
+    T: Into<SqlParam>,
+{
+    fn from(value: Option<T>) -> Self {
+        value.map_or(SqlParam::Null, Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truncated(value: &str, max_chars: usize) -> (String, bool) {
+        match SqlParam::nvarchar_truncated(value, max_chars) {
+            (SqlParam::String(s), truncated) => (s, truncated),
+            (param, _) => panic!("unexpected {param:?}"),
+        }
+    }
+
+    #[test]
+    fn nvarchar_truncated_counts_utf16_units() {
+        assert_eq!(truncated("", 0), (String::new(), false));
+        assert_eq!(truncated("abc", 3), ("abc".to_owned(), false));
+        assert_eq!(truncated("abcd", 3), ("abc".to_owned(), true));
+        // é is one unit despite being two UTF-8 bytes.
+        assert_eq!(truncated("éé", 2), ("éé".to_owned(), false));
+        assert_eq!(truncated("ééé", 2), ("éé".to_owned(), true));
+    }
+
+    #[test]
+    fn nvarchar_truncated_keeps_surrogate_pairs_whole() {
+        // Each emoji is a surrogate pair, two units.
+        assert_eq!(truncated("😀😀", 4), ("😀😀".to_owned(), false));
+        assert_eq!(truncated("😀😀", 3), ("😀".to_owned(), true));
+        assert_eq!(truncated("a😀", 2), ("a".to_owned(), true));
+        assert_eq!(truncated("😀", 1), (String::new(), true));
+        // A flag is two regional indicators, each a surrogate pair, so it can be cut between them.
+        assert_eq!(truncated("🇫🇷", 2), ("🇫".to_owned(), true));
+    }
+
+    #[test]
+    fn nvarchar_truncated_cuts_combining_marks_separately() {
+        // e followed by a combining acute accent is two characters.
+        assert_eq!(truncated("cafe\u{301}", 4), ("cafe".to_owned(), true));
+        assert_eq!(
+            truncated("cafe\u{301}", 5),
+            ("cafe\u{301}".to_owned(), false)
+        );
+    }
+}
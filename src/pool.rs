@@ -1,23 +1,140 @@
+#[cfg(feature = "codegen")]
+use crate::codegen::{self, SchemaSnapshot, TableSchema};
+#[cfg(feature = "test-util")]
+use crate::fault::FaultInjector;
+#[cfg(feature = "protocol-debug")]
+use crate::trace::ProtocolTrace;
 use crate::{
-    error::Error,
-    manager::{ConnectionManager, ConnectionManagerBuilder},
+    audit::{current_actor, AuditAssignments, AuditConfig, AuditTimestamp, Auditor},
+    chunks::Chunks,
+    clock::{Clock, TokioClock},
+    codec::{ColumnCodec, ColumnCodecs},
+    connection::{Client, PooledConnection, Priority},
+    credentials::CredentialsProvider,
+    csv::{
+        BadRowPolicy, CsvImportOptions, CsvReader, ImportColumn, ImportStats, RejectedRow,
+        IMPORT_COLUMNS_QUERY,
+    },
+    error::{Error, PartialError},
+    fault::{FaultPoint, Faults},
+    limiter::InFlightLimiter,
+    manager::{
+        ConnectionAges, ConnectionManager, ConnectionManagerBuilder, DEFAULT_YIELD_EVERY_ROWS,
+        VALIDATION_QUERY,
+    },
+    metered::{ByteCount, ByteCounters},
+    named_params::{bind_named, ToSqlParams},
+    observer::{BrokenReason, ConnectionObserver, OnBroken},
+    options::QueryOptions,
+    param::{bind_param, SqlParam},
+    query::{
+        begin_exchange, collect_rows_on, execute_on, find_row_on, for_each_batch_on,
+        for_each_json_element_on, for_each_row_async_on, for_each_row_on, json_query_on,
+        query_rows_limited_on, run_with_options, simple_query_on, GroupedRows,
+    },
+    rate_limit::{FingerprintPattern, Rate, RateLimiter},
+    resumable::{self, ResumableQuery, ResumeOptions},
+    row::{hide_trailing_columns, value_at, FromSqlValue},
+    scoped_config::{ScopedConfigKey, PERMISSION_ERRORS, SCOPED_CONFIG_QUERY},
+    session_options::{
+        current_lock_settings, decode_options, DeadlockPriority, LockSettings,
+        SessionOptionsPreset, SESSION_OPTIONS_QUERY,
+    },
+    snapshot::SnapshotReader,
+    sql::{
+        check_sql, has_keyword,
+        lexer::{self, TokenKind},
+        quote_identifier, quote_object_name, split_script, ObjectName,
+    },
+    sync::{self, SyncOptions, SyncStats},
+    temp_table::{key_type, TempColumn, TempTable},
+    transform::RowTransformer,
+    truncation::{TruncationPolicy, Truncator},
+    validator::{ValidationStats, Validator},
+    value::{DynamicRow, SqlValue},
+    version::ServerVersion,
+    write_behind::{
+        self, Target as WriteBehindTarget, WriteBehindOptions, WriteBehindQueue, WriteBehindStats,
+    },
     TryFromRow,
 };
-use futures_util::{Stream, TryStreamExt};
+use futures_util::future::{join_all, BoxFuture};
+use futures_util::{Future, Stream};
 use serde::de::DeserializeOwned;
-use tiberius::{Query, QueryItem};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tiberius::{Column, ColumnData, EncryptionLevel, Query, Row, TokenRow};
+use tokio::io::AsyncRead;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
 
 /// An abstraction over a SQL Server connection pool.
 #[derive(Debug)]
 pub struct SqlServerPool {
-    inner: bb8::Pool<ConnectionManager>,
+    sized: Arc<RwLock<Arc<SizedPool>>>,
+    factory: Arc<PoolFactory>,
+    stable_param_types: bool,
+    output_into_tables: Arc<Mutex<HashSet<String>>>,
+    #[cfg(feature = "protocol-debug")]
+    protocol_trace: Option<ProtocolTrace>,
+    resume_timeout: Option<Duration>,
+    resuming: Arc<AtomicBool>,
+    transformers: Arc<[Arc<dyn RowTransformer>]>,
+    readiness_query: Option<Arc<str>>,
+    codecs: Arc<ColumnCodecs>,
+    fetch_buffer_rows: usize,
+    validation_stats: Arc<ValidationStats>,
+    in_flight: Option<Arc<InFlightLimiter>>,
+    snapshot_fallback: bool,
+    max_query_length: Option<usize>,
+    max_rows: Option<usize>,
+    ages: Arc<ConnectionAges>,
+    auditor: Option<Arc<Auditor>>,
+    truncator: Option<Arc<Truncator>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    lock_settings: LockSettings,
+    faults: Faults,
+    clock: Arc<dyn Clock>,
+    write_behind: Arc<WriteBehindStats>,
+    bytes: Arc<ByteCounters>,
 }
 
 /// Cloning is cheap, as the pool internals are behind an Arc. Each clone refers to the same pool.
 impl Clone for SqlServerPool {
     fn clone(&self) -> Self {
         Self {
-            inner: self.inner.clone(),
+            sized: self.sized.clone(),
+            factory: self.factory.clone(),
+            stable_param_types: self.stable_param_types,
+            output_into_tables: self.output_into_tables.clone(),
+            #[cfg(feature = "protocol-debug")]
+            protocol_trace: self.protocol_trace.clone(),
+            resume_timeout: self.resume_timeout,
+            resuming: self.resuming.clone(),
+            transformers: self.transformers.clone(),
+            readiness_query: self.readiness_query.clone(),
+            codecs: self.codecs.clone(),
+            fetch_buffer_rows: self.fetch_buffer_rows,
+            validation_stats: self.validation_stats.clone(),
+            in_flight: self.in_flight.clone(),
+            snapshot_fallback: self.snapshot_fallback,
+            max_query_length: self.max_query_length,
+            max_rows: self.max_rows,
+            ages: self.ages.clone(),
+            auditor: self.auditor.clone(),
+            truncator: self.truncator.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            observer: self.observer.clone(),
+            lock_settings: self.lock_settings,
+            faults: self.faults.clone(),
+            clock: self.clock.clone(),
+            write_behind: self.write_behind.clone(),
+            bytes: self.bytes.clone(),
         }
     }
 }
@@ -30,21 +147,527 @@ impl SqlServerPool {
         SqlServerPoolBuilder::new().build(config).await
     }
 
+    /// Read the effective `SET` options of a pooled connection, keyed by option name, e.g. `ARITHABORT`.
+    ///
+    /// Compare them with the output of `DBCC USEROPTIONS` in SSMS when a query is fast there but slow from the
+    /// application, see [`SessionOptionsPreset`] for the options that affect plan choice. They are read from
+    /// `@@OPTIONS`, so they include every option set with `SET`, as on or off.
+    pub async fn session_options(&self) -> Result<HashMap<String, bool>, Error> {
+        self.session_options_with_options(&QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::session_options`] with per-query options.
+    pub async fn session_options_with_options(
+        &self,
+        options: &QueryOptions,
+    ) -> Result<HashMap<String, bool>, Error> {
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            let results = simple_query_on(conn, SESSION_OPTIONS_QUERY).await?;
+            let row = results
+                .first()
+                .and_then(|rows| rows.first())
+                .ok_or(Error::EmptyResult)?;
+            let options: i32 = row.try_get(0)?.unwrap_or_default();
+            Ok(decode_options(options))
+        })
+        .await
+    }
+
+    /// Read the current database's scoped configuration, e.g. `MAXDOP`, along with its `COMPATIBILITY_LEVEL`,
+    /// keyed by name. Requires SQL Server 2016 or later.
+    ///
+    /// Values are as the server reports them, e.g. `"0"` for `MAXDOP` or `"1"` for a setting that is on.
+    pub async fn database_scoped_config(&self) -> Result<HashMap<String, String>, Error> {
+        self.database_scoped_config_with_options(&QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::database_scoped_config`] with per-query options.
+    pub async fn database_scoped_config_with_options(
+        &self,
+        options: &QueryOptions,
+    ) -> Result<HashMap<String, String>, Error> {
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            conn.server_version().require(
+                ServerVersion::SQL_SERVER_2016,
+                "database scoped configuration",
+            )?;
+            let results = simple_query_on(conn, SCOPED_CONFIG_QUERY).await?;
+            results
+                .first()
+                .into_iter()
+                .flatten()
+                .map(|row| {
+                    let name: &str = row.try_get(0)?.unwrap_or_default();
+                    let value: &str = row.try_get(1)?.unwrap_or_default();
+                    Ok((name.to_owned(), value.to_owned()))
+                })
+                .collect()
+        })
+        .await
+    }
+
+    /// Change a setting of the current database, e.g. for automated provisioning. Requires SQL Server 2016 or later.
+    ///
+    /// Only the settings of [`ScopedConfigKey`] can be changed, and `value` must be one the key accepts, or
+    /// [`Error::InvalidArgument`] is returned before anything is sent. A denied permission is reported as
+    /// [`Error::PermissionDenied`], naming the permission the setting requires.
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{ScopedConfigKey, SqlServerPool};
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// sql_server.set_database_scoped_config(ScopedConfigKey::MaxDop, "4").await?;
+    /// sql_server
+    ///     .set_database_scoped_config(ScopedConfigKey::LegacyCardinalityEstimation, "OFF")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_database_scoped_config(
+        &self,
+        key: ScopedConfigKey,
+        value: &str,
+    ) -> Result<(), Error> {
+        self.set_database_scoped_config_with_options(key, value, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::set_database_scoped_config`] with per-query options.
+    pub async fn set_database_scoped_config_with_options(
+        &self,
+        key: ScopedConfigKey,
+        value: &str,
+        options: &QueryOptions,
+    ) -> Result<(), Error> {
+        let statement = key.statement(value)?;
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            conn.server_version().require(
+                ServerVersion::SQL_SERVER_2016,
+                "database scoped configuration",
+            )?;
+            let result = simple_query_on(conn, &statement).await.map(drop);
+            result.map_err(|e| match e.server_code() {
+                Some(code) if PERMISSION_ERRORS.contains(&code) => Error::PermissionDenied {
+                    setting: key.name(),
+                    permission: key.permission(),
+                    source: Box::new(e),
+                },
+                _ => e,
+            })
+        })
+        .await
+    }
+
     /// Returns true if a connection is successfully returned from the pool
     pub async fn connection_ok(&self) -> bool {
-        self.inner.get().await.is_ok()
+        self.sized().pool.get().await.is_ok()
+    }
+
+    /// Check that the database is ready to serve the application, by running the readiness query.
+    ///
+    /// Unlike the validation query, the readiness query (see [`SqlServerPoolBuilder::readiness_query`]) can exercise
+    /// the application's own schema, so a missing, locked or inaccessible table is reported as not ready.
+    /// Without a readiness query this runs the validation query. Any rows are read and discarded.
+    pub async fn ready(&self) -> Result<(), Error> {
+        self.ready_with_options(&QueryOptions::default()).await
+    }
+
+    /// Run [`SqlServerPool::ready`] with per-query options.
+    pub async fn ready_with_options(&self, options: &QueryOptions) -> Result<(), Error> {
+        let query = self.readiness_query.as_deref().unwrap_or(VALIDATION_QUERY);
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            simple_query_on(conn, query).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Check whether the connection's login can read `object`, as a preflight check before a job that
+    /// spans several databases.
+    ///
+    /// Returns true if the object exists and the login has `SELECT` permission on it, checked with `OBJECT_ID`
+    /// and `HAS_PERMS_BY_NAME`. An object in a database the login can't open is reported as false.
+    /// For a linked server name only the linked server's definition is checked, not the remote object,
+    /// as the server can't resolve remote objects locally.
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{sql::ObjectName, SqlServerPool};
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let orders = ObjectName::new().database("Sales").schema("dbo").object("Orders");
+    /// if !sql_server.can_access(&orders).await? {
+    ///     eprintln!("skipping {}", orders.quoted()?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn can_access(&self, object: &ObjectName) -> Result<bool, Error> {
+        self.can_access_with_options(object, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::can_access`] with per-query options.
+    pub async fn can_access_with_options(
+        &self,
+        object: &ObjectName,
+        options: &QueryOptions,
+    ) -> Result<bool, Error> {
+        let quoted = object.quoted()?;
+        let query = match object.server_name() {
+            Some(server) => {
+                let mut query = Query::new(LINKED_SERVER_ACCESS_QUERY);
+                query.bind(server.to_owned());
+                query
+            }
+            None => {
+                let mut query = Query::new(OBJECT_ACCESS_QUERY);
+                query.bind(quoted);
+                query
+            }
+        };
+
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            let row = query
+                .query(conn)
+                .await?
+                .into_row()
+                .await?
+                .ok_or(Error::EmptyResult)?;
+            Ok(row.get::<bool, _>(0).unwrap_or(false))
+        })
+        .await
+    }
+
+    /// Validate every connection the pool can hold, not just one.
+    ///
+    /// `pool_max_size` connections are checked out concurrently, taking every idle connection and opening new ones
+    /// up to the pool's size, and all of them are validated concurrently with the validation query, so the check
+    /// takes about as long as the slowest connection rather than the sum of them. Connections that fail are
+    /// discarded rather than returned to the pool. Every connection is held until all are validated, so each
+    /// connection of the pool is validated once, unless another caller holds it past the connection timeout, when
+    /// its checkout counts as a failure. The checkouts bypass [`SqlServerPoolBuilder::max_in_flight`] and the
+    /// priority limits, which would otherwise let them wait on each other. The connections of the
+    /// [affinity shards](SqlServerPoolBuilder::affinity_shards) are validated along with the main pool's.
+    ///
+    /// As this holds every connection for the duration of the check, it is intended as a deploy-time smoke test
+    /// rather than a regular health check.
+    pub async fn validate_all(&self) -> Result<(), Error> {
+        let sized = self.sized();
+        let pools =
+            std::iter::repeat_n(&sized.pool, sized.max_size as usize).chain(&sized.affinity);
+        let total = sized.max_size as usize + sized.affinity.len();
+        let checkouts = pools.map(|pool| async {
+            let conn = pool.get_owned().await?;
+            Ok::<_, Error>(PooledConnection::new(conn, None, None, self.faults.clone()))
+        });
+
+        let mut conns = Vec::with_capacity(total);
+        let mut failed = 0;
+        let mut first_error = None;
+        for checkout in join_all(checkouts).await {
+            match checkout {
+                Ok(conn) => conns.push(conn),
+                Err(e) => {
+                    failed += 1;
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        let validations = conns.iter_mut().map(|conn| async move {
+            let result = conn.simple_query(VALIDATION_QUERY).await.map(drop);
+            if result.is_err() {
+                conn.mark_broken_because(BrokenReason::ValidationFailed);
+            }
+            result
+        });
+        for result in join_all(validations).await {
+            if let Err(e) = result {
+                failed += 1;
+                first_error.get_or_insert(e.into());
+            }
+        }
+
+        match first_error {
+            Some(source) => Err(Error::ValidationFailed {
+                failed,
+                total,
+                source: Box::new(source),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check out a connection from the pool with the given priority.
+    ///
+    /// Low priority requests are limited to `pool_max_size - high_priority_reserve` concurrent connections
+    /// (but always at least one), so the remaining connections stay available to high priority requests under saturation.
+    /// The connection is returned to the pool when the [`PooledConnection`] is dropped.
+    ///
+    /// With [`SqlServerPoolBuilder::max_in_flight`], fails with [`Error::Overloaded`] if the queue is full.
+    pub async fn get_with_priority(
+        &self,
+        priority: Priority,
+    ) -> Result<PooledConnection<'_>, Error> {
+        self.inject_checkout_fault().await?;
+        let in_flight = self.acquire_in_flight().await?;
+        let sized = self.sized();
+        let permit = match priority {
+            Priority::High => None,
+            Priority::Low => Some(
+                sized
+                    .low_priority
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("low priority semaphore is never closed"),
+            ),
+        };
+
+        let conn = self.checkout_owned(&sized.pool).await?;
+        let mut conn = PooledConnection::new(conn, permit, in_flight, self.faults.clone());
+        conn.apply_lock_settings(self.lock_settings()).await?;
+        Ok(conn)
+    }
+
+    /// Check out a connection from the affinity shard for `key`, see [`SqlServerPoolBuilder::affinity_shards`], or
+    /// from the main pool if there are no shards.
+    async fn get_affine(&self, key: u64) -> Result<PooledConnection<'_>, Error> {
+        let sized = self.sized();
+        if sized.affinity.is_empty() {
+            return self.get().await;
+        }
+
+        let shard = &sized.affinity[(key % sized.affinity.len() as u64) as usize];
+        self.inject_checkout_fault().await?;
+        let in_flight = self.acquire_in_flight().await?;
+        let conn = self.checkout_owned(shard).await?;
+        let mut conn = PooledConnection::new(conn, None, in_flight, self.faults.clone());
+        conn.apply_lock_settings(self.lock_settings()).await?;
+        Ok(conn)
+    }
+
+    /// Check out a connection from `pool`, the main pool or a shard, waiting for a resuming database.
+    async fn checkout_owned(
+        &self,
+        pool: &bb8::Pool<ConnectionManager>,
+    ) -> Result<bb8::PooledConnection<'static, ConnectionManager>, Error> {
+        let start = self.clock.now();
+        loop {
+            // An owned connection keeps its pool alive, so one checked out before a reconfigure can still be returned.
+            match pool.get_owned().await {
+                Err(bb8::RunError::TimedOut) if self.waiting_for_resume(start) => continue,
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    /// The locking settings for a checkout: those of the [`QueryOptions`] of the call running, if any, else the pool's.
+    fn lock_settings(&self) -> LockSettings {
+        current_lock_settings().unwrap_or(self.lock_settings)
+    }
+
+    /// Run a query method's work under `options`, over the pool's clock and locking settings, see
+    /// [`query::run_with_options`](run_with_options). `statements` are checked against the column codecs and
+    /// admitted first, within the timeout, see [`ColumnCodec`] and [`SqlServerPool::admit`].
+    async fn run_with_options<R>(
+        &self,
+        statements: &[&str],
+        options: &QueryOptions,
+        work: impl Future<Output = Result<R, Error>>,
+    ) -> Result<R, Error> {
+        run_with_options(&*self.clock, self.lock_settings, options, async {
+            for statement in statements {
+                self.codecs.check_statement(statement)?;
+                self.admit(statement).await?;
+            }
+            work.await
+        })
+        .await
+    }
+
+    /// Run a query method's `work` on a connection checked out for `plan`, under `options`.
+    ///
+    /// Every query method runs through here, or through [`SqlServerPool::run_with_options`] if it checks out
+    /// connections of its own, so each admits its statements, honours the timeout and locking settings of its
+    /// [`QueryOptions`] and checks out its connection the same way.
+    async fn execute_internal<R>(
+        &self,
+        plan: QueryPlan<'_>,
+        options: &QueryOptions,
+        work: impl AsyncFnOnce(&mut PooledConnection<'_>) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        self.run_with_options(plan.statements, options, async {
+            let start = self.clock.now();
+            let mut conn = match plan.source {
+                ConnectionSource::Pool(priority) => self.get_with_priority(priority).await?,
+                ConnectionSource::Affine(key) => self.get_affine(key).await?,
+            };
+            if let Some(acquire) = plan.acquire {
+                *acquire = self.clock.elapsed(start);
+            }
+            work(&mut conn).await
+        })
+        .await
+    }
+
+    /// Consult the fault injector before a checkout. There is no connection to kill yet, so any fault only fails.
+    async fn inject_checkout_fault(&self) -> Result<(), Error> {
+        self.faults
+            .inject(FaultPoint::Checkout)
+            .await
+            .map_err(|fault| fault.error)
+    }
+
+    /// Take an in-flight slot, if the pool limits them.
+    async fn acquire_in_flight(&self) -> Result<Option<OwnedSemaphorePermit>, Error> {
+        match &self.in_flight {
+            Some(limiter) => limiter.acquire().await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns whether parameters are bound with stable types, see [`SqlServerPoolBuilder::stable_param_types`].
+    pub(crate) fn stable_param_types(&self) -> bool {
+        self.stable_param_types
+    }
+
+    /// Returns the pool's clock, see [`SqlServerPoolBuilder::clock`].
+    pub(crate) fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    /// Whether a checkout that started at `start` should keep waiting for a resuming database.
+    fn waiting_for_resume(&self, start: Instant) -> bool {
+        self.resume_timeout
+            .is_some_and(|timeout| self.clock.elapsed(start) < timeout)
+            && self.resuming.load(Ordering::Relaxed)
+    }
+
+    /// The delay before retrying after `error`: the one the server suggested, see [`Error::throttle_retry_after`], or
+    /// else `backoff`, at most `max` either way.
+    pub(crate) fn retry_delay(&self, error: &Error, backoff: Duration, max: Duration) -> Duration {
+        match error.throttle_retry_after() {
+            Some(suggested) => {
+                let delay = suggested.min(max);
+                if let Some(observer) = &self.observer {
+                    observer.on_throttled(suggested, delay);
+                }
+                delay
+            }
+            None => backoff.min(max),
+        }
+    }
+
+    /// Check `query` may run before checking out a connection for it, failing with [`Error::EmptySql`],
+    /// [`Error::NulInSql`], [`Error::QueryTooLong`] or [`Error::RateLimited`], see
+    /// [`SqlServerPoolBuilder::max_query_length`] and [`SqlServerPoolBuilder::query_rate_limit`].
+    pub(crate) async fn admit(&self, query: &str) -> Result<(), Error> {
+        check_sql(query)?;
+        self.check_query_length(query)?;
+        match &self.rate_limiter {
+            Some(limiter) => limiter.acquire(&*self.clock, query).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the number of queries each fingerprint has had rate limited by
+    /// [`SqlServerPoolBuilder::query_rate_limit`], for the fingerprints the pool is tracking.
+    pub fn query_throttle_counts(&self) -> HashMap<String, u64> {
+        self.rate_limiter
+            .as_ref()
+            .map(|limiter| limiter.throttled())
+            .unwrap_or_default()
+    }
+
+    /// Fail with [`Error::QueryTooLong`] if `query` exceeds [`SqlServerPoolBuilder::max_query_length`].
+    fn check_query_length(&self, query: &str) -> Result<(), Error> {
+        match self.max_query_length {
+            Some(limit) if query.len() > limit => Err(Error::QueryTooLong {
+                len: query.len(),
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check out a connection from the pool for a query.
+    pub(crate) async fn get(&self) -> Result<PooledConnection<'_>, Error> {
+        self.get_with_priority(Priority::High).await
+    }
+
+    /// Returns the maximum number of connections in the pool, see [`SqlServerPoolBuilder::pool_max_size`].
+    pub fn max_size(&self) -> u32 {
+        self.sized().max_size
+    }
+
+    /// Resize the pool to `new_max` connections, e.g. as traffic patterns change, without restarting.
+    ///
+    /// bb8 can't resize a pool, so this builds a new main pool of the new size and swaps it in for every clone of
+    /// this pool. Connections checked out of the old pool stay usable and are closed as they are returned, and its
+    /// idle connections are closed once those are. The low priority limit is recalculated from
+    /// [`SqlServerPoolBuilder::high_priority_reserve`], and background validation moves to the new pool.
+    /// The [affinity shards](SqlServerPoolBuilder::affinity_shards) are rebuilt the same way, scaled with the pool
+    /// to keep their ratio to its size, rounding up, so keys may move to other shards. Every other setting is
+    /// unchanged. Fails with [`Error::InvalidConfig`] if `new_max` is 0, or with the connection error if the new pool
+    /// can't be built, keeping the old one.
+    pub async fn reconfigure(&self, new_max: u32) -> Result<(), Error> {
+        let sized = self.factory.build(new_max).await?;
+        *self.sized.write().expect("sized pool lock poisoned") = Arc::new(sized);
+        Ok(())
+    }
+
+    /// Returns the main pool, as last sized by [`SqlServerPool::reconfigure`].
+    fn sized(&self) -> Arc<SizedPool> {
+        self.sized.read().expect("sized pool lock poisoned").clone()
     }
 
     /// Returns the state of the pool, which includes the number of idle and total connections.
     pub fn pool_state(&self) -> bb8::State {
-        self.inner.state()
+        self.sized().pool.state()
+    }
+
+    /// Returns the state of the pool along with the results of background validation.
+    pub fn status(&self) -> PoolStatus {
+        let state = self.sized().pool.state();
+        PoolStatus {
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            last_validation: self.validation_stats.last(),
+            validation_failures: self.validation_stats.failures(),
+            in_flight: self.in_flight.as_ref().map_or(0, |l| l.in_flight()),
+            queued: self.in_flight.as_ref().map_or(0, |l| l.queued()),
+            write_behind_queued: self.write_behind.queued(),
+            write_behind_batches: self.write_behind.batches(),
+            write_behind_dropped: self.write_behind.dropped(),
+            bytes: self.bytes.get(),
+        }
+    }
+
+    /// Returns the ages of the idle connections, oldest first.
+    ///
+    /// Many young connections point to churn, e.g. connections discarded as broken, while old ones show how long
+    /// connections live before [`SqlServerPoolBuilder::max_lifetime`] recycles them.
+    pub fn connection_ages(&self) -> Vec<Duration> {
+        self.ages.idle()
+    }
+
+    /// Returns the protocol trace set with [`SqlServerPoolBuilder::protocol_trace`], if any.
+    ///
+    /// After a protocol error, the trace holds the most recent packet headers, ready to attach to a bug report.
+    #[cfg(feature = "protocol-debug")]
+    pub fn protocol_trace(&self) -> Option<&ProtocolTrace> {
+        self.protocol_trace.as_ref()
     }
 
     /// Run a JSON query (e.g. SELECT ... FOR JSON PATH;) and return the result as a serde deserializable object.
+    /// FOR JSON requires SQL Server 2016 or later.
     ///
     /// # Example
     ///
-    /// ```
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
     /// let sql_server = SqlServerPool::new(cfg).await?;
     ///
     /// #[derive(serde::Deserialize)]
@@ -56,46 +679,226 @@ impl SqlServerPool {
     /// let query = "SELECT id, name FROM people FOR JSON PATH;";
     ///
     /// let rows = sql_server.json_query::<Vec<Person>>(query, &[]).await?;
+    /// # Ok(())
+    /// # }
     /// ```
     pub async fn json_query<T>(&self, query: &str, params: &[String]) -> Result<T, Error>
     where
         T: DeserializeOwned,
     {
-        let mut select = Query::new(query);
-        for param in params {
-            select.bind(param);
-        }
+        self.json_query_with_options(query, params, &QueryOptions::default())
+            .await
+    }
 
-        let mut conn = self.inner.get().await?;
-        let mut stream = select.query(&mut conn).await?;
+    /// Run [`SqlServerPool::json_query`] with per-query options.
+    pub async fn json_query_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            json_query_on(conn, query, params).await
+        })
+        .await
+    }
 
-        let size = stream.size_hint().1.unwrap_or(0);
-        let mut json_buffer = String::with_capacity(size);
+    /// Run a JSON array query (e.g. SELECT ... FOR JSON PATH;) and stream the elements of the array as they are
+    /// parsed, rather than deserializing the whole array at once like [`SqlServerPool::json_query`].
+    ///
+    /// Only the current element and the current chunk of the result are held in memory, so any size of array can be
+    /// streamed. The query runs on a spawned task, reading ahead by at most one chunk's elements, and stops when
+    /// the stream is dropped. An empty result is an empty stream. Malformed JSON, or an element that doesn't
+    /// deserialize to `T`, ends the stream with [`Error::JsonStream`], giving the approximate byte offset.
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # use futures_util::TryStreamExt;
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// #[derive(serde::Deserialize)]
+    /// struct Event {
+    ///    id: i64,
+    ///    kind: String,
+    /// }
+    ///
+    /// let events = sql_server.json_stream::<Event>("SELECT id, kind FROM events FOR JSON PATH;", &[]);
+    /// futures_util::pin_mut!(events);
+    /// while let Some(event) = events.try_next().await? {
+    ///     println!("{} {}", event.id, event.kind);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn json_stream<T>(
+        &self,
+        query: &str,
+        params: &[String],
+    ) -> impl Stream<Item = Result<T, Error>> + Send + 'static
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        self.json_stream_with_options(query, params, &QueryOptions::default())
+    }
 
-        while let Some(item) = stream.try_next().await? {
-            if let QueryItem::Row(row) = item {
-                if let Some(partial) = row.get(0) {
-                    json_buffer.push_str(partial);
-                }
+    /// Run [`SqlServerPool::json_stream`] with per-query options. [`QueryOptions::timeout`] limits the whole
+    /// stream, including the time the consumer takes, and ends it with [`Error::QueryTimeout`].
+    pub fn json_stream_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> impl Stream<Item = Result<T, Error>> + Send + 'static
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        let pool = self.clone();
+        let query = query.to_owned();
+        let params = params.to_vec();
+        let options = options.clone();
+        tokio::spawn(async move {
+            let query = query.as_str();
+            let plan = QueryPlan::query(&query);
+            let result = pool
+                .execute_internal(plan, &options, async |conn| {
+                    let result = for_each_json_element_on(conn, query, &params, |element| {
+                        let sender = &sender;
+                        async move {
+                            // A closed channel means the stream was dropped, so stop reading.
+                            sender.send(Ok(element)).await.map_err(|_| Error::Abandoned)
+                        }
+                    })
+                    .await;
+                    if sender.is_closed() {
+                        // The rest of the result was never read, so don't make the next query drain it.
+                        conn.mark_broken_because(BrokenReason::Abandoned);
+                    }
+                    result
+                })
+                .await;
+            if let Err(e) = result {
+                let _ = sender.send(Err(e)).await;
             }
-        }
+        });
 
-        if json_buffer.is_empty() {
-            // Return an error if the result set is empty, as this won't be valid JSON.
-            // This error should be semantically different from a failure to parse.
-            return Err(Error::EmptyResult);
-        }
+        futures_util::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        })
+    }
+
+    /// Stream the rows of a keyset-ordered query, resuming on a new connection after the last key read if the
+    /// connection fails partway, e.g. for a long export over a flaky network.
+    ///
+    /// The query must return its rows ordered by the unique `key_column`, and its last placeholder, after `params`,
+    /// must be the last key read, which is NULL the first time. Resuming reruns the query with the last key sent,
+    /// so it must skip the rows up to and including that key, as with
+    /// `WHERE @P1 IS NULL OR id > @P1 ORDER BY id`. The key is bound as a string, for the server to convert back, and
+    /// must be a non-NULL integer, decimal, `uniqueidentifier` or string; `uniqueidentifier` keys must be ordered the
+    /// way the server compares them. Rows are sent in order, each exactly once, as long as the keys are.
+    ///
+    /// Connection failures, checkout timeouts and throttling resume the query, up to [`ResumeOptions::max_retries`]
+    /// times in a row without reading a new row. Any other error, or the last failure once the retries run out, ends the stream.
+    /// Like [`SqlServerPool::json_stream`], the query runs on a spawned task, reading ahead by one row, and stops when
+    /// the stream is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{ResumeOptions, SqlServerPool, TryFromRow};
+    /// # use futures_util::TryStreamExt;
+    /// # struct Order;
+    /// # impl TryFromRow for Order {
+    /// #     fn try_from(_: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Order) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let orders = sql_server.resumable_stream::<Order>(
+    ///     "SELECT id, total FROM orders WHERE placed >= @P1 AND (@P2 IS NULL OR id > @P2) ORDER BY id;",
+    ///     &["2024-01-01".to_owned()],
+    ///     "id",
+    ///     ResumeOptions::default(),
+    /// );
+    /// futures_util::pin_mut!(orders);
+    /// while let Some(order) = orders.try_next().await? {
+    ///     // Write the order to the export.
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn resumable_stream<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        key_column: &str,
+        options: ResumeOptions,
+    ) -> impl Stream<Item = Result<T, Error>> + Send + 'static
+    where
+        T: TryFromRow + Send + 'static,
+    {
+        self.resumable_stream_with_options(
+            query,
+            params,
+            key_column,
+            options,
+            &QueryOptions::default(),
+        )
+    }
+
+    /// Run [`SqlServerPool::resumable_stream`] with per-query options. As with
+    /// [`SqlServerPool::json_stream_with_options`], [`QueryOptions::timeout`] limits the whole stream, including any
+    /// resumes.
+    pub fn resumable_stream_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        key_column: &str,
+        resume: ResumeOptions,
+        options: &QueryOptions,
+    ) -> impl Stream<Item = Result<T, Error>> + Send + 'static
+    where
+        T: TryFromRow + Send + 'static,
+    {
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        let pool = self.clone();
+        let resumable = ResumableQuery {
+            query: query.to_owned(),
+            params: params.to_vec(),
+            key_column: key_column.to_owned(),
+            options: resume,
+        };
+        let options = options.clone();
+        tokio::spawn(async move {
+            let query = resumable.query.clone();
+            let result = pool
+                .run_with_options(&[&query], &options, async {
+                    resumable::run(pool.clone(), resumable, sender.clone()).await;
+                    Ok(())
+                })
+                .await;
+            if let Err(e) = result {
+                let _ = sender.send(Err(e)).await;
+            }
+        });
 
-        serde_json::from_str::<T>(&json_buffer).map_err(Into::into)
+        futures_util::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        })
     }
 
     /// Run a SQL query and return the result as Vec<T>.
     ///
     /// T must implement the [`TryFromRow`] trait, which specifies how to convert a [`tiberius::Row`] into T.
     ///
+    /// Parameters are bound positionally to the `@P1`, `@P2`, ... placeholders in the query. If the highest placeholder
+    /// doesn't match the number of parameters, [`Error::ParameterCountMismatch`] is returned before the query is sent.
+    ///
     /// # Example
     ///
-    /// ```
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, TryFromRow};
+    /// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
     /// let sql_server = SqlServerPool::new(cfg).await?;
     ///
     /// struct Person {
@@ -115,87 +918,3558 @@ impl SqlServerPool {
     /// let query = "SELECT id, name FROM people;";
     ///
     /// let rows = sql_server.row_query::<Person>(query, &[]).await;
+    /// # Ok(())
+    /// # }
     /// ```
     pub async fn row_query<T>(&self, query: &str, params: &[String]) -> Result<Vec<T>, Error>
     where
         T: TryFromRow,
     {
-        let mut select = Query::new(query);
-        for param in params {
-            select.bind(param);
-        }
+        self.row_query_with_options(query, params, &QueryOptions::default())
+            .await
+    }
 
-        let mut conn = self.inner.get().await?;
-        let mut stream = select.query(&mut conn).await?;
+    /// Run [`SqlServerPool::row_query`] with per-query options.
+    pub async fn row_query_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        let max_rows = options.max_rows.or(self.max_rows);
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            query_rows_limited_on(conn, query, params, max_rows).await
+        })
+        .await
+    }
 
-        let size = stream.size_hint().1.unwrap_or(0);
-        let mut buf = Vec::with_capacity(size);
+    /// Run [`SqlServerPool::row_query`], abandoning it with [`Error::Cancelled`] once `cancel` is cancelled, e.g.
+    /// when the HTTP request it serves is dropped.
+    ///
+    /// tiberius can't cancel a query midway, so a query cut short has its connection discarded rather than returned
+    /// to the pool, which also ends the query on the server. The pool replaces the connection as needed.
+    ///
+    /// [`cancel_on_drop`](crate::cancel_on_drop) bridges a token to a request, e.g. in a Tower middleware, so a
+    /// client disconnect stops the request's queries.
+    pub async fn row_query_cancellable<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        self.row_query_cancellable_with_options(query, params, &QueryOptions::default(), cancel)
+            .await
+    }
 
-        while let Some(item) = stream.try_next().await? {
-            if let QueryItem::Row(row) = item {
-                let value = T::try_from(row)?;
-                buf.push(value);
-            }
+    /// Run [`SqlServerPool::row_query_cancellable`] with per-query options.
+    pub async fn row_query_cancellable_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err(Error::Cancelled),
+            rows = self.row_query_with_options(query, params, options) => rows,
         }
-
-        Ok(buf)
     }
-}
-
-/// A builder for a `SqlServerPool`
-///
-/// The builder provides configuration options for the maximum pool size, connection timeout, and whether to use SQL Browser.
-pub struct SqlServerPoolBuilder {
-    pool_max_size: u32,
-    pool_connection_timeout: std::time::Duration,
-    use_sql_browser: bool,
-}
 
-impl SqlServerPoolBuilder {
-    /// Create a new builder for configuring a `SqlServerPool`.
-    pub fn new() -> Self {
-        Self::default()
+    /// Run [`SqlServerPool::row_query`], also returning how long the connection checkout and the query itself took,
+    /// and the bytes the query sent and received.
+    ///
+    /// A slow query with a long `acquire` points to pool contention, e.g. a pool too small for the load, while a
+    /// long `execute` points to the query or the server, or to a large result on a slow network, as `bytes` shows.
+    /// Times are measured with the pool's clock.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, TryFromRow};
+    /// # struct Person;
+    /// # impl TryFromRow for Person {
+    /// #     fn try_from(_: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Person) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let (people, timings) = sql_server
+    ///     .row_query_timed::<Person>("SELECT id, name FROM people;", &[])
+    ///     .await?;
+    /// println!("waited {:?} for a connection, ran for {:?}", timings.acquire, timings.execute);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_timed<T>(
+        &self,
+        query: &str,
+        params: &[String],
+    ) -> Result<(Vec<T>, QueryTimings), Error>
+    where
+        T: TryFromRow,
+    {
+        self.row_query_timed_with_options(query, params, &QueryOptions::default())
+            .await
     }
-    /// Build a `SqlServerPool` using the provided configuration.
-    pub async fn build(&self, config: tiberius::Config) -> Result<SqlServerPool, Error> {
-        let manager = ConnectionManagerBuilder::new()
-            .use_sql_browser(self.use_sql_browser)
-            .build(config)?;
 
-        let pool = bb8::Pool::builder()
-            .max_size(self.pool_max_size)
-            .connection_timeout(self.pool_connection_timeout)
-            .build(manager)
+    /// Run [`SqlServerPool::row_query_timed`] with per-query options.
+    pub async fn row_query_timed_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<(Vec<T>, QueryTimings), Error>
+    where
+        T: TryFromRow,
+    {
+        let max_rows = options.max_rows.or(self.max_rows);
+        let mut acquire = Duration::ZERO;
+        let plan = QueryPlan::query(&query).timed(&mut acquire);
+        let (rows, execute, bytes) = self
+            .execute_internal(plan, options, async |conn| {
+                let start = self.clock.now();
+                let before = conn.bytes();
+                let rows = query_rows_limited_on(conn, query, params, max_rows).await?;
+                Ok((rows, self.clock.elapsed(start), conn.bytes().since(before)))
+            })
             .await?;
+        Ok((
+            rows,
+            QueryTimings {
+                acquire,
+                execute,
+                bytes,
+            },
+        ))
+    }
 
-        Ok(SqlServerPool { inner: pool })
+    /// Run a SQL query with `@name` placeholders bound to the fields of `params`, and return the result as Vec<T>.
+    ///
+    /// Each `@name` is bound to the field of the same name, matched ignoring case. Any [`serde::Serialize`] struct
+    /// can be bound, see [`ToSqlParams`] for the field types accepted. Variables that aren't fields, e.g. ones the
+    /// query declares, are left as they are, and positional `@P{n}` placeholders are rejected. For inserts and
+    /// updates without an `OUTPUT` clause, the result is empty.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, TryFromRow};
+    /// # struct Id;
+    /// # impl TryFromRow for Id {
+    /// #     fn try_from(_: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Id) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// #[derive(serde::Serialize)]
+    /// struct NewUser {
+    ///     name: String,
+    ///     email: Option<String>,
+    /// }
+    ///
+    /// let user = NewUser { name: "Alice".to_owned(), email: None };
+    /// let ids = sql_server
+    ///     .row_query_named::<Id, _>(
+    ///         "INSERT INTO users (name, email) OUTPUT inserted.id VALUES (@name, @email);",
+    ///         &user,
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_named<T, P>(&self, query: &str, params: &P) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+        P: ToSqlParams + ?Sized,
+    {
+        self.row_query_named_with_options(query, params, &QueryOptions::default())
+            .await
     }
-    /// Set the maximum pool size. Defaults to 3.
-    pub fn pool_max_size(&mut self, pool_max_size: u32) -> &mut Self {
-        self.pool_max_size = pool_max_size;
-        self
+
+    /// Run [`SqlServerPool::row_query_named`] with per-query options.
+    pub async fn row_query_named_with_options<T, P>(
+        &self,
+        query: &str,
+        params: &P,
+        options: &QueryOptions,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+        P: ToSqlParams + ?Sized,
+    {
+        let (query, params) = bind_named(query, params.to_sql_params()?)?;
+        let query = query.as_str();
+        let stable_types = options
+            .stable_param_types
+            .unwrap_or(self.stable_param_types);
+        let mut select = Query::new(query);
+        for param in &params {
+            bind_param(&mut select, param, stable_types);
+        }
+
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut rows = Vec::new();
+            for_each_row_async_on(conn, query, select, |row| {
+                let result = T::try_from(row).map(|row| rows.push(row));
+                async move { result }
+            })
+            .await?;
+            Ok(rows)
+        })
+        .await
     }
-    /// Set whether to use SQL Browser. Defaults to false.
-    pub fn use_sql_browser(&mut self, yes: bool) -> &mut Self {
-        self.use_sql_browser = yes;
-        self
+
+    /// Run a SQL query returning a single value that may legitimately be NULL, e.g. `SELECT MAX(x) FROM t`.
+    ///
+    /// Returns `None` if the first column of the first row is NULL or there are no rows.
+    /// Any further columns and rows are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let latest: Option<i64> = sql_server.scalar_opt("SELECT MAX(id) FROM orders", &[]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn scalar_opt<T>(&self, query: &str, params: &[String]) -> Result<Option<T>, Error>
+    where
+        T: FromSqlValue,
+    {
+        self.scalar_opt_with_options(query, params, &QueryOptions::default())
+            .await
     }
-    /// Set the connection timeout. Defaults to 5 seconds.
-    pub fn pool_connection_timeout(
-        &mut self,
-        pool_connection_timeout: std::time::Duration,
-    ) -> &mut Self {
-        self.pool_connection_timeout = pool_connection_timeout;
-        self
+
+    /// Run [`SqlServerPool::scalar_opt`] with per-query options.
+    pub async fn scalar_opt_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Option<T>, Error>
+    where
+        T: FromSqlValue,
+    {
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut value = None;
+            let mut first = true;
+
+            for_each_row_on(conn, query, params, |row| {
+                if first {
+                    first = false;
+                    value = value_at(&row, 0)?;
+                }
+                Ok(())
+            })
+            .await?;
+
+            Ok(value)
+        })
+        .await
     }
-}
 
-impl Default for SqlServerPoolBuilder {
-    fn default() -> Self {
-        Self {
-            pool_max_size: 3,
-            use_sql_browser: false,
-            pool_connection_timeout: std::time::Duration::from_secs(5),
-        }
+    /// Run a `DELETE ... OUTPUT deleted.*` statement, returning the deleted rows, e.g. for an audit log.
+    ///
+    /// An empty `Vec` means nothing was deleted, rather than [`Error::EmptyResult`]. The statement must have an
+    /// `OUTPUT` clause, as without one it would always appear to delete nothing, so its absence is reported as
+    /// [`Error::InvalidArgument`]. Like [`SqlServerPool::upsert`], tables with enabled triggers, which reject a plain
+    /// `OUTPUT` clause, are detected on first use and handled with `OUTPUT ... INTO` a temp table from then on. That
+    /// needs a statement naming the table it deletes from, as in `DELETE FROM t OUTPUT ...`, so a
+    /// `DELETE t OUTPUT ... FROM` a join of tables with triggers needs its own `OUTPUT ... INTO`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, TryFromRow};
+    /// # struct Session;
+    /// # impl TryFromRow for Session {
+    /// #     fn try_from(row: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Session) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let expired: Vec<Session> = sql_server
+    ///     .delete_returning(
+    ///         "DELETE FROM sessions OUTPUT deleted.* WHERE expires_at < SYSUTCDATETIME()",
+    ///         &[],
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn delete_returning<T>(&self, query: &str, params: &[String]) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        self.delete_returning_with_options(query, params, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::delete_returning`] with per-query options.
+    pub async fn delete_returning_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        if !has_keyword(query, "OUTPUT") {
+            return Err(Error::InvalidArgument(
+                "delete_returning requires an OUTPUT clause".to_owned(),
+            ));
+        }
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut rows = Vec::new();
+            self.for_each_output_row_on(conn, query, params, "deleted", |row| {
+                rows.push(T::try_from(row)?);
+                Ok(())
+            })
+            .await?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    /// Run an `INSERT ... OUTPUT inserted.id` statement, returning the identity generated for each inserted row.
+    ///
+    /// `SCOPE_IDENTITY()` only returns the last identity of a multi-row insert, and the others can't be derived from
+    /// it, as identities needn't be consecutive. An `OUTPUT` clause is the only reliable way to get them all, so its
+    /// absence is reported as [`Error::InvalidArgument`]. The ids are read from the first output column, which may be
+    /// `tinyint`, `smallint`, `int` or `bigint`, in the order the server returns them, which isn't necessarily the
+    /// order of the `VALUES`. Tables with enabled triggers are handled like [`SqlServerPool::delete_returning`]
+    /// handles them.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let ids = sql_server
+    ///     .insert_returning_ids(
+    ///         "INSERT INTO people (name) OUTPUT inserted.id VALUES (@P1), (@P2)",
+    ///         &["Alice".into(), "Bob".into()],
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn insert_returning_ids(
+        &self,
+        query: &str,
+        params: &[String],
+    ) -> Result<Vec<i64>, Error> {
+        self.insert_returning_ids_with_options(query, params, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::insert_returning_ids`] with per-query options.
+    pub async fn insert_returning_ids_with_options(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Vec<i64>, Error> {
+        if !has_keyword(query, "OUTPUT") {
+            return Err(Error::InvalidArgument(
+                "insert_returning_ids requires an OUTPUT clause, e.g. OUTPUT inserted.id"
+                    .to_owned(),
+            ));
+        }
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut ids = Vec::new();
+            self.for_each_output_row_on(conn, query, params, "inserted", |row| {
+                ids.push(read_identity(&row)?);
+                Ok(())
+            })
+            .await?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    /// Run a statement with an `OUTPUT` clause reading `pseudo_table`, `deleted` or `inserted`, passing each output
+    /// row to `f`. Tables with triggers are detected on first use and handled with [`output_into_statement`] from then on.
+    async fn for_each_output_row_on<F>(
+        &self,
+        conn: &mut PooledConnection<'_>,
+        query: &str,
+        params: &[String],
+        pseudo_table: &str,
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(Row) -> Result<(), Error>,
+    {
+        let output_into = output_into_statement(query, pseudo_table);
+        if let Some((table, batch)) = &output_into {
+            if self.uses_output_into(table) {
+                return for_each_row_on(conn, batch, params, f).await;
+            }
+        }
+
+        match for_each_row_on(conn, query, params, &mut f).await {
+            Err(e) if e.server_code() == Some(TRIGGER_OUTPUT_ERROR) => {
+                let Some((table, batch)) = output_into else {
+                    return Err(e);
+                };
+                self.output_into_tables
+                    .lock()
+                    .expect("output into cache poisoned")
+                    .insert(table);
+                for_each_row_on(conn, &batch, params, f).await
+            }
+            result => result,
+        }
+    }
+
+    /// Insert a row, or update it if a row with the same key already exists, using a `MERGE` statement.
+    ///
+    /// `keys` are the columns identifying the row, and `values` are the remaining columns to insert or update.
+    /// The `MERGE` runs with `HOLDLOCK`, so concurrent upserts of the same key don't race.
+    /// Tables with triggers are detected on first use and handled with `OUTPUT ... INTO` from then on.
+    /// Audit columns the table has are set too, see [`SqlServerPoolBuilder::audit_columns`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, SqlParam, UpsertAction};
+    /// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+    /// let sql_server = SqlServerPool::new(cfg).await?;
+    ///
+    /// let action = sql_server
+    ///     .upsert(
+    ///         "dbo.people",
+    ///         &[("id", SqlParam::from(1))],
+    ///         &[("name", SqlParam::from("Alice"))],
+    ///     )
+    ///     .await?;
+    ///
+    /// assert_eq!(action, UpsertAction::Inserted);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn upsert(
+        &self,
+        table: &str,
+        keys: &[(&str, SqlParam)],
+        values: &[(&str, SqlParam)],
+    ) -> Result<UpsertAction, Error> {
+        self.upsert_with_options(table, keys, values, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::upsert`] with per-query options.
+    pub async fn upsert_with_options(
+        &self,
+        table: &str,
+        keys: &[(&str, SqlParam)],
+        values: &[(&str, SqlParam)],
+        options: &QueryOptions,
+    ) -> Result<UpsertAction, Error> {
+        if keys.is_empty() {
+            return Err(Error::InvalidArgument(
+                "upsert requires at least one key column".to_owned(),
+            ));
+        }
+
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            self.merge_row(conn, table, keys, values, options).await
+        })
+        .await
+    }
+
+    async fn merge_row(
+        &self,
+        conn: &mut PooledConnection<'_>,
+        table: &str,
+        keys: &[(&str, SqlParam)],
+        values: &[(&str, SqlParam)],
+        options: &QueryOptions,
+    ) -> Result<UpsertAction, Error> {
+        let quoted = quote_object_name(table)?;
+
+        let audit = match &self.auditor {
+            Some(auditor) => {
+                let explicit: Vec<&str> = keys.iter().chain(values).map(|(c, _)| *c).collect();
+                let actor = options.actor.clone().or_else(current_actor);
+                auditor.assignments(conn, &quoted, &explicit, actor).await?
+            }
+            None => AuditAssignments::default(),
+        };
+        let mut values = values.to_vec();
+        if let Some((column, actor)) = &audit.actor {
+            values.push((column.as_str(), actor.clone()));
+        }
+        if let Some(truncator) = &self.truncator {
+            if let Some(truncated) = truncator.truncate_params(conn, &quoted, &values).await? {
+                values = truncated;
+            }
+        }
+
+        let encrypted_keys = self.codecs.encrypt_params(table, keys)?;
+        let encrypted_values = self.codecs.encrypt_params(table, &values)?;
+        let keys = encrypted_keys.as_deref().unwrap_or(keys);
+        let values = encrypted_values.as_deref().unwrap_or(&values);
+
+        let table = quoted;
+        let stable_types = options
+            .stable_param_types
+            .unwrap_or(self.stable_param_types);
+        let timestamps = &audit.timestamps;
+
+        let output_into = self.uses_output_into(&table);
+        let was_broken = begin_exchange(conn);
+        let result = run_upsert(
+            conn,
+            &table,
+            keys,
+            values,
+            timestamps,
+            stable_types,
+            output_into,
+        )
+        .await;
+
+        let row = match result {
+            Err(e) if !output_into && e.server_code() == Some(TRIGGER_OUTPUT_ERROR) => {
+                self.output_into_tables
+                    .lock()
+                    .expect("output into cache poisoned")
+                    .insert(table.clone());
+                run_upsert(conn, &table, keys, values, timestamps, stable_types, true).await
+            }
+            result => result,
+        };
+        conn.set_broken(was_broken);
+        let row = row?;
+
+        match row.try_get::<&str, _>(0)? {
+            Some("INSERT") => Ok(UpsertAction::Inserted),
+            _ => Ok(UpsertAction::Updated),
+        }
+    }
+
+    /// Make a small reference table match `desired`, inserting missing rows, updating changed ones and deleting the
+    /// rest, returning how many of each.
+    ///
+    /// Rows are matched by `key_columns`, which must be fields of `T`. The table's rows are read into `T`s by
+    /// selecting the columns named by the fields of [`ToSqlParams`], and a row is updated if it isn't equal to the
+    /// desired one. The changes are worked out client-side, so this is meant for tables of up to a few thousand rows,
+    /// and may be rerun freely, as a table already in sync gets no statements. Key values are compared exactly, and
+    /// must not be NULL or repeat within `desired`.
+    ///
+    /// The read and the changes run in one transaction, holding locks on the table's rows until it commits, with the
+    /// changes sent in batches under the server's parameter limit. A sync that would delete more than
+    /// [`SyncOptions::max_delete_fraction`] of the rows fails before changing anything, and with
+    /// [`SyncOptions::dry_run`] the changes are only counted. Audit columns and truncation policies aren't applied,
+    /// and neither are column codecs, so a table with any fails with [`Error::InvalidArgument`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, SyncOptions, TryFromRow, RowExt};
+    /// #[derive(PartialEq, serde::Serialize)]
+    /// struct Country {
+    ///     code: String,
+    ///     name: String,
+    /// }
+    /// # impl TryFromRow for Country {
+    /// #     fn try_from(row: tiberius::Row) -> mssql_rs::Result<Self> {
+    /// #         Ok(Country { code: row.get_named("code")?.unwrap_or_default(), name: row.get_named("name")?.unwrap_or_default() })
+    /// #     }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let countries = [Country { code: "NZ".into(), name: "New Zealand".into() }];
+    /// let stats = sql_server
+    ///     .sync_table("ref.countries", &["code"], &countries, &SyncOptions::default())
+    ///     .await?;
+    /// println!("{} inserted, {} updated, {} deleted", stats.inserted, stats.updated, stats.deleted);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn sync_table<T>(
+        &self,
+        table: &str,
+        key_columns: &[&str],
+        desired: &[T],
+        options: &SyncOptions,
+    ) -> Result<SyncStats, Error>
+    where
+        T: ToSqlParams + TryFromRow + PartialEq,
+    {
+        self.sync_table_with_options(
+            table,
+            key_columns,
+            desired,
+            options,
+            &QueryOptions::default(),
+        )
+        .await
+    }
+
+    /// Run [`SqlServerPool::sync_table`] with per-query options, which apply to the sync as a whole.
+    pub async fn sync_table_with_options<T>(
+        &self,
+        table: &str,
+        key_columns: &[&str],
+        desired: &[T],
+        sync: &SyncOptions,
+        options: &QueryOptions,
+    ) -> Result<SyncStats, Error>
+    where
+        T: ToSqlParams + TryFromRow + PartialEq,
+    {
+        self.codecs.reject_table(table, "sync_table")?;
+        let stable_types = options
+            .stable_param_types
+            .unwrap_or(self.stable_param_types);
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            sync::sync_table(conn, table, key_columns, desired, sync, stable_types).await
+        })
+        .await
+    }
+
+    /// Read the columns of `tables` for [`codegen`](crate::codegen), in the order given, failing with
+    /// [`Error::InvalidArgument`] if a table doesn't exist.
+    ///
+    /// ```no_run
+    /// # async fn example(sql_server: mssql_rs::SqlServerPool) -> mssql_rs::Result<()> {
+    /// let snapshot = sql_server.schema_snapshot(&["dbo.people", "dbo.orders"]).await?;
+    /// std::fs::write("schema.json", snapshot.to_json())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "codegen")]
+    pub async fn schema_snapshot(&self, tables: &[&str]) -> Result<SchemaSnapshot, Error> {
+        self.execute_internal(
+            QueryPlan::new(&[]),
+            &QueryOptions::default(),
+            async |conn| {
+                let mut snapshot = SchemaSnapshot::default();
+                for table in tables {
+                    let mut columns = Vec::new();
+                    for_each_row_on(
+                        conn,
+                        codegen::SCHEMA_QUERY,
+                        &[quote_object_name(table)?],
+                        |row| {
+                            columns.push(codegen::column_schema(&row)?);
+                            Ok(())
+                        },
+                    )
+                    .await?;
+                    if columns.is_empty() {
+                        return Err(Error::InvalidArgument(format!(
+                            "table {table} doesn't exist"
+                        )));
+                    }
+                    snapshot.tables.push(TableSchema {
+                        name: (*table).to_owned(),
+                        columns,
+                    });
+                }
+                Ok(snapshot)
+            },
+        )
+        .await
+    }
+
+    /// Insert a row unless one with the same unique key exists, returning the row and whether it was inserted.
+    ///
+    /// The existence check and insert run as one `INSERT ... WHERE NOT EXISTS` statement holding `UPDLOCK, HOLDLOCK`
+    /// on the key, so concurrent calls with the same key insert exactly one row. If an insert still loses a race,
+    /// e.g. against a filtered unique index that doesn't match `unique_key`, the unique key violation is resolved
+    /// by reading the winning row rather than returned. The row is read back with `SELECT *` and converted with
+    /// [`TryFromRow`], including columns set by defaults and triggers. Key values must not be `NULL`.
+    /// Audit columns the table has are set on insert, see [`SqlServerPoolBuilder::audit_columns`]. The statement is
+    /// admitted like any query, see [`SqlServerPoolBuilder::max_query_length`] and
+    /// [`SqlServerPoolBuilder::query_rate_limit`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlParam, SqlServerPool, TryFromRow};
+    /// # struct Customer;
+    /// # impl TryFromRow for Customer {
+    /// #     fn try_from(row: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Customer) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let (customer, inserted): (Customer, bool) = sql_server
+    ///     .insert_or_get(
+    ///         "dbo.customers",
+    ///         &[("email", SqlParam::from("alice@example.com"))],
+    ///         &[("name", SqlParam::from("Alice"))],
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn insert_or_get<T>(
+        &self,
+        table: &str,
+        unique_key: &[(&str, SqlParam)],
+        insert_values: &[(&str, SqlParam)],
+    ) -> Result<(T, bool), Error>
+    where
+        T: TryFromRow,
+    {
+        self.insert_or_get_with_options(table, unique_key, insert_values, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::insert_or_get`] with per-query options.
+    pub async fn insert_or_get_with_options<T>(
+        &self,
+        table: &str,
+        unique_key: &[(&str, SqlParam)],
+        insert_values: &[(&str, SqlParam)],
+        options: &QueryOptions,
+    ) -> Result<(T, bool), Error>
+    where
+        T: TryFromRow,
+    {
+        if unique_key.is_empty() {
+            return Err(Error::InvalidArgument(
+                "insert_or_get requires at least one key column".to_owned(),
+            ));
+        }
+
+        let quoted = quote_object_name(table)?;
+        let actor = options.actor.clone().or_else(current_actor);
+        let stable_types = options
+            .stable_param_types
+            .unwrap_or(self.stable_param_types);
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            let audit = match &self.auditor {
+                Some(auditor) => {
+                    let explicit: Vec<&str> = unique_key
+                        .iter()
+                        .chain(insert_values)
+                        .map(|(c, _)| *c)
+                        .collect();
+                    auditor.assignments(conn, &quoted, &explicit, actor).await?
+                }
+                None => AuditAssignments::default(),
+            };
+            let mut values = insert_values.to_vec();
+            if let Some((column, actor)) = &audit.actor {
+                values.push((column.as_str(), actor.clone()));
+            }
+            if let Some(truncator) = &self.truncator {
+                if let Some(truncated) = truncator.truncate_params(conn, &quoted, &values).await? {
+                    values = truncated;
+                }
+            }
+
+            let encrypted_keys = self.codecs.encrypt_params(table, unique_key)?;
+            let encrypted_values = self.codecs.encrypt_params(table, &values)?;
+            let keys = encrypted_keys.as_deref().unwrap_or(unique_key);
+            let values = encrypted_values.as_deref().unwrap_or(&values);
+
+            // The statement depends on the table's audit columns, so it can only be admitted once built.
+            let statement = insert_or_get_statement(&quoted, keys, values, &audit.timestamps);
+            self.admit(&statement).await?;
+            let mut insert = Query::new(statement);
+            for (_, param) in keys.iter().chain(values) {
+                bind_param(&mut insert, param, stable_types);
+            }
+
+            let result = match insert.query(conn).await {
+                Ok(stream) => stream.into_results().await,
+                Err(e) => Err(e),
+            };
+            let (inserted, rows) = match result {
+                // The flag and the row are the last two result sets, after any returned by triggers.
+                Ok(mut results) if results.len() >= 2 => {
+                    let rows = results.pop().unwrap_or_default();
+                    let inserted = results
+                        .pop()
+                        .and_then(|flag| flag.first().and_then(|row| row.get::<bool, _>(0)))
+                        .unwrap_or(false);
+                    (inserted, rows)
+                }
+                Ok(_) => return Err(Error::EmptyResult),
+                Err(e)
+                    if e.code()
+                        .is_some_and(|code| UNIQUE_VIOLATION_ERRORS.contains(&code)) =>
+                {
+                    let mut select = Query::new(select_by_key_statement(&quoted, keys));
+                    for (_, param) in keys {
+                        bind_param(&mut select, param, stable_types);
+                    }
+                    let rows = select.query(conn).await?.into_first_result().await?;
+                    (false, rows)
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let row = rows.into_iter().next().ok_or(Error::EmptyResult)?;
+            Ok((T::try_from(row)?, inserted))
+        })
+        .await
+    }
+
+    /// Create a queue of rows written to `table`'s `columns` in the background, for fire-and-forget writes such as
+    /// telemetry, where losing rows under sustained overload is better than slowing down the caller.
+    ///
+    /// Rows are queued by [`WriteBehindQueue::push`], which never waits for the database, and written by a background
+    /// task in batches of multi-row `INSERT`s, see [`WriteBehindOptions`] for the batching, overflow and retry policy.
+    /// Values bound to encrypted columns are encrypted when pushed. The task holds a clone of the pool until the queue
+    /// is closed, by dropping every handle, [`WriteBehindQueue::close`] or [`SqlServerPool::close`], and then writes
+    /// the remaining rows before exiting. Queued rows and the batches written or rows dropped by all queues are
+    /// reported by [`SqlServerPool::status`].
+    ///
+    /// Must be called within a tokio runtime. Fails with [`Error::InvalidArgument`] if there are no columns, a
+    /// column is repeated, or the table name is invalid.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlParam, SqlServerPool, WriteBehindOptions};
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let events = sql_server.write_behind(
+    ///     "dbo.Events",
+    ///     &["kind", "payload"],
+    ///     WriteBehindOptions::default(),
+    /// )?;
+    ///
+    /// events.push(vec![SqlParam::from("login"), SqlParam::from("{}")])?;
+    /// events.flush().await;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_behind(
+        &self,
+        table: &str,
+        columns: &[&str],
+        options: WriteBehindOptions,
+    ) -> Result<WriteBehindQueue, Error> {
+        if columns.is_empty() {
+            return Err(Error::InvalidArgument(
+                "write_behind requires at least one column".to_owned(),
+            ));
+        }
+        let mut seen = HashSet::new();
+        if let Some(column) = columns
+            .iter()
+            .find(|column| !seen.insert(column.to_lowercase()))
+        {
+            return Err(Error::InvalidArgument(format!(
+                "write_behind column {column} is repeated"
+            )));
+        }
+
+        let target = WriteBehindTarget {
+            table: table.to_owned(),
+            quoted_table: quote_object_name(table)?,
+            columns: columns.iter().map(|column| (*column).to_owned()).collect(),
+            quoted_columns: columns
+                .iter()
+                .map(|column| quote_identifier(column))
+                .collect(),
+        };
+        Ok(write_behind::spawn(
+            self.clone(),
+            target,
+            self.codecs.clone(),
+            options,
+            self.write_behind.clone(),
+        ))
+    }
+
+    /// Close the pool's write-behind queues, waiting until each has written its queued rows, or dropped those it
+    /// couldn't write. Pushing to a closed queue fails.
+    ///
+    /// Call this before shutting down so buffered rows aren't lost. Connections are closed when the last clone of the
+    /// pool is dropped.
+    pub async fn close(&self) {
+        self.write_behind.close_all().await;
+    }
+
+    /// Run a closure against a temp table loaded with `rows`, on a single pinned connection.
+    ///
+    /// The temp table `name` (prefixed with `#` if needed) is created with the given columns, and the rows are loaded
+    /// with multi-row inserts, chunked to stay within the server's parameter limits. The closure receives the
+    /// connection, so its queries can join against the table. Afterwards the table is dropped, whether the closure
+    /// succeeded or not. If the table can't be dropped, or the closure panics or is cancelled, the connection is
+    /// discarded rather than returned to the pool, so the table can't leak into later queries.
+    ///
+    /// A table of the same name left on the connection is replaced.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, SqlParam, TempColumn, TryFromRow};
+    /// # struct Person;
+    /// # impl TryFromRow for Person {
+    /// #     fn try_from(_: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Person) }
+    /// # }
+    /// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+    /// let sql_server = SqlServerPool::new(cfg).await?;
+    ///
+    /// let ids: Vec<Vec<SqlParam>> = (1..=5000).map(|id| vec![SqlParam::from(id)]).collect();
+    ///
+    /// let people = sql_server
+    ///     .with_temp_table("#ids", &[TempColumn::new("id", "int")], &ids, |conn| {
+    ///         Box::pin(async move {
+    ///             let query = "SELECT p.id, p.name FROM people p JOIN #ids i ON i.id = p.id;";
+    ///             conn.row_query::<Person>(query, &[]).await
+    ///         })
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn with_temp_table<R, F>(
+        &self,
+        name: &str,
+        columns: &[TempColumn],
+        rows: &[Vec<SqlParam>],
+        f: F,
+    ) -> Result<R, Error>
+    where
+        F: for<'c, 'p> FnOnce(&'c mut PooledConnection<'p>) -> BoxFuture<'c, Result<R, Error>>,
+    {
+        self.with_temp_table_with_options(name, columns, rows, &QueryOptions::default(), f)
+            .await
+    }
+
+    /// Run [`SqlServerPool::with_temp_table`] with per-query options, which apply to the closure's queries too.
+    pub async fn with_temp_table_with_options<R, F>(
+        &self,
+        name: &str,
+        columns: &[TempColumn],
+        rows: &[Vec<SqlParam>],
+        options: &QueryOptions,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        F: for<'c, 'p> FnOnce(&'c mut PooledConnection<'p>) -> BoxFuture<'c, Result<R, Error>>,
+    {
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            self.in_temp_table(conn, name, columns, rows, options, f)
+                .await
+        })
+        .await
+    }
+
+    /// Load `rows` into the temp table `name` on `conn`, run `f` there and drop the table again, for
+    /// [`SqlServerPool::with_temp_table`].
+    async fn in_temp_table<R, F>(
+        &self,
+        conn: &mut PooledConnection<'_>,
+        name: &str,
+        columns: &[TempColumn],
+        rows: &[Vec<SqlParam>],
+        options: &QueryOptions,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        F: for<'c, 'p> FnOnce(&'c mut PooledConnection<'p>) -> BoxFuture<'c, Result<R, Error>>,
+    {
+        // Discard the connection unless the table is known to be dropped.
+        conn.mark_broken_because(BrokenReason::SessionState);
+
+        let table = TempTable::create(conn, name, columns).await?;
+
+        let stable_types = options
+            .stable_param_types
+            .unwrap_or(self.stable_param_types);
+        let result = match table.load(conn, columns.len(), rows, stable_types).await {
+            Ok(()) => f(conn).await,
+            Err(e) => Err(e),
+        };
+
+        if table.drop(conn).await.is_ok() {
+            conn.set_broken(false);
+        }
+
+        result
+    }
+
+    /// Run a SQL query joining against a `#keys` temp table of `keys`, and return the result as Vec<T>.
+    ///
+    /// This is the scalable form of `WHERE key IN (...)` for lists of thousands of keys or more, which would
+    /// otherwise exceed the server's parameter limit or plan poorly. The keys are loaded into `#keys`, as a column
+    /// called `key_column` typed from the keys, on one pinned connection, and the query runs there, e.g. joining
+    /// `JOIN #keys k ON k.id = o.customer_id`, see [`SqlServerPool::with_temp_table`]. The table is dropped
+    /// afterwards.
+    ///
+    /// Keys must all be of one type, and NULL keys match nothing. Duplicate keys are loaded as given, so a repeated key
+    /// repeats its rows in a join; `WHERE o.customer_id IN (SELECT id FROM #keys)` matches each row once either way.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, SqlParam, TryFromRow};
+    /// # struct Order;
+    /// # impl TryFromRow for Order {
+    /// #     fn try_from(_: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Order) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool, customer_ids: Vec<i64>) -> mssql_rs::Result<()> {
+    /// let keys: Vec<SqlParam> = customer_ids.into_iter().map(SqlParam::from).collect();
+    /// let orders = sql_server
+    ///     .row_query_in_temp::<Order>(
+    ///         "SELECT o.* FROM orders o JOIN #keys k ON k.customer_id = o.customer_id;",
+    ///         "customer_id",
+    ///         &keys,
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_in_temp<T>(
+        &self,
+        query: &str,
+        key_column: &str,
+        keys: &[SqlParam],
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow + Send,
+    {
+        self.row_query_in_temp_with_options(query, key_column, keys, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::row_query_in_temp`] with per-query options.
+    pub async fn row_query_in_temp_with_options<T>(
+        &self,
+        query: &str,
+        key_column: &str,
+        keys: &[SqlParam],
+        options: &QueryOptions,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow + Send,
+    {
+        let column = TempColumn::new(key_column, key_type(keys)?);
+        let rows: Vec<Vec<SqlParam>> = keys.iter().map(|key| vec![key.clone()]).collect();
+        let max_rows = options.max_rows.or(self.max_rows);
+        let owned = query.to_owned();
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            self.in_temp_table(conn, "#keys", &[column], &rows, options, |conn| {
+                Box::pin(async move { query_rows_limited_on(conn, &owned, &[], max_rows).await })
+            })
+            .await
+        })
+        .await
+    }
+
+    /// Run a closure with `IDENTITY_INSERT` on for `table`, on a single pinned connection.
+    ///
+    /// `SET IDENTITY_INSERT` is session-scoped, so the closure's inserts must run on the connection it receives
+    /// to insert explicit identity values. Afterwards it is turned off again, whether the closure succeeded or not.
+    /// If it can't be turned off, or the closure panics or is cancelled, the connection is discarded rather than
+    /// returned to the pool, so the setting can't leak into later queries.
+    ///
+    /// The closure's queries bypass column codecs, so a table with any fails with [`Error::InvalidArgument`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+    /// let sql_server = SqlServerPool::new(cfg).await?;
+    ///
+    /// sql_server
+    ///     .with_identity_insert("dbo.people", |conn| {
+    ///         Box::pin(async move {
+    ///             conn.execute("INSERT INTO dbo.people (id, name) VALUES (@P1, @P2)", &[&42, &"Alice"])
+    ///                 .await?;
+    ///             Ok(())
+    ///         })
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn with_identity_insert<R, F>(&self, table: &str, f: F) -> Result<R, Error>
+    where
+        F: for<'c, 'p> FnOnce(&'c mut PooledConnection<'p>) -> BoxFuture<'c, Result<R, Error>>,
+    {
+        self.with_identity_insert_with_options(table, &QueryOptions::default(), f)
+            .await
+    }
+
+    /// Run [`SqlServerPool::with_identity_insert`] with per-query options, which apply to the closure's queries too.
+    pub async fn with_identity_insert_with_options<R, F>(
+        &self,
+        table: &str,
+        options: &QueryOptions,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        F: for<'c, 'p> FnOnce(&'c mut PooledConnection<'p>) -> BoxFuture<'c, Result<R, Error>>,
+    {
+        self.codecs.reject_table(table, "with_identity_insert")?;
+        let table = quote_object_name(table)?;
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            Self::in_identity_insert(conn, &table, f).await
+        })
+        .await
+    }
+
+    /// Turn `IDENTITY_INSERT` on for the quoted `table` on `conn`, run `f` there and turn it off again, for
+    /// [`SqlServerPool::with_identity_insert`].
+    async fn in_identity_insert<R, F>(
+        conn: &mut PooledConnection<'_>,
+        table: &str,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        F: for<'c, 'p> FnOnce(&'c mut PooledConnection<'p>) -> BoxFuture<'c, Result<R, Error>>,
+    {
+        // Discard the connection unless the setting is known to be turned off.
+        conn.mark_broken_because(BrokenReason::SessionState);
+
+        conn.simple_query(format!("SET IDENTITY_INSERT {table} ON;"))
+            .await?
+            .into_results()
+            .await?;
+
+        let result = f(conn).await;
+
+        let off = match conn
+            .simple_query(format!("SET IDENTITY_INSERT {table} OFF;"))
+            .await
+        {
+            Ok(stream) => stream.into_results().await.map(drop),
+            Err(e) => Err(e),
+        };
+        if off.is_ok() {
+            conn.set_broken(false);
+        }
+
+        // The closure's error takes precedence, as it is likely the cause of the failure to turn the setting off.
+        let value = result?;
+        off?;
+        Ok(value)
+    }
+
+    /// Run a closure's queries in one read-only transaction, so they all read the same point in time.
+    ///
+    /// The transaction runs at `SNAPSHOT` isolation on a single pinned connection, and is committed when the closure
+    /// returns. Queries are run through the [`SnapshotReader`] the closure receives, which rejects statements that
+    /// could write with [`Error::ReadOnlyViolation`].
+    ///
+    /// If the database doesn't allow snapshot isolation, this fails with [`Error::SnapshotIsolationDisabled`],
+    /// unless [`SqlServerPoolBuilder::snapshot_fallback`] is set. The fallback runs at `REPEATABLE READ`, under which rows
+    /// already read can't change, but rows inserted since can still appear, and the reads take shared locks until the end.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let (orders, revenue) = sql_server
+    ///     .read_snapshot(|reader| {
+    ///         Box::pin(async move {
+    ///             let orders: serde_json::Value =
+    ///                 reader.json_query("SELECT COUNT(*) AS n FROM orders FOR JSON PATH", &[]).await?;
+    ///             let revenue: serde_json::Value =
+    ///                 reader.json_query("SELECT SUM(total) AS total FROM orders FOR JSON PATH", &[]).await?;
+    ///             Ok((orders, revenue))
+    ///         })
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn read_snapshot<R, F>(&self, f: F) -> Result<R, Error>
+    where
+        F: for<'c, 'r, 'p> FnOnce(
+            &'c mut SnapshotReader<'r, 'p>,
+        ) -> BoxFuture<'c, Result<R, Error>>,
+    {
+        self.read_snapshot_with_options(&QueryOptions::default(), f)
+            .await
+    }
+
+    /// Run [`SqlServerPool::read_snapshot`] with per-query options, which apply to the closure's queries too.
+    pub async fn read_snapshot_with_options<R, F>(
+        &self,
+        options: &QueryOptions,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        F: for<'c, 'r, 'p> FnOnce(
+            &'c mut SnapshotReader<'r, 'p>,
+        ) -> BoxFuture<'c, Result<R, Error>>,
+    {
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            self.in_snapshot(conn, f).await
+        })
+        .await
+    }
+
+    /// Run `f` in a snapshot transaction on `conn`, for [`SqlServerPool::read_snapshot`].
+    async fn in_snapshot<R, F>(&self, conn: &mut PooledConnection<'_>, f: F) -> Result<R, Error>
+    where
+        F: for<'c, 'r, 'p> FnOnce(
+            &'c mut SnapshotReader<'r, 'p>,
+        ) -> BoxFuture<'c, Result<R, Error>>,
+    {
+        // Discard the connection unless the transaction and isolation level are known to be reset.
+        conn.mark_broken_because(BrokenReason::SessionState);
+
+        let row = conn
+            .simple_query(SNAPSHOT_STATE_QUERY)
+            .await?
+            .into_row()
+            .await?
+            .ok_or(Error::EmptyResult)?;
+        let database = row.get::<&str, _>(0).unwrap_or_default().to_owned();
+        let isolation = match row.get::<u8, _>(1) {
+            Some(SNAPSHOT_ISOLATION_ON) => "SNAPSHOT",
+            _ if self.snapshot_fallback => "REPEATABLE READ",
+            _ => return Err(Error::SnapshotIsolationDisabled { database }),
+        };
+
+        let begin = format!("SET TRANSACTION ISOLATION LEVEL {isolation}; BEGIN TRANSACTION;");
+        conn.simple_query(begin).await?.into_results().await?;
+
+        let mut reader = SnapshotReader { conn, database };
+        let result = f(&mut reader).await;
+
+        let end = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+        let reset = format!(
+            "IF @@TRANCOUNT > 0 {end} TRANSACTION; SET TRANSACTION ISOLATION LEVEL READ COMMITTED;"
+        );
+        let reset = match reader.conn.simple_query(reset).await {
+            Ok(stream) => stream.into_results().await.map(drop),
+            Err(e) => Err(e),
+        };
+        if reset.is_ok() {
+            reader.conn.set_broken(false);
+        }
+
+        let value = result?;
+        reset?;
+        Ok(value)
+    }
+
+    /// Run a statement that returns no result, e.g. an `INSERT`, `UPDATE` or `DELETE`, returning the number of rows
+    /// it affected.
+    ///
+    /// Parameters are bound as for [`SqlServerPool::row_query`]. A batch of several statements returns the rows they
+    /// affected in total. A statement matching no rows returns `Ok(0)`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let expired = sql_server
+    ///     .execute("DELETE FROM sessions WHERE user_id = @P1;", &["42".to_owned()])
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn execute(&self, query: &str, params: &[String]) -> Result<u64, Error> {
+        self.execute_with_options(query, params, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::execute`], returning the rows affected by each statement of the batch, in order, rather
+    /// than their total.
+    ///
+    /// Only statements that report a count are included, e.g. not `DECLARE` or `SET`.
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let counts = sql_server
+    ///     .execute_counts(
+    ///         "UPDATE accounts SET frozen = 1 WHERE owner = @P1; DELETE FROM sessions WHERE owner = @P1;",
+    ///         &["42".to_owned()],
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn execute_counts(&self, query: &str, params: &[String]) -> Result<Vec<u64>, Error> {
+        self.execute_counts_with_options(query, params, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::execute_counts`] with per-query options.
+    pub async fn execute_counts_with_options(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Vec<u64>, Error> {
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            execute_on(conn, query, params).await
+        })
+        .await
+    }
+
+    /// Run [`SqlServerPool::execute`] with per-query options.
+    pub async fn execute_with_options(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<u64, Error> {
+        let counts = self
+            .execute_counts_with_options(query, params, options)
+            .await?;
+        Ok(counts.iter().sum())
+    }
+
+    /// Run statements in order on a single connection, returning the total number of rows affected.
+    ///
+    /// Each statement is sent as its own batch, so e.g. `CREATE PROCEDURE` can follow other statements.
+    /// Execution stops at the first failure, which is returned as [`Error::StatementFailed`] with the statement's
+    /// zero-based index and text. The statements don't run in a transaction, so those before the failure stay applied.
+    /// After a failure the connection is discarded, so a transaction a statement opened can't leak into later queries.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// sql_server
+    ///     .execute_batch(&[
+    ///         "CREATE TABLE audit (id int IDENTITY PRIMARY KEY, note nvarchar(200));",
+    ///         "CREATE INDEX ix_audit_note ON audit (note);",
+    ///     ])
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn execute_batch(&self, statements: &[&str]) -> Result<u64, Error> {
+        self.execute_batch_with_options(statements, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::execute_batch`] with per-query options.
+    pub async fn execute_batch_with_options(
+        &self,
+        statements: &[&str],
+        options: &QueryOptions,
+    ) -> Result<u64, Error> {
+        self.execute_internal(QueryPlan::new(statements), options, async |conn| {
+            let was_broken = begin_exchange(conn);
+
+            let mut total = 0;
+            for (index, statement) in statements.iter().enumerate() {
+                conn.track_temp_objects(statement);
+                match conn.execute(*statement, &[]).await {
+                    Ok(result) => total += result.total(),
+                    Err(e) => {
+                        // Keep the connection marked broken, as earlier statements may have left session state.
+                        return Err(Error::StatementFailed {
+                            index,
+                            statement: (*statement).to_owned(),
+                            source: Box::new(e.into()),
+                        });
+                    }
+                }
+            }
+            conn.set_broken(was_broken);
+            Ok(total)
+        })
+        .await
+    }
+
+    /// Run a script of batches separated by `GO` lines, as written by SQL Server Management Studio or `sqlcmd`,
+    /// returning the total number of rows affected. See [`SqlServerPool::execute_script_with`].
+    pub async fn execute_script(&self, script: &str) -> Result<u64, Error> {
+        self.execute_script_with(script, "GO", true).await
+    }
+
+    /// Run a script of batches separated by `separator` lines, returning the total number of rows affected.
+    ///
+    /// A separator line holds only the separator, matched ignoring ASCII case, optionally followed by a count and a
+    /// `--` comment. Separators inside literals, quoted identifiers or block comments don't split the script. With
+    /// `respect_go_count`, a batch followed by `GO 5` runs five times, otherwise once. Batches of only whitespace and
+    /// comments are skipped.
+    ///
+    /// The batches run one after another on one connection as with [`SqlServerPool::execute_batch`], and the first
+    /// to fail stops the script with [`Error::StatementFailed`], whose index counts repeated batches once per run.
+    /// An empty or whitespace-containing `separator`, or a count of 0, fails with [`Error::InvalidArgument`] before
+    /// anything runs.
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let script = "CREATE TABLE dbo.ticks (id int IDENTITY)\nGO\nINSERT INTO dbo.ticks DEFAULT VALUES\nGO 3\n";
+    /// assert_eq!(sql_server.execute_script(script).await?, 3);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn execute_script_with(
+        &self,
+        script: &str,
+        separator: &str,
+        respect_go_count: bool,
+    ) -> Result<u64, Error> {
+        self.execute_script_with_options(
+            script,
+            separator,
+            respect_go_count,
+            &QueryOptions::default(),
+        )
+        .await
+    }
+
+    /// Run [`SqlServerPool::execute_script_with`] with per-query options, which apply to the script as a whole.
+    pub async fn execute_script_with_options(
+        &self,
+        script: &str,
+        separator: &str,
+        respect_go_count: bool,
+        options: &QueryOptions,
+    ) -> Result<u64, Error> {
+        let batches = split_script(script, separator, respect_go_count)?;
+        self.execute_batch_with_options(&batches, options).await
+    }
+
+    /// Load CSV data into `table` with a bulk insert, returning the number of rows loaded and those rejected.
+    ///
+    /// The input is streamed, one record at a time, so memory use doesn't grow with its size. Fields are converted to
+    /// the types of the table's columns, which are read from `sys.columns` first. Supported are the integer, `bit`,
+    /// `real`, `float`, `decimal`/`numeric`, string, `uniqueidentifier`, `binary`/`varbinary` (as hex), `date`,
+    /// `datetime` and `datetime2` types, with dates as `YYYY-MM-DD` optionally followed by `HH:MM:SS.fffffff`.
+    /// Unquoted empty fields load as NULL, while a quoted empty field (`""`) is an empty string.
+    /// Identity, computed and rowversion columns are skipped, as the server generates them. If
+    /// any other column has an unsupported type, the import fails with [`Error::InvalidArgument`] before loading
+    /// anything, as does a header naming a column the table doesn't have.
+    ///
+    /// Rows that can't be converted, or have too many or too few fields, are handled according to
+    /// [`CsvImportOptions::on_bad_row`]. With [`BadRowPolicy::FailFast`], the first fails the import with
+    /// [`Error::CsvRow`] and the connection is discarded mid-load, so the server rolls back the rows sent so far.
+    ///
+    /// Column codecs aren't applied, so a table with any fails with [`Error::InvalidArgument`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{BadRowPolicy, CsvImportOptions, SqlServerPool};
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let file = tokio::fs::File::open("people.csv").await?;
+    /// let options = CsvImportOptions {
+    ///     on_bad_row: BadRowPolicy::Skip,
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let stats = sql_server.csv_import(file, "staging.people", &options).await?;
+    /// for row in &stats.rejected {
+    ///     eprintln!("line {}: {}", row.line, row.reason);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn csv_import<R>(
+        &self,
+        reader: R,
+        table: &str,
+        options: &CsvImportOptions,
+    ) -> Result<ImportStats, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.csv_import_with_options(reader, table, options, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::csv_import`] with per-query options, which apply to the import as a whole.
+    pub async fn csv_import_with_options<R>(
+        &self,
+        reader: R,
+        table: &str,
+        import: &CsvImportOptions,
+        options: &QueryOptions,
+    ) -> Result<ImportStats, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.codecs.reject_table(table, "csv_import")?;
+        let table = quote_object_name(table)?;
+        self.execute_internal(QueryPlan::new(&[]), options, async |conn| {
+            Self::load_csv(conn, reader, &table, import).await
+        })
+        .await
+    }
+
+    /// Bulk load the CSV data of `reader` into the quoted `table` on `conn`, for [`SqlServerPool::csv_import`].
+    async fn load_csv<R>(
+        conn: &mut PooledConnection<'_>,
+        reader: R,
+        table: &str,
+        options: &CsvImportOptions,
+    ) -> Result<ImportStats, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        // Discard the connection unless the bulk load is known to have completed.
+        conn.mark_broken();
+
+        let mut columns = Vec::new();
+        let mut stream = Query::new(IMPORT_COLUMNS_QUERY);
+        stream.bind(table);
+        for row in stream.query(conn).await?.into_first_result().await? {
+            let name: &str = row.get(0).unwrap_or_default();
+            columns.push(ImportColumn::new(
+                name.to_owned(),
+                row.get(1).unwrap_or_default(),
+                row.get(2).unwrap_or_default(),
+                row.get(3).unwrap_or_default(),
+                row.get(4).unwrap_or_default(),
+            )?);
+        }
+        if columns.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "table {table} doesn't exist or has no insertable columns"
+            )));
+        }
+
+        let mut csv = CsvReader::new(reader, options.delimiter);
+
+        // The CSV field each column is read from, if any.
+        let (fields, mapping): (usize, Vec<Option<usize>>) = if options.has_header {
+            let header = match csv.next_record().await? {
+                Some((_, Ok(header))) => header,
+                Some((line, Err(reason))) => return Err(Error::CsvRow { line, reason }),
+                None => return Ok(ImportStats::default()),
+            };
+            let mut mapping = vec![None; columns.len()];
+            for (index, name) in header.iter().enumerate() {
+                let name = name.as_deref().unwrap_or_default();
+                let column = columns
+                    .iter()
+                    .position(|c| c.name.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| {
+                        Error::InvalidArgument(format!(
+                            "CSV header names column {name:?}, which table {table} doesn't have or can't insert"
+                        ))
+                    })?;
+                if mapping[column].replace(index).is_some() {
+                    return Err(Error::InvalidArgument(format!(
+                        "CSV header names column {name:?} more than once"
+                    )));
+                }
+            }
+            (header.len(), mapping)
+        } else {
+            (columns.len(), (0..columns.len()).map(Some).collect())
+        };
+
+        let mut stats = ImportStats::default();
+        let mut bulk = conn.bulk_insert(table).await?;
+        while let Some((line, record)) = csv.next_record().await? {
+            let row = record.and_then(|mut record| {
+                if record.len() != fields {
+                    return Err(format!("expected {fields} fields, found {}", record.len()));
+                }
+                let mut row = TokenRow::with_capacity(columns.len());
+                for (column, field) in columns.iter().zip(&mapping) {
+                    let value = field.and_then(|index| record[index].take());
+                    row.push(match field {
+                        Some(_) => column.convert(value)?,
+                        None => column.null(),
+                    });
+                }
+                Ok(row)
+            });
+
+            match row {
+                Ok(row) => {
+                    bulk.send(row).await?;
+                    stats.rows_loaded += 1;
+                }
+                Err(reason) if options.on_bad_row == BadRowPolicy::Skip => {
+                    stats.rows_rejected += 1;
+                    if stats.rejected.len() < options.max_rejected_rows {
+                        stats.rejected.push(RejectedRow { line, reason });
+                    }
+                }
+                Err(reason) => return Err(Error::CsvRow { line, reason }),
+            }
+        }
+        bulk.finalize().await?;
+
+        conn.set_broken(false);
+        Ok(stats)
+    }
+
+    /// Returns true if `table` is known to have triggers, so statements must use `OUTPUT ... INTO`.
+    fn uses_output_into(&self, table: &str) -> bool {
+        self.output_into_tables
+            .lock()
+            .expect("output into cache poisoned")
+            .contains(table)
+    }
+
+    /// Run a SQL query like [`SqlServerPool::row_query`], accumulating the rows in chunks rather than one Vec.
+    ///
+    /// Growing a single Vec by doubling can briefly hold up to twice the memory of a very large result while reallocating.
+    /// Fixed-size chunks avoid both the copy and the overshoot. The strategy is set with [`QueryOptions::accumulation`].
+    pub async fn row_query_chunked<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Chunks<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut chunks = Chunks::new(options.accumulation);
+
+            for_each_row_on(conn, query, params, |row| {
+                chunks.push(T::try_from(row)?);
+                Ok(())
+            })
+            .await?;
+
+            Ok(chunks)
+        })
+        .await
+    }
+
+    /// Run a SQL query like [`SqlServerPool::row_query`], passing the rows to `f` in batches as they arrive.
+    ///
+    /// Rows are buffered until a batch of [`QueryOptions::fetch_buffer_rows`] (or the pool's
+    /// [`SqlServerPoolBuilder::fetch_buffer_rows`]) is full, so larger batches trade latency for throughput,
+    /// e.g. over high-latency links. The next rows aren't read until `f` completes, so a slow consumer
+    /// applies backpressure to the server rather than buffering the whole result.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{QueryOptions, SqlServerPool, TryFromRow};
+    /// # struct Event;
+    /// # impl TryFromRow for Event {
+    /// #     fn try_from(_: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Event) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let options = QueryOptions {
+    ///     fetch_buffer_rows: Some(10_000),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// sql_server
+    ///     .row_query_batched("SELECT * FROM events", &[], &options, |batch: Vec<Event>| async move {
+    ///         println!("{} events", batch.len());
+    ///         Ok(())
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_batched<T, F, Fut>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+        f: F,
+    ) -> Result<(), Error>
+    where
+        T: TryFromRow,
+        F: FnMut(Vec<T>) -> Fut,
+        Fut: Future<Output = Result<(), Error>>,
+    {
+        let batch_rows = options.fetch_buffer_rows.unwrap_or(self.fetch_buffer_rows);
+
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            for_each_batch_on(conn, query, params, batch_rows, f).await
+        })
+        .await
+    }
+
+    /// Run a SQL query, returning the first row that matches `pred`, or `None` if no row does.
+    ///
+    /// Rows are converted and tested as they arrive, and no further rows are converted once one matches.
+    /// The rest of the result is still read from the server and discarded before the connection is returned to the
+    /// pool, as tiberius can't cancel a query midway, so a connection is never returned with unread results.
+    /// Limit the query itself (e.g. with `TOP` or a `WHERE` clause) when the remainder could be large.
+    /// If draining fails, the matching row is still returned, and the connection is discarded instead.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, TryFromRow};
+    /// # struct Reading { value: f64 }
+    /// # impl TryFromRow for Reading {
+    /// #     fn try_from(row: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Reading { value: row.get(0).unwrap() }) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let first_spike = sql_server
+    ///     .row_query_find("SELECT value FROM readings ORDER BY taken_at", &[], |r: &Reading| {
+    ///         r.value > 100.0
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_find<T, P>(
+        &self,
+        query: &str,
+        params: &[String],
+        pred: P,
+    ) -> Result<Option<T>, Error>
+    where
+        T: TryFromRow,
+        P: FnMut(&T) -> bool,
+    {
+        self.row_query_find_with_options(query, params, &QueryOptions::default(), pred)
+            .await
+    }
+
+    /// Run [`SqlServerPool::row_query_find`] with per-query options.
+    pub async fn row_query_find_with_options<T, P>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+        pred: P,
+    ) -> Result<Option<T>, Error>
+    where
+        T: TryFromRow,
+        P: FnMut(&T) -> bool,
+    {
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            find_row_on(conn, query, params, pred).await
+        })
+        .await
+    }
+
+    /// Run a SQL query, returning the rows indexed by `key_fn`.
+    ///
+    /// The map is built as rows arrive, so no intermediate `Vec` is collected. Returns [`Error::DuplicateRowKey`]
+    /// if two rows have the same key, see [`SqlServerPool::row_query_indexed_allow_dup`] to keep the last instead.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, TryFromRow};
+    /// # struct Person { id: i32 }
+    /// # impl TryFromRow for Person {
+    /// #     fn try_from(row: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Person { id: row.get(0).unwrap() }) }
+    /// # }
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let people = sql_server
+    ///     .row_query_indexed("SELECT id, name FROM people", &[], |p: &Person| p.id)
+    ///     .await?;
+    /// let alice = people.get(&1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_indexed<K, T>(
+        &self,
+        query: &str,
+        params: &[String],
+        key_fn: impl Fn(&T) -> K,
+    ) -> Result<HashMap<K, T>, Error>
+    where
+        K: Eq + Hash,
+        T: TryFromRow,
+    {
+        self.row_query_indexed_with_options(query, params, &QueryOptions::default(), key_fn)
+            .await
+    }
+
+    /// Run [`SqlServerPool::row_query_indexed`] with per-query options.
+    pub async fn row_query_indexed_with_options<K, T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+        key_fn: impl Fn(&T) -> K,
+    ) -> Result<HashMap<K, T>, Error>
+    where
+        K: Eq + Hash,
+        T: TryFromRow,
+    {
+        self.index_rows(query, params, options, key_fn, false).await
+    }
+
+    /// Run [`SqlServerPool::row_query_indexed`], keeping the last row for a duplicate key rather than failing.
+    pub async fn row_query_indexed_allow_dup<K, T>(
+        &self,
+        query: &str,
+        params: &[String],
+        key_fn: impl Fn(&T) -> K,
+    ) -> Result<HashMap<K, T>, Error>
+    where
+        K: Eq + Hash,
+        T: TryFromRow,
+    {
+        self.row_query_indexed_allow_dup_with_options(
+            query,
+            params,
+            &QueryOptions::default(),
+            key_fn,
+        )
+        .await
+    }
+
+    /// Run [`SqlServerPool::row_query_indexed_allow_dup`] with per-query options.
+    pub async fn row_query_indexed_allow_dup_with_options<K, T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+        key_fn: impl Fn(&T) -> K,
+    ) -> Result<HashMap<K, T>, Error>
+    where
+        K: Eq + Hash,
+        T: TryFromRow,
+    {
+        self.index_rows(query, params, options, key_fn, true).await
+    }
+
+    async fn index_rows<K, T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+        key_fn: impl Fn(&T) -> K,
+        allow_dup: bool,
+    ) -> Result<HashMap<K, T>, Error>
+    where
+        K: Eq + Hash,
+        T: TryFromRow,
+    {
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut rows = HashMap::new();
+            let mut index = 0;
+
+            for_each_row_on(conn, query, params, |row| {
+                let value = T::try_from(row)?;
+                if rows.insert(key_fn(&value), value).is_some() && !allow_dup {
+                    return Err(Error::DuplicateRowKey { index });
+                }
+                index += 1;
+                Ok(())
+            })
+            .await?;
+
+            Ok(rows)
+        })
+        .await
+    }
+
+    /// Run a SQL query, building each row with `factory`, e.g. as a trait object chosen by a discriminator column.
+    ///
+    /// This suits union-style queries whose rows convert to different types, which a single [`TryFromRow`] type can't
+    /// express. The first error `factory` returns fails the query.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// trait Shape {
+    ///     fn area(&self) -> f64;
+    /// }
+    /// struct Circle(f64);
+    /// struct Square(f64);
+    /// impl Shape for Circle {
+    ///     fn area(&self) -> f64 { std::f64::consts::PI * self.0 * self.0 }
+    /// }
+    /// impl Shape for Square {
+    ///     fn area(&self) -> f64 { self.0 * self.0 }
+    /// }
+    ///
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let shapes = sql_server
+    ///     .row_query_dyn::<dyn Shape, _>("SELECT kind, size FROM shapes", &[], |row| {
+    ///         let size: f64 = row.get(1).unwrap_or_default();
+    ///         Ok(match row.get::<&str, _>(0) {
+    ///             Some("circle") => Box::new(Circle(size)),
+    ///             _ => Box::new(Square(size)),
+    ///         })
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_dyn<T, F>(
+        &self,
+        query: &str,
+        params: &[String],
+        factory: F,
+    ) -> Result<Vec<Box<T>>, Error>
+    where
+        T: ?Sized,
+        F: Fn(&Row) -> Result<Box<T>, Error>,
+    {
+        self.row_query_dyn_with_options(query, params, &QueryOptions::default(), factory)
+            .await
+    }
+
+    /// Run [`SqlServerPool::row_query_dyn`] with per-query options.
+    pub async fn row_query_dyn_with_options<T, F>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+        factory: F,
+    ) -> Result<Vec<Box<T>>, Error>
+    where
+        T: ?Sized,
+        F: Fn(&Row) -> Result<Box<T>, Error>,
+    {
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut rows = Vec::new();
+            for_each_row_on(conn, query, params, |row| {
+                rows.push(factory(&row)?);
+                Ok(())
+            })
+            .await?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    /// Run a SQL query and read the rows without a target type.
+    ///
+    /// Values of encrypted columns are decrypted if [`QueryOptions::codec_table`] is set.
+    /// Each value then passes through the pool's [`RowTransformer`]s and then those in [`QueryOptions::transformers`],
+    /// when it is first read from its [`DynamicRow`]. Rows from later result sets carry their own columns.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, QueryOptions};
+    /// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+    /// let sql_server = SqlServerPool::new(cfg).await?;
+    ///
+    /// let rows = sql_server
+    ///     .row_query_dynamic("SELECT id, name FROM people", &[], &QueryOptions::default())
+    ///     .await?;
+    ///
+    /// for row in &rows {
+    ///     let name: Option<String> = row.get("name")?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_dynamic(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Vec<DynamicRow>, Error> {
+        let transformers: Arc<[Arc<dyn RowTransformer>]> = if options.transformers.is_empty() {
+            self.transformers.clone()
+        } else {
+            self.transformers
+                .iter()
+                .chain(&options.transformers)
+                .cloned()
+                .collect()
+        };
+
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut rows = Vec::new();
+            let mut columns: Option<(usize, Arc<[Column]>)> = None;
+
+            for_each_row_on(conn, query, params, |row| {
+                let columns = match &columns {
+                    Some((index, columns)) if *index == row.result_index() => columns.clone(),
+                    _ => {
+                        let shared: Arc<[Column]> = row.columns().into();
+                        columns = Some((row.result_index(), shared.clone()));
+                        shared
+                    }
+                };
+
+                let mut values: Vec<SqlValue> = row.into_iter().collect();
+                if let Some(table) = &options.codec_table {
+                    self.codecs.decrypt_values(table, &columns, &mut values)?;
+                }
+                rows.push(DynamicRow::new(columns, values, transformers.clone()));
+                Ok(())
+            })
+            .await?;
+
+            Ok(rows)
+        })
+        .await
+    }
+
+    /// Run a SQL query selecting `N` integer columns, reading each row straight into an array.
+    ///
+    /// A fast path for large all-numeric results, skipping the per-value `Option` and [`TryFromRow`] conversion.
+    /// Columns may be `tinyint`, `smallint`, `int` or `bigint`. A NULL, a column of another type, or a row without
+    /// exactly `N` columns fails the query.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::SqlServerPool;
+    /// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+    /// let edges: Vec<[i64; 2]> = sql_server
+    ///     .row_query_numeric("SELECT source_id, target_id FROM edges", &[])
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_numeric<const N: usize>(
+        &self,
+        query: &str,
+        params: &[String],
+    ) -> Result<Vec<[i64; N]>, Error> {
+        self.row_query_numeric_with_options(query, params, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::row_query_numeric`] with per-query options.
+    pub async fn row_query_numeric_with_options<const N: usize>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Vec<[i64; N]>, Error> {
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut rows = Vec::new();
+            for_each_row_on(conn, query, params, |row| {
+                if row.len() != N {
+                    return Err(Error::InvalidArgument(format!(
+                        "row_query_numeric expected {N} columns, but the query returned {}",
+                        row.len()
+                    )));
+                }
+                let mut values = [0; N];
+                for (index, (slot, value)) in values.iter_mut().zip(row).enumerate() {
+                    *slot = match value {
+                        ColumnData::U8(Some(v)) => v.into(),
+                        ColumnData::I16(Some(v)) => v.into(),
+                        ColumnData::I32(Some(v)) => v.into(),
+                        ColumnData::I64(Some(v)) => v,
+                        _ => {
+                            return Err(tiberius::error::Error::Conversion(
+                                format!("Column {index} is NULL or not an integer").into(),
+                            )
+                            .into())
+                        }
+                    };
+                }
+                rows.push(values);
+                Ok(())
+            })
+            .await?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    /// Run a SQL query like [`SqlServerPool::row_query`], preferring the same connection for the same `affinity_key`.
+    ///
+    /// Keys are mapped onto a fixed set of single-connection shards (see [`SqlServerPoolBuilder::affinity_shards`]),
+    /// so queries sharing a key reuse that connection's plan cache and session state (e.g. temp tables),
+    /// while different keys may share a shard. Queries sharing a shard also queue behind each other.
+    /// If no shards are configured, this behaves like [`SqlServerPool::row_query`].
+    pub async fn row_query_affine<T>(
+        &self,
+        affinity_key: u64,
+        query: &str,
+        params: &[String],
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        self.row_query_affine_with_options(affinity_key, query, params, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::row_query_affine`] with per-query options.
+    pub async fn row_query_affine_with_options<T>(
+        &self,
+        affinity_key: u64,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        let max_rows = options.max_rows.or(self.max_rows);
+        let plan = QueryPlan::query(&query).affine(affinity_key);
+        self.execute_internal(plan, options, async |conn| {
+            query_rows_limited_on(conn, query, params, max_rows).await
+        })
+        .await
+    }
+
+    /// Run a one-to-many SQL query (e.g. orders joined to their lines) and group the rows into parents with their children.
+    ///
+    /// For each row, `parent_key` reads the parent's key and `child` reads the child, returning `None` when the child
+    /// columns are NULL (a LEFT JOIN parent with no children), so such parents get an empty Vec rather than a phantom child.
+    /// The parent is converted with [`TryFromRow`] only from the first row of each key.
+    ///
+    /// The query must be ordered by the parent key. If a key reappears after a different key,
+    /// [`Error::UngroupedRows`] is returned rather than silently splitting the parent.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, TryFromRow};
+    /// # struct Order;
+    /// # impl TryFromRow for Order {
+    /// #     fn try_from(_: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Order) }
+    /// # }
+    /// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+    /// let sql_server = SqlServerPool::new(cfg).await?;
+    ///
+    /// let query = "SELECT o.id, o.customer, l.product FROM orders o LEFT JOIN order_lines l ON l.order_id = o.id ORDER BY o.id;";
+    ///
+    /// let orders = sql_server
+    ///     .row_query_grouped::<Order, String, i32>(
+    ///         query,
+    ///         &[],
+    ///         |row| Ok(row.try_get::<i32, _>(0)?.unwrap_or_default()),
+    ///         |row| Ok(row.try_get::<&str, _>(2)?.map(str::to_owned)),
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_grouped<P, C, K>(
+        &self,
+        query: &str,
+        params: &[String],
+        parent_key: impl Fn(&tiberius::Row) -> Result<K, Error>,
+        child: impl Fn(&tiberius::Row) -> Result<Option<C>, Error>,
+    ) -> Result<Vec<(P, Vec<C>)>, Error>
+    where
+        P: TryFromRow,
+        K: Eq + Hash + Clone,
+    {
+        self.row_query_grouped_with_options(
+            query,
+            params,
+            &QueryOptions::default(),
+            parent_key,
+            child,
+        )
+        .await
+    }
+
+    /// Run [`SqlServerPool::row_query_grouped`] with per-query options.
+    pub async fn row_query_grouped_with_options<P, C, K>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+        parent_key: impl Fn(&tiberius::Row) -> Result<K, Error>,
+        child: impl Fn(&tiberius::Row) -> Result<Option<C>, Error>,
+    ) -> Result<Vec<(P, Vec<C>)>, Error>
+    where
+        P: TryFromRow,
+        K: Eq + Hash + Clone,
+    {
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut groups = GroupedRows::new();
+
+            for_each_row_on(conn, query, params, |row| {
+                let key = parent_key(&row)?;
+                let child = child(&row)?;
+                groups.push(key, child, row)
+            })
+            .await?;
+
+            Ok(groups.finish())
+        })
+        .await
+    }
+
+    /// Run a SQL query like [`SqlServerPool::row_query`], keeping the rows received before an error.
+    ///
+    /// A batch can emit rows and then fail partway (e.g. a divide-by-zero in a later row), which `row_query` reports
+    /// as an error alone. Here the error is returned as a [`PartialError`] holding the rows converted before it,
+    /// for best-effort reads. The rows may be empty, e.g. if no connection could be acquired.
+    pub async fn row_query_partial<T>(
+        &self,
+        query: &str,
+        params: &[String],
+    ) -> Result<Vec<T>, PartialError<T>>
+    where
+        T: TryFromRow,
+    {
+        self.row_query_partial_with_options(query, params, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::row_query_partial`] with per-query options. A call cut short by
+    /// [`QueryOptions::timeout`] keeps the rows read before it, like any other error.
+    pub async fn row_query_partial_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<Vec<T>, PartialError<T>>
+    where
+        T: TryFromRow,
+    {
+        let mut buf = Vec::new();
+
+        let result = self
+            .execute_internal(QueryPlan::query(&query), options, async |conn| {
+                collect_rows_on(conn, query, params, &mut buf).await
+            })
+            .await;
+
+        match result {
+            Ok(()) => Ok(buf),
+            Err(source) => Err(PartialError { rows: buf, source }),
+        }
+    }
+
+    /// Run a SQL query and return the result as Vec<T>, along with the total number of rows
+    /// the query would return without paging (e.g. for "showing X of Y").
+    ///
+    /// The query must either contain a `{count}` marker in its select list, which is replaced with
+    /// `COUNT(*) OVER() AS __total`, or already select a `__total` column, in which case it is sent untouched.
+    /// Markers and names in string literals and comments don't count. The count column must be the last column,
+    /// or the query fails with [`Error::InvalidQuery`]. It is hidden from [`RowExt`](crate::RowExt)'s name lookups
+    /// and the tuple impls of [`TryFromRow`] while each row converts, and positional reads of the other columns
+    /// are unaffected by it.
+    ///
+    /// If no rows are returned (e.g. when paging past the end of the result set)
+    /// the window function has nothing to report, and the total is returned as 0.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{SqlServerPool, TryFromRow};
+    /// # struct Person;
+    /// # impl TryFromRow for Person {
+    /// #     fn try_from(_: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Person) }
+    /// # }
+    /// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+    /// let sql_server = SqlServerPool::new(cfg).await?;
+    ///
+    /// let query = "SELECT id, name, {count} FROM people ORDER BY id OFFSET 0 ROWS FETCH NEXT 20 ROWS ONLY;";
+    ///
+    /// let (rows, total) = sql_server.row_query_counted::<Person>(query, &[]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn row_query_counted<T>(
+        &self,
+        query: &str,
+        params: &[String],
+    ) -> Result<(Vec<T>, u64), Error>
+    where
+        T: TryFromRow,
+    {
+        self.row_query_counted_with_options(query, params, &QueryOptions::default())
+            .await
+    }
+
+    /// Run [`SqlServerPool::row_query_counted`] with per-query options.
+    pub async fn row_query_counted_with_options<T>(
+        &self,
+        query: &str,
+        params: &[String],
+        options: &QueryOptions,
+    ) -> Result<(Vec<T>, u64), Error>
+    where
+        T: TryFromRow,
+    {
+        let counted = count_query(query)?;
+
+        self.execute_internal(QueryPlan::query(&query), options, async |conn| {
+            let mut buf = Vec::new();
+            let mut total = 0;
+
+            for_each_row_on(conn, &counted, params, |row| {
+                let row_total = read_total(&row)?;
+                if buf.is_empty() {
+                    total = row_total;
+                }
+                buf.push(hide_trailing_columns(1, || T::try_from(row))?);
+                Ok(())
+            })
+            .await?;
+
+            Ok((buf, total))
+        })
+        .await
+    }
+}
+
+/// The state of a pool, returned by [`SqlServerPool::status`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PoolStatus {
+    /// The number of open connections, idle or checked out. Affinity shard connections aren't counted.
+    pub connections: u32,
+    /// The number of idle connections. Affinity shard connections aren't counted.
+    pub idle_connections: u32,
+    /// When background validation last ran, if it is enabled and has run.
+    pub last_validation: Option<SystemTime>,
+    /// The number of connections background validation has discarded.
+    pub validation_failures: u64,
+    /// The number of checkouts holding an in-flight slot. Always 0 without [`SqlServerPoolBuilder::max_in_flight`].
+    pub in_flight: usize,
+    /// The number of checkouts waiting for an in-flight slot. Always 0 without [`SqlServerPoolBuilder::max_in_flight`].
+    pub queued: usize,
+    /// The number of rows waiting in write-behind queues, see [`SqlServerPool::write_behind`].
+    pub write_behind_queued: usize,
+    /// The number of batches write-behind queues have written.
+    pub write_behind_batches: u64,
+    /// The number of rows write-behind queues have dropped, when full or after a batch failed.
+    pub write_behind_dropped: u64,
+    /// The bytes read and written by all of the pool's connections, including those since closed.
+    pub bytes: ByteCount,
+}
+
+/// How long the phases of a query took, returned by [`SqlServerPool::row_query_timed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryTimings {
+    /// Waiting for a connection from the pool, including connecting and any in-flight queueing.
+    pub acquire: Duration,
+    /// Running the query and reading its rows.
+    pub execute: Duration,
+    /// The bytes sent for the query and received for its results.
+    pub bytes: ByteCount,
+}
+
+/// What a query method runs, for [`SqlServerPool::execute_internal`].
+struct QueryPlan<'a> {
+    /// The caller's statements, admitted before checking out a connection, empty for a method sending only its own.
+    statements: &'a [&'a str],
+    source: ConnectionSource,
+    /// Where to record how long the checkout took, for [`QueryTimings::acquire`].
+    acquire: Option<&'a mut Duration>,
+}
+
+impl<'a> QueryPlan<'a> {
+    /// Run `statements` on a connection from the main pool.
+    fn new(statements: &'a [&'a str]) -> Self {
+        Self {
+            statements,
+            source: ConnectionSource::Pool(Priority::High),
+            acquire: None,
+        }
+    }
+
+    /// Run `query` on a connection from the main pool.
+    fn query(query: &'a &'a str) -> Self {
+        Self::new(std::slice::from_ref(query))
+    }
+
+    /// Check out the connection with [`ConnectionSource::Affine`] instead.
+    fn affine(mut self, key: u64) -> Self {
+        self.source = ConnectionSource::Affine(key);
+        self
+    }
+
+    /// Record how long the checkout took in `acquire`.
+    fn timed(mut self, acquire: &'a mut Duration) -> Self {
+        self.acquire = Some(acquire);
+        self
+    }
+}
+
+/// Where [`SqlServerPool::execute_internal`] checks out a connection.
+#[derive(Debug, Clone, Copy)]
+enum ConnectionSource {
+    /// The main pool, at the given priority.
+    Pool(Priority),
+    /// The affinity shard for a key, or the main pool without shards.
+    Affine(u64),
+}
+
+/// Whether [`SqlServerPool::upsert`] inserted a new row or updated an existing one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsertAction {
+    Inserted,
+    Updated,
+}
+
+/// Reads the current database's name and `snapshot_isolation_state`, which is 1 when snapshot isolation is allowed.
+/// Whether the object named by `@P1` exists and can be read, for [`SqlServerPool::can_access`].
+const OBJECT_ACCESS_QUERY: &str = "SELECT CAST(CASE WHEN OBJECT_ID(@P1) IS NOT NULL \
+    AND HAS_PERMS_BY_NAME(@P1, 'OBJECT', 'SELECT') = 1 THEN 1 ELSE 0 END AS bit);";
+
+/// Whether the linked server named by `@P1` is defined, for [`SqlServerPool::can_access`].
+const LINKED_SERVER_ACCESS_QUERY: &str = "SELECT CAST(CASE WHEN EXISTS \
+    (SELECT 1 FROM sys.servers WHERE name = @P1 AND is_linked = 1) THEN 1 ELSE 0 END AS bit);";
+
+const SNAPSHOT_STATE_QUERY: &str =
+    "SELECT name, snapshot_isolation_state FROM sys.databases WHERE database_id = DB_ID();";
+
+const SNAPSHOT_ISOLATION_ON: u8 = 1;
+
+/// The server error raised when a statement with a plain `OUTPUT` clause targets a table with enabled triggers.
+const TRIGGER_OUTPUT_ERROR: u32 = 334;
+
+/// Server errors for a duplicate key in a unique index (2601) or a primary key or unique constraint (2627).
+const UNIQUE_VIOLATION_ERRORS: [u32; 2] = [2601, 2627];
+
+/// Run the `MERGE` statement for [`SqlServerPool::upsert`], returning the row holding the `$action`.
+async fn run_upsert(
+    conn: &mut Client,
+    table: &str,
+    keys: &[(&str, SqlParam)],
+    values: &[(&str, SqlParam)],
+    timestamps: &[AuditTimestamp],
+    stable_types: bool,
+    output_into: bool,
+) -> Result<tiberius::Row, Error> {
+    let mut merge = Query::new(upsert_statement(
+        table,
+        keys,
+        values,
+        timestamps,
+        output_into,
+    ));
+    for (_, param) in keys.iter().chain(values) {
+        bind_param(&mut merge, param, stable_types);
+    }
+
+    merge
+        .query(conn)
+        .await?
+        .into_row()
+        .await?
+        .ok_or(Error::EmptyResult)
+}
+
+/// Build the `MERGE` statement used by [`SqlServerPool::upsert`] for an already quoted table name.
+/// Parameters are numbered in order: keys first, then values. Audit `timestamps` are set to `SYSUTCDATETIME()`.
+///
+/// Tables with triggers reject a plain `OUTPUT` clause, so with `output_into` the action is output
+/// into a table variable and selected from there instead.
+fn upsert_statement(
+    table: &str,
+    keys: &[(&str, SqlParam)],
+    values: &[(&str, SqlParam)],
+    timestamps: &[AuditTimestamp],
+    output_into: bool,
+) -> String {
+    let columns: Vec<String> = keys
+        .iter()
+        .chain(values)
+        .map(|(name, _)| quote_identifier(name))
+        .collect();
+    let (key_columns, value_columns) = columns.split_at(keys.len());
+
+    let source = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| format!("@P{} AS {column}", i + 1))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let on = key_columns
+        .iter()
+        .map(|column| format!("target.{column} = source.{column}"))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+
+    // With no value columns, a no-op update still lets OUTPUT report the match.
+    let set = if value_columns.is_empty() && !timestamps.iter().any(|t| t.on_update) {
+        key_columns
+    } else {
+        value_columns
+    }
+    .iter()
+    .map(|column| format!("target.{column} = source.{column}"))
+    .chain(
+        timestamps
+            .iter()
+            .filter(|t| t.on_update)
+            .map(|t| format!("target.{} = SYSUTCDATETIME()", t.column)),
+    )
+    .collect::<Vec<_>>()
+    .join(", ");
+
+    let insert_columns = columns
+        .iter()
+        .chain(timestamps.iter().map(|t| &t.column))
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let insert_values = columns
+        .iter()
+        .map(|column| format!("source.{column}"))
+        .chain(timestamps.iter().map(|_| "SYSUTCDATETIME()".to_owned()))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let merge = format!(
+        "MERGE INTO {table} WITH (HOLDLOCK) AS target \
+         USING (SELECT {source}) AS source \
+         ON {on} \
+         WHEN MATCHED THEN UPDATE SET {set} \
+         WHEN NOT MATCHED THEN INSERT ({insert_columns}) VALUES ({insert_values}) \
+         OUTPUT $action"
+    );
+
+    if output_into {
+        format!(
+            "DECLARE @output TABLE ([action] nvarchar(10)); \
+             {merge} INTO @output; \
+             SELECT [action] FROM @output;"
+        )
+    } else {
+        format!("{merge};")
+    }
+}
+
+/// The temp table [`output_into_statement`] outputs into.
+const OUTPUT_TABLE: &str = "#mssql_rs_output";
+
+/// Rewrite a `DELETE` or `INSERT` statement with a plain `OUTPUT` clause to output `INTO` a temp table shaped like the
+/// clause, then select from it, as tables with enabled triggers reject a plain `OUTPUT` clause.
+///
+/// `pseudo_table` is the table the clause reads, `deleted` or `inserted`. The temp table is created from the clause
+/// with `SELECT TOP 0 ... INTO`, aliasing the target as `pseudo_table`, through a `UNION ALL` so identity columns
+/// lose their identity property. Returns the quoted target table and the batch, or `None` unless the statement
+/// names its target directly and has a plain `OUTPUT` clause, e.g. for `DELETE t FROM t JOIN ...`.
+fn output_into_statement(query: &str, pseudo_table: &str) -> Option<(String, String)> {
+    let mut offset = 0;
+    let tokens: Vec<_> = lexer::tokenize(query)
+        .map(|token| {
+            offset += token.text.len();
+            (offset - token.text.len(), token)
+        })
+        .filter(|(_, token)| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment))
+        .collect();
+    let is_word = |i: usize, words: &[&str]| {
+        tokens.get(i).is_some_and(|(_, token)| {
+            token.kind == TokenKind::Word
+                && words.iter().any(|w| token.text.eq_ignore_ascii_case(w))
+        })
+    };
+    // The index after the parenthesised list starting at `i`, if there is one.
+    let skip_parens = |i: usize| {
+        if tokens.get(i).is_none_or(|(_, token)| token.text != "(") {
+            return i;
+        }
+        let mut depth = 0;
+        for (j, (_, token)) in tokens.iter().enumerate().skip(i) {
+            match token.text {
+                "(" => depth += 1,
+                ")" if depth == 1 => return j + 1,
+                ")" => depth -= 1,
+                _ => {}
+            }
+        }
+        tokens.len()
+    };
+    let is_name = |i: usize| {
+        tokens.get(i).is_some_and(|(_, token)| {
+            matches!(token.kind, TokenKind::Word | TokenKind::QuotedIdentifier)
+        })
+    };
+    let start_of = |i: usize| tokens.get(i).map_or(query.len(), |(offset, _)| *offset);
+    let end_of = |i: usize| tokens[i].0 + tokens[i].1.text.len();
+
+    let (target_keyword, list_ends): (&str, &[&str]) = if is_word(0, &["DELETE"]) {
+        ("FROM", &["FROM", "WHERE", "OPTION"])
+    } else if is_word(0, &["INSERT"]) {
+        ("INTO", &["VALUES", "SELECT", "DEFAULT", "EXEC", "EXECUTE"])
+    } else {
+        return None;
+    };
+
+    let mut i = 1;
+    if is_word(i, &["TOP"]) {
+        i = skip_parens(i + 1);
+        if is_word(i, &["PERCENT"]) {
+            i += 1;
+        }
+    }
+    if is_word(i, &[target_keyword]) {
+        i += 1;
+    }
+
+    let table_start = i;
+    if !is_name(i) || is_word(i, &["OUTPUT", "WITH"]) {
+        return None;
+    }
+    i += 1;
+    while tokens.get(i).is_some_and(|(_, token)| token.text == ".") {
+        i += 1;
+        if is_name(i) {
+            i += 1;
+        }
+    }
+    let table = quote_object_name(&query[start_of(table_start)..end_of(i - 1)]).ok()?;
+
+    // Table hints, or an INSERT's column list.
+    if is_word(i, &["WITH"]) {
+        i += 1;
+    }
+    i = skip_parens(skip_parens(i));
+    if !is_word(i, &["OUTPUT"]) {
+        return None;
+    }
+
+    let list_start = i + 1;
+    i = list_start;
+    let mut depth = 0;
+    while let Some((_, token)) = tokens.get(i) {
+        match token.text {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            ";" if depth == 0 => break,
+            _ if depth == 0 && is_word(i, list_ends) => break,
+            _ if depth == 0 && is_word(i, &["INTO"]) => return None,
+            _ => {}
+        }
+        i += 1;
+    }
+    // In `DELETE t OUTPUT ... FROM t JOIN ...`, `t` may be an alias rather than a table.
+    if i == list_start || is_word(i, &["FROM"]) {
+        return None;
+    }
+    let list = &query[start_of(list_start)..end_of(i - 1)];
+    let statement = &query[..end_of(i - 1)];
+    let rest = query[end_of(i - 1)..].trim_end();
+    let terminated = tokens.last().is_some_and(|(_, token)| token.text == ";");
+    let terminator = if terminated { "" } else { ";" };
+
+    // Statements start on new lines, as the caller's may end with a line comment.
+    let batch = format!(
+        "IF OBJECT_ID('tempdb..{OUTPUT_TABLE}') IS NOT NULL DROP TABLE {OUTPUT_TABLE};\n\
+         SELECT TOP 0 {list} INTO {OUTPUT_TABLE} FROM {table} AS {pseudo_table} \
+         UNION ALL SELECT TOP 0 {list} FROM {table} AS {pseudo_table};\n\
+         {statement} INTO {OUTPUT_TABLE}{rest}\n{terminator}\n\
+         SELECT * FROM {OUTPUT_TABLE};\n\
+         DROP TABLE {OUTPUT_TABLE};"
+    );
+    Some((table, batch))
+}
+
+/// Build the batch for [`SqlServerPool::insert_or_get`], returning whether a row was inserted and then the row.
+///
+/// Parameters are numbered in order: keys first, then values.
+fn insert_or_get_statement(
+    table: &str,
+    keys: &[(&str, SqlParam)],
+    values: &[(&str, SqlParam)],
+    timestamps: &[AuditTimestamp],
+) -> String {
+    let columns = keys
+        .iter()
+        .chain(values)
+        .map(|(name, _)| quote_identifier(name))
+        .chain(timestamps.iter().map(|t| t.column.clone()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let placeholders = (1..=keys.len() + values.len())
+        .map(|i| format!("@P{i}"))
+        .chain(timestamps.iter().map(|_| "SYSUTCDATETIME()".to_owned()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let matches_key = key_condition(keys);
+
+    format!(
+        "INSERT INTO {table} ({columns}) SELECT {placeholders} \
+         WHERE NOT EXISTS (SELECT 1 FROM {table} WITH (UPDLOCK, HOLDLOCK) WHERE {matches_key}); \
+         SELECT CAST(@@ROWCOUNT AS bit); \
+         SELECT * FROM {table} WHERE {matches_key};"
+    )
+}
+
+/// Build the query reading back a row by its key for [`SqlServerPool::insert_or_get`].
+fn select_by_key_statement(table: &str, keys: &[(&str, SqlParam)]) -> String {
+    format!("SELECT * FROM {table} WHERE {};", key_condition(keys))
+}
+
+/// Match each key column against its parameter, numbered from `@P1`.
+fn key_condition(keys: &[(&str, SqlParam)]) -> String {
+    keys.iter()
+        .enumerate()
+        .map(|(i, (name, _))| format!("{} = @P{}", quote_identifier(name), i + 1))
+        .collect::<Vec<_>>()
+        .join(" AND ")
+}
+
+/// The marker replaced with a window count by [`SqlServerPool::row_query_counted`].
+const COUNT_MARKER: &str = "{count}";
+
+/// The name of the window count column read by [`SqlServerPool::row_query_counted`].
+const TOTAL_COLUMN: &str = "__total";
+
+/// The default batch size of [`SqlServerPool::row_query_batched`].
+const DEFAULT_FETCH_BUFFER_ROWS: usize = 1024;
+
+/// The default for [`SqlServerPoolBuilder::max_lifetime`], matching bb8's.
+const DEFAULT_MAX_LIFETIME: Duration = Duration::from_secs(30 * 60);
+
+/// The default for [`SqlServerPoolBuilder::reaper_rate`], matching bb8's.
+const DEFAULT_REAPER_RATE: Duration = Duration::from_secs(30);
+
+/// Prepare a query for [`SqlServerPool::row_query_counted`]: replace its `{count}` marker with the window count,
+/// or check that it selects the count column itself.
+fn count_query(query: &str) -> Result<String, Error> {
+    let tokens: Vec<_> = lexer::tokenize(query).collect();
+    let mut counted = String::with_capacity(query.len());
+    let mut markers = 0;
+    let mut selects_total = false;
+
+    let mut i = 0;
+    while let Some(token) = tokens.get(i) {
+        if let Some([open, count, close]) = tokens.get(i..i + 3) {
+            if [open.text, count.text, close.text].concat() == COUNT_MARKER {
+                counted.push_str(&format!("COUNT(*) OVER() AS {TOTAL_COLUMN}"));
+                markers += 1;
+                i += 3;
+                continue;
+            }
+        }
+
+        selects_total |= match token.kind {
+            TokenKind::Word => token.text == TOTAL_COLUMN,
+            TokenKind::QuotedIdentifier => {
+                token.text.get(1..token.text.len() - 1) == Some(TOTAL_COLUMN)
+            }
+            _ => false,
+        };
+        counted.push_str(token.text);
+        i += 1;
+    }
+
+    match markers {
+        0 if selects_total => Ok(counted),
+        0 => Err(Error::InvalidQuery(format!(
+            "the query must contain a {COUNT_MARKER} marker or select a {TOTAL_COLUMN} column"
+        ))),
+        1 => Ok(counted),
+        _ => Err(Error::InvalidQuery(format!(
+            "the query contains {markers} {COUNT_MARKER} markers, but only one count can be selected"
+        ))),
+    }
+}
+
+/// Read the window count from a row, accepting both `COUNT` (int) and `COUNT_BIG` (bigint) results.
+///
+/// The count must be the row's last column, named exactly [`TOTAL_COLUMN`].
+fn read_total(row: &tiberius::Row) -> Result<u64, Error> {
+    let idx = match row.columns().last() {
+        Some(column) if column.name() == TOTAL_COLUMN => row.len() - 1,
+        _ => {
+            return Err(Error::InvalidQuery(format!(
+                "the {TOTAL_COLUMN} column must be the last column of the result"
+            )))
+        }
+    };
+
+    let total = match row.try_get::<i32, _>(idx) {
+        Ok(total) => total.map(i64::from),
+        Err(_) => row.try_get::<i64, _>(idx)?,
+    };
+
+    Ok(total.unwrap_or(0).max(0) as u64)
+}
+
+/// Read a generated identity from the first column of a row, accepting every integer type an identity can have.
+fn read_identity(row: &tiberius::Row) -> Result<i64, Error> {
+    let id = match row.try_get::<i64, _>(0) {
+        Ok(id) => id,
+        Err(_) => match row.try_get::<i32, _>(0) {
+            Ok(id) => id.map(i64::from),
+            Err(_) => match row.try_get::<i16, _>(0) {
+                Ok(id) => id.map(i64::from),
+                Err(_) => row.try_get::<u8, _>(0)?.map(i64::from),
+            },
+        },
+    };
+
+    id.ok_or_else(|| Error::InvalidArgument("the OUTPUT clause returned a NULL id".to_owned()))
+}
+
+/// The main pool and the state sized with it, replaced as a whole by [`SqlServerPool::reconfigure`].
+#[derive(Debug)]
+struct SizedPool {
+    pool: bb8::Pool<ConnectionManager>,
+    low_priority: Arc<Semaphore>,
+    max_size: u32,
+    affinity: Vec<bb8::Pool<ConnectionManager>>,
+    _validator: Option<Validator>,
+}
+
+/// Builds the main pool with the builder's settings, at any size.
+struct PoolFactory {
+    manager_builder: ConnectionManagerBuilder,
+    config: tiberius::Config,
+    connection_timeout: Duration,
+    max_lifetime: Option<Duration>,
+    reaper_rate: Duration,
+    validate_on_checkout: bool,
+    high_priority_reserve: u32,
+    background_validation: Option<Duration>,
+    validation_stats: Arc<ValidationStats>,
+    clock: Arc<dyn Clock>,
+    affinity_shards: u32,
+    initial_max_size: u32,
+}
+
+impl std::fmt::Debug for PoolFactory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolFactory")
+            .field("connection_timeout", &self.connection_timeout)
+            .field("max_lifetime", &self.max_lifetime)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PoolFactory {
+    async fn build(&self, max_size: u32) -> Result<SizedPool, Error> {
+        if max_size == 0 {
+            // bb8 panics on a size of zero, so report it as a configuration error instead.
+            return Err(Error::InvalidConfig(
+                "pool_max_size must be at least 1, a pool of size 0 can never hand out a connection".into(),
+            ));
+        }
+
+        let pool = bb8::Pool::builder()
+            .max_size(max_size)
+            .connection_timeout(self.connection_timeout)
+            .max_lifetime(self.max_lifetime)
+            .reaper_rate(self.reaper_rate)
+            .test_on_check_out(self.validate_on_checkout)
+            .build(self.manager_builder.build(self.config.clone())?)
+            .await?;
+
+        // Shard connections aren't part of the main pool, so they aren't reported with its ages.
+        let mut shard_manager = self.manager_builder.clone();
+        shard_manager.ages(Arc::default());
+        let shards = affinity_shard_count(self.affinity_shards, self.initial_max_size, max_size);
+        let mut affinity = Vec::with_capacity(shards as usize);
+        for _ in 0..shards {
+            let shard = bb8::Pool::builder()
+                .max_size(1)
+                .connection_timeout(self.connection_timeout)
+                .max_lifetime(self.max_lifetime)
+                .reaper_rate(self.reaper_rate)
+                .test_on_check_out(self.validate_on_checkout)
+                .build(shard_manager.build(self.config.clone())?)
+                .await?;
+            affinity.push(shard);
+        }
+
+        let validator = self.background_validation.map(|interval| {
+            Validator::spawn(
+                std::iter::once(&pool).chain(&affinity).cloned().collect(),
+                interval,
+                self.validation_stats.clone(),
+                self.clock.clone(),
+            )
+        });
+
+        let low_priority_permits = max_size.saturating_sub(self.high_priority_reserve).max(1);
+
+        Ok(SizedPool {
+            pool,
+            low_priority: Arc::new(Semaphore::new(low_priority_permits as usize)),
+            max_size,
+            affinity,
+            _validator: validator,
+        })
+    }
+}
+
+/// The number of affinity shards of a pool of `max_size` connections, when `configured` shards were asked for at
+/// `initial_max_size`: scaled with the pool, rounding up so a pool with shards keeps at least one.
+fn affinity_shard_count(configured: u32, initial_max_size: u32, max_size: u32) -> u32 {
+    if initial_max_size == 0 {
+        return configured;
+    }
+    (u64::from(configured) * u64::from(max_size))
+        .div_ceil(u64::from(initial_max_size))
+        .try_into()
+        .unwrap_or(u32::MAX)
+}
+
+/// A builder for a `SqlServerPool`
+///
+/// The builder provides configuration options for the maximum pool size, connection timeout, and whether to use SQL Browser.
+pub struct SqlServerPoolBuilder {
+    pool_max_size: u32,
+    pool_connection_timeout: std::time::Duration,
+    use_sql_browser: bool,
+    tcp_nodelay: bool,
+    plaintext: bool,
+    credentials_provider: Option<CredentialsProvider>,
+    high_priority_reserve: u32,
+    minimum_server_version: ServerVersion,
+    affinity_shards: u32,
+    stable_param_types: bool,
+    pub(crate) observer: Option<Arc<dyn ConnectionObserver>>,
+    #[cfg(feature = "protocol-debug")]
+    protocol_trace: Option<ProtocolTrace>,
+    resume_timeout: Option<Duration>,
+    transformers: Vec<Arc<dyn RowTransformer>>,
+    readiness_query: Option<String>,
+    codecs: Vec<(String, String, Arc<dyn ColumnCodec>)>,
+    fetch_buffer_rows: usize,
+    yield_every_rows: usize,
+    on_broken: OnBroken,
+    validate_on_checkout: bool,
+    background_validation: Option<Duration>,
+    max_in_flight: Option<usize>,
+    max_queue_depth: Option<usize>,
+    lock_settings: LockSettings,
+    query_rate_limit: (Option<Rate>, Vec<(FingerprintPattern, Rate)>),
+    query_rate_limit_queue: u32,
+    snapshot_fallback: bool,
+    max_query_length: Option<usize>,
+    max_rows: Option<usize>,
+    max_lifetime: Option<Duration>,
+    audit_columns: Option<AuditConfig>,
+    truncation_policies: Vec<(String, String, TruncationPolicy)>,
+    reaper_rate: Duration,
+    #[cfg(feature = "test-util")]
+    fault_injector: Option<Arc<dyn FaultInjector>>,
+    pub(crate) clock: Arc<dyn Clock>,
+    session_options: SessionOptionsPreset,
+}
+
+impl SqlServerPoolBuilder {
+    /// Create a new builder for configuring a `SqlServerPool`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Build a `SqlServerPool` using the provided configuration.
+    pub async fn build(&self, mut config: tiberius::Config) -> Result<SqlServerPool, Error> {
+        if self.plaintext {
+            config.encryption(EncryptionLevel::NotSupported);
+            tracing::warn!(
+                "mssql_rs pool built with plaintext_dangerous, connections are not encrypted. \
+                 Never use this outside local development."
+            );
+        }
+
+        if self.reaper_rate.is_zero() {
+            // The reaper's interval panics on a period of zero.
+            return Err(Error::InvalidConfig(
+                "reaper_rate must be greater than zero".into(),
+            ));
+        }
+
+        if self.max_in_flight == Some(0) {
+            return Err(Error::InvalidConfig(
+                "max_in_flight must be at least 1, a limit of 0 can never run a query".into(),
+            ));
+        }
+
+        let mut manager_builder = ConnectionManagerBuilder::new();
+        manager_builder
+            .use_sql_browser(self.use_sql_browser)
+            .tcp_nodelay(self.tcp_nodelay)
+            .credentials_provider(self.credentials_provider.clone())
+            .minimum_server_version(self.minimum_server_version)
+            .observer(self.observer.clone());
+        #[cfg(feature = "protocol-debug")]
+        manager_builder.protocol_trace(self.protocol_trace.clone());
+        let resuming = Arc::new(AtomicBool::new(false));
+        manager_builder.resume_timeout(self.resume_timeout, resuming.clone());
+        manager_builder.clock(self.clock.clone());
+        manager_builder.session_options(self.session_options.batch()?);
+        let ages = Arc::new(ConnectionAges::default());
+        manager_builder.ages(ages.clone());
+        let bytes = Arc::new(ByteCounters::default());
+        manager_builder.bytes(bytes.clone());
+        manager_builder.yield_every_rows(self.yield_every_rows);
+        manager_builder.on_broken(self.on_broken);
+        manager_builder.max_lifetime(self.max_lifetime);
+
+        let validation_stats = Arc::new(ValidationStats::default());
+        let factory = PoolFactory {
+            manager_builder: manager_builder.clone(),
+            config: config.clone(),
+            connection_timeout: self.pool_connection_timeout,
+            max_lifetime: self.max_lifetime,
+            reaper_rate: self.reaper_rate,
+            validate_on_checkout: self.validate_on_checkout,
+            high_priority_reserve: self.high_priority_reserve,
+            background_validation: self.background_validation,
+            validation_stats: validation_stats.clone(),
+            clock: self.clock.clone(),
+            affinity_shards: self.affinity_shards,
+            initial_max_size: self.pool_max_size,
+        };
+        let sized = factory.build(self.pool_max_size).await?;
+
+        let mut codecs = ColumnCodecs::default();
+        for (table, column, codec) in &self.codecs {
+            codecs.insert(table, column, codec.clone())?;
+        }
+
+        Ok(SqlServerPool {
+            sized: Arc::new(RwLock::new(Arc::new(sized))),
+            factory: Arc::new(factory),
+            stable_param_types: self.stable_param_types,
+            output_into_tables: Arc::default(),
+            #[cfg(feature = "protocol-debug")]
+            protocol_trace: self.protocol_trace.clone(),
+            resume_timeout: self.resume_timeout,
+            resuming,
+            transformers: self.transformers.clone().into(),
+            readiness_query: self.readiness_query.as_deref().map(Arc::from),
+            codecs: Arc::new(codecs),
+            fetch_buffer_rows: self.fetch_buffer_rows,
+            validation_stats,
+            in_flight: self
+                .max_in_flight
+                .map(|max| Arc::new(InFlightLimiter::new(max, self.max_queue_depth))),
+            snapshot_fallback: self.snapshot_fallback,
+            max_query_length: self.max_query_length,
+            max_rows: self.max_rows,
+            ages,
+            auditor: self
+                .audit_columns
+                .clone()
+                .map(|config| Arc::new(Auditor::new(config))),
+            truncator: Truncator::new(&self.truncation_policies, self.observer.clone())?
+                .map(Arc::new),
+            rate_limiter: RateLimiter::new(
+                self.query_rate_limit.0,
+                self.query_rate_limit.1.clone(),
+                self.query_rate_limit_queue,
+            )
+            .map(Arc::new),
+            observer: self.observer.clone(),
+            lock_settings: self.lock_settings,
+            #[cfg(feature = "test-util")]
+            faults: Faults::new(self.fault_injector.clone(), self.clock.clone()),
+            #[cfg(not(feature = "test-util"))]
+            faults: Faults::default(),
+            clock: self.clock.clone(),
+            write_behind: Arc::default(),
+            bytes,
+        })
+    }
+    /// Set the maximum pool size. Defaults to 3. Must be at least 1, or [`SqlServerPoolBuilder::build`] fails.
+    pub fn pool_max_size(&mut self, pool_max_size: u32) -> &mut Self {
+        self.pool_max_size = pool_max_size;
+        self
+    }
+    /// Set whether to use SQL Browser. Defaults to false.
+    pub fn use_sql_browser(&mut self, yes: bool) -> &mut Self {
+        self.use_sql_browser = yes;
+        self
+    }
+    /// Set whether connections disable Nagle's algorithm with `TCP_NODELAY`. Defaults to true.
+    ///
+    /// A typical query is a small request followed by a wait for the response. With Nagle's algorithm, a request
+    /// packet that doesn't fill a segment can be held until the previous one is acknowledged, and with delayed
+    /// acknowledgements on the server that can add tens of milliseconds to every round trip. Keep the default for
+    /// query workloads. Turning it off lets the kernel coalesce many small writes into fewer segments, which may
+    /// help connections that mostly stream large requests, e.g. bulk inserts over a high-latency link. tiberius
+    /// already writes whole TDS packets, so measure before and after; the gain is usually small.
+    pub fn tcp_nodelay(&mut self, yes: bool) -> &mut Self {
+        self.tcp_nodelay = yes;
+        self
+    }
+    /// Connect without TLS, sending credentials and data in plaintext, e.g. to a local development container with
+    /// TLS disabled. Overrides the encryption level of the config passed to [`SqlServerPoolBuilder::build`].
+    ///
+    /// Anyone on the network path can read and alter the traffic, so never use this outside local development.
+    /// Every pool built with it logs a `tracing` warning, so a config copied from a dev setup is noticed.
+    pub fn plaintext_dangerous(&mut self) -> &mut Self {
+        self.plaintext = true;
+        self
+    }
+    /// Set the connection timeout. Defaults to 5 seconds.
+    pub fn pool_connection_timeout(
+        &mut self,
+        pool_connection_timeout: std::time::Duration,
+    ) -> &mut Self {
+        self.pool_connection_timeout = pool_connection_timeout;
+        self
+    }
+    /// Set a provider for the credentials used by new connections, overriding the authentication in the config.
+    /// The provider is consulted for every new physical connection, so rotated secrets are picked up without rebuilding the pool.
+    ///
+    /// See [`Credentials::from_files`](crate::Credentials::from_files) for reading mounted secret files.
+    pub fn credentials_provider(&mut self, provider: CredentialsProvider) -> &mut Self {
+        self.credentials_provider = Some(provider);
+        self
+    }
+    /// Set the number of connections that [`Priority::Low`] requests can't use. Defaults to 1.
+    pub fn high_priority_reserve(&mut self, high_priority_reserve: u32) -> &mut Self {
+        self.high_priority_reserve = high_priority_reserve;
+        self
+    }
+    /// Set the minimum server version new connections accept. Defaults to SQL Server 2008, the earliest version tiberius supports.
+    /// Connecting to an older server fails with [`Error::UnsupportedServerVersion`].
+    pub fn minimum_server_version(&mut self, version: ServerVersion) -> &mut Self {
+        self.minimum_server_version = version;
+        self
+    }
+    /// Set the number of single-connection shards used by [`SqlServerPool::row_query_affine`]. Defaults to 0.
+    /// Shard connections are in addition to the `pool_max_size` connections of the main pool, and
+    /// [`SqlServerPool::reconfigure`] scales their number with the pool. They are validated along with the main
+    /// pool's, but aren't counted in [`SqlServerPool::status`], [`SqlServerPool::pool_state`] or
+    /// [`SqlServerPool::connection_ages`].
+    pub fn affinity_shards(&mut self, affinity_shards: u32) -> &mut Self {
+        self.affinity_shards = affinity_shards;
+        self
+    }
+    /// Set whether [`SqlParam`] parameters are bound with stable declared types. Defaults to false.
+    ///
+    /// Queries are sent via `sp_executesql`, and every distinct set of declared parameter types gets its own cached plan.
+    /// tiberius declares numerics as `numeric(p, s)` using each value's own precision, so repeated calls
+    /// with different values can fill the plan cache with near-duplicates.
+    /// With stable types, numerics are sent as text instead, so the declaration is the same for every call.
+    /// The trade-off is an implicit conversion on the server, which has a small cost and follows the usual
+    /// conversion rules. Strings and binaries are already declared with fixed lengths by tiberius.
+    ///
+    /// This can be overridden per query with [`QueryOptions::stable_param_types`].
+    pub fn stable_param_types(&mut self, yes: bool) -> &mut Self {
+        self.stable_param_types = yes;
+        self
+    }
+    /// Set an observer for connection lifecycle events (connect, validate, broken, close).
+    pub fn observer(&mut self, observer: Arc<dyn ConnectionObserver>) -> &mut Self {
+        self.observer = Some(observer);
+        self
+    }
+    /// Set what the pool does with connections found broken on return. Defaults to [`OnBroken::Discard`].
+    ///
+    /// With [`OnBroken::DiscardAndAlert`], the [`observer`](SqlServerPoolBuilder::observer) is also alerted with
+    /// [`ConnectionObserver::on_broken_alert`], e.g. to page someone when connections keep breaking.
+    pub fn on_broken(&mut self, policy: OnBroken) -> &mut Self {
+        self.on_broken = policy;
+        self
+    }
+    /// Set a trace to record the TDS packet headers of every connection in the pool. Defaults to no trace.
+    ///
+    /// Payload data is never recorded, see [`ProtocolTrace`]. Requires the `protocol-debug` feature.
+    #[cfg(feature = "protocol-debug")]
+    pub fn protocol_trace(&mut self, trace: ProtocolTrace) -> &mut Self {
+        self.protocol_trace = Some(trace);
+        self
+    }
+    /// Set how long to keep retrying while an Azure SQL database is resuming. Defaults to no resume handling.
+    ///
+    /// A serverless database that has auto-paused rejects connections with error 40613 until it resumes,
+    /// which can take far longer than `pool_connection_timeout`. With a resume timeout, connects that fail this
+    /// way are retried for up to `timeout`, and checkouts keep waiting while they are, so the first query after a
+    /// pause succeeds slowly instead of failing. [`ConnectionObserver::on_resume`] is called once connected.
+    /// All other connect failures still fail within `pool_connection_timeout`.
+    pub fn resume_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.resume_timeout = Some(timeout);
+        self
+    }
+    /// Add a transformer applied to the values of every [`SqlServerPool::row_query_dynamic`] query.
+    /// Transformers run in the order they are added. Defaults to none.
+    pub fn row_transformer(&mut self, transformer: Arc<dyn RowTransformer>) -> &mut Self {
+        self.transformers.push(transformer);
+        self
+    }
+    /// Set the query run by [`SqlServerPool::ready`], e.g. `SELECT TOP 1 id FROM critical_table`.
+    /// Defaults to the validation query, `SELECT 1`.
+    pub fn readiness_query(&mut self, query: String) -> &mut Self {
+        self.readiness_query = Some(query);
+        self
+    }
+    /// Encrypt and decrypt the values of `table`'s `column` with `codec`. Defaults to no codecs.
+    ///
+    /// Tables are matched by their unqualified name and columns by name, both case-insensitively.
+    /// See [`ColumnCodec`] for where codecs are applied.
+    pub fn column_codec(
+        &mut self,
+        table: &str,
+        column: &str,
+        codec: Arc<dyn ColumnCodec>,
+    ) -> &mut Self {
+        self.codecs
+            .push((table.to_owned(), column.to_owned(), codec));
+        self
+    }
+    /// Set how many rows [`SqlServerPool::row_query_batched`] buffers before passing them on. Defaults to 1024.
+    pub fn fetch_buffer_rows(&mut self, rows: usize) -> &mut Self {
+        self.fetch_buffer_rows = rows;
+        self
+    }
+    /// Set how many rows of a result are read between yields to the tokio runtime, or 0 to never yield.
+    /// Defaults to 1024.
+    ///
+    /// Rows tiberius has already buffered convert without yielding, so a large result, e.g. millions of rows for
+    /// [`SqlServerPool::row_query`], could otherwise hold a worker thread for as long as it takes to read, delaying
+    /// other tasks on it. This applies to every method reading rows, including streams and JSON results.
+    pub fn yield_every_rows(&mut self, rows: usize) -> &mut Self {
+        self.yield_every_rows = rows;
+        self
+    }
+    /// Set whether connections are validated each time they are checked out. Defaults to true.
+    ///
+    /// Validation costs a round trip per checkout. Without it, a dead idle connection is only discovered by the
+    /// query that gets it, unless [`SqlServerPoolBuilder::background_validation`] finds it first.
+    pub fn validate_on_checkout(&mut self, yes: bool) -> &mut Self {
+        self.validate_on_checkout = yes;
+        self
+    }
+    /// Validate idle connections in the background every `interval`, discarding those that fail so replacements
+    /// are opened off the request path. Defaults to off.
+    ///
+    /// Idle connections are checked out one at a time, and only while some are idle, so queries don't wait on it.
+    /// The results are reported by [`SqlServerPool::status`]. The task stops when the last clone of the pool is dropped.
+    pub fn background_validation(&mut self, interval: Duration) -> &mut Self {
+        self.background_validation = Some(interval);
+        self
+    }
+    /// Set the maximum number of checkouts in flight across the pool, or `None` for no limit. Defaults to no limit.
+    ///
+    /// A checkout holds its slot until the connection is returned, covering the time spent converting rows as well as
+    /// the query itself, so this bounds client-side work that `pool_max_size` doesn't. With a limit above the pool size,
+    /// the extra checkouts wait for a connection as usual. Applies to every query method and
+    /// [`SqlServerPool::get_with_priority`], but not to background validation.
+    pub fn max_in_flight(&mut self, max_in_flight: Option<usize>) -> &mut Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+    /// Set the maximum number of checkouts waiting for an in-flight slot, or `None` for no limit. Defaults to no limit.
+    ///
+    /// Checkouts beyond the limit fail immediately with [`Error::Overloaded`] rather than queueing, so callers can shed
+    /// load. Has no effect without [`SqlServerPoolBuilder::max_in_flight`].
+    pub fn max_queue_depth(&mut self, max_queue_depth: Option<usize>) -> &mut Self {
+        self.max_queue_depth = max_queue_depth;
+        self
+    }
+    /// Set the `DEADLOCK_PRIORITY` of every connection. Defaults to [`DeadlockPriority::Normal`].
+    ///
+    /// Give a pool for background jobs [`DeadlockPriority::Low`], so they are the victim when they deadlock with
+    /// interactive traffic, and override it per call with [`QueryOptions::deadlock_priority`]. The priority is set
+    /// when a connection is checked out, and only sent when it differs from the one the connection last ran with, so
+    /// a call's override never leaks into the next checkout. A query's own `SET DEADLOCK_PRIORITY` isn't tracked and
+    /// persists on the pooled connection.
+    pub fn deadlock_priority(&mut self, priority: DeadlockPriority) -> &mut Self {
+        self.lock_settings.deadlock_priority = priority;
+        self
+    }
+    /// Set the `LOCK_TIMEOUT` of every connection, how long a statement waits for a lock before failing. Defaults
+    /// to `None`, waiting indefinitely as the server does.
+    ///
+    /// Let a batch pool wait patiently while an interactive pool fails fast, and override it per call with
+    /// [`QueryOptions::lock_timeout`]. Set like [`SqlServerPoolBuilder::deadlock_priority`]. Timeouts are rounded down
+    /// to milliseconds, so one under a millisecond fails as soon as a lock isn't free.
+    pub fn lock_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.lock_settings.lock_timeout = timeout;
+        self
+    }
+    /// Limit the rate of each distinct query, so one misbehaving caller can't flood the database with a single
+    /// expensive query. Defaults to no limit.
+    ///
+    /// Queries are told apart by fingerprint, see [`FingerprintPattern`], and each fingerprint has its own token
+    /// bucket, limited by the rate of the first override matching it, or else by `default`. With a `default` of
+    /// `None`, only the overrides are limited. A query beyond its rate fails with [`Error::RateLimited`] before it
+    /// checks out a connection, unless [`SqlServerPoolBuilder::query_rate_limit_queue`] lets it wait. Buckets are
+    /// kept for the 1024 most recently used fingerprints. Applies to the query methods taking query text, but not
+    /// to queries run directly on a [`PooledConnection`].
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{FingerprintPattern, Rate, SqlServerPoolBuilder};
+    /// # use std::time::Duration;
+    /// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+    /// let pool = SqlServerPoolBuilder::new()
+    ///     .query_rate_limit(
+    ///         Some(Rate::per_second(200)),
+    ///         vec![(FingerprintPattern::Contains("dbo.report_totals".into()), Rate::new(10, Duration::from_secs(60)))],
+    ///     )
+    ///     .build(cfg)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn query_rate_limit(
+        &mut self,
+        default: Option<Rate>,
+        overrides: Vec<(FingerprintPattern, Rate)>,
+    ) -> &mut Self {
+        self.query_rate_limit = (default, overrides);
+        self
+    }
+    /// Set how many queries per fingerprint may wait for their turn when over their
+    /// [`SqlServerPoolBuilder::query_rate_limit`], rather than failing with [`Error::RateLimited`]. Defaults to 0,
+    /// failing fast.
+    pub fn query_rate_limit_queue(&mut self, max_queued: u32) -> &mut Self {
+        self.query_rate_limit_queue = max_queued;
+        self
+    }
+    /// Set whether [`SqlServerPool::read_snapshot`] falls back to `REPEATABLE READ` in databases that don't allow
+    /// snapshot isolation, rather than failing. Defaults to false.
+    pub fn snapshot_fallback(&mut self, yes: bool) -> &mut Self {
+        self.snapshot_fallback = yes;
+        self
+    }
+    /// Set the maximum length in bytes of query text, rejecting longer queries with [`Error::QueryTooLong`] before
+    /// they are sent. Defaults to no limit.
+    ///
+    /// This guards against runaway dynamic SQL, e.g. an `IN` list generated from an unbounded input.
+    /// It applies to the queries passed to the pool's methods, not to those run directly on a [`PooledConnection`].
+    pub fn max_query_length(&mut self, max_query_length: usize) -> &mut Self {
+        self.max_query_length = Some(max_query_length);
+        self
+    }
+    /// Set the most rows [`SqlServerPool::row_query`] buffers, failing with [`Error::TooManyRows`] once a result
+    /// has more, rather than exhausting memory. Defaults to no limit, and [`QueryOptions::max_rows`] overrides it per
+    /// call.
+    ///
+    /// The rest of a result cut short isn't read, so its connection is discarded. Results too large to buffer are
+    /// better streamed, e.g. with [`SqlServerPool::resumable_stream`].
+    pub fn max_rows(&mut self, max_rows: usize) -> &mut Self {
+        self.max_rows = Some(max_rows);
+        self
+    }
+    /// Set how long a connection is kept before it is closed and replaced, or `None` to keep connections
+    /// indefinitely. Defaults to 30 minutes. See [`SqlServerPool::connection_ages`] for tuning it.
+    pub fn max_lifetime(&mut self, max_lifetime: Option<Duration>) -> &mut Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+    /// Set how often idle connections past their [`max_lifetime`](SqlServerPoolBuilder::max_lifetime), or idle for
+    /// more than 10 minutes, are closed. Defaults to 30 seconds.
+    ///
+    /// A shorter interval replaces expired connections sooner, e.g. after a failover, but each run locks the pool
+    /// and scans every idle connection, so an interval of milliseconds costs CPU and contention for little benefit.
+    /// The reaper only checks ages: to find dead connections, see
+    /// [`background_validation`](SqlServerPoolBuilder::background_validation).
+    pub fn reaper_rate(&mut self, reaper_rate: Duration) -> &mut Self {
+        self.reaper_rate = reaper_rate;
+        self
+    }
+    /// Set audit columns that [`SqlServerPool::upsert`] sets automatically on tables that have them. Defaults to none.
+    ///
+    /// Which columns a table has is read from `sys.columns` on its first upsert and cached for the pool's lifetime.
+    /// A value the caller passes for an audit column is used instead of the automatic one.
+    pub fn audit_columns(&mut self, config: AuditConfig) -> &mut Self {
+        self.audit_columns = Some(config);
+        self
+    }
+    /// Set how [`SqlServerPool::upsert`] and [`SqlServerPool::insert_or_get`] handle a string too long for `table`'s
+    /// `nvarchar` or `nchar` `column`. Defaults to [`TruncationPolicy::Error`] for every column.
+    ///
+    /// Tables are matched by their unqualified name and columns by name, both case-insensitively, as for
+    /// [`SqlServerPoolBuilder::column_codec`]. Column lengths are read from `sys.columns` on a table's first write
+    /// and cached for the pool's lifetime. Only the written values are truncated, never the key columns, and values
+    /// are truncated before any codec encrypts them.
+    pub fn truncation_policy(
+        &mut self,
+        table: &str,
+        column: &str,
+        policy: TruncationPolicy,
+    ) -> &mut Self {
+        self.truncation_policies
+            .push((table.to_owned(), column.to_owned(), policy));
+        self
+    }
+    /// Set a fault injector for resilience testing, see [`FaultInjector`]. Defaults to none.
+    ///
+    /// Requires the `test-util` feature, so release builds without it can't inject faults.
+    #[cfg(feature = "test-util")]
+    pub fn fault_injector(&mut self, injector: Arc<dyn FaultInjector>) -> &mut Self {
+        self.fault_injector = Some(injector);
+        self
+    }
+    /// Set the clock the pool's own timeouts, retry delays and background validation use, e.g. a [`MockClock`](crate::MockClock)
+    /// in tests. Defaults to tokio's timers.
+    ///
+    /// bb8's checkout timeout, [`SqlServerPoolBuilder::max_lifetime`] and the idle reaper always use real time.
+    /// Requires the `test-util` feature.
+    #[cfg(feature = "test-util")]
+    pub fn clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+    /// Set the `SET` options applied to each new connection, see [`SessionOptionsPreset`]. Defaults to
+    /// [`SessionOptionsPreset::ServerDefault`], leaving them as the server sets them.
+    ///
+    /// Use [`SessionOptionsPreset::SsmsCompatible`] when a query is fast in SSMS but slow from the application,
+    /// so both get the same plan. Options changed by a query's own `SET` statements persist on the pooled connection.
+    pub fn session_options(&mut self, preset: SessionOptionsPreset) -> &mut Self {
+        self.session_options = preset;
+        self
+    }
+}
+
+impl Default for SqlServerPoolBuilder {
+    fn default() -> Self {
+        Self {
+            pool_max_size: 3,
+            use_sql_browser: false,
+            tcp_nodelay: true,
+            plaintext: false,
+            pool_connection_timeout: std::time::Duration::from_secs(5),
+            credentials_provider: None,
+            high_priority_reserve: 1,
+            minimum_server_version: ServerVersion::SQL_SERVER_2008,
+            affinity_shards: 0,
+            stable_param_types: false,
+            observer: None,
+            #[cfg(feature = "protocol-debug")]
+            protocol_trace: None,
+            resume_timeout: None,
+            transformers: Vec::new(),
+            readiness_query: None,
+            codecs: Vec::new(),
+            fetch_buffer_rows: DEFAULT_FETCH_BUFFER_ROWS,
+            yield_every_rows: DEFAULT_YIELD_EVERY_ROWS,
+            on_broken: OnBroken::default(),
+            validate_on_checkout: true,
+            background_validation: None,
+            max_in_flight: None,
+            max_queue_depth: None,
+            lock_settings: LockSettings::default(),
+            query_rate_limit: (None, Vec::new()),
+            query_rate_limit_queue: 0,
+            snapshot_fallback: false,
+            max_query_length: None,
+            max_rows: None,
+            max_lifetime: Some(DEFAULT_MAX_LIFETIME),
+            audit_columns: None,
+            truncation_policies: Vec::new(),
+            reaper_rate: DEFAULT_REAPER_RATE,
+            #[cfg(feature = "test-util")]
+            fault_injector: None,
+            clock: Arc::new(TokioClock),
+            session_options: SessionOptionsPreset::ServerDefault,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn affinity_shards_scale_with_the_pool() {
+        assert_eq!(affinity_shard_count(4, 10, 10), 4);
+        assert_eq!(affinity_shard_count(4, 10, 20), 8);
+        assert_eq!(affinity_shard_count(4, 10, 5), 2);
+        assert_eq!(affinity_shard_count(4, 10, 1), 1);
+        assert_eq!(affinity_shard_count(0, 10, 20), 0);
+        assert_eq!(affinity_shard_count(3, 8, 10), 4);
+    }
+
+    #[test]
+    fn output_into_rewrites_a_delete() {
+        let (table, batch) = output_into_statement(
+            "DELETE FROM dbo.sessions OUTPUT deleted.id, deleted.user_id WHERE expired = 1",
+            "deleted",
+        )
+        .unwrap();
+        assert_eq!(table, "[dbo].[sessions]");
+        assert_eq!(
+            batch,
+            "IF OBJECT_ID('tempdb..#mssql_rs_output') IS NOT NULL DROP TABLE #mssql_rs_output;\n\
+             SELECT TOP 0 deleted.id, deleted.user_id INTO #mssql_rs_output FROM [dbo].[sessions] AS deleted \
+             UNION ALL SELECT TOP 0 deleted.id, deleted.user_id FROM [dbo].[sessions] AS deleted;\n\
+             DELETE FROM dbo.sessions OUTPUT deleted.id, deleted.user_id INTO #mssql_rs_output WHERE expired = 1\n;\n\
+             SELECT * FROM #mssql_rs_output;\n\
+             DROP TABLE #mssql_rs_output;"
+        );
+    }
+
+    #[test]
+    fn output_into_rewrites_an_insert() {
+        let (table, batch) = output_into_statement(
+            "INSERT INTO [people] (name, note) OUTPUT inserted.id VALUES (@P1, 'OUTPUT'), (@P2, NULL);",
+            "inserted",
+        )
+        .unwrap();
+        assert_eq!(table, "[people]");
+        assert!(batch
+            .contains("SELECT TOP 0 inserted.id INTO #mssql_rs_output FROM [people] AS inserted"));
+        assert!(batch.contains(
+            "INSERT INTO [people] (name, note) OUTPUT inserted.id INTO #mssql_rs_output \
+             VALUES (@P1, 'OUTPUT'), (@P2, NULL);\n\nSELECT * FROM #mssql_rs_output;"
+        ));
+    }
+
+    #[test]
+    fn output_into_skips_hints_top_and_comments() {
+        let (table, batch) = output_into_statement(
+            "DELETE TOP (10) sessions WITH (ROWLOCK) OUTPUT deleted.* -- audit\nWHERE id > 1 -- old",
+            "deleted",
+        )
+        .unwrap();
+        assert_eq!(table, "[sessions]");
+        assert!(batch.contains("SELECT TOP 0 deleted.* INTO #mssql_rs_output FROM [sessions]"));
+        assert!(batch
+            .contains("OUTPUT deleted.* INTO #mssql_rs_output -- audit\nWHERE id > 1 -- old\n;\n"));
+
+        let (_, batch) = output_into_statement(
+            "INSERT TOP (1) t WITH (TABLOCK) (a) OUTPUT inserted.id, CAST(inserted.a AS int) SELECT a FROM s",
+            "inserted",
+        )
+        .unwrap();
+        assert!(batch.contains(
+            "OUTPUT inserted.id, CAST(inserted.a AS int) INTO #mssql_rs_output SELECT a FROM s"
+        ));
+    }
+
+    #[test]
+    fn output_into_leaves_other_statements_alone() {
+        for query in [
+            "DELETE s OUTPUT deleted.id FROM sessions s JOIN users u ON u.id = s.user_id",
+            "DELETE FROM sessions OUTPUT deleted.id INTO @ids WHERE 1 = 1",
+            "DELETE FROM sessions WHERE 1 = 1",
+            "UPDATE t SET a = 1 OUTPUT inserted.id",
+            "DELETE FROM sessions OUTPUT WHERE 1 = 1",
+        ] {
+            assert_eq!(output_into_statement(query, "deleted"), None, "{query}");
+        }
+    }
+
+    #[test]
+    fn count_query_replaces_the_marker() {
+        assert_eq!(
+            count_query("SELECT id, {count} FROM t ORDER BY id").unwrap(),
+            "SELECT id, COUNT(*) OVER() AS __total FROM t ORDER BY id"
+        );
+    }
+
+    #[test]
+    fn count_query_keeps_markers_in_literals_and_comments() {
+        let query = "SELECT id, '{count}' AS note, {count} /* {count} */ FROM t -- {count}";
+        assert_eq!(
+            count_query(query).unwrap(),
+            "SELECT id, '{count}' AS note, COUNT(*) OVER() AS __total /* {count} */ FROM t -- {count}"
+        );
+    }
+
+    #[test]
+    fn count_query_accepts_a_selected_total() {
+        for query in [
+            "SELECT id, COUNT(*) OVER() AS __total FROM t",
+            "SELECT id, COUNT(*) OVER() AS [__total] FROM t",
+            "SELECT id, COUNT(*) OVER() AS \"__total\" FROM t",
+        ] {
+            assert_eq!(count_query(query).unwrap(), query);
+        }
+    }
+
+    #[test]
+    fn count_query_requires_the_exact_total_name() {
+        for query in [
+            "SELECT id, COUNT(*) OVER() AS __totals FROM t",
+            "SELECT id, COUNT(*) OVER() AS my__total FROM t",
+            "SELECT id, '__total' FROM t",
+            "SELECT id FROM t -- __total",
+            "SELECT id, { count } FROM t",
+        ] {
+            assert!(
+                matches!(count_query(query), Err(Error::InvalidQuery(_))),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn count_query_rejects_several_markers() {
+        let err = count_query("SELECT {count}, {count} FROM t").unwrap_err();
+        assert!(matches!(err, Error::InvalidQuery(_)));
+        assert_eq!(err.kind(), crate::ErrorKind::InvalidInput);
     }
 }
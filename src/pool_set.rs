@@ -0,0 +1,212 @@
+use crate::{
+    error::Error, pool::PoolStatus, pool::SqlServerPool, pool::SqlServerPoolBuilder,
+    typed::TypedPool,
+};
+use futures_util::future::join_all;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tiberius::{Config, EncryptionLevel};
+
+/// The TLS settings applied to a host's config by a [`PoolSet`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostTls {
+    /// Require encryption, validating the server certificate as configured (by default, against the system trust store).
+    Required,
+    /// Require encryption, but accept the server certificate without validating it.
+    /// Intended for legacy internal servers with self-signed certificates.
+    ///
+    /// tiberius panics if this is combined with a config that already uses `trust_cert_ca`.
+    TrustCert,
+}
+
+impl HostTls {
+    fn apply(self, config: &mut Config) {
+        config.encryption(EncryptionLevel::Required);
+        if self == HostTls::TrustCert {
+            config.trust_cert();
+        }
+    }
+}
+
+/// A set of pools, one per host, with TLS settings chosen per host in one place.
+///
+/// One pool has one config, so servers that need different TLS settings need separate pools.
+/// A `PoolSet` keeps those settings together rather than scattered across each config.
+///
+/// # Example
+///
+/// ```no_run
+/// # use mssql_rs::{HostTls, PoolSet, SqlServerPoolBuilder};
+/// # async fn example(main: tiberius::Config, legacy: tiberius::Config) -> mssql_rs::Result<()> {
+/// let pools = PoolSet::builder(SqlServerPoolBuilder::new())
+///     .tls_override("legacy-db01", HostTls::TrustCert)
+///     .host(main)
+///     .host(legacy)
+///     .build()
+///     .await?;
+///
+/// let pool = pools.for_host("legacy-db01").expect("configured above");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PoolSet {
+    pools: HashMap<String, SqlServerPool>,
+}
+
+impl PoolSet {
+    /// Create a builder for a `PoolSet`, where every pool is built with `pool_builder`.
+    pub fn builder(pool_builder: SqlServerPoolBuilder) -> PoolSetBuilder {
+        PoolSetBuilder {
+            pool_builder,
+            default_tls: HostTls::Required,
+            overrides: HashMap::new(),
+            configs: Vec::new(),
+        }
+    }
+
+    /// Returns the pool for `host` (case-insensitive), if one was configured.
+    pub fn for_host(&self, host: &str) -> Option<&SqlServerPool> {
+        self.pools.get(&host.to_lowercase())
+    }
+
+    /// Returns the pool for `host` (case-insensitive) tagged with the database marker `M`, see [`TypedPool`].
+    pub fn typed<M>(&self, host: &str) -> Option<TypedPool<M>> {
+        self.for_host(host).cloned().map(TypedPool::new)
+    }
+
+    /// Returns the configured hosts.
+    pub fn hosts(&self) -> impl Iterator<Item = &str> {
+        self.pools.keys().map(String::as_str)
+    }
+
+    /// Probe every pool and collect its status, e.g. for a health dashboard.
+    ///
+    /// The probes run [`SqlServerPool::ready`] concurrently, and any still running after `timeout` are reported as
+    /// failed, so one unreachable host can't stall the snapshot. The snapshot holds no credentials or connection strings.
+    ///
+    /// # Example
+    ///
+    /// Serve the snapshot as JSON, e.g. from an `/internal/db-status` route:
+    ///
+    /// ```no_run
+    /// # use mssql_rs::PoolSet;
+    /// # use std::time::Duration;
+    /// # async fn example(pools: PoolSet) -> mssql_rs::Result<()> {
+    /// let snapshot = pools.snapshot(Duration::from_secs(2)).await;
+    /// let body = serde_json::to_string(&snapshot)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn snapshot(&self, timeout: Duration) -> PoolSetSnapshot {
+        let probes = self.pools.iter().map(|(host, pool)| async move {
+            let clock = pool.clock();
+            let start = clock.now();
+            let error = match clock.timeout(timeout, pool.ready()).await {
+                Some(Ok(())) => None,
+                Some(Err(e)) => Some(e.to_string()),
+                None => Some(format!("Probe timed out after {}ms", timeout.as_millis())),
+            };
+            let snapshot = PoolSnapshot {
+                status: pool.status(),
+                max_size: pool.max_size(),
+                probe: ProbeResult {
+                    ok: error.is_none(),
+                    latency_ms: clock.elapsed(start).as_millis() as u64,
+                    error,
+                },
+            };
+            (host.clone(), snapshot)
+        });
+
+        PoolSetSnapshot {
+            pools: join_all(probes).await.into_iter().collect(),
+        }
+    }
+}
+
+/// The health of every pool in a [`PoolSet`], see [`PoolSet::snapshot`]. Serializes with the pools keyed by host.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolSetSnapshot {
+    pub pools: BTreeMap<String, PoolSnapshot>,
+}
+
+/// The health of a single pool, see [`PoolSet::snapshot`].
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolSnapshot {
+    pub status: PoolStatus,
+    /// The maximum number of connections, see [`SqlServerPoolBuilder::pool_max_size`].
+    pub max_size: u32,
+    pub probe: ProbeResult,
+}
+
+/// The result of a readiness probe, see [`SqlServerPool::ready`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub ok: bool,
+    /// How long the probe took, including waiting for a connection.
+    pub latency_ms: u64,
+    /// Why the probe failed, if it did.
+    pub error: Option<String>,
+}
+
+/// A builder for a [`PoolSet`].
+pub struct PoolSetBuilder {
+    pool_builder: SqlServerPoolBuilder,
+    default_tls: HostTls,
+    overrides: HashMap<String, HostTls>,
+    configs: Vec<Config>,
+}
+
+impl PoolSetBuilder {
+    /// Set the TLS settings for hosts without an override. Defaults to [`HostTls::Required`].
+    pub fn default_tls(&mut self, tls: HostTls) -> &mut Self {
+        self.default_tls = tls;
+        self
+    }
+
+    /// Set the TLS settings for a single host (case-insensitive), overriding the default.
+    pub fn tls_override(&mut self, host: &str, tls: HostTls) -> &mut Self {
+        self.overrides.insert(host.to_lowercase(), tls);
+        self
+    }
+
+    /// Add a host's config. The host name is taken from the config.
+    pub fn host(&mut self, config: Config) -> &mut Self {
+        self.configs.push(config);
+        self
+    }
+
+    /// Build a pool for every host, applying each host's TLS settings to its config.
+    pub async fn build(&self) -> Result<PoolSet, Error> {
+        let mut pools = HashMap::with_capacity(self.configs.len());
+
+        for config in &self.configs {
+            let host = host_of(config);
+            if pools.contains_key(&host) {
+                return Err(Error::InvalidArgument(format!(
+                    "host {host} was added to the pool set more than once"
+                )));
+            }
+
+            let mut config = config.clone();
+            let tls = self.overrides.get(&host).copied();
+            tls.unwrap_or(self.default_tls).apply(&mut config);
+
+            let pool = self.pool_builder.build(config).await?;
+            pools.insert(host, pool);
+        }
+
+        Ok(PoolSet { pools })
+    }
+}
+
+/// The lowercased host name of a config.
+fn host_of(config: &Config) -> String {
+    let addr = config.get_addr();
+    let host = addr
+        .rsplit_once(':')
+        .map_or(addr.as_str(), |(host, _)| host);
+    host.to_lowercase()
+}
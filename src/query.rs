@@ -0,0 +1,580 @@
+use crate::{
+    clock::Clock,
+    connection::PooledConnection,
+    error::Error,
+    fault::FaultPoint,
+    json_array::JsonArrayParser,
+    observer::BrokenReason,
+    options::QueryOptions,
+    session_options::{with_lock_settings, LockSettings},
+    sql::{check_sql, max_placeholder},
+    version::ServerVersion,
+    TryFromRow,
+};
+use futures_util::{Future, Stream, TryStreamExt};
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+use std::hash::Hash;
+use tiberius::{Query, QueryItem, Row};
+
+/// Create a query with bound parameters, checking that the number of `@P{n}` placeholders matches the parameters,
+/// and that there is a query, see [`check_sql`].
+///
+/// tiberius binds parameters positionally, so a mismatch would otherwise either fail on the server
+/// or silently bind values to the wrong placeholders.
+pub(crate) fn bind_params<'a>(query: &'a str, params: &'a [String]) -> Result<Query<'a>, Error> {
+    check_sql(query)?;
+    let placeholders = max_placeholder(query);
+    if placeholders != params.len() {
+        return Err(Error::ParameterCountMismatch {
+            placeholders,
+            bound: params.len(),
+        });
+    }
+
+    let mut select = Query::new(query);
+    for param in params {
+        select.bind(param);
+    }
+    Ok(select)
+}
+
+/// Run a query method's work under the options that apply to every query: [`QueryOptions::timeout`],
+/// [`QueryOptions::deadlock_priority`] and [`QueryOptions::lock_timeout`], the latter two over the pool's `defaults`.
+///
+/// Every method taking [`QueryOptions`] runs through here, so these options behave the same whichever is used.
+pub(crate) async fn run_with_options<R>(
+    clock: &dyn Clock,
+    defaults: LockSettings,
+    options: &QueryOptions,
+    work: impl Future<Output = Result<R, Error>>,
+) -> Result<R, Error> {
+    let limited = async {
+        match options.timeout {
+            Some(timeout) => clock
+                .timeout(timeout, work)
+                .await
+                .ok_or(Error::QueryTimeout { timeout })?,
+            None => work.await,
+        }
+    };
+
+    if options.deadlock_priority.is_none() && options.lock_timeout.is_none() {
+        return limited.await;
+    }
+    let settings = LockSettings {
+        deadlock_priority: options
+            .deadlock_priority
+            .unwrap_or(defaults.deadlock_priority),
+        lock_timeout: options.lock_timeout.or(defaults.lock_timeout),
+    };
+    with_lock_settings(settings, limited).await
+}
+
+/// Run a JSON query on a checked out connection and deserialize the result.
+pub(crate) async fn json_query_on<T>(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    params: &[String],
+) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let select = bind_params(query, params)?;
+
+    conn.server_version()
+        .require(ServerVersion::SQL_SERVER_2016, "FOR JSON")?;
+
+    inject_query_fault(conn).await?;
+    let faults = conn.faults().clone();
+    let mut yields = RowYields::new(conn.yield_every_rows());
+    let mut killed = false;
+    conn.track_temp_objects(query);
+    let was_broken = begin_exchange(conn);
+    let json_buffer = async {
+        let mut stream = select.query(&mut **conn).await?;
+
+        let size = stream.size_hint().1.unwrap_or(0);
+        let mut json_buffer = String::with_capacity(size);
+
+        while let Some(item) = stream.try_next().await? {
+            if let QueryItem::Row(row) = item {
+                if let Err(fault) = faults.inject(FaultPoint::Row).await {
+                    killed = fault.kill;
+                    return Err(fault.error);
+                }
+                yields.row().await;
+                if let Some(partial) = row.get(0) {
+                    json_buffer.push_str(partial);
+                }
+            }
+        }
+        Ok::<_, Error>(json_buffer)
+    }
+    .await;
+    conn.set_broken(was_broken || killed);
+    let json_buffer = json_buffer?;
+
+    if json_buffer.is_empty() {
+        // Return an error if the result set is empty, as this won't be valid JSON.
+        // This error should be semantically different from a failure to parse.
+        return Err(Error::EmptyResult);
+    }
+
+    serde_json::from_str::<T>(&json_buffer).map_err(Into::into)
+}
+
+/// Run a `FOR JSON` query on a checked out connection, deserializing each element of the array as its chunks arrive
+/// and passing it to `f`. The next chunks aren't read until `f` completes.
+pub(crate) async fn for_each_json_element_on<T, F, Fut>(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    params: &[String],
+    mut f: F,
+) -> Result<(), Error>
+where
+    T: DeserializeOwned,
+    F: FnMut(T) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let select = bind_params(query, params)?;
+
+    conn.server_version()
+        .require(ServerVersion::SQL_SERVER_2016, "FOR JSON")?;
+
+    inject_query_fault(conn).await?;
+    let faults = conn.faults().clone();
+    let mut yields = RowYields::new(conn.yield_every_rows());
+    let mut killed = false;
+    conn.track_temp_objects(query);
+    let was_broken = begin_exchange(conn);
+    let result = async {
+        let mut stream = select.query(&mut **conn).await?;
+
+        let mut parser = JsonArrayParser::new();
+        let mut elements = Vec::new();
+        while let Some(item) = stream.try_next().await? {
+            if let QueryItem::Row(row) = item {
+                if let Err(fault) = faults.inject(FaultPoint::Row).await {
+                    killed = fault.kill;
+                    return Err(fault.error);
+                }
+                yields.row().await;
+                if let Some(chunk) = row.get(0) {
+                    parser.feed(chunk, &mut elements)?;
+                }
+                for element in elements.drain(..) {
+                    f(element).await?;
+                }
+            }
+        }
+        parser.finish()
+    }
+    .await;
+    conn.set_broken(was_broken || killed);
+    result
+}
+
+/// Run a SQL query on a connection that is already checked out, and convert each row with [`TryFromRow`].
+///
+/// This is the logic behind [`SqlServerPool::row_query`](crate::SqlServerPool::row_query), for code that holds a
+/// connection across several queries, e.g. one checked out with
+/// [`SqlServerPool::get_with_priority`](crate::SqlServerPool::get_with_priority).
+///
+/// # Example
+///
+/// ```no_run
+/// # use mssql_rs::{query_rows_on, Priority, SqlServerPool, TryFromRow};
+/// # struct Person;
+/// # impl TryFromRow for Person {
+/// #     fn try_from(row: tiberius::Row) -> mssql_rs::Result<Self> { Ok(Person) }
+/// # }
+/// # async fn example(sql_server: SqlServerPool) -> mssql_rs::Result<()> {
+/// let mut conn = sql_server.get_with_priority(Priority::High).await?;
+///
+/// let people: Vec<Person> = query_rows_on(&mut conn, "SELECT id, name FROM people", &[]).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn query_rows_on<T>(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    params: &[String],
+) -> Result<Vec<T>, Error>
+where
+    T: TryFromRow,
+{
+    let mut buf = Vec::new();
+    collect_rows_on(conn, query, params, &mut buf).await?;
+    Ok(buf)
+}
+
+/// Run [`query_rows_on`], failing with [`Error::TooManyRows`] once the result has more than `max_rows` rows, see
+/// [`SqlServerPoolBuilder::max_rows`](crate::SqlServerPoolBuilder::max_rows).
+///
+/// The rest of a result cut short isn't read, so the connection is discarded rather than drained on its next use.
+pub(crate) async fn query_rows_limited_on<T>(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    params: &[String],
+    max_rows: Option<usize>,
+) -> Result<Vec<T>, Error>
+where
+    T: TryFromRow,
+{
+    let Some(limit) = max_rows else {
+        return query_rows_on(conn, query, params).await;
+    };
+    let mut buf = Vec::new();
+    let result = for_each_row_on(conn, query, params, |row| {
+        if buf.len() == limit {
+            return Err(Error::TooManyRows { limit });
+        }
+        buf.push(T::try_from(row)?);
+        Ok(())
+    })
+    .await;
+    if let Err(Error::TooManyRows { .. }) = result {
+        conn.mark_broken_because(BrokenReason::Abandoned);
+    }
+    result.map(|()| buf)
+}
+
+/// Run a statement returning no result on a checked out connection, returning the rows affected by each statement
+/// of the batch.
+pub(crate) async fn execute_on(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    params: &[String],
+) -> Result<Vec<u64>, Error> {
+    let statement = bind_params(query, params)?;
+
+    inject_query_fault(conn).await?;
+    conn.track_temp_objects(query);
+    let was_broken = begin_exchange(conn);
+    let result = statement.execute(&mut **conn).await;
+    conn.set_broken(was_broken);
+    Ok(result?.rows_affected().to_vec())
+}
+
+/// Run a SQL query on a checked out connection, converting each row with [`TryFromRow`] and pushing it to `buf`.
+/// On error, `buf` holds the rows converted before the failure.
+pub(crate) async fn collect_rows_on<T>(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    params: &[String],
+    buf: &mut Vec<T>,
+) -> Result<(), Error>
+where
+    T: TryFromRow,
+{
+    for_each_row_on(conn, query, params, |row| {
+        buf.push(T::try_from(row)?);
+        Ok(())
+    })
+    .await
+}
+
+/// Run a SQL query on a checked out connection, passing each row to `f` as it arrives.
+pub(crate) async fn for_each_row_on<F>(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    params: &[String],
+    mut f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(Row) -> Result<(), Error>,
+{
+    let select = bind_params(query, params)?;
+
+    inject_query_fault(conn).await?;
+    let faults = conn.faults().clone();
+    let mut yields = RowYields::new(conn.yield_every_rows());
+    let mut killed = false;
+    conn.track_temp_objects(query);
+    let was_broken = begin_exchange(conn);
+    let result = async {
+        let mut stream = select.query(&mut **conn).await?;
+
+        while let Some(item) = stream.try_next().await? {
+            if let QueryItem::Row(row) = item {
+                if let Err(fault) = faults.inject(FaultPoint::Row).await {
+                    killed = fault.kill;
+                    return Err(fault.error);
+                }
+                yields.row().await;
+                f(row)?;
+            }
+        }
+        Ok(())
+    }
+    .await;
+    conn.set_broken(was_broken || killed);
+    result
+}
+
+/// Run an already bound query on a checked out connection, passing each row to `f` as it arrives.
+/// The next rows aren't read until `f` completes.
+pub(crate) async fn for_each_row_async_on<F, Fut>(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    select: Query<'_>,
+    mut f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(Row) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    inject_query_fault(conn).await?;
+    let faults = conn.faults().clone();
+    let mut yields = RowYields::new(conn.yield_every_rows());
+    let mut killed = false;
+    conn.track_temp_objects(query);
+    let was_broken = begin_exchange(conn);
+    let result = async {
+        let mut stream = select.query(&mut **conn).await?;
+
+        while let Some(item) = stream.try_next().await? {
+            if let QueryItem::Row(row) = item {
+                if let Err(fault) = faults.inject(FaultPoint::Row).await {
+                    killed = fault.kill;
+                    return Err(fault.error);
+                }
+                yields.row().await;
+                f(row).await?;
+            }
+        }
+        Ok(())
+    }
+    .await;
+    conn.set_broken(was_broken || killed);
+    result
+}
+
+/// Run a SQL query on a checked out connection, returning the first row converted with [`TryFromRow`] that matches `pred`.
+///
+/// The rest of the result is drained so the connection can be reused. If draining fails the row is still returned,
+/// but the connection is discarded.
+pub(crate) async fn find_row_on<T, P>(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    params: &[String],
+    mut pred: P,
+) -> Result<Option<T>, Error>
+where
+    T: TryFromRow,
+    P: FnMut(&T) -> bool,
+{
+    let select = bind_params(query, params)?;
+
+    inject_query_fault(conn).await?;
+    let faults = conn.faults().clone();
+    let mut yields = RowYields::new(conn.yield_every_rows());
+    let mut killed = false;
+    conn.track_temp_objects(query);
+    let was_broken = begin_exchange(conn);
+    let result = async {
+        let mut stream = select.query(&mut **conn).await?;
+
+        let mut found = None;
+        while let Some(item) = stream.try_next().await? {
+            if let QueryItem::Row(row) = item {
+                if let Err(fault) = faults.inject(FaultPoint::Row).await {
+                    killed = fault.kill;
+                    return Err(fault.error);
+                }
+                yields.row().await;
+                let value = T::try_from(row)?;
+                if pred(&value) {
+                    found = Some(value);
+                    break;
+                }
+            }
+        }
+
+        let mut drained = true;
+        if found.is_some() {
+            while let Some(item) = stream.try_next().await.transpose() {
+                if item.is_err() {
+                    drained = false;
+                    break;
+                }
+            }
+        }
+        Ok::<_, Error>((found, drained))
+    }
+    .await;
+
+    match result {
+        Ok((found, drained)) => {
+            conn.set_broken(was_broken || killed || !drained);
+            Ok(found)
+        }
+        Err(e) => {
+            conn.set_broken(was_broken || killed);
+            Err(e)
+        }
+    }
+}
+
+/// Run a SQL query on a checked out connection, converting each row with [`TryFromRow`] and passing them to `f`
+/// in batches of `batch_rows` (the last batch may be smaller). The next rows aren't read until `f` completes.
+pub(crate) async fn for_each_batch_on<T, F, Fut>(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+    params: &[String],
+    batch_rows: usize,
+    mut f: F,
+) -> Result<(), Error>
+where
+    T: TryFromRow,
+    F: FnMut(Vec<T>) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let batch_rows = batch_rows.max(1);
+    let select = bind_params(query, params)?;
+
+    inject_query_fault(conn).await?;
+    let faults = conn.faults().clone();
+    let mut yields = RowYields::new(conn.yield_every_rows());
+    let mut killed = false;
+    conn.track_temp_objects(query);
+    let was_broken = begin_exchange(conn);
+    let result = async {
+        let mut stream = select.query(&mut **conn).await?;
+
+        let mut batch = Vec::with_capacity(batch_rows);
+        while let Some(item) = stream.try_next().await? {
+            if let QueryItem::Row(row) = item {
+                if let Err(fault) = faults.inject(FaultPoint::Row).await {
+                    killed = fault.kill;
+                    return Err(fault.error);
+                }
+                yields.row().await;
+                batch.push(T::try_from(row)?);
+                if batch.len() == batch_rows {
+                    let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_rows));
+                    f(full).await?;
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            f(batch).await?;
+        }
+        Ok(())
+    }
+    .await;
+    conn.set_broken(was_broken || killed);
+    result
+}
+
+/// Yields to the runtime every so many rows of a result, see
+/// [`SqlServerPoolBuilder::yield_every_rows`](crate::SqlServerPoolBuilder::yield_every_rows).
+///
+/// Rows already buffered by tiberius convert without awaiting anything that would yield, so without this a large
+/// result can hold a worker thread until it is fully read.
+struct RowYields {
+    every: usize,
+    rows: usize,
+}
+
+impl RowYields {
+    fn new(every: usize) -> Self {
+        Self { every, rows: 0 }
+    }
+
+    /// Count a row, yielding if it completes an interval.
+    async fn row(&mut self) {
+        if self.every == 0 {
+            return;
+        }
+        self.rows += 1;
+        if self.rows == self.every {
+            self.rows = 0;
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+/// Consult the fault injector before a query is sent, discarding the connection if it is killed.
+async fn inject_query_fault(conn: &mut PooledConnection<'_>) -> Result<(), Error> {
+    match conn.faults().inject(FaultPoint::Query).await {
+        Ok(()) => Ok(()),
+        Err(fault) => {
+            if fault.kill {
+                conn.mark_broken();
+            }
+            Err(fault.error)
+        }
+    }
+}
+
+/// Run a batch without parameters on a checked out connection, returning its result sets.
+///
+/// Like every other query here, the connection is discarded if the future is dropped before the results are read,
+/// e.g. by a probe's timeout, see [`begin_exchange`].
+pub(crate) async fn simple_query_on(
+    conn: &mut PooledConnection<'_>,
+    query: &str,
+) -> Result<Vec<Vec<Row>>, Error> {
+    let was_broken = begin_exchange(conn);
+    let result = async { Ok(conn.simple_query(query).await?.into_results().await?) }.await;
+    conn.set_broken(was_broken);
+    result
+}
+
+/// Mark the connection broken for the duration of a request, returning whether it was already broken.
+///
+/// A query future dropped before it completes, e.g. by a timeout or `select!`, may have sent only part of its
+/// request, so the connection is discarded rather than reused. Callers restore the returned state once the
+/// request completes, even with an error, as tiberius flushes any unread results before the next request.
+pub(crate) fn begin_exchange(conn: &mut PooledConnection<'_>) -> bool {
+    let was_broken = conn.is_broken();
+    conn.mark_broken();
+    was_broken
+}
+
+/// Groups consecutive rows of a one-to-many result (e.g. a parent joined to its children) by a parent key.
+pub(crate) struct GroupedRows<P, C, K> {
+    groups: Vec<(P, Vec<C>)>,
+    current: Option<K>,
+    seen: HashSet<K>,
+}
+
+impl<P, C, K> GroupedRows<P, C, K>
+where
+    P: TryFromRow,
+    K: Eq + Hash + Clone,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            groups: Vec::new(),
+            current: None,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Add a row, constructing a new parent if its key differs from the previous row's.
+    /// A key that reappears after another key means the rows weren't ordered by the key.
+    pub(crate) fn push(&mut self, key: K, child: Option<C>, row: Row) -> Result<(), Error> {
+        if self.current.as_ref() != Some(&key) {
+            if !self.seen.insert(key.clone()) {
+                return Err(Error::UngroupedRows);
+            }
+            self.groups.push((P::try_from(row)?, Vec::new()));
+            self.current = Some(key);
+        }
+
+        if let Some(child) = child {
+            let (_, children) = self.groups.last_mut().expect("a group was just pushed");
+            children.push(child);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Vec<(P, Vec<C>)> {
+        self.groups
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{
+    clock::Clock,
+    error::Error,
+    sql::lexer::{tokenize, TokenKind},
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A rate of queries, allowing bursts of up to `count` queries, see
+/// [`SqlServerPoolBuilder::query_rate_limit`](crate::SqlServerPoolBuilder::query_rate_limit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    count: u32,
+    per: Duration,
+}
+
+impl Rate {
+    /// `count` queries every `per`, e.g. `Rate::new(100, Duration::from_secs(60))` for 100 a minute, in bursts of up
+    /// to `count`.
+    pub fn new(count: u32, per: Duration) -> Self {
+        Self { count, per }
+    }
+
+    /// `count` queries a second.
+    pub fn per_second(count: u32) -> Self {
+        Self::new(count, Duration::from_secs(1))
+    }
+
+    /// The time to earn one query back.
+    fn interval(self) -> Duration {
+        if self.count == 0 {
+            Duration::MAX
+        } else {
+            self.per / self.count
+        }
+    }
+}
+
+/// Which queries a [`Rate`] given to
+/// [`SqlServerPoolBuilder::query_rate_limit`](crate::SqlServerPoolBuilder::query_rate_limit) applies to, by their
+/// fingerprint.
+///
+/// A query's fingerprint is its text with comments removed, literals replaced by `?`, whitespace collapsed and
+/// identifiers and keywords uppercased, so queries differing only in literals, e.g. ones built with `format!`, share
+/// a fingerprint and a limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FingerprintPattern {
+    /// Queries with the same fingerprint as this query.
+    Query(String),
+    /// Queries whose fingerprint contains this text, e.g. a table name, compared ignoring case.
+    Contains(String),
+}
+
+impl FingerprintPattern {
+    fn matches(&self, fingerprint: &str) -> bool {
+        match self {
+            FingerprintPattern::Query(query) => self::fingerprint(query) == fingerprint,
+            FingerprintPattern::Contains(text) => fingerprint.contains(&text.to_uppercase()),
+        }
+    }
+}
+
+/// Returns the fingerprint of a query, see [`FingerprintPattern`].
+pub(crate) fn fingerprint(sql: &str) -> String {
+    let mut fingerprint = String::with_capacity(sql.len());
+    let mut space = false;
+    for token in tokenize(sql) {
+        let text = match token.kind {
+            TokenKind::Whitespace | TokenKind::Comment => {
+                space = !fingerprint.is_empty();
+                continue;
+            }
+            TokenKind::Literal => "?",
+            TokenKind::Word if token.text.starts_with(|c: char| c.is_ascii_digit()) => "?",
+            _ => token.text,
+        };
+        if space {
+            fingerprint.push(' ');
+            space = false;
+        }
+        fingerprint.push_str(&text.to_uppercase());
+    }
+    fingerprint
+}
+
+/// The most fingerprints with a bucket at once. The least recently used bucket is dropped to make room, so a stream
+/// of distinct queries can't grow the limiter without bound.
+const MAX_BUCKETS: usize = 1024;
+
+/// A token bucket, with the time it was last used for eviction.
+#[derive(Debug)]
+struct Bucket {
+    rate: Rate,
+    /// When the bucket will be full again. Earlier than now means it is already full.
+    full_at: Instant,
+    last_used: u64,
+    throttled: u64,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    uses: u64,
+}
+
+/// Rate limits queries per fingerprint, see
+/// [`SqlServerPoolBuilder::query_rate_limit`](crate::SqlServerPoolBuilder::query_rate_limit).
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    default: Option<Rate>,
+    overrides: Vec<(FingerprintPattern, Rate)>,
+    max_queued: u32,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    /// Returns `None` if no query is limited.
+    pub(crate) fn new(
+        default: Option<Rate>,
+        overrides: Vec<(FingerprintPattern, Rate)>,
+        max_queued: u32,
+    ) -> Option<Self> {
+        (default.is_some() || !overrides.is_empty()).then(|| Self {
+            default,
+            overrides,
+            max_queued,
+            buckets: Mutex::default(),
+        })
+    }
+
+    /// Take a query from the bucket of `sql`'s fingerprint, waiting while up to `max_queued` other queries are
+    /// waiting, or fail with [`Error::RateLimited`].
+    pub(crate) async fn acquire(&self, clock: &dyn Clock, sql: &str) -> Result<(), Error> {
+        let fingerprint = fingerprint(sql);
+        let Some(rate) = self.rate(&fingerprint) else {
+            return Ok(());
+        };
+
+        let wait = self.reserve(clock.now(), fingerprint, rate)?;
+        if !wait.is_zero() {
+            clock.sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    /// The rate of the first override matching `fingerprint`, else the default.
+    fn rate(&self, fingerprint: &str) -> Option<Rate> {
+        self.overrides
+            .iter()
+            .find(|(pattern, _)| pattern.matches(fingerprint))
+            .map(|(_, rate)| *rate)
+            .or(self.default)
+    }
+
+    /// Reserve a query, returning how long to wait for it.
+    fn reserve(&self, now: Instant, fingerprint: String, rate: Rate) -> Result<Duration, Error> {
+        let mut buckets = self.buckets.lock().expect("rate limit buckets poisoned");
+        buckets.uses += 1;
+        let uses = buckets.uses;
+        if !buckets.buckets.contains_key(&fingerprint) && buckets.buckets.len() >= MAX_BUCKETS {
+            let oldest = buckets
+                .buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.last_used)
+                .map(|(fingerprint, _)| fingerprint.clone());
+            if let Some(oldest) = oldest {
+                buckets.buckets.remove(&oldest);
+            }
+        }
+        let bucket = buckets.buckets.entry(fingerprint).or_insert(Bucket {
+            rate,
+            full_at: now,
+            last_used: uses,
+            throttled: 0,
+        });
+        bucket.last_used = uses;
+        bucket.rate = rate;
+
+        // The bucket holds `count` queries, each earned back after `interval`. Taking one moves the time it is full
+        // again one interval later; queries beyond the bucket wait until their turn, up to `max_queued` of them.
+        let interval = rate.interval();
+        let capacity = interval.saturating_mul(rate.count);
+        let full_at = bucket.full_at.max(now);
+        let after = full_at
+            .saturating_duration_since(now)
+            .saturating_add(interval);
+        let wait = after.saturating_sub(capacity);
+        let queue = interval.saturating_mul(self.max_queued);
+        if rate.count == 0 || wait > queue {
+            bucket.throttled += 1;
+            let retry_after = if rate.count == 0 {
+                rate.per
+            } else {
+                wait.saturating_sub(queue)
+            };
+            return Err(Error::RateLimited { retry_after });
+        }
+        bucket.full_at = now + after;
+        Ok(wait)
+    }
+
+    /// The number of queries rate limited for each fingerprint with a bucket.
+    pub(crate) fn throttled(&self) -> HashMap<String, u64> {
+        self.buckets
+            .lock()
+            .expect("rate limit buckets poisoned")
+            .buckets
+            .iter()
+            .map(|(fingerprint, bucket)| (fingerprint.clone(), bucket.throttled))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_ignore_literals_comments_and_layout() {
+        assert_eq!(
+            fingerprint(
+                "select *\n  from dbo.users -- all of them\nwhere id = 42 and name = N'Ann'"
+            ),
+            "SELECT * FROM DBO.USERS WHERE ID = ? AND NAME = ?"
+        );
+        assert_eq!(
+            fingerprint("SELECT /* hint */ *\tFROM users WHERE id = 7 AND name = 'Bob'"),
+            fingerprint("select * from USERS where id = 8 and name = 'Al'"),
+        );
+        assert_eq!(fingerprint("  \n-- only a comment\n"), "");
+    }
+
+    #[test]
+    fn fingerprints_keep_parameters_and_identifiers() {
+        assert_eq!(
+            fingerprint("SELECT t1.x FROM t1 WHERE x = @P1 OR y = 0x1F"),
+            "SELECT T1.X FROM T1 WHERE X = @P1 OR Y = ?"
+        );
+        assert_ne!(
+            fingerprint("SELECT a FROM t"),
+            fingerprint("SELECT b FROM t")
+        );
+        assert_eq!(
+            fingerprint("SELECT [a b] FROM t WHERE s = 'x''y'"),
+            "SELECT [A B] FROM T WHERE S = ?"
+        );
+        assert_eq!(fingerprint("WHERE x IN (1, 2, 3)"), "WHERE X IN (?, ?, ?)");
+    }
+
+    #[test]
+    fn patterns_match_fingerprints() {
+        let query = FingerprintPattern::Query("SELECT * FROM orders WHERE id = 1".into());
+        assert!(query.matches(&fingerprint("select * from orders where id = 99")));
+        assert!(!query.matches(&fingerprint("SELECT * FROM orders WHERE id = @P1")));
+        assert!(!query.matches(&fingerprint("SELECT * FROM orders")));
+
+        let contains = FingerprintPattern::Contains("dbo.Orders".into());
+        assert!(contains.matches(&fingerprint("SELECT * FROM DBO.ORDERS")));
+        assert!(contains.matches(&fingerprint("delete from dbo.orders_archive")));
+        assert!(!contains.matches(&fingerprint("SELECT 'dbo.orders'")));
+        assert!(!contains.matches(&fingerprint("SELECT * FROM /* dbo.orders */ users")));
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_the_default_in_order() {
+        let limiter = RateLimiter::new(
+            Some(Rate::per_second(100)),
+            vec![
+                (
+                    FingerprintPattern::Contains("audit".into()),
+                    Rate::per_second(0),
+                ),
+                (
+                    FingerprintPattern::Contains("orders".into()),
+                    Rate::per_second(1),
+                ),
+            ],
+            0,
+        )
+        .unwrap();
+        let rate = |sql: &str| limiter.rate(&fingerprint(sql));
+        assert_eq!(
+            rate("SELECT * FROM audit_orders"),
+            Some(Rate::per_second(0))
+        );
+        assert_eq!(rate("SELECT * FROM orders"), Some(Rate::per_second(1)));
+        assert_eq!(rate("SELECT * FROM users"), Some(Rate::per_second(100)));
+        assert!(RateLimiter::new(None, Vec::new(), 0).is_none());
+    }
+
+    #[test]
+    fn buckets_allow_bursts_then_queue_then_throttle() {
+        let limiter = RateLimiter::new(Some(Rate::per_second(2)), Vec::new(), 1).unwrap();
+        let now = Instant::now();
+        let reserve = |at: Duration| limiter.reserve(now + at, "Q".into(), Rate::per_second(2));
+
+        assert_eq!(reserve(Duration::ZERO).unwrap(), Duration::ZERO);
+        assert_eq!(reserve(Duration::ZERO).unwrap(), Duration::ZERO);
+        // The bucket is empty, so one more query waits for the next one earned back, and the next is throttled.
+        assert_eq!(reserve(Duration::ZERO).unwrap(), Duration::from_millis(500));
+        assert!(matches!(
+            reserve(Duration::ZERO),
+            Err(Error::RateLimited { retry_after }) if retry_after == Duration::from_millis(500)
+        ));
+        // After a second and a half the bucket is full again.
+        assert_eq!(
+            reserve(Duration::from_millis(1500)).unwrap(),
+            Duration::ZERO
+        );
+        assert_eq!(limiter.throttled(), HashMap::from([("Q".to_owned(), 1)]));
+    }
+
+    #[test]
+    fn a_rate_of_zero_throttles_everything() {
+        let limiter =
+            RateLimiter::new(Some(Rate::new(0, Duration::from_secs(5))), Vec::new(), 10).unwrap();
+        assert!(matches!(
+            limiter.reserve(Instant::now(), "Q".into(), Rate::new(0, Duration::from_secs(5))),
+            Err(Error::RateLimited { retry_after }) if retry_after == Duration::from_secs(5)
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{
+    columns::ColumnMatching,
+    error::{Error, ErrorKind},
+    observer::BrokenReason,
+    pool::SqlServerPool,
+    query::for_each_row_async_on,
+    row::raw_value_at,
+    sql::max_placeholder,
+    TryFromRow,
+};
+use std::time::Duration;
+use tiberius::{ColumnData, Query};
+use tokio::sync::mpsc;
+
+/// Options for [`SqlServerPool::resumable_stream`].
+#[derive(Debug, Clone)]
+pub struct ResumeOptions {
+    /// How many times in a row the query is resumed without reading a new row before the stream ends with the
+    /// error. Defaults to 5.
+    pub max_retries: u32,
+    /// The delay before the first resume, doubled for each further resume without a new row. Defaults to 500ms.
+    pub retry_delay: Duration,
+    /// The longest delay before a resume. A delay suggested by a throttled server, see
+    /// [`Error::throttle_retry_after`], is used instead of the doubling one, up to this. Defaults to 30 seconds.
+    pub max_retry_delay: Duration,
+}
+
+impl Default for ResumeOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            retry_delay: Duration::from_millis(500),
+            max_retry_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A streamed query, resumed after the last key read when its connection fails.
+#[derive(Debug)]
+pub(crate) struct ResumableQuery {
+    pub(crate) query: String,
+    pub(crate) params: Vec<String>,
+    pub(crate) key_column: String,
+    pub(crate) options: ResumeOptions,
+}
+
+/// Errors a dropped or unreachable connection or a throttled server causes, which resuming on a new connection may
+/// get past.
+fn is_resumable(error: &Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::Connection | ErrorKind::Timeout | ErrorKind::Overloaded
+    )
+}
+
+/// Convert a key value to text, so it can be bound as an `nvarchar` for the server to convert back to the column's
+/// type. Only exact types are accepted, as anything else may not convert back to the same value.
+fn key_text(value: &ColumnData<'static>) -> Result<String, Error> {
+    let text = match value {
+        ColumnData::U8(Some(v)) => v.to_string(),
+        ColumnData::I16(Some(v)) => v.to_string(),
+        ColumnData::I32(Some(v)) => v.to_string(),
+        ColumnData::I64(Some(v)) => v.to_string(),
+        ColumnData::Numeric(Some(v)) => v.to_string(),
+        ColumnData::Guid(Some(v)) => v.to_string(),
+        ColumnData::String(Some(v)) => v.to_string(),
+        ColumnData::U8(None)
+        | ColumnData::I16(None)
+        | ColumnData::I32(None)
+        | ColumnData::I64(None)
+        | ColumnData::Numeric(None)
+        | ColumnData::Guid(None)
+        | ColumnData::String(None) => {
+            return Err(Error::InvalidArgument(
+                "resumable_stream key column is NULL".to_owned(),
+            ))
+        }
+        _ => return Err(Error::InvalidArgument(
+            "resumable_stream key column must be an integer, decimal, uniqueidentifier or string"
+                .to_owned(),
+        )),
+    };
+    Ok(text)
+}
+
+/// Stream the rows of `resumable` to `sender`, resuming after the last key read when the connection fails, until
+/// the result ends, a row fails, the retries run out, or the stream is dropped.
+pub(crate) async fn run<T>(
+    pool: SqlServerPool,
+    resumable: ResumableQuery,
+    sender: mpsc::Sender<Result<T, Error>>,
+) where
+    T: TryFromRow + Send,
+{
+    let placeholders = max_placeholder(&resumable.query);
+    if placeholders != resumable.params.len() + 1 {
+        let error = Error::ParameterCountMismatch {
+            placeholders,
+            bound: resumable.params.len() + 1,
+        };
+        let _ = sender.send(Err(error)).await;
+        return;
+    }
+
+    let options = &resumable.options;
+    let mut last_key = None;
+    let mut retries = 0;
+    let mut delay = options.retry_delay;
+    loop {
+        let mut progressed = false;
+        let result = read(&pool, &resumable, &mut last_key, &mut progressed, &sender).await;
+        let error = match result {
+            Ok(()) => return,
+            // The stream was dropped.
+            Err(Error::Abandoned) => return,
+            Err(_) if sender.is_closed() => return,
+            Err(e) => e,
+        };
+
+        if progressed {
+            retries = 0;
+            delay = options.retry_delay;
+        }
+        if !is_resumable(&error) || retries >= options.max_retries {
+            let _ = sender.send(Err(error)).await;
+            return;
+        }
+        retries += 1;
+        let wait = pool.retry_delay(&error, delay, options.max_retry_delay);
+        pool.clock().sleep(wait).await;
+        delay *= 2;
+    }
+}
+
+/// Run the query once, after `last_key` if a row has been read, updating it with each row sent.
+async fn read<T>(
+    pool: &SqlServerPool,
+    resumable: &ResumableQuery,
+    last_key: &mut Option<String>,
+    progressed: &mut bool,
+    sender: &mpsc::Sender<Result<T, Error>>,
+) -> Result<(), Error>
+where
+    T: TryFromRow + Send,
+{
+    let mut select = Query::new(resumable.query.as_str());
+    for param in &resumable.params {
+        select.bind(param);
+    }
+    select.bind(last_key.clone());
+
+    let mut conn = pool.get().await?;
+    let mut key_index = None;
+    let result = for_each_row_async_on(&mut conn, &resumable.query, select, |row| {
+        let value = (|| {
+            let index = match key_index {
+                Some(index) => index,
+                None => {
+                    let index =
+                        ColumnMatching::default().resolve(row.columns(), &resumable.key_column)?;
+                    *key_index.insert(index)
+                }
+            };
+            *last_key = Some(key_text(raw_value_at(&row, index)?)?);
+            *progressed = true;
+            T::try_from(row)
+        })();
+        async move {
+            // A closed channel means the stream was dropped, so stop reading.
+            sender.send(Ok(value?)).await.map_err(|_| Error::Abandoned)
+        }
+    })
+    .await;
+    if sender.is_closed() {
+        // The rest of the result was never read, so don't make the next query drain it.
+        conn.mark_broken_because(BrokenReason::Abandoned);
+    }
+    result
+}
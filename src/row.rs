@@ -0,0 +1,300 @@
+use crate::{columns::ColumnMatching, error::Error, SqlDateTime, TryFromRow};
+use serde::de::DeserializeOwned;
+use std::cell::Cell;
+use tiberius::{numeric::Numeric, Column, ColumnData, ColumnType, FromSql, FromSqlOwned, Row};
+
+/// A conversion from a SQL value, the extension point for reading custom column types.
+///
+/// Every type tiberius can read ([`FromSqlOwned`]) already implements this, so implement it for domain newtypes
+/// by delegating to the wrapped type:
+///
+/// ```
+/// use mssql_rs::{tiberius::ColumnData, FromSqlValue};
+///
+/// struct UserId(i32);
+///
+/// impl FromSqlValue for UserId {
+///     fn from_sql_value(value: &ColumnData<'static>) -> mssql_rs::Result<Option<Self>> {
+///         Ok(i32::from_sql_value(value)?.map(UserId))
+///     }
+/// }
+/// ```
+pub trait FromSqlValue: Sized {
+    /// Convert a column value, returning `None` for NULL.
+    fn from_sql_value(value: &ColumnData<'static>) -> Result<Option<Self>, Error>;
+}
+
+impl<T: FromSqlOwned> FromSqlValue for T {
+    fn from_sql_value(value: &ColumnData<'static>) -> Result<Option<Self>, Error> {
+        Ok(T::from_sql_owned(value.clone())?)
+    }
+}
+
+/// Accessors on [`tiberius::Row`] that convert through [`FromSqlValue`].
+///
+/// ```no_run
+/// # use mssql_rs::{tiberius::Row, RowExt};
+/// # fn example(row: &Row) -> mssql_rs::Result<()> {
+/// let name: Option<String> = row.get_named("name")?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait RowExt {
+    /// Get the value of the column called `name`, matched ignoring case. Fails if there is no such column, if
+    /// several columns match, or if the conversion fails.
+    fn get_named<T: FromSqlValue>(&self, name: &str) -> Result<Option<T>, Error> {
+        self.get_named_with(name, ColumnMatching::default())
+    }
+
+    /// Get the value of the column called `name`, matched as `matching` says, see [`RowExt::get_named`].
+    fn get_named_with<T: FromSqlValue>(
+        &self,
+        name: &str,
+        matching: ColumnMatching,
+    ) -> Result<Option<T>, Error>;
+
+    /// Get the value of the column called `name`, matched ignoring case, or `T::default()` if there is no such
+    /// column or the value is NULL.
+    ///
+    /// For conversions that tolerate an optional column, e.g. one added to a view after the code reading it.
+    /// Fails if several columns match or the conversion fails.
+    fn get_named_or_default<T: FromSqlValue + Default>(&self, name: &str) -> Result<T, Error>;
+
+    /// Fail with [`Error::UnexpectedColumns`], naming them, if the row has columns other than `expected`, matched
+    /// ignoring case.
+    ///
+    /// Conversions ignore columns they don't read, so a column added to a view, or one renamed so that a
+    /// [`RowExt::get_named_or_default`] silently falls back to its default, goes unnoticed. Call this at the top of a
+    /// [`TryFromRow`](crate::TryFromRow) implementation to make such a change fail loudly instead:
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{tiberius::Row, RowExt, TryFromRow};
+    /// struct Person {
+    ///     id: i32,
+    ///     nickname: String,
+    /// }
+    ///
+    /// impl TryFromRow for Person {
+    ///     fn try_from(row: Row) -> mssql_rs::Result<Self> {
+    ///         row.deny_unknown_columns(&["id", "nickname"])?;
+    ///         Ok(Person {
+    ///             id: row.get_named("id")?.unwrap_or_default(),
+    ///             nickname: row.get_named_or_default("nickname")?,
+    ///         })
+    ///     }
+    /// }
+    /// ```
+    fn deny_unknown_columns(&self, expected: &[&str]) -> Result<(), Error>;
+
+    /// Get the string column at `idx`, with the padding of a `char(n)` or `nchar(n)` column trimmed from the end.
+    ///
+    /// The server pads fixed-length values with spaces to the column's full length, so `'ab'` stored in a `char(5)`
+    /// column reads back as `"ab   "`. Other string columns are returned as stored, trailing spaces included.
+    /// Binding a shorter string to such a column needs no padding, as the server pads it when storing and ignores
+    /// trailing spaces when comparing. To trim every fixed-length column of a query, see
+    /// [`TrimFixedChar`](crate::TrimFixedChar).
+    fn get_trimmed(&self, idx: usize) -> Result<Option<String>, Error>;
+
+    /// Get the value of the `real` column called `name`.
+    ///
+    /// A `real` is read as is, without passing through `f64`, so e.g. `0.1` reads back as `0.1f32`.
+    /// Reading a `float` column this way fails rather than narrowing it.
+    fn get_f32(&self, name: &str) -> Result<Option<f32>, Error> {
+        self.get_named(name)
+    }
+
+    /// Get the `decimal` or `numeric` column at `idx` as tiberius's [`Numeric`](tiberius::numeric::Numeric), exactly
+    /// and without `rust_decimal`.
+    ///
+    /// The value keeps the column's scale, so `1.5` read from a `decimal(10, 2)` column has a
+    /// [`value`](tiberius::numeric::Numeric::value) of 150 and a [`scale`](tiberius::numeric::Numeric::scale) of 2.
+    /// The column's declared precision isn't sent with the value; [`precision`](tiberius::numeric::Numeric::precision)
+    /// counts the value's own digits. Reading a column of another type, e.g. `float` or `money`, fails rather than
+    /// rounding. Write values back with [`SqlParam::Numeric`](crate::SqlParam::Numeric).
+    fn get_numeric(&self, idx: usize) -> Result<Option<Numeric>, Error>;
+
+    /// Get the date or time-stamp column at `idx`, of any of the types [`SqlDateTime`](SqlDateTime) reads,
+    /// exactly as stored. See [`SqlDateTime`](SqlDateTime) for the precision of each type, e.g. the 1/300 second
+    /// ticks of `datetime`.
+    fn get_datetime(&self, idx: usize) -> Result<Option<SqlDateTime>, Error>;
+
+    /// Deserialize the JSON document stored in the string column called `name`, e.g. an `nvarchar(max)` column.
+    ///
+    /// NULL deserializes as JSON `null`, so it reads as `None` into an `Option` and fails for other types.
+    /// Errors name the column and include the start of the offending document.
+    ///
+    /// ```no_run
+    /// # use mssql_rs::{tiberius::Row, RowExt};
+    /// # fn example(row: &Row) -> mssql_rs::Result<()> {
+    /// let settings: Option<serde_json::Value> = row.try_get_json("settings")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn try_get_json<T: DeserializeOwned>(&self, name: &str) -> Result<T, Error> {
+        let result = match self.get_named::<String>(name)? {
+            Some(json) => serde_json::from_str(&json).map_err(|e| (e, Some(json))),
+            None => serde_json::from_value(serde_json::Value::Null).map_err(|e| (e, None)),
+        };
+        result.map_err(|(e, json)| {
+            let snippet = json.as_deref().map_or("NULL", json_snippet);
+            Error::from(e).context(format!("Invalid JSON in column {name} ({snippet})"))
+        })
+    }
+}
+
+impl RowExt for Row {
+    fn get_named_with<T: FromSqlValue>(
+        &self,
+        name: &str,
+        matching: ColumnMatching,
+    ) -> Result<Option<T>, Error> {
+        let index = matching.resolve(visible_columns(self), name)?;
+        value_at(self, index)
+    }
+
+    fn get_named_or_default<T: FromSqlValue + Default>(&self, name: &str) -> Result<T, Error> {
+        match ColumnMatching::default().find(visible_columns(self), name)? {
+            Some(index) => Ok(value_at::<T>(self, index)?.unwrap_or_default()),
+            None => Ok(T::default()),
+        }
+    }
+
+    fn deny_unknown_columns(&self, expected: &[&str]) -> Result<(), Error> {
+        ColumnMatching::default().deny_unknown(visible_columns(self), expected)
+    }
+
+    fn get_trimmed(&self, idx: usize) -> Result<Option<String>, Error> {
+        let fixed = self.columns().get(idx).is_some_and(|column| {
+            matches!(
+                column.column_type(),
+                ColumnType::BigChar | ColumnType::NChar
+            )
+        });
+        let value = self.try_get::<&str, _>(idx)?;
+        Ok(value.map(|s| if fixed { s.trim_end_matches(' ') } else { s }.to_owned()))
+    }
+
+    fn get_numeric(&self, idx: usize) -> Result<Option<Numeric>, Error> {
+        Ok(self.try_get::<Numeric, _>(idx)?)
+    }
+
+    fn get_datetime(&self, idx: usize) -> Result<Option<SqlDateTime>, Error> {
+        value_at(self, idx)
+    }
+}
+
+/// Get the value of the column at `index`, converted through [`FromSqlValue`].
+pub(crate) fn value_at<T: FromSqlValue>(row: &Row, index: usize) -> Result<Option<T>, Error> {
+    let RawValue(value) = row
+        .try_get::<RawValue, _>(index)?
+        .expect("RawValue never converts to None");
+    T::from_sql_value(value)
+}
+
+/// Read element `index` of a tuple from the column at the same position, naming the column and its type if the
+/// conversion fails, as that usually means the `SELECT` lists its columns in a different order than the tuple.
+fn tuple_element<T: FromSqlValue>(row: &Row, index: usize) -> Result<Option<T>, Error> {
+    value_at(row, index).map_err(|e| {
+        let column = row.columns().get(index);
+        let name = column.map_or("?", |column| column.name());
+        let ty = column.map_or(ColumnType::Null, |column| column.column_type());
+        e.context(format!(
+            "Tuple element {index} is a {}, but column {index} ({name}) is {ty:?}; \
+             does the SELECT list its columns in the tuple's order?",
+            std::any::type_name::<T>()
+        ))
+    })
+}
+
+/// Check the row has as many columns as the tuple has elements, in debug builds only, see the tuple impls of
+/// [`TryFromRow`].
+fn check_tuple_arity(row: &Row, arity: usize) -> Result<(), Error> {
+    let columns = visible_columns(row).len();
+    if cfg!(debug_assertions) && columns != arity {
+        return Err(Error::InvalidArgument(format!(
+            "a tuple of {arity} elements can't read a row of {columns} columns"
+        )));
+    }
+    Ok(())
+}
+
+macro_rules! impl_try_from_row_for_tuple {
+    ($($index:tt $ty:ident),+; $arity:expr) => {
+        /// Reads the columns positionally, each as an `Option`, with `None` for NULL.
+        ///
+        /// Only the position links a column to an element, so reordering the `SELECT` mismaps them. A conversion
+        /// between mismatched types fails, naming the column and its type, and debug builds also fail a row whose
+        /// column count differs from the tuple's. Columns of the same type that swap places can't be detected, so
+        /// prefer reading by name with [`RowExt::get_named`] for more than a few columns.
+        impl<$($ty: FromSqlValue),+> TryFromRow for ($(Option<$ty>,)+) {
+            fn try_from(row: Row) -> Result<Self, Error> {
+                check_tuple_arity(&row, $arity)?;
+                Ok(($(tuple_element::<$ty>(&row, $index)?,)+))
+            }
+        }
+    };
+}
+
+impl_try_from_row_for_tuple!(0 A; 1);
+impl_try_from_row_for_tuple!(0 A, 1 B; 2);
+impl_try_from_row_for_tuple!(0 A, 1 B, 2 C; 3);
+impl_try_from_row_for_tuple!(0 A, 1 B, 2 C, 3 D; 4);
+impl_try_from_row_for_tuple!(0 A, 1 B, 2 C, 3 D, 4 E; 5);
+impl_try_from_row_for_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F; 6);
+impl_try_from_row_for_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G; 7);
+impl_try_from_row_for_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H; 8);
+
+thread_local! {
+    /// The number of trailing columns hidden from the conversion running on this thread, see [`hide_trailing_columns`].
+    static HIDDEN_COLUMNS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Run `convert`, a conversion of a row, with the row's last `hidden` columns hidden from [`RowExt`]'s name lookups
+/// and the tuple impls of [`TryFromRow`], for columns the crate added to the caller's query.
+///
+/// A [`Row`] can't be rebuilt without a column, so the columns are hidden rather than removed: [`Row::len`] and the
+/// positional accessors of [`Row`] still see them.
+pub(crate) fn hide_trailing_columns<R>(hidden: usize, convert: impl FnOnce() -> R) -> R {
+    struct Restore(usize);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            HIDDEN_COLUMNS.set(self.0);
+        }
+    }
+
+    let _restore = Restore(HIDDEN_COLUMNS.replace(hidden));
+    convert()
+}
+
+/// The columns of a row a conversion may see, see [`hide_trailing_columns`].
+fn visible_columns(row: &Row) -> &[Column] {
+    let columns = row.columns();
+    &columns[..columns.len().saturating_sub(HIDDEN_COLUMNS.get())]
+}
+
+/// Borrow the value of the column at `index` as is.
+pub(crate) fn raw_value_at(row: &Row, index: usize) -> Result<&ColumnData<'static>, Error> {
+    let RawValue(value) = row
+        .try_get::<RawValue, _>(index)?
+        .expect("RawValue never converts to None");
+    Ok(value)
+}
+
+/// The length of the start of a document included in JSON errors.
+const JSON_SNIPPET_CHARS: usize = 64;
+
+fn json_snippet(json: &str) -> &str {
+    json.char_indices()
+        .nth(JSON_SNIPPET_CHARS)
+        .map_or(json, |(end, _)| &json[..end])
+}
+
+/// Borrows a column value as is, since tiberius only exposes row values through [`FromSql`].
+struct RawValue<'a>(&'a ColumnData<'static>);
+
+impl<'a> FromSql<'a> for RawValue<'a> {
+    fn from_sql(value: &'a ColumnData<'static>) -> tiberius::Result<Option<Self>> {
+        Ok(Some(RawValue(value)))
+    }
+}
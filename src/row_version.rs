@@ -0,0 +1,312 @@
+//! The [`RowVersion`] type for optimistic concurrency with `rowversion` columns.
+
+use crate::error::Error;
+use crate::param::SqlParam;
+use crate::row::FromSqlValue;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use tiberius::ColumnData;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// The value of a `rowversion` (or `timestamp`) column.
+///
+/// Ordering matches the server's comparison of the same values, as both compare the bytes big-endian.
+/// Serializes as base64 for JSON APIs, or as hex with `#[serde(with = "mssql_rs::row_version::hex")]`.
+/// Binds as a binary parameter, so it can be compared to the column in a `WHERE` clause:
+///
+/// ```no_run
+/// # use mssql_rs::{RowExt, RowVersion, SqlParam};
+/// # fn example(row: &tiberius::Row) -> mssql_rs::Result<()> {
+/// let version: Option<RowVersion> = row.get_named("version")?;
+/// let param = version.map(SqlParam::from);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct RowVersion([u8; 8]);
+
+impl RowVersion {
+    pub fn new(bytes: [u8; 8]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn to_bytes(self) -> [u8; 8] {
+        self.0
+    }
+
+    /// Encode as padded standard base64.
+    ///
+    /// ```
+    /// # use mssql_rs::RowVersion;
+    /// let version = RowVersion::new(2001u64.to_be_bytes());
+    /// assert_eq!(version.to_base64(), "AAAAAAAAB9E=");
+    /// assert_eq!(RowVersion::from_base64("AAAAAAAAB9E=").unwrap(), version);
+    /// assert_eq!(version.to_hex(), "00000000000007d1");
+    /// ```
+    pub fn to_base64(self) -> String {
+        let mut padded = [0u8; 9];
+        padded[..8].copy_from_slice(&self.0);
+        let mut out = String::with_capacity(12);
+        for group in padded.chunks(3) {
+            let n = u32::from(group[0]) << 16 | u32::from(group[1]) << 8 | u32::from(group[2]);
+            for shift in [18, 12, 6, 0] {
+                out.push(BASE64[(n >> shift & 0x3f) as usize] as char);
+            }
+        }
+        out.replace_range(11.., "=");
+        out
+    }
+
+    /// Decode padded or unpadded standard base64.
+    pub fn from_base64(s: &str) -> Result<Self, Error> {
+        let digits = s.strip_suffix('=').unwrap_or(s).as_bytes();
+        if digits.len() != 11 {
+            return Err(invalid(s));
+        }
+
+        let mut n: u128 = 0;
+        for &digit in digits {
+            let value = BASE64
+                .iter()
+                .position(|&b| b == digit)
+                .ok_or_else(|| invalid(s))?;
+            n = n << 6 | value as u128;
+        }
+        // 11 digits hold 66 bits, the last 2 of which are padding and must be zero.
+        if n & 0b11 != 0 {
+            return Err(invalid(s));
+        }
+        Ok(Self(((n >> 2) as u64).to_be_bytes()))
+    }
+
+    /// Encode as 16 lowercase hex digits, e.g. `00000000000007d1`.
+    pub fn to_hex(self) -> String {
+        format!("{:016x}", u64::from_be_bytes(self.0))
+    }
+
+    /// Decode 16 hex digits, with or without a `0x` prefix as SQL Server displays them.
+    pub fn from_hex(s: &str) -> Result<Self, Error> {
+        let digits = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+        if digits.len() != 16 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid(s));
+        }
+        u64::from_str_radix(digits, 16)
+            .map(|n| Self(n.to_be_bytes()))
+            .map_err(|_| invalid(s))
+    }
+}
+
+fn invalid(s: &str) -> Error {
+    Error::InvalidArgument(format!("Invalid rowversion: {s}"))
+}
+
+impl From<[u8; 8]> for RowVersion {
+    fn from(bytes: [u8; 8]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<RowVersion> for SqlParam {
+    fn from(version: RowVersion) -> Self {
+        SqlParam::Binary(version.0.to_vec())
+    }
+}
+
+impl FromSqlValue for RowVersion {
+    fn from_sql_value(value: &ColumnData<'static>) -> Result<Option<Self>, Error> {
+        match value {
+            ColumnData::Binary(None) => Ok(None),
+            ColumnData::Binary(Some(bytes)) => match <[u8; 8]>::try_from(bytes.as_ref()) {
+                Ok(bytes) => Ok(Some(Self(bytes))),
+                Err(_) => Err(conversion(value)),
+            },
+            _ => Err(conversion(value)),
+        }
+    }
+}
+
+fn conversion(value: &ColumnData<'static>) -> Error {
+    tiberius::error::Error::Conversion(
+        format!("cannot interpret {value:?} as a rowversion value").into(),
+    )
+    .into()
+}
+
+/// Displays as base64, like the serialized form.
+impl fmt::Display for RowVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_base64())
+    }
+}
+
+impl FromStr for RowVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_base64(s)
+    }
+}
+
+impl Serialize for RowVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for RowVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Self::from_base64(&s).map_err(de::Error::custom)
+    }
+}
+
+/// Serialize a [`RowVersion`] as hex rather than base64, for use with `#[serde(with = "mssql_rs::row_version::hex")]`.
+pub mod hex {
+    use super::RowVersion;
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(
+        version: &RowVersion,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&version.to_hex())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RowVersion, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        RowVersion::from_hex(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(n: u64) -> RowVersion {
+        RowVersion::new(n.to_be_bytes())
+    }
+
+    #[test]
+    fn ordering_matches_the_server() {
+        // The server compares rowversions as big-endian binary, i.e. as unsigned 64-bit counters.
+        let values = [
+            0,
+            1,
+            0xff,
+            0x100,
+            0x7fff_ffff_ffff_ffff,
+            0x8000_0000_0000_0000,
+            u64::MAX,
+        ];
+        for pair in values.windows(2) {
+            assert!(version(pair[0]) < version(pair[1]), "{pair:x?}");
+        }
+        let mut shuffled = [version(0x100), version(u64::MAX), version(1), version(0xff)];
+        shuffled.sort();
+        assert_eq!(
+            shuffled,
+            [version(1), version(0xff), version(0x100), version(u64::MAX)]
+        );
+        assert_eq!(version(7).max(version(0x0100_0000)), version(0x0100_0000));
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        for n in [0, 1, 2001, 0x8000_0000_0000_0000, u64::MAX] {
+            let encoded = version(n).to_base64();
+            assert_eq!(encoded.len(), 12);
+            assert_eq!(RowVersion::from_base64(&encoded).unwrap(), version(n));
+            assert_eq!(
+                RowVersion::from_base64(encoded.trim_end_matches('=')).unwrap(),
+                version(n)
+            );
+            assert_eq!(encoded.parse::<RowVersion>().unwrap(), version(n));
+        }
+        assert_eq!(version(0).to_base64(), "AAAAAAAAAAA=");
+        assert_eq!(version(u64::MAX).to_base64(), "//////////8=");
+    }
+
+    #[test]
+    fn invalid_base64_is_rejected() {
+        // Too short, too long, not base64, and nonzero padding bits.
+        for s in [
+            "",
+            "AAAAAAAAAA=",
+            "AAAAAAAAAAAA=",
+            "AAAAAAAAAA*=",
+            "AAAAAAAAAAB=",
+        ] {
+            assert!(RowVersion::from_base64(s).is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(version(0x07d1).to_hex(), "00000000000007d1");
+        assert_eq!(
+            RowVersion::from_hex("0x00000000000007D1").unwrap(),
+            version(0x07d1)
+        );
+        assert_eq!(
+            RowVersion::from_hex("ffffffffffffffff").unwrap(),
+            version(u64::MAX)
+        );
+        for s in [
+            "7d1",
+            "0x",
+            "00000000000007d1ff",
+            "000000000000g7d1",
+            "+000000000007d1",
+        ] {
+            assert!(RowVersion::from_hex(s).is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn serde_uses_base64_or_hex() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Row {
+            version: RowVersion,
+            #[serde(with = "super::hex")]
+            hex: RowVersion,
+        }
+
+        let row = Row {
+            version: version(2001),
+            hex: version(2001),
+        };
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":"AAAAAAAAB9E=","hex":"00000000000007d1"}"#
+        );
+        assert_eq!(serde_json::from_str::<Row>(&json).unwrap(), row);
+        assert!(serde_json::from_str::<RowVersion>(r#""not base64""#).is_err());
+    }
+
+    #[test]
+    fn reads_only_eight_byte_binary_values() {
+        let read = |value: ColumnData<'static>| RowVersion::from_sql_value(&value);
+        assert_eq!(
+            read(ColumnData::Binary(Some(
+                vec![0, 0, 0, 0, 0, 0, 7, 0xd1].into()
+            )))
+            .unwrap(),
+            Some(version(2001))
+        );
+        assert_eq!(read(ColumnData::Binary(None)).unwrap(), None);
+        assert!(read(ColumnData::Binary(Some(vec![1, 2, 3].into()))).is_err());
+        assert!(read(ColumnData::I64(Some(2001))).is_err());
+        assert_eq!(
+            SqlParam::from(version(1)),
+            SqlParam::Binary(vec![0, 0, 0, 0, 0, 0, 0, 1])
+        );
+    }
+}
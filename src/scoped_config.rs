@@ -0,0 +1,107 @@
+use crate::error::Error;
+
+/// A database setting that [`SqlServerPool::set_database_scoped_config`](crate::SqlServerPool::set_database_scoped_config)
+/// can change, with the values it accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ScopedConfigKey {
+    /// `MAXDOP`, the maximum degree of parallelism: 0 (let the server decide) to 32767.
+    MaxDop,
+    /// `LEGACY_CARDINALITY_ESTIMATION`: `ON` or `OFF`.
+    LegacyCardinalityEstimation,
+    /// `PARAMETER_SNIFFING`: `ON` or `OFF`.
+    ParameterSniffing,
+    /// `QUERY_OPTIMIZER_HOTFIXES`: `ON` or `OFF`.
+    QueryOptimizerHotfixes,
+    /// `OPTIMIZE_FOR_AD_HOC_WORKLOADS`: `ON` or `OFF`. Requires SQL Server 2019.
+    OptimizeForAdHocWorkloads,
+    /// The database's compatibility level, one of 100, 110, 120, 130, 140, 150 or 160.
+    /// Set with `ALTER DATABASE CURRENT`, rather than as a database scoped configuration.
+    CompatibilityLevel,
+}
+
+/// The compatibility levels SQL Server 2016 and later accept.
+const COMPATIBILITY_LEVELS: [u32; 7] = [100, 110, 120, 130, 140, 150, 160];
+
+/// The highest `MAXDOP` the server accepts.
+const MAX_MAXDOP: u32 = 32767;
+
+impl ScopedConfigKey {
+    /// The name of the setting, as reported by [`SqlServerPool::database_scoped_config`](crate::SqlServerPool::database_scoped_config).
+    pub fn name(self) -> &'static str {
+        match self {
+            ScopedConfigKey::MaxDop => "MAXDOP",
+            ScopedConfigKey::LegacyCardinalityEstimation => "LEGACY_CARDINALITY_ESTIMATION",
+            ScopedConfigKey::ParameterSniffing => "PARAMETER_SNIFFING",
+            ScopedConfigKey::QueryOptimizerHotfixes => "QUERY_OPTIMIZER_HOTFIXES",
+            ScopedConfigKey::OptimizeForAdHocWorkloads => "OPTIMIZE_FOR_AD_HOC_WORKLOADS",
+            ScopedConfigKey::CompatibilityLevel => "COMPATIBILITY_LEVEL",
+        }
+    }
+
+    /// The permission needed to change the setting.
+    pub(crate) fn permission(self) -> &'static str {
+        match self {
+            ScopedConfigKey::CompatibilityLevel => "ALTER",
+            _ => "ALTER ANY DATABASE SCOPED CONFIGURATION",
+        }
+    }
+
+    /// The statement setting the key to `value`, after checking the value is one the key accepts.
+    ///
+    /// `ALTER` statements can't take parameters, so the value is validated and normalised before it is inlined.
+    pub(crate) fn statement(self, value: &str) -> Result<String, Error> {
+        let invalid = |expected: &str| {
+            Error::InvalidArgument(format!(
+                "invalid value for {}: {value}, expected {expected}",
+                self.name()
+            ))
+        };
+        let value = value.trim();
+
+        match self {
+            ScopedConfigKey::MaxDop => {
+                let maxdop = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|maxdop| *maxdop <= MAX_MAXDOP)
+                    .ok_or_else(|| invalid("0 to 32767"))?;
+                Ok(format!(
+                    "ALTER DATABASE SCOPED CONFIGURATION SET MAXDOP = {maxdop};"
+                ))
+            }
+            ScopedConfigKey::CompatibilityLevel => {
+                let level = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|level| COMPATIBILITY_LEVELS.contains(level))
+                    .ok_or_else(|| invalid("100, 110, 120, 130, 140, 150 or 160"))?;
+                Ok(format!(
+                    "ALTER DATABASE CURRENT SET COMPATIBILITY_LEVEL = {level};"
+                ))
+            }
+            _ => {
+                let switch = if value.eq_ignore_ascii_case("ON") {
+                    "ON"
+                } else if value.eq_ignore_ascii_case("OFF") {
+                    "OFF"
+                } else {
+                    return Err(invalid("ON or OFF"));
+                };
+                Ok(format!(
+                    "ALTER DATABASE SCOPED CONFIGURATION SET {} = {switch};",
+                    self.name()
+                ))
+            }
+        }
+    }
+}
+
+/// Reads the current database's scoped configuration and compatibility level, as names and values.
+pub(crate) const SCOPED_CONFIG_QUERY: &str = "\
+SELECT name, CAST(value AS nvarchar(4000)) FROM sys.database_scoped_configurations
+UNION ALL
+SELECT N'COMPATIBILITY_LEVEL', CAST(compatibility_level AS nvarchar(4000)) FROM sys.databases WHERE database_id = DB_ID();";
+
+/// Server errors for a denied permission (262) and for a user without permission to alter the database (5011).
+pub(crate) const PERMISSION_ERRORS: [u32; 2] = [262, 5011];
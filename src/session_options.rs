@@ -0,0 +1,243 @@
+use crate::error::Error;
+use futures_util::Future;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The `SET` options applied to each new connection, see [`SqlServerPoolBuilder::session_options`](crate::SqlServerPoolBuilder::session_options).
+///
+/// The session's `SET` options are part of the plan cache key, so a query run with different options from
+/// SSMS compiles its own plan, which can be much slower, e.g. when it was compiled for a different parameter value.
+/// The options that affect plan choice and reuse are `ANSI_NULLS`, `ANSI_PADDING`, `ANSI_WARNINGS`, `ARITHABORT`,
+/// `CONCAT_NULL_YIELDS_NULL`, `NUMERIC_ROUNDABORT`, `QUOTED_IDENTIFIER`, `ANSI_NULL_DFLT_ON` and
+/// `ANSI_NULL_DFLT_OFF`, along with `DATEFIRST`, `DATEFORMAT` and `LANGUAGE`. The usual culprit is `ARITHABORT`,
+/// which SSMS turns on and client drivers leave off. Compare the effective options with
+/// [`SqlServerPool::session_options`](crate::SqlServerPool::session_options).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SessionOptionsPreset {
+    /// Keep the options the server and login give the session.
+    #[default]
+    ServerDefault,
+    /// Apply the options SSMS sets on its connections, so queries get the same plans as when run from SSMS.
+    SsmsCompatible,
+    /// Run these `SET` statements, e.g. `SET ARITHABORT ON`. Anything other than a `SET` statement is rejected by
+    /// [`SqlServerPoolBuilder::build`](crate::SqlServerPoolBuilder::build).
+    Custom(Vec<String>),
+}
+
+/// The options SSMS sets on its connections.
+const SSMS_OPTIONS: &str =
+    "SET ANSI_NULLS, ANSI_PADDING, ANSI_WARNINGS, ARITHABORT, CONCAT_NULL_YIELDS_NULL, \
+     QUOTED_IDENTIFIER, ANSI_NULL_DFLT_ON ON; SET NUMERIC_ROUNDABORT OFF;";
+
+impl SessionOptionsPreset {
+    /// The batch applying the options to a new connection, or `None` if there is nothing to apply.
+    pub(crate) fn batch(&self) -> Result<Option<String>, Error> {
+        match self {
+            SessionOptionsPreset::ServerDefault => Ok(None),
+            SessionOptionsPreset::SsmsCompatible => Ok(Some(SSMS_OPTIONS.to_owned())),
+            SessionOptionsPreset::Custom(statements) => {
+                let mut batch = String::new();
+                for statement in statements {
+                    let statement = statement.trim().trim_end_matches(';');
+                    let is_set = statement
+                        .get(..4)
+                        .is_some_and(|start| start.eq_ignore_ascii_case("SET "));
+                    if !is_set || statement.contains(';') {
+                        return Err(Error::InvalidConfig(format!(
+                            "session options must be single SET statements, got: {statement}"
+                        )));
+                    }
+                    batch.push_str(statement);
+                    batch.push_str(";\n");
+                }
+                Ok((!batch.is_empty()).then_some(batch))
+            }
+        }
+    }
+}
+
+/// How willing a session is to be chosen as the victim of a deadlock, see
+/// [`SqlServerPoolBuilder::deadlock_priority`](crate::SqlServerPoolBuilder::deadlock_priority).
+///
+/// When sessions deadlock, the server rolls back the one with the lowest priority, so background jobs that can
+/// simply retry should run at [`DeadlockPriority::Low`] to keep interactive queries from losing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeadlockPriority {
+    /// Priority -5.
+    Low,
+    /// Priority 0, the server's default.
+    #[default]
+    Normal,
+    /// Priority 5.
+    High,
+    /// A priority from -10 to 10, clamped to that range.
+    Numeric(i8),
+}
+
+impl DeadlockPriority {
+    fn sql(self) -> String {
+        match self {
+            DeadlockPriority::Low => "LOW".to_owned(),
+            DeadlockPriority::Normal => "NORMAL".to_owned(),
+            DeadlockPriority::High => "HIGH".to_owned(),
+            DeadlockPriority::Numeric(n) => n.clamp(-10, 10).to_string(),
+        }
+    }
+}
+
+/// The locking settings of a session, set on each connection as it is checked out.
+///
+/// Each connection remembers the settings it last ran with, so they are only sent when a checkout wants different
+/// ones, e.g. when a query's [`QueryOptions`](crate::QueryOptions) override the pool's.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct LockSettings {
+    pub(crate) deadlock_priority: DeadlockPriority,
+    /// `None` waits for locks indefinitely, the server's default.
+    pub(crate) lock_timeout: Option<Duration>,
+}
+
+impl LockSettings {
+    /// The batch changing a session's settings to these.
+    pub(crate) fn batch(&self) -> String {
+        let lock_timeout = match self.lock_timeout {
+            Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i64,
+            None => -1,
+        };
+        format!(
+            "SET DEADLOCK_PRIORITY {}; SET LOCK_TIMEOUT {lock_timeout};",
+            self.deadlock_priority.sql()
+        )
+    }
+}
+
+tokio::task_local! {
+    static LOCK_SETTINGS: LockSettings;
+}
+
+/// Run `f` with `settings` overriding the pool's for the connections it checks out.
+pub(crate) async fn with_lock_settings<F: Future>(settings: LockSettings, f: F) -> F::Output {
+    LOCK_SETTINGS.scope(settings, f).await
+}
+
+/// The settings set by [`with_lock_settings`] for the current task, if any.
+pub(crate) fn current_lock_settings() -> Option<LockSettings> {
+    LOCK_SETTINGS.try_with(|settings| *settings).ok()
+}
+
+/// Reads the session's `SET` options as the bits of `@@OPTIONS`.
+pub(crate) const SESSION_OPTIONS_QUERY: &str = "SELECT @@OPTIONS;";
+
+/// The `SET` options reported by the bits of `@@OPTIONS`, lowest bit first, skipping the obsolete
+/// `DISABLE_DEF_CNST_CHK`.
+const OPTION_BITS: [(i32, &str); 14] = [
+    (2, "IMPLICIT_TRANSACTIONS"),
+    (4, "CURSOR_CLOSE_ON_COMMIT"),
+    (8, "ANSI_WARNINGS"),
+    (16, "ANSI_PADDING"),
+    (32, "ANSI_NULLS"),
+    (64, "ARITHABORT"),
+    (128, "ARITHIGNORE"),
+    (256, "QUOTED_IDENTIFIER"),
+    (512, "NOCOUNT"),
+    (1024, "ANSI_NULL_DFLT_ON"),
+    (2048, "ANSI_NULL_DFLT_OFF"),
+    (4096, "CONCAT_NULL_YIELDS_NULL"),
+    (8192, "NUMERIC_ROUNDABORT"),
+    (16384, "XACT_ABORT"),
+];
+
+/// Decode the value of `@@OPTIONS` into whether each option is on.
+pub(crate) fn decode_options(options: i32) -> HashMap<String, bool> {
+    OPTION_BITS
+        .iter()
+        .map(|(bit, name)| ((*name).to_owned(), options & bit != 0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(
+        deadlock_priority: DeadlockPriority,
+        lock_timeout: Option<Duration>,
+    ) -> LockSettings {
+        LockSettings {
+            deadlock_priority,
+            lock_timeout,
+        }
+    }
+
+    #[test]
+    fn lock_settings_batch() {
+        assert_eq!(
+            LockSettings::default().batch(),
+            "SET DEADLOCK_PRIORITY NORMAL; SET LOCK_TIMEOUT -1;"
+        );
+        assert_eq!(
+            settings(DeadlockPriority::Low, Some(Duration::from_secs(30))).batch(),
+            "SET DEADLOCK_PRIORITY LOW; SET LOCK_TIMEOUT 30000;"
+        );
+        // A zero timeout fails as soon as a lock is held, rather than waiting forever.
+        assert_eq!(
+            settings(DeadlockPriority::High, Some(Duration::ZERO)).batch(),
+            "SET DEADLOCK_PRIORITY HIGH; SET LOCK_TIMEOUT 0;"
+        );
+        assert_eq!(
+            settings(
+                DeadlockPriority::Normal,
+                Some(Duration::from_secs(u64::MAX))
+            )
+            .batch(),
+            format!(
+                "SET DEADLOCK_PRIORITY NORMAL; SET LOCK_TIMEOUT {};",
+                i32::MAX
+            )
+        );
+    }
+
+    #[test]
+    fn numeric_priorities_are_clamped() {
+        assert_eq!(DeadlockPriority::Numeric(-3).sql(), "-3");
+        assert_eq!(DeadlockPriority::Numeric(10).sql(), "10");
+        assert_eq!(DeadlockPriority::Numeric(11).sql(), "10");
+        assert_eq!(DeadlockPriority::Numeric(i8::MIN).sql(), "-10");
+    }
+
+    #[tokio::test]
+    async fn lock_settings_are_scoped_to_the_task() {
+        assert_eq!(current_lock_settings(), None);
+        let low = settings(DeadlockPriority::Low, None);
+        let high = settings(DeadlockPriority::High, Some(Duration::from_secs(1)));
+
+        with_lock_settings(low, async {
+            assert_eq!(current_lock_settings(), Some(low));
+            with_lock_settings(high, async {
+                assert_eq!(current_lock_settings(), Some(high));
+            })
+            .await;
+            assert_eq!(current_lock_settings(), Some(low));
+            // Spawned tasks don't inherit the override.
+            let spawned = tokio::spawn(async { current_lock_settings() })
+                .await
+                .unwrap();
+            assert_eq!(spawned, None);
+        })
+        .await;
+        assert_eq!(current_lock_settings(), None);
+    }
+
+    #[test]
+    fn decode_options_reads_each_bit() {
+        let options = decode_options(8 | 32 | 512 | 16384);
+        assert_eq!(options.len(), OPTION_BITS.len());
+        assert!(
+            options["ANSI_WARNINGS"]
+                && options["ANSI_NULLS"]
+                && options["NOCOUNT"]
+                && options["XACT_ABORT"]
+        );
+        assert!(!options["ARITHABORT"] && !options["IMPLICIT_TRANSACTIONS"]);
+        assert!(decode_options(0).values().all(|on| !on));
+    }
+}
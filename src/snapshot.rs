@@ -0,0 +1,66 @@
+use crate::{
+    connection::PooledConnection,
+    error::Error,
+    query::{json_query_on, query_rows_on},
+    sql::write_keyword,
+    TryFromRow,
+};
+use serde::de::DeserializeOwned;
+
+/// The server error for a snapshot transaction in a database that doesn't allow snapshot isolation.
+const SNAPSHOT_DISABLED_ERROR: u32 = 3952;
+
+/// A read-only handle to the transaction of [`SqlServerPool::read_snapshot`](crate::SqlServerPool::read_snapshot).
+///
+/// Every query runs in the same transaction. Queries containing a write keyword, see
+/// [`Error::ReadOnlyViolation`], are rejected before they are sent.
+pub struct SnapshotReader<'a, 'p> {
+    pub(crate) conn: &'a mut PooledConnection<'p>,
+    pub(crate) database: String,
+}
+
+impl SnapshotReader<'_, '_> {
+    /// Returns the name of the database the transaction reads from.
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    /// Run a JSON query in the transaction, see [`SqlServerPool::json_query`](crate::SqlServerPool::json_query).
+    pub async fn json_query<T>(&mut self, query: &str, params: &[String]) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        check_read_only(query)?;
+        let result = json_query_on(self.conn, query, params).await;
+        result.map_err(|e| snapshot_error(e, &self.database))
+    }
+
+    /// Run a SQL query in the transaction, see [`SqlServerPool::row_query`](crate::SqlServerPool::row_query).
+    pub async fn row_query<T>(&mut self, query: &str, params: &[String]) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        check_read_only(query)?;
+        let result = query_rows_on(self.conn, query, params).await;
+        result.map_err(|e| snapshot_error(e, &self.database))
+    }
+}
+
+fn check_read_only(query: &str) -> Result<(), Error> {
+    match write_keyword(query) {
+        Some(keyword) => Err(Error::ReadOnlyViolation {
+            keyword: keyword.to_uppercase(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Replace the server's error for a database without snapshot isolation with one naming the setting.
+fn snapshot_error(e: Error, database: &str) -> Error {
+    match e.server_code() {
+        Some(SNAPSHOT_DISABLED_ERROR) => Error::SnapshotIsolationDisabled {
+            database: database.to_owned(),
+        },
+        _ => e,
+    }
+}
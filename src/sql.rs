@@ -0,0 +1,813 @@
+//! Utilities for building T-SQL text safely.
+
+pub mod lexer;
+
+use crate::error::Error;
+use lexer::TokenKind;
+
+/// Quote a single identifier (e.g. a column name) with brackets, escaping any closing brackets.
+///
+/// ```
+/// assert_eq!(mssql_rs::sql::quote_identifier("weird]name"), "[weird]]name]");
+/// ```
+pub fn quote_identifier(name: &str) -> String {
+    format!("[{}]", name.replace(']', "]]"))
+}
+
+/// Quote a possibly multi-part object name (e.g. `dbo.People` or `[my schema].[People]`).
+///
+/// Each dot-separated part is quoted with [`quote_identifier`]. Parts that are already bracketed are
+/// unquoted first, so they may contain dots. Returns [`Error::InvalidIdentifier`] for empty parts or unbalanced brackets.
+///
+/// ```
+/// assert_eq!(mssql_rs::sql::quote_object_name("dbo.People").unwrap(), "[dbo].[People]");
+/// ```
+pub fn quote_object_name(name: &str) -> Result<String, Error> {
+    let parts = split_object_name(name)?;
+    Ok(parts
+        .iter()
+        .map(|part| quote_identifier(part))
+        .collect::<Vec<_>>()
+        .join("."))
+}
+
+/// Escape the `LIKE` wildcards `%`, `_` and `[` in `s`, so it matches only itself as part of a pattern.
+///
+/// Each wildcard is wrapped in brackets, e.g. `50%` becomes `50[%]`, which needs no `ESCAPE` clause.
+/// Bind the result as a parameter and add any wildcards of your own in the query, e.g.
+/// `WHERE name LIKE '%' + @P1 + '%'`. See also [`SqlParam::like_literal`](crate::SqlParam::like_literal).
+///
+/// ```
+/// assert_eq!(mssql_rs::sql::escape_like("100%_[a]"), "100[%][_][[]a]");
+/// ```
+pub fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' | '_' | '[' => {
+                escaped.push('[');
+                escaped.push(c);
+                escaped.push(']');
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The longest identifier the server accepts, the length of `sysname`.
+const MAX_IDENTIFIER_CHARS: usize = 128;
+
+/// A one to four part object name, `[server].[database].[schema].[object]`, for queries that reference other
+/// databases or linked servers.
+///
+/// Build one part by part, or parse one from user input with [`ObjectName::parse`]. Parts are stored unquoted,
+/// so names containing dots or brackets round-trip through [`ObjectName::quoted`]. Helpers that take a table name,
+/// such as [`SqlServerPool::upsert`](crate::SqlServerPool::upsert), accept the quoted name.
+///
+/// ```
+/// use mssql_rs::sql::ObjectName;
+///
+/// let name = ObjectName::new().server("linked.srv").database("Sales").schema("dbo").object("Orders");
+/// assert_eq!(name.quoted().unwrap(), "[linked.srv].[Sales].[dbo].[Orders]");
+/// assert_eq!(ObjectName::parse(&name.quoted().unwrap()).unwrap(), name);
+///
+/// // Omitted inner parts use the server's default, e.g. the user's default schema.
+/// let name = ObjectName::new().database("Sales").object("Orders");
+/// assert_eq!(name.quoted().unwrap(), "[Sales]..[Orders]");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ObjectName {
+    server: Option<String>,
+    database: Option<String>,
+    schema: Option<String>,
+    object: Option<String>,
+}
+
+impl ObjectName {
+    /// Create an empty name. At least the object must be set before it is quoted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the linked server.
+    pub fn server(mut self, server: impl Into<String>) -> Self {
+        self.server = Some(server.into());
+        self
+    }
+
+    /// Set the database.
+    pub fn database(mut self, database: impl Into<String>) -> Self {
+        self.database = Some(database.into());
+        self
+    }
+
+    /// Set the schema.
+    pub fn schema(mut self, schema: impl Into<String>) -> Self {
+        self.schema = Some(schema.into());
+        self
+    }
+
+    /// Set the object, e.g. the table name.
+    pub fn object(mut self, object: impl Into<String>) -> Self {
+        self.object = Some(object.into());
+        self
+    }
+
+    /// Parse a one to four part name, where parts may be bracketed and inner parts may be empty, as in `Sales..Orders`.
+    ///
+    /// Returns [`Error::InvalidIdentifier`] for more than four parts, an empty first or last part,
+    /// unbalanced brackets, or a part longer than 128 characters.
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidIdentifier(name.to_owned());
+
+        let mut parts = split_parts(name)?;
+        if parts.len() > 4
+            || parts.first().is_some_and(String::is_empty)
+            || parts.last().is_some_and(String::is_empty)
+        {
+            return Err(invalid());
+        }
+
+        let part = |part: Option<String>| part.filter(|p| !p.is_empty());
+        let object = part(parts.pop());
+        let schema = part(parts.pop());
+        let database = part(parts.pop());
+        let server = part(parts.pop());
+
+        let name = Self {
+            server,
+            database,
+            schema,
+            object,
+        };
+        name.quoted()?;
+        Ok(name)
+    }
+
+    /// Returns the linked server, if set.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server.as_deref()
+    }
+
+    /// Returns the database, if set.
+    pub fn database_name(&self) -> Option<&str> {
+        self.database.as_deref()
+    }
+
+    /// Returns the schema, if set.
+    pub fn schema_name(&self) -> Option<&str> {
+        self.schema.as_deref()
+    }
+
+    /// Returns the object, if set.
+    pub fn object_name(&self) -> Option<&str> {
+        self.object.as_deref()
+    }
+
+    /// Returns the quoted name, e.g. `[Sales].[dbo].[Orders]`.
+    ///
+    /// Returns [`Error::InvalidIdentifier`] if the object is unset, a part is empty or longer than 128 characters,
+    /// or the name has a server but no database.
+    pub fn quoted(&self) -> Result<String, Error> {
+        let parts = [&self.server, &self.database, &self.schema, &self.object];
+        let invalid = || {
+            let given = parts.iter().filter_map(|p| p.as_deref());
+            Error::InvalidIdentifier(given.collect::<Vec<_>>().join("."))
+        };
+
+        // A linked server name is always four parts, and the server needs to know which database to use.
+        if self.object.is_none() || (self.server.is_some() && self.database.is_none()) {
+            return Err(invalid());
+        }
+
+        let first = parts.iter().position(|p| p.is_some()).unwrap_or(3);
+        let mut quoted = Vec::with_capacity(4 - first);
+        for part in &parts[first..] {
+            match part.as_deref() {
+                Some(p) if p.is_empty() || p.chars().count() > MAX_IDENTIFIER_CHARS => {
+                    return Err(invalid())
+                }
+                Some(p) => quoted.push(quote_identifier(p)),
+                None => quoted.push(String::new()),
+            }
+        }
+        Ok(quoted.join("."))
+    }
+}
+
+impl std::str::FromStr for ObjectName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Returns the highest `@P{n}` parameter placeholder in a query, or 0 if there are none.
+///
+/// String literals, quoted identifiers and comments are skipped, so e.g. `'@P9'` doesn't count.
+pub(crate) fn max_placeholder(sql: &str) -> usize {
+    lexer::tokenize(sql)
+        .filter_map(|token| match token.kind {
+            TokenKind::Parameter(n) => Some(n),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Keywords of statements that write data or schema, change the session's database, or end a transaction.
+const WRITE_KEYWORDS: [&str; 20] = [
+    "ALTER",
+    "BULK",
+    "COMMIT",
+    "CREATE",
+    "DELETE",
+    "DENY",
+    "DROP",
+    "EXEC",
+    "EXECUTE",
+    "GRANT",
+    "INSERT",
+    "INTO",
+    "MERGE",
+    "REVOKE",
+    "ROLLBACK",
+    "SAVE",
+    "TRUNCATE",
+    "UPDATE",
+    "UPDATETEXT",
+    "USE",
+];
+
+/// Returns the first keyword in a query that could write, or `None` if the query only reads.
+///
+/// The check is conservative: `INTO` is rejected since `SELECT ... INTO` creates a table, and `EXEC` since a
+/// procedure could write. Literals, quoted identifiers and comments are skipped.
+pub(crate) fn write_keyword(sql: &str) -> Option<&str> {
+    lexer::tokenize(sql)
+        .filter(|token| token.kind == TokenKind::Word)
+        .map(|token| token.text)
+        .find(|word| WRITE_KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(word)))
+}
+
+/// The session temp objects `sql` creates and drops, lowercased, e.g. `#staging`.
+///
+/// `CREATE TABLE #t`, `CREATE PROCEDURE #p` and `SELECT ... INTO #t` create one, and `DROP TABLE #t` and
+/// `DROP PROCEDURE #p` drop them, ignoring literals and comments. Global `##` objects count too, as they also live as
+/// long as the session that created them. A database or schema qualifier, e.g. `tempdb..#t`, is skipped, as SQL
+/// Server ignores it for temp objects.
+pub(crate) fn temp_objects(sql: &str) -> (Vec<String>, Vec<String>) {
+    let (mut created, mut dropped) = (Vec::new(), Vec::new());
+    if !sql.contains('#') {
+        return (created, dropped);
+    }
+    let tokens: Vec<_> = lexer::tokenize(sql)
+        .filter(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment))
+        .collect();
+    let is_word = |i: usize, words: &[&str]| {
+        tokens.get(i).is_some_and(|token: &lexer::Token<'_>| {
+            token.kind == TokenKind::Word
+                && words.iter().any(|w| token.text.eq_ignore_ascii_case(w))
+        })
+    };
+    // One part of an object name at `i`, e.g. `#t` or `[#t]`, unquoted.
+    let part = |i: usize| -> Option<String> {
+        let token = tokens.get(i)?;
+        match token.kind {
+            TokenKind::Word => Some(token.text.to_owned()),
+            TokenKind::QuotedIdentifier => {
+                let close = if token.text.starts_with('[') {
+                    "]"
+                } else {
+                    "\""
+                };
+                let inner = token.text.get(1..token.text.len().saturating_sub(1))?;
+                Some(inner.replace(&close.repeat(2), close))
+            }
+            _ => None,
+        }
+    };
+    // A temp object's name starting at `i`, e.g. `#t`, `[#t]` or `tempdb..#t`, and the index after it.
+    let temp_name = |i: usize| -> Option<(String, usize)> {
+        let mut next = i;
+        let name = loop {
+            let name = part(next);
+            if name.is_some() {
+                next += 1;
+            }
+            if tokens.get(next).is_none_or(|t| t.text != ".") {
+                break name?;
+            }
+            next += 1;
+        };
+        name.starts_with('#').then(|| (name.to_lowercase(), next))
+    };
+    const KINDS: [&str; 3] = ["TABLE", "PROC", "PROCEDURE"];
+
+    for i in 0..tokens.len() {
+        if is_word(i, &["CREATE"]) && is_word(i + 1, &KINDS) {
+            created.extend(temp_name(i + 2).map(|(name, _)| name));
+        } else if is_word(i, &["INTO"]) && !(i > 0 && is_word(i - 1, &["INSERT", "MERGE"])) {
+            created.extend(temp_name(i + 1).map(|(name, _)| name));
+        } else if is_word(i, &["DROP"]) && is_word(i + 1, &KINDS) {
+            let mut next = i + 2;
+            if is_word(next, &["IF"]) && is_word(next + 1, &["EXISTS"]) {
+                next += 2;
+            }
+            while let Some((name, after)) = temp_name(next) {
+                dropped.push(name);
+                if tokens.get(after).is_some_and(|t| t.text == ",") {
+                    next = after + 1;
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+    (created, dropped)
+}
+
+/// Returns true if a query contains `keyword`, ignoring literals, quoted identifiers and comments.
+pub(crate) fn has_keyword(sql: &str, keyword: &str) -> bool {
+    lexer::tokenize(sql)
+        .any(|token| token.kind == TokenKind::Word && token.text.eq_ignore_ascii_case(keyword))
+}
+
+/// Split a script into batches at its separator lines, see
+/// [`SqlServerPool::execute_script_with`](crate::SqlServerPool::execute_script_with).
+///
+/// A batch followed by a count, e.g. `GO 5`, is returned that many times if `respect_count`, else once. Batches of
+/// only whitespace and comments are dropped.
+pub(crate) fn split_script<'a>(
+    script: &'a str,
+    separator: &str,
+    respect_count: bool,
+) -> Result<Vec<&'a str>, Error> {
+    if separator.is_empty() || separator.contains(char::is_whitespace) {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid batch separator {separator:?}"
+        )));
+    }
+
+    let mut batches = Vec::new();
+    let mut batch_start = 0;
+    let mut line_start = 0;
+    let mut pos = 0;
+    // Whether the line so far has no literal, quoted identifier or block comment, which a separator line can't have.
+    let mut plain = true;
+    let mut comment_start = None;
+    // A final `None` ends the last line.
+    for token in lexer::tokenize(script).map(Some).chain([None]) {
+        let start = pos;
+        let line_end = match token {
+            Some(token) => {
+                pos += token.text.len();
+                match token.kind {
+                    TokenKind::Whitespace => token.text.find('\n').map(|i| start + i),
+                    TokenKind::Comment if token.text.starts_with("--") => {
+                        comment_start.get_or_insert(start);
+                        None
+                    }
+                    TokenKind::Literal | TokenKind::QuotedIdentifier | TokenKind::Comment => {
+                        plain = false;
+                        None
+                    }
+                    _ => None,
+                }
+            }
+            None => Some(script.len()),
+        };
+        let Some(line_end) = line_end else {
+            continue;
+        };
+
+        let next_line = match token {
+            Some(token) => start + token.text.rfind('\n').map_or(0, |i| i + 1),
+            None => script.len(),
+        };
+        let line = &script[line_start..comment_start.unwrap_or(line_end)];
+        if plain {
+            if let Some(count) = separator_count(line, separator)? {
+                let count = if respect_count { count } else { 1 };
+                push_batch(&mut batches, &script[batch_start..line_start], count);
+                batch_start = next_line;
+            }
+        }
+        line_start = next_line;
+        plain = true;
+        comment_start = None;
+    }
+    push_batch(&mut batches, &script[batch_start..], 1);
+    Ok(batches)
+}
+
+/// Returns the count of a separator line, 1 if it has none, or `None` if `line` isn't a separator line.
+fn separator_count(line: &str, separator: &str) -> Result<Option<u32>, Error> {
+    let line = line.trim();
+    let (Some(head), Some(rest)) = (line.get(..separator.len()), line.get(separator.len()..))
+    else {
+        return Ok(None);
+    };
+    if !head.eq_ignore_ascii_case(separator) {
+        return Ok(None);
+    }
+    if rest.is_empty() {
+        return Ok(Some(1));
+    }
+    let count = rest.trim_start();
+    if count.len() == rest.len() || !count.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(None);
+    }
+    match count.parse() {
+        Ok(0) | Err(_) => Err(Error::InvalidArgument(format!(
+            "Invalid batch count in {line:?}"
+        ))),
+        Ok(count) => Ok(Some(count)),
+    }
+}
+
+fn push_batch<'a>(batches: &mut Vec<&'a str>, batch: &'a str, count: u32) {
+    if !is_blank(batch) {
+        batches.extend(std::iter::repeat_n(batch, count as usize));
+    }
+}
+
+/// Returns true if `sql` is nothing but whitespace and comments.
+fn is_blank(sql: &str) -> bool {
+    lexer::tokenize(sql)
+        .all(|token| matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment))
+}
+
+/// Fail with [`Error::EmptySql`] if `sql` is nothing but whitespace and comments, or with [`Error::NulInSql`] if
+/// it contains a NUL character.
+///
+/// Both are always bugs, e.g. in a template building the query, so they are rejected before reaching the server,
+/// which would report a confusing syntax error or, for a NUL, may ignore the rest of the query.
+pub(crate) fn check_sql(sql: &str) -> Result<(), Error> {
+    if let Some(offset) = sql.find('\0') {
+        return Err(Error::NulInSql { offset });
+    }
+    if is_blank(sql) {
+        return Err(Error::EmptySql);
+    }
+    Ok(())
+}
+
+/// Split an object name into its unquoted parts.
+pub(crate) fn split_object_name(name: &str) -> Result<Vec<String>, Error> {
+    let parts = split_parts(name)?;
+    if parts.iter().any(String::is_empty) {
+        return Err(Error::InvalidIdentifier(name.to_owned()));
+    }
+    Ok(parts)
+}
+
+/// Split an object name into its unquoted parts, which may be empty.
+fn split_parts(name: &str) -> Result<Vec<String>, Error> {
+    let invalid = || Error::InvalidIdentifier(name.to_owned());
+
+    let mut parts = Vec::new();
+    let mut chars = name.trim().chars().peekable();
+
+    loop {
+        let mut part = String::new();
+
+        if chars.peek() == Some(&'[') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some(']') if chars.peek() == Some(&']') => {
+                        chars.next();
+                        part.push(']');
+                    }
+                    Some(']') => break,
+                    Some(c) => part.push(c),
+                    None => return Err(invalid()),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == '.' {
+                    break;
+                }
+                if c == '[' || c == ']' {
+                    return Err(invalid());
+                }
+                part.push(c);
+                chars.next();
+            }
+            part = part.trim().to_owned();
+        }
+
+        parts.push(part);
+
+        match chars.next() {
+            Some('.') => continue,
+            None => break,
+            Some(_) => return Err(invalid()),
+        }
+    }
+
+    Ok(parts)
+}
+
+/// A table hint, applied to a single table reference with [`hinted_table`].
+///
+/// Lock hints such as [`TableHint::UpdLock`] only hold their locks until the end of the statement
+/// unless it runs inside an explicit transaction. A queue consumer should therefore either claim rows in a single
+/// statement (e.g. `UPDATE TOP (1) ... OUTPUT inserted.*` against a `READPAST, UPDLOCK` table reference),
+/// or run the `SELECT` and the following `UPDATE` in one transaction on the same connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TableHint {
+    NoLock,
+    ReadUncommitted,
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+    HoldLock,
+    ReadPast,
+    UpdLock,
+    XLock,
+    RowLock,
+    PagLock,
+    TabLock,
+    TabLockX,
+    NoWait,
+    ForceSeek,
+}
+
+impl TableHint {
+    /// The T-SQL keyword for the hint.
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            TableHint::NoLock => "NOLOCK",
+            TableHint::ReadUncommitted => "READUNCOMMITTED",
+            TableHint::ReadCommitted => "READCOMMITTED",
+            TableHint::RepeatableRead => "REPEATABLEREAD",
+            TableHint::Serializable => "SERIALIZABLE",
+            TableHint::HoldLock => "HOLDLOCK",
+            TableHint::ReadPast => "READPAST",
+            TableHint::UpdLock => "UPDLOCK",
+            TableHint::XLock => "XLOCK",
+            TableHint::RowLock => "ROWLOCK",
+            TableHint::PagLock => "PAGLOCK",
+            TableHint::TabLock => "TABLOCK",
+            TableHint::TabLockX => "TABLOCKX",
+            TableHint::NoWait => "NOWAIT",
+            TableHint::ForceSeek => "FORCESEEK",
+        }
+    }
+
+    /// Returns true for hints that read without taking shared locks.
+    fn is_dirty_read(&self) -> bool {
+        matches!(self, TableHint::NoLock | TableHint::ReadUncommitted)
+    }
+
+    /// Returns true for hints that take or wait on locks.
+    fn is_locking(&self) -> bool {
+        !self.is_dirty_read() && !matches!(self, TableHint::ForceSeek)
+    }
+}
+
+/// Build a quoted table reference with table hints, for use in a `FROM` clause.
+///
+/// Duplicate hints are removed. Combinations the server rejects, such as `NOLOCK` with `UPDLOCK` or `READPAST`,
+/// return [`Error::InvalidArgument`] rather than failing on the server.
+///
+/// ```
+/// use mssql_rs::sql::{hinted_table, TableHint};
+///
+/// let table = hinted_table("dbo.jobs", &[TableHint::ReadPast, TableHint::UpdLock]).unwrap();
+/// assert_eq!(table, "[dbo].[jobs] WITH (READPAST, UPDLOCK)");
+///
+/// let query = format!("SELECT TOP (10) id FROM {table} ORDER BY id;");
+/// ```
+pub fn hinted_table(table: &str, hints: &[TableHint]) -> Result<String, Error> {
+    let table = quote_object_name(table)?;
+
+    let mut unique: Vec<TableHint> = Vec::with_capacity(hints.len());
+    for hint in hints {
+        if !unique.contains(hint) {
+            unique.push(*hint);
+        }
+    }
+
+    if unique.is_empty() {
+        return Ok(table);
+    }
+
+    if let Some(dirty) = unique.iter().find(|h| h.is_dirty_read()) {
+        if let Some(locking) = unique.iter().find(|h| h.is_locking()) {
+            return Err(Error::InvalidArgument(format!(
+                "table hint {} cannot be combined with {}",
+                dirty.keyword(),
+                locking.keyword()
+            )));
+        }
+    }
+
+    let hints = unique
+        .iter()
+        .map(TableHint::keyword)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Ok(format!("{table} WITH ({hints})"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn created(sql: &str) -> Vec<String> {
+        temp_objects(sql).0
+    }
+
+    fn dropped(sql: &str) -> Vec<String> {
+        temp_objects(sql).1
+    }
+
+    #[test]
+    fn escape_like_brackets_each_wildcard() {
+        assert_eq!(escape_like(""), "");
+        assert_eq!(escape_like("plain text"), "plain text");
+        assert_eq!(escape_like("50%"), "50[%]");
+        assert_eq!(escape_like("snake_case"), "snake[_]case");
+        assert_eq!(escape_like("[abc]"), "[[]abc]");
+        assert_eq!(escape_like("%%__[["), "[%][%][_][_][[][[]");
+    }
+
+    #[test]
+    fn escape_like_leaves_other_characters_alone() {
+        // `]` only closes a set opened by `[`, and `^` and `-` only mean something inside one.
+        assert_eq!(escape_like("a]b^c-d"), "a]b^c-d");
+        assert_eq!(escape_like("O'Brien"), "O'Brien");
+        assert_eq!(escape_like("caf\u{e9} 😀_"), "caf\u{e9} 😀[_]");
+        // Escaping twice escapes the brackets added the first time.
+        assert_eq!(escape_like(&escape_like("%")), "[[][%]]");
+    }
+
+    #[test]
+    fn object_name_parses_one_to_four_parts() {
+        let name = ObjectName::parse("Orders").unwrap();
+        assert_eq!(name, ObjectName::new().object("Orders"));
+
+        let name = ObjectName::parse(" dbo . Orders ").unwrap();
+        assert_eq!(
+            (name.schema_name(), name.object_name()),
+            (Some("dbo"), Some("Orders"))
+        );
+
+        let name = ObjectName::parse("[linked.srv].Sales.[dbo].[Order Lines]").unwrap();
+        assert_eq!(name.server_name(), Some("linked.srv"));
+        assert_eq!(name.database_name(), Some("Sales"));
+        assert_eq!(name.schema_name(), Some("dbo"));
+        assert_eq!(name.object_name(), Some("Order Lines"));
+    }
+
+    #[test]
+    fn object_name_empty_inner_parts_use_defaults() {
+        let name = ObjectName::parse("Sales..Orders").unwrap();
+        assert_eq!(name, ObjectName::new().database("Sales").object("Orders"));
+        assert_eq!(name.quoted().unwrap(), "[Sales]..[Orders]");
+
+        let name = ObjectName::parse("srv.Sales..Orders").unwrap();
+        assert_eq!(name.quoted().unwrap(), "[srv].[Sales]..[Orders]");
+    }
+
+    #[test]
+    fn object_name_round_trips_brackets_and_dots() {
+        let name = ObjectName::new().schema("my.schema").object("a]]b]");
+        let quoted = name.quoted().unwrap();
+        assert_eq!(quoted, "[my.schema].[a]]]]b]]]");
+        assert_eq!(ObjectName::parse(&quoted).unwrap(), name);
+        assert_eq!(
+            "[x]]y]".parse::<ObjectName>().unwrap().object_name(),
+            Some("x]y")
+        );
+    }
+
+    #[test]
+    fn object_name_rejects_invalid_names() {
+        for name in [
+            "",
+            ".Orders",
+            "dbo.",
+            "a.b.c.d.e",
+            "[unterminated",
+            "dbo].Orders",
+            "[a]b",
+            "d[bo.Orders",
+        ] {
+            assert!(
+                matches!(ObjectName::parse(name), Err(Error::InvalidIdentifier(_))),
+                "{name:?}"
+            );
+        }
+        let long = "x".repeat(MAX_IDENTIFIER_CHARS + 1);
+        assert!(ObjectName::parse(&long).is_err());
+        assert!(ObjectName::parse(&"x".repeat(MAX_IDENTIFIER_CHARS)).is_ok());
+    }
+
+    #[test]
+    fn object_name_quoted_needs_an_object_and_a_database_for_a_server() {
+        assert!(ObjectName::new().schema("dbo").quoted().is_err());
+        assert!(ObjectName::new()
+            .server("srv")
+            .object("Orders")
+            .quoted()
+            .is_err());
+        assert!(ObjectName::new().object("").quoted().is_err());
+        assert_eq!(
+            ObjectName::new()
+                .server("srv")
+                .database("db")
+                .object("t")
+                .quoted()
+                .unwrap(),
+            "[srv].[db]..[t]"
+        );
+    }
+
+    #[test]
+    fn temp_objects_created_by_create_and_select_into() {
+        assert_eq!(
+            created("CREATE TABLE #Staging (id int)"),
+            names(&["#staging"])
+        );
+        assert_eq!(created("create proc #p as select 1"), names(&["#p"]));
+        assert_eq!(
+            created("SELECT id, name INTO #copy FROM dbo.users WHERE id > @P1"),
+            names(&["#copy"])
+        );
+    }
+
+    #[test]
+    fn temp_objects_insert_into_is_not_a_create() {
+        assert_eq!(created("INSERT INTO #t (id) VALUES (1)"), names(&[]));
+        assert_eq!(
+            created(
+                "MERGE INTO #t AS t USING s ON 1 = 0 WHEN NOT MATCHED THEN INSERT DEFAULT VALUES"
+            ),
+            names(&[])
+        );
+        assert_eq!(created("SELECT id INTO dbo.archive FROM users"), names(&[]));
+    }
+
+    #[test]
+    fn temp_objects_dropped_by_drop() {
+        assert_eq!(dropped("DROP TABLE #t"), names(&["#t"]));
+        assert_eq!(dropped("DROP TABLE IF EXISTS #a, #B"), names(&["#a", "#b"]));
+        assert_eq!(dropped("DROP PROCEDURE #p"), names(&["#p"]));
+        assert_eq!(dropped("DROP TABLE #a, dbo.t"), names(&["#a"]));
+    }
+
+    #[test]
+    fn temp_objects_quoted_and_global_names() {
+        assert_eq!(created("CREATE TABLE [#t] (id int)"), names(&["#t"]));
+        assert_eq!(created("CREATE TABLE [#a]]b] (id int)"), names(&["#a]b"]));
+        assert_eq!(created("CREATE TABLE \"#q\" (id int)"), names(&["#q"]));
+        assert_eq!(
+            created("CREATE TABLE ##global (id int)"),
+            names(&["##global"])
+        );
+        assert_eq!(dropped("DROP TABLE [##global]"), names(&["##global"]));
+    }
+
+    #[test]
+    fn temp_objects_qualified_names() {
+        assert_eq!(created("CREATE TABLE tempdb..#t (id int)"), names(&["#t"]));
+        assert_eq!(created("SELECT 1 AS x INTO tempdb.dbo.#t"), names(&["#t"]));
+        assert_eq!(
+            dropped("DROP TABLE IF EXISTS [tempdb]..[#t], #u"),
+            names(&["#t", "#u"])
+        );
+        assert_eq!(created("CREATE TABLE tempdb..staging (id int)"), names(&[]));
+    }
+
+    #[test]
+    fn temp_objects_ignores_literals_and_comments() {
+        assert_eq!(
+            temp_objects("SELECT 'CREATE TABLE #t (id int)'"),
+            (names(&[]), names(&[]))
+        );
+        assert_eq!(
+            temp_objects("SELECT N'DROP TABLE #t'"),
+            (names(&[]), names(&[]))
+        );
+        assert_eq!(
+            temp_objects("-- CREATE TABLE #t\nSELECT 1 /* DROP TABLE #u */"),
+            (names(&[]), names(&[]))
+        );
+        assert_eq!(
+            created("CREATE /* temp */ TABLE -- staging\n #t (id int)"),
+            names(&["#t"])
+        );
+    }
+}
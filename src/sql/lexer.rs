@@ -0,0 +1,369 @@
+//! A T-SQL tokenizer for the utilities that scan query text.
+//!
+//! The lexer never fails and never rewrites its input: every byte belongs to exactly one token,
+//! so concatenating the tokens' text reproduces the input.
+//!
+//! ```
+//! use mssql_rs::sql::lexer::{tokenize, TokenKind};
+//!
+//! let sql = "SELECT name /* @P9 */ FROM [my table] WHERE id = @P1 AND note = N'@P2'";
+//! let tokens: Vec<_> = tokenize(sql).collect();
+//!
+//! assert_eq!(tokens.iter().map(|t| t.text).collect::<String>(), sql);
+//! let params: Vec<_> = tokens
+//!     .iter()
+//!     .filter_map(|t| match t.kind {
+//!         TokenKind::Parameter(n) => Some(n),
+//!         _ => None,
+//!     })
+//!     .collect();
+//! assert_eq!(params, [1]);
+//! ```
+
+/// The kind of a [`Token`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A string literal, e.g. `'it''s'` or `N'text'`. Unterminated literals run to the end of the input.
+    Literal,
+    /// A quoted identifier, e.g. `[my table]` or `"my table"`.
+    QuotedIdentifier,
+    /// A line comment (`-- ...`, excluding the newline) or a block comment (`/* ... */`, which nest).
+    Comment,
+    /// A keyword, unquoted identifier or number. Words may contain `@`, e.g. `email@P1`.
+    Word,
+    /// A variable, e.g. `@name` or `@@ROWCOUNT`.
+    Variable,
+    /// A `@P{n}` parameter placeholder, with its number.
+    Parameter(usize),
+    /// A run of ASCII whitespace.
+    Whitespace,
+    /// Any other single character, e.g. `,` or `(`.
+    Punctuation,
+}
+
+/// A token of T-SQL text, see [`tokenize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+}
+
+/// Split T-SQL text into tokens.
+pub fn tokenize(sql: &str) -> Lexer<'_> {
+    Lexer { sql, pos: 0 }
+}
+
+/// An iterator over the tokens of T-SQL text, created by [`tokenize`].
+#[derive(Debug, Clone)]
+pub struct Lexer<'a> {
+    sql: &'a str,
+    pos: usize,
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let bytes = self.sql.as_bytes();
+        let start = self.pos;
+        let first = *bytes.get(start)?;
+
+        let (kind, end) = match first {
+            b'\'' => (TokenKind::Literal, skip_quoted(bytes, start, b'\'')),
+            b'N' | b'n' if bytes.get(start + 1) == Some(&b'\'') => {
+                (TokenKind::Literal, skip_quoted(bytes, start + 1, b'\''))
+            }
+            b'"' => (TokenKind::QuotedIdentifier, skip_quoted(bytes, start, b'"')),
+            b'[' => (TokenKind::QuotedIdentifier, skip_quoted(bytes, start, b']')),
+            b'-' if bytes.get(start + 1) == Some(&b'-') => {
+                let end = bytes[start..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .map_or(bytes.len(), |n| start + n);
+                (TokenKind::Comment, end)
+            }
+            b'/' if bytes.get(start + 1) == Some(&b'*') => {
+                (TokenKind::Comment, skip_block_comment(bytes, start))
+            }
+            b'@' => {
+                let mut end = start + 1;
+                if bytes.get(end) == Some(&b'@') {
+                    end += 1;
+                }
+                end = skip_while(bytes, end, is_word_byte);
+                let name = &self.sql[start + 1..end];
+                let kind = name
+                    .strip_prefix(['P', 'p'])
+                    .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+                    .and_then(|n| n.parse().ok())
+                    .map_or(TokenKind::Variable, TokenKind::Parameter);
+                (kind, end)
+            }
+            b if is_word_byte(b) => (
+                TokenKind::Word,
+                skip_while(bytes, start, |b| is_word_byte(b) || b == b'@'),
+            ),
+            b if b.is_ascii_whitespace() => (
+                TokenKind::Whitespace,
+                skip_while(bytes, start, |b| b.is_ascii_whitespace()),
+            ),
+            // Bytes from 0x80 are word bytes, so this is always a single ASCII character.
+            _ => (TokenKind::Punctuation, start + 1),
+        };
+
+        self.pos = end;
+        Some(Token {
+            kind,
+            text: &self.sql[start..end],
+        })
+    }
+}
+
+/// Skip past a quoted section starting at `start`, where a doubled `close` is an escaped quote.
+/// Returns the index after the closing quote, or the end of the input if unterminated.
+fn skip_quoted(bytes: &[u8], start: usize, close: u8) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if bytes[i] == close {
+            if bytes.get(i + 1) == Some(&close) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Skip past a block comment starting at `start`. Block comments nest in T-SQL.
+/// Returns the index after the outermost `*/`, or the end of the input if unterminated.
+fn skip_block_comment(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"/*") {
+            depth += 1;
+            i += 2;
+        } else if bytes[i..].starts_with(b"*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    bytes.len()
+}
+
+fn skip_while(bytes: &[u8], start: usize, f: impl Fn(u8) -> bool) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|&b| !f(b))
+        .map_or(bytes.len(), |n| start + n)
+}
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'#' || b == b'$' || b >= 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::split_script;
+
+    fn kinds(sql: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(sql).map(|t| (t.kind, t.text)).collect()
+    }
+
+    fn texts(sql: &str, kind: TokenKind) -> Vec<&str> {
+        tokenize(sql)
+            .filter(|t| t.kind == kind)
+            .map(|t| t.text)
+            .collect()
+    }
+
+    /// A xorshift generator, so the generated inputs are the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    #[test]
+    fn nested_block_comments() {
+        let sql = "/* a /* b */ c */SELECT 1";
+        assert_eq!(
+            kinds(sql)[..2],
+            [
+                (TokenKind::Comment, "/* a /* b */ c */"),
+                (TokenKind::Word, "SELECT")
+            ]
+        );
+        assert_eq!(texts("/* /* */ @P1", TokenKind::Comment), ["/* /* */ @P1"]);
+        assert_eq!(texts("/* */ */", TokenKind::Comment), ["/* */"]);
+    }
+
+    #[test]
+    fn unicode_literals_with_doubled_quotes() {
+        assert_eq!(
+            kinds("N'it''s @P1'"),
+            [(TokenKind::Literal, "N'it''s @P1'")]
+        );
+        assert_eq!(texts("n'''' + 'x'", TokenKind::Literal), ["n''''", "'x'"]);
+        assert_eq!(
+            texts("SELECT N'caf\u{e9}''s', 1", TokenKind::Literal),
+            ["N'caf\u{e9}''s'"]
+        );
+        // A word ending in N isn't a literal prefix.
+        assert_eq!(
+            kinds("IN'x'"),
+            [(TokenKind::Word, "IN"), (TokenKind::Literal, "'x'")]
+        );
+        assert_eq!(
+            texts("'unterminated '' @P1", TokenKind::Literal),
+            ["'unterminated '' @P1"]
+        );
+    }
+
+    #[test]
+    fn bracketed_identifiers_with_escaped_brackets() {
+        assert_eq!(
+            kinds("[a]]b].[c]"),
+            [
+                (TokenKind::QuotedIdentifier, "[a]]b]"),
+                (TokenKind::Punctuation, "."),
+                (TokenKind::QuotedIdentifier, "[c]"),
+            ]
+        );
+        assert_eq!(texts("[ ]]]", TokenKind::QuotedIdentifier), ["[ ]]]"]);
+        assert_eq!(texts("[it's] = 'x'", TokenKind::Literal), ["'x'"]);
+        assert_eq!(
+            texts("\"a\"\"b\" [@P1]", TokenKind::QuotedIdentifier),
+            ["\"a\"\"b\"", "[@P1]"]
+        );
+        assert!(texts("[@P1]", TokenKind::Parameter(1)).is_empty());
+    }
+
+    #[test]
+    fn parameters_variables_and_words() {
+        assert_eq!(
+            kinds("@P1,@p22 @@ROWCOUNT @Pa email@P3"),
+            [
+                (TokenKind::Parameter(1), "@P1"),
+                (TokenKind::Punctuation, ","),
+                (TokenKind::Parameter(22), "@p22"),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Variable, "@@ROWCOUNT"),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Variable, "@Pa"),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Word, "email@P3"),
+            ]
+        );
+        assert_eq!(texts("#t ##g $x", TokenKind::Word), ["#t", "##g", "$x"]);
+    }
+
+    #[test]
+    fn line_comments_end_before_the_newline() {
+        assert_eq!(
+            kinds("-- 'x\r\nGO"),
+            [
+                (TokenKind::Comment, "-- 'x\r"),
+                (TokenKind::Whitespace, "\n"),
+                (TokenKind::Word, "GO"),
+            ]
+        );
+    }
+
+    #[test]
+    fn separators_on_comment_lines_dont_split() {
+        let script = "SELECT 1\n-- GO\nSELECT 2\n/* GO */\nSELECT 3\n/*\nGO\n*/\nSELECT 4\nGO -- end of first batch\nSELECT 5";
+        assert_eq!(
+            split_script(script, "GO", true).unwrap(),
+            [
+                "SELECT 1\n-- GO\nSELECT 2\n/* GO */\nSELECT 3\n/*\nGO\n*/\nSELECT 4\n",
+                "SELECT 5"
+            ]
+        );
+        assert_eq!(
+            split_script("SELECT 'a\nGO\n'\ngo\r\nSELECT [b\nGO\n]", "GO", true).unwrap(),
+            ["SELECT 'a\nGO\n'\n", "SELECT [b\nGO\n]"]
+        );
+        assert_eq!(
+            split_script(
+                "SELECT 1\nGO 2 -- twice\n-- only a comment\nGO\n",
+                "GO",
+                true
+            )
+            .unwrap(),
+            ["SELECT 1\n", "SELECT 1\n"]
+        );
+    }
+
+    /// Concatenating the tokens reproduces arbitrary input, including unterminated quotes and comments and
+    /// multi-byte characters.
+    #[test]
+    fn round_trips_arbitrary_input() {
+        const PIECES: &[&str] = &[
+            "'", "N", "n'", "[", "]", "\"", "/*", "*/", "--", "-", "/", "*", "@", "@@", "P", "1",
+            "#", "\n", "\r\n", " ", "\t", "é", "😀", "\u{0}", "x", ",", ";", "GO",
+        ];
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2_000 {
+            let sql: String = (0..rng.below(40))
+                .map(|_| PIECES[rng.below(PIECES.len())])
+                .collect();
+            let tokens: Vec<_> = tokenize(&sql).collect();
+            assert_eq!(tokens.iter().map(|t| t.text).collect::<String>(), sql);
+            assert!(tokens.iter().all(|t| !t.text.is_empty()), "{sql:?}");
+        }
+    }
+
+    /// Self-contained pieces tokenize the same wherever they appear, so literals are never split, merged or
+    /// swallowed by their neighbours.
+    #[test]
+    fn literals_are_never_rewritten() {
+        const ATOMS: &[&str] = &[
+            "'it''s'",
+            "N'caf\u{e9} ''q'''",
+            "''",
+            "N''",
+            "'multi\nline -- not a comment'",
+            "[a]]b]",
+            "\"x\"\"y\"",
+            "[]",
+            "/* outer /* 'inner */ still */",
+            "/**/",
+            "-- 'not a literal",
+            "@P1",
+            "@@ROWCOUNT",
+            "#t",
+            "SELECT",
+            "email@P2",
+            ",",
+            "(",
+            "0x1F",
+            "😀",
+        ];
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2_000 {
+            let atoms: Vec<_> = (0..rng.below(12))
+                .map(|_| ATOMS[rng.below(ATOMS.len())])
+                .collect();
+            let sql = atoms.join("\n");
+            let expected: Vec<_> = atoms
+                .iter()
+                .flat_map(|atom| texts(atom, TokenKind::Literal))
+                .collect();
+            assert_eq!(texts(&sql, TokenKind::Literal), expected, "{sql:?}");
+        }
+    }
+}
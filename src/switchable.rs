@@ -0,0 +1,157 @@
+use crate::{error::Error, pool::SqlServerPool, pool::SqlServerPoolBuilder};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tiberius::Config;
+
+/// How often a draining pool is checked for connections still in use.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The state of a [`SwitchablePool`], see [`SwitchablePool::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchStatus {
+    /// Serving from the current pool, with no replacement prepared.
+    Serving,
+    /// A warmed replacement pool is ready for [`SwitchablePool::switch`].
+    Prepared,
+    /// Serving from the new pool, while the old pool's in-flight queries finish.
+    Draining,
+}
+
+/// A pool whose target can be switched at runtime, e.g. to a new primary for planned maintenance.
+///
+/// The replacement is built and warmed ahead of time with [`SwitchablePool::prepare_switch`], then swapped in
+/// atomically with [`SwitchablePool::switch`]. Queries already running on the old pool complete, while new checkouts
+/// go to the new one. Clones share the same target, so every clone sees the switch.
+///
+/// Each milestone is reported to the builder's [`ConnectionObserver`](crate::ConnectionObserver).
+///
+/// # Example
+///
+/// ```no_run
+/// # use mssql_rs::{SqlServerPoolBuilder, SwitchablePool};
+/// # use std::time::Duration;
+/// # async fn example(old: tiberius::Config, new: tiberius::Config) -> mssql_rs::Result<()> {
+/// let builder = SqlServerPoolBuilder::new();
+/// let pool = builder.build(old).await?;
+/// let switchable = SwitchablePool::new(pool, builder);
+///
+/// switchable.prepare_switch(new).await?;
+/// switchable.switch(Duration::from_secs(30)).await?;
+///
+/// let sql_server = switchable.pool();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SwitchablePool {
+    inner: Arc<SwitchableInner>,
+}
+
+struct SwitchableInner {
+    builder: SqlServerPoolBuilder,
+    current: RwLock<SqlServerPool>,
+    prepared: Mutex<Option<SqlServerPool>>,
+    status: Mutex<SwitchStatus>,
+    switching: tokio::sync::Mutex<()>,
+}
+
+impl SwitchablePool {
+    /// Wrap `pool`, building replacements with `builder`.
+    pub fn new(pool: SqlServerPool, builder: SqlServerPoolBuilder) -> Self {
+        Self {
+            inner: Arc::new(SwitchableInner {
+                builder,
+                current: RwLock::new(pool),
+                prepared: Mutex::new(None),
+                status: Mutex::new(SwitchStatus::Serving),
+                switching: tokio::sync::Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Returns the current pool. Run queries on the returned pool rather than holding on to it,
+    /// so that later queries follow a switch.
+    pub fn pool(&self) -> SqlServerPool {
+        self.inner
+            .current
+            .read()
+            .expect("switchable pool lock poisoned")
+            .clone()
+    }
+
+    /// Returns the state of the switchover.
+    pub fn status(&self) -> SwitchStatus {
+        *self.inner.status.lock().expect("switch status poisoned")
+    }
+
+    /// Build a replacement pool for `config` and warm it by opening and validating every connection.
+    ///
+    /// The replacement isn't used until [`SwitchablePool::switch`]. Preparing again replaces an unused replacement.
+    pub async fn prepare_switch(&self, config: Config) -> Result<(), Error> {
+        let _switching = self.inner.switching.lock().await;
+
+        let pool = self.inner.builder.build(config).await?;
+        pool.validate_all().await?;
+
+        *self.inner.prepared.lock().expect("prepared pool poisoned") = Some(pool);
+        self.set_status(SwitchStatus::Prepared);
+        if let Some(observer) = self.inner.builder.observer.as_deref() {
+            observer.on_switch_prepared();
+        }
+        Ok(())
+    }
+
+    /// Swap in the prepared pool, then wait up to `grace` for the old pool's checked out connections to be returned.
+    ///
+    /// New checkouts use the new pool as soon as this is called. The old pool closes once its last clone is dropped.
+    /// Fails with [`Error::InvalidConfig`] if no replacement was prepared.
+    pub async fn switch(&self, grace: Duration) -> Result<(), Error> {
+        let _switching = self.inner.switching.lock().await;
+
+        let new = self
+            .inner
+            .prepared
+            .lock()
+            .expect("prepared pool poisoned")
+            .take()
+            .ok_or_else(|| {
+                Error::InvalidConfig("prepare_switch must be called before switch".into())
+            })?;
+
+        let old = std::mem::replace(
+            &mut *self
+                .inner
+                .current
+                .write()
+                .expect("switchable pool lock poisoned"),
+            new,
+        );
+        self.set_status(SwitchStatus::Draining);
+        let observer = self.inner.builder.observer.as_deref();
+        if let Some(observer) = observer {
+            observer.on_switch();
+        }
+
+        let clock = &*self.inner.builder.clock;
+        let deadline = clock.now() + grace;
+        let in_use = loop {
+            let state = old.pool_state();
+            let in_use = state.connections - state.idle_connections;
+            if in_use == 0 || clock.now() >= deadline {
+                break in_use;
+            }
+            clock.sleep(DRAIN_POLL_INTERVAL).await;
+        };
+
+        drop(old);
+        self.set_status(SwitchStatus::Serving);
+        if let Some(observer) = observer {
+            observer.on_drained(in_use as usize);
+        }
+        Ok(())
+    }
+
+    fn set_status(&self, status: SwitchStatus) {
+        *self.inner.status.lock().expect("switch status poisoned") = status;
+    }
+}
@@ -0,0 +1,403 @@
+use crate::{
+    connection::PooledConnection,
+    error::Error,
+    named_params::ToSqlParams,
+    observer::BrokenReason,
+    param::{bind_param, SqlParam},
+    query::for_each_row_on,
+    sql::{quote_identifier, quote_object_name},
+    temp_table::{MAX_PARAMS, MAX_VALUES_ROWS},
+    TryFromRow,
+};
+use tiberius::Query;
+
+/// Options for [`SqlServerPool::sync_table`](crate::SqlServerPool::sync_table).
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Work out the changes and report them, without making them. Defaults to false.
+    pub dry_run: bool,
+    /// The largest fraction of the table's rows a sync may delete, from 0 to 1. A sync that would delete more fails
+    /// with [`Error::InvalidArgument`] before changing anything, dry run or not, so e.g. a `desired` slice that was
+    /// wrongly loaded empty can't empty the table. Defaults to 0.5.
+    pub max_delete_fraction: f64,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            max_delete_fraction: 0.5,
+        }
+    }
+}
+
+/// The rows [`SqlServerPool::sync_table`](crate::SqlServerPool::sync_table) inserted, updated and deleted, or would
+/// have in a dry run, and those already as desired.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    pub inserted: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+}
+
+/// The table being synced, with its columns as named by the desired rows' fields.
+struct Target<'a> {
+    table: String,
+    columns: Vec<String>,
+    /// The positions of the key columns in `columns`.
+    keys: Vec<usize>,
+    key_columns: &'a [&'a str],
+}
+
+impl Target<'_> {
+    fn key<'p>(&self, row: &'p [(String, SqlParam)]) -> Vec<&'p SqlParam> {
+        self.keys.iter().map(|&i| &row[i].1).collect()
+    }
+}
+
+/// The statements' parameters for each changed row.
+#[derive(Default)]
+struct Plan {
+    /// Every column of each row to insert.
+    inserts: Vec<Vec<SqlParam>>,
+    /// The non-key columns, then the key columns, of each row to update.
+    updates: Vec<Vec<SqlParam>>,
+    /// The key columns of each row to delete.
+    deletes: Vec<Vec<SqlParam>>,
+    /// Delete every row, as nothing is desired.
+    delete_all: bool,
+    stats: SyncStats,
+}
+
+/// Sync `table` to `desired`, see [`SqlServerPool::sync_table`](crate::SqlServerPool::sync_table).
+pub(crate) async fn sync_table<T>(
+    conn: &mut PooledConnection<'_>,
+    table: &str,
+    key_columns: &[&str],
+    desired: &[T],
+    options: &SyncOptions,
+    stable_types: bool,
+) -> Result<SyncStats, Error>
+where
+    T: ToSqlParams + TryFromRow + PartialEq,
+{
+    if key_columns.is_empty() {
+        return Err(Error::InvalidArgument(
+            "sync_table requires at least one key column".to_owned(),
+        ));
+    }
+    if !(0.0..=1.0).contains(&options.max_delete_fraction) {
+        return Err(Error::InvalidArgument(
+            "max_delete_fraction must be between 0 and 1".to_owned(),
+        ));
+    }
+    let desired_params = desired
+        .iter()
+        .map(ToSqlParams::to_sql_params)
+        .collect::<Result<Vec<_>, _>>()?;
+    let target = target(table, key_columns, &desired_params)?;
+
+    if options.dry_run {
+        return Ok(plan(conn, &target, desired, desired_params, options, false)
+            .await?
+            .stats);
+    }
+
+    // Discard the connection unless the transaction is known to have ended.
+    conn.mark_broken_because(BrokenReason::SessionState);
+    conn.simple_query("BEGIN TRANSACTION;")
+        .await?
+        .into_results()
+        .await?;
+    let result = async {
+        let plan = plan(conn, &target, desired, desired_params, options, true).await?;
+        apply(conn, &target, &plan, stable_types).await?;
+        Ok::<_, Error>(plan.stats)
+    }
+    .await;
+
+    let end = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+    let end = match conn
+        .simple_query(format!("IF @@TRANCOUNT > 0 {end} TRANSACTION;"))
+        .await
+    {
+        Ok(stream) => stream.into_results().await.map(drop),
+        Err(e) => Err(e),
+    };
+    if end.is_ok() {
+        conn.set_broken(false);
+    }
+
+    let stats = result?;
+    end?;
+    Ok(stats)
+}
+
+/// Check the desired rows all have the same fields, including the key columns, and that their keys are unique.
+fn target<'a>(
+    table: &str,
+    key_columns: &'a [&'a str],
+    desired: &[Vec<(String, SqlParam)>],
+) -> Result<Target<'a>, Error> {
+    let table = quote_object_name(table)?;
+    let Some(first) = desired.first() else {
+        return Ok(Target {
+            table,
+            columns: Vec::new(),
+            keys: Vec::new(),
+            key_columns,
+        });
+    };
+
+    let columns: Vec<String> = first.iter().map(|(name, _)| name.clone()).collect();
+    let keys = key_columns
+        .iter()
+        .map(|key| {
+            columns
+                .iter()
+                .position(|column| column.eq_ignore_ascii_case(key))
+                .ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "sync_table key column {key} is not a field of the desired rows"
+                    ))
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let target = Target {
+        table,
+        columns,
+        keys,
+        key_columns,
+    };
+
+    for (i, row) in desired.iter().enumerate() {
+        if !row.iter().map(|(name, _)| name).eq(&target.columns) {
+            return Err(Error::InvalidArgument(
+                "sync_table desired rows must all have the same fields".to_owned(),
+            ));
+        }
+        let key = target.key(row);
+        if key.iter().any(|param| **param == SqlParam::Null) {
+            return Err(Error::InvalidArgument(
+                "sync_table key columns must not be NULL".to_owned(),
+            ));
+        }
+        if desired[..i]
+            .iter()
+            .any(|earlier| target.key(earlier) == key)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "sync_table desired rows have a duplicate key: {key:?}"
+            )));
+        }
+    }
+    Ok(target)
+}
+
+/// Read the table's rows and work out the changes, locking the rows read until the transaction ends if `lock`.
+async fn plan<T>(
+    conn: &mut PooledConnection<'_>,
+    target: &Target<'_>,
+    desired: &[T],
+    desired_params: Vec<Vec<(String, SqlParam)>>,
+    options: &SyncOptions,
+    lock: bool,
+) -> Result<Plan, Error>
+where
+    T: ToSqlParams + TryFromRow + PartialEq,
+{
+    let hint = if lock {
+        " WITH (UPDLOCK, HOLDLOCK)"
+    } else {
+        ""
+    };
+    let mut plan = Plan::default();
+
+    let existing = if desired.is_empty() {
+        // Without a desired row there are no fields to read into a `T`, so only count the rows, all to be deleted.
+        let count = format!("SELECT COUNT_BIG(*) FROM {}{hint}", target.table);
+        let mut rows = 0;
+        for_each_row_on(conn, &count, &[], |row| {
+            rows = row.try_get::<i64, _>(0)?.unwrap_or_default() as usize;
+            Ok(())
+        })
+        .await?;
+        plan.delete_all = rows > 0;
+        plan.stats.deleted = rows;
+        rows
+    } else {
+        let columns: Vec<String> = target.columns.iter().map(|c| quote_identifier(c)).collect();
+        let select = format!("SELECT {} FROM {}{hint}", columns.join(", "), target.table);
+        let mut current: Vec<(T, Vec<(String, SqlParam)>)> = Vec::new();
+        for_each_row_on(conn, &select, &[], |row| {
+            let value = T::try_from(row)?;
+            let params = value.to_sql_params()?;
+            current.push((value, params));
+            Ok(())
+        })
+        .await?;
+
+        let mut kept = vec![false; current.len()];
+        for (value, params) in desired.iter().zip(desired_params) {
+            let key = target.key(&params);
+            match current.iter().position(|(_, row)| target.key(row) == key) {
+                Some(i) => {
+                    kept[i] = true;
+                    // With only key columns, there is nothing to update.
+                    if current[i].0 == *value || target.keys.len() == target.columns.len() {
+                        plan.stats.unchanged += 1;
+                    } else {
+                        let (keys, values): (Vec<_>, Vec<_>) = params
+                            .into_iter()
+                            .enumerate()
+                            .partition(|(i, _)| target.keys.contains(i));
+                        plan.updates.push(
+                            values
+                                .into_iter()
+                                .chain(keys)
+                                .map(|(_, (_, param))| param)
+                                .collect(),
+                        );
+                    }
+                }
+                None => plan
+                    .inserts
+                    .push(params.into_iter().map(|(_, param)| param).collect()),
+            }
+        }
+        for ((_, row), kept) in current.iter().zip(kept) {
+            if !kept {
+                plan.deletes
+                    .push(target.key(row).into_iter().cloned().collect());
+            }
+        }
+        plan.stats.inserted = plan.inserts.len();
+        plan.stats.updated = plan.updates.len();
+        plan.stats.deleted = plan.deletes.len();
+        current.len()
+    };
+
+    let deleted = plan.stats.deleted;
+    if deleted > 0 && deleted as f64 > existing as f64 * options.max_delete_fraction {
+        return Err(Error::InvalidArgument(format!(
+            "sync_table would delete {deleted} of the {existing} rows of {}, more than max_delete_fraction {}",
+            target.table, options.max_delete_fraction
+        )));
+    }
+    Ok(plan)
+}
+
+/// Make the planned changes: deletes first, so a key that changed case or was reused can be inserted again.
+async fn apply(
+    conn: &mut PooledConnection<'_>,
+    target: &Target<'_>,
+    plan: &Plan,
+    stable_types: bool,
+) -> Result<(), Error> {
+    let table = &target.table;
+    if plan.delete_all {
+        conn.simple_query(format!("DELETE FROM {table};"))
+            .await?
+            .into_results()
+            .await?;
+        return Ok(());
+    }
+
+    let columns: Vec<String> = target.columns.iter().map(|c| quote_identifier(c)).collect();
+    let keys: Vec<String> = target
+        .key_columns
+        .iter()
+        .map(|c| quote_identifier(c))
+        .collect();
+    let values: Vec<&String> = columns
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !target.keys.contains(i))
+        .map(|(_, c)| c)
+        .collect();
+
+    let matching = |placeholders: &[String]| {
+        keys.iter()
+            .zip(placeholders)
+            .map(|(key, p)| format!("{key} = {p}"))
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    };
+
+    run_chunked(
+        conn,
+        &plan.deletes,
+        stable_types,
+        |p| format!("({})", matching(p)),
+        |rows| format!("DELETE FROM {table} WHERE {};", rows.join(" OR ")),
+    )
+    .await?;
+    run_chunked(
+        conn,
+        &plan.updates,
+        stable_types,
+        |p| {
+            let (set, key) = p.split_at(values.len());
+            let set = values
+                .iter()
+                .zip(set)
+                .map(|(column, p)| format!("{column} = {p}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("UPDATE {table} SET {set} WHERE {};", matching(key))
+        },
+        |rows| rows.join(" "),
+    )
+    .await?;
+    run_chunked(
+        conn,
+        &plan.inserts,
+        stable_types,
+        |p| format!("({})", p.join(", ")),
+        |rows| {
+            format!(
+                "INSERT INTO {table} ({}) VALUES {};",
+                columns.join(", "),
+                rows.join(", ")
+            )
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Run a statement for `rows` in chunks under the server's parameter limit. Each row's part is written by `row` from
+/// its placeholders, and a chunk's parts are combined into one batch by `statement`.
+async fn run_chunked(
+    conn: &mut PooledConnection<'_>,
+    rows: &[Vec<SqlParam>],
+    stable_types: bool,
+    row: impl Fn(&[String]) -> String,
+    statement: impl Fn(Vec<String>) -> String,
+) -> Result<(), Error> {
+    let Some(first) = rows.first() else {
+        return Ok(());
+    };
+    let chunk_rows = (MAX_PARAMS / first.len().max(1)).clamp(1, MAX_VALUES_ROWS);
+    for chunk in rows.chunks(chunk_rows) {
+        let mut next = 0;
+        let parts = chunk
+            .iter()
+            .map(|params| {
+                let placeholders: Vec<String> = (next..next + params.len())
+                    .map(|n| format!("@P{}", n + 1))
+                    .collect();
+                next += params.len();
+                row(&placeholders)
+            })
+            .collect();
+
+        let mut query = Query::new(statement(parts));
+        for param in chunk.iter().flatten() {
+            bind_param(&mut query, param, stable_types);
+        }
+        query.execute(&mut **conn).await?;
+    }
+    Ok(())
+}
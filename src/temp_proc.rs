@@ -0,0 +1,131 @@
+use crate::{
+    connection::PooledConnection,
+    error::Error,
+    observer::BrokenReason,
+    param::{bind_param, SqlParam},
+    sql::quote_identifier,
+    temp_table::is_type_name,
+    TryFromRow,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tiberius::Query;
+
+/// The longest name hint kept, well within the server's 116 character limit for temp procedure names.
+const MAX_HINT_CHARS: usize = 64;
+
+/// Numbers temp procedures, so handles on the same connection never collide.
+static NEXT_PROC: AtomicU64 = AtomicU64::new(1);
+
+/// A temporary stored procedure on a pinned connection, created by [`PooledConnection::prepare_temp_proc`].
+///
+/// The procedure is compiled once, on its first execution, and the plan is reused for later executions with other
+/// parameters, rather than compiling each dynamically built query again. The handle borrows the connection, so it
+/// can't outlive it or be used on another connection.
+///
+/// Call [`TempProc::drop_proc`] when done to drop the procedure and keep the connection. If the handle is dropped
+/// instead, or dropping the procedure fails, the connection is discarded rather than returned to the pool, which
+/// drops the procedure on the server.
+pub struct TempProc<'c, 'p> {
+    conn: &'c mut PooledConnection<'p>,
+    name: String,
+    params: usize,
+    was_broken: bool,
+}
+
+impl<'c, 'p> TempProc<'c, 'p> {
+    pub(crate) async fn create(
+        conn: &'c mut PooledConnection<'p>,
+        name_hint: &str,
+        body_sql: &str,
+        param_decls: &[(&str, &str)],
+    ) -> Result<Self, Error> {
+        let decls = param_decls
+            .iter()
+            .map(|(name, sql_type)| param_declaration(name, sql_type))
+            .collect::<Result<Vec<_>, _>>()?
+            .join(", ");
+
+        let hint: String = name_hint
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .take(MAX_HINT_CHARS)
+            .collect();
+        let number = NEXT_PROC.fetch_add(1, Ordering::Relaxed);
+        let name = quote_identifier(&format!("#proc_{hint}_{number}"));
+
+        // Discard the connection unless the procedure is known to be dropped.
+        let was_broken = conn.is_broken();
+        conn.mark_broken_because(BrokenReason::SessionState);
+
+        // CREATE PROCEDURE must be alone in its batch.
+        let create = format!("CREATE PROCEDURE {name} {decls}\nAS\n{body_sql}");
+        conn.simple_query(create).await?.into_results().await?;
+
+        Ok(Self {
+            conn,
+            name,
+            params: param_decls.len(),
+            was_broken,
+        })
+    }
+
+    /// Returns the quoted name of the procedure, e.g. `[#proc_tenant_report_1]`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Execute the procedure with `params`, in the order they were declared, converting the rows of its first
+    /// result set with [`TryFromRow`].
+    pub async fn exec<T>(&mut self, params: &[SqlParam]) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        if params.len() != self.params {
+            return Err(Error::ParameterCountMismatch {
+                placeholders: self.params,
+                bound: params.len(),
+            });
+        }
+
+        let placeholders = (1..=params.len())
+            .map(|i| format!("@P{i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut exec = Query::new(format!("EXEC {} {placeholders};", self.name));
+        for param in params {
+            bind_param(&mut exec, param, false);
+        }
+
+        exec.query(&mut **self.conn)
+            .await?
+            .into_first_result()
+            .await?
+            .into_iter()
+            .map(T::try_from)
+            .collect()
+    }
+
+    /// Drop the procedure, so the connection can be returned to the pool as usual.
+    pub async fn drop_proc(self) -> Result<(), Error> {
+        let drop = format!("DROP PROCEDURE {};", self.name);
+        self.conn.simple_query(drop).await?.into_results().await?;
+        self.conn.set_broken(self.was_broken);
+        Ok(())
+    }
+}
+
+/// The declaration of a procedure parameter, e.g. `@tenant int`.
+fn param_declaration(name: &str, sql_type: &str) -> Result<String, Error> {
+    let bare = name.strip_prefix('@').unwrap_or(name);
+    let valid_name =
+        !bare.is_empty() && bare.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        return Err(Error::InvalidIdentifier(name.to_owned()));
+    }
+    if !is_type_name(sql_type) {
+        return Err(Error::InvalidArgument(format!(
+            "invalid type for parameter {name}: {sql_type}"
+        )));
+    }
+    Ok(format!("@{bare} {}", sql_type.trim()))
+}
@@ -0,0 +1,239 @@
+use crate::{
+    connection::PooledConnection,
+    error::Error,
+    param::{bind_param, SqlParam},
+    sql::quote_identifier,
+};
+use tiberius::Query;
+
+/// The maximum number of rows in a single `INSERT ... VALUES` statement.
+pub(crate) const MAX_VALUES_ROWS: usize = 1000;
+
+/// The maximum number of parameters in a single statement (the server limit is 2100).
+pub(crate) const MAX_PARAMS: usize = 2000;
+
+/// A column of a temp table created by [`SqlServerPool::with_temp_table`](crate::SqlServerPool::with_temp_table).
+#[derive(Debug, Clone)]
+pub struct TempColumn {
+    name: String,
+    sql_type: String,
+}
+
+impl TempColumn {
+    /// Create a column with the given name and SQL type, e.g. `TempColumn::new("id", "int")`
+    /// or `TempColumn::new("code", "nvarchar(50)")`.
+    pub fn new(name: impl Into<String>, sql_type: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            sql_type: sql_type.into(),
+        }
+    }
+
+    /// The column definition, e.g. `[id] int NULL`.
+    fn definition(&self) -> Result<String, Error> {
+        if !is_type_name(&self.sql_type) {
+            return Err(Error::InvalidArgument(format!(
+                "invalid type for column {}: {}",
+                self.name, self.sql_type
+            )));
+        }
+
+        Ok(format!(
+            "{} {} NULL",
+            quote_identifier(&self.name),
+            self.sql_type.trim()
+        ))
+    }
+}
+
+/// Returns true if `sql_type` only contains what a type name can, e.g. `nvarchar(50)` or `decimal(18, 2)`.
+///
+/// Types are interpolated into statements, so anything else is rejected.
+pub(crate) fn is_type_name(sql_type: &str) -> bool {
+    !sql_type.trim().is_empty()
+        && sql_type
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '(' | ')' | ',' | ' '))
+}
+
+/// The SQL type of a temp table column holding `keys`, for
+/// [`SqlServerPool::row_query_in_temp`](crate::SqlServerPool::row_query_in_temp).
+///
+/// The keys must all be of one kind, ignoring NULLs. Strings and binaries fit an indexable length where they can, and
+/// strings take the database's collation, so joins against the database's columns don't conflict with tempdb's.
+pub(crate) fn key_type(keys: &[SqlParam]) -> Result<String, Error> {
+    let mut kind: Option<std::mem::Discriminant<SqlParam>> = None;
+    for key in keys.iter().filter(|key| **key != SqlParam::Null) {
+        let discriminant = std::mem::discriminant(key);
+        if kind.is_some_and(|kind| kind != discriminant) {
+            return Err(Error::InvalidArgument(
+                "keys must all be of the same type".to_owned(),
+            ));
+        }
+        kind = Some(discriminant);
+    }
+    let Some(first) = keys.iter().find(|key| **key != SqlParam::Null) else {
+        return Err(Error::InvalidArgument(
+            "at least one key must not be NULL".to_owned(),
+        ));
+    };
+
+    let sql_type = match first {
+        SqlParam::Null => unreachable!("NULL keys are skipped"),
+        SqlParam::Bool(_) => "bit".to_owned(),
+        SqlParam::U8(_) => "tinyint".to_owned(),
+        SqlParam::I16(_) => "smallint".to_owned(),
+        SqlParam::I32(_) => "int".to_owned(),
+        SqlParam::I64(_) => "bigint".to_owned(),
+        SqlParam::F32(_) => "real".to_owned(),
+        SqlParam::F64(_) => "float".to_owned(),
+        SqlParam::String(_) => {
+            let longest = keys
+                .iter()
+                .map(|key| match key {
+                    SqlParam::String(s) => s.encode_utf16().count(),
+                    _ => 0,
+                })
+                .max()
+                .unwrap_or_default();
+            let length = if longest <= 450 {
+                "450".to_owned()
+            } else if longest <= 4000 {
+                "4000".to_owned()
+            } else {
+                "max".to_owned()
+            };
+            format!("nvarchar({length}) COLLATE DATABASE_DEFAULT")
+        }
+        SqlParam::Binary(_) => {
+            let longest = keys
+                .iter()
+                .map(|key| match key {
+                    SqlParam::Binary(b) => b.len(),
+                    _ => 0,
+                })
+                .max()
+                .unwrap_or_default();
+            if longest <= 900 {
+                "varbinary(900)".to_owned()
+            } else if longest <= 8000 {
+                "varbinary(8000)".to_owned()
+            } else {
+                "varbinary(max)".to_owned()
+            }
+        }
+        SqlParam::Numeric(_) => {
+            let scale = keys
+                .iter()
+                .map(|key| match key {
+                    SqlParam::Numeric(n) => n.scale(),
+                    _ => 0,
+                })
+                .max()
+                .unwrap_or_default();
+            format!("numeric(38, {scale})")
+        }
+    };
+    Ok(sql_type)
+}
+
+/// A temp table on a pinned connection.
+pub(crate) struct TempTable {
+    name: String,
+}
+
+impl TempTable {
+    /// Create the temp table `name` (prefixed with `#` if needed), replacing any leftover table of the same name
+    /// on this connection.
+    pub(crate) async fn create(
+        conn: &mut PooledConnection<'_>,
+        name: &str,
+        columns: &[TempColumn],
+    ) -> Result<Self, Error> {
+        if columns.is_empty() {
+            return Err(Error::InvalidArgument(
+                "a temp table requires at least one column".to_owned(),
+            ));
+        }
+
+        let name = if name.starts_with('#') {
+            name.to_owned()
+        } else {
+            format!("#{name}")
+        };
+        let table = Self {
+            name: quote_identifier(&name),
+        };
+
+        let definitions = columns
+            .iter()
+            .map(TempColumn::definition)
+            .collect::<Result<Vec<_>, _>>()?
+            .join(", ");
+
+        let create = format!(
+            "{} CREATE TABLE {} ({definitions});",
+            table.drop_statement(),
+            table.name
+        );
+        conn.simple_query(create).await?.into_results().await?;
+
+        Ok(table)
+    }
+
+    /// Insert rows into the table with multi-row inserts, chunked to stay within the server's limits.
+    pub(crate) async fn load(
+        &self,
+        conn: &mut PooledConnection<'_>,
+        columns: usize,
+        rows: &[Vec<SqlParam>],
+        stable_types: bool,
+    ) -> Result<(), Error> {
+        if let Some(row) = rows.iter().find(|row| row.len() != columns) {
+            return Err(Error::InvalidArgument(format!(
+                "temp table row has {} values, expected {columns}",
+                row.len()
+            )));
+        }
+
+        let chunk_rows = (MAX_PARAMS / columns.max(1)).clamp(1, MAX_VALUES_ROWS);
+
+        for chunk in rows.chunks(chunk_rows) {
+            let values = (0..chunk.len())
+                .map(|row| {
+                    let placeholders = (0..columns)
+                        .map(|col| format!("@P{}", row * columns + col + 1))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    format!("({placeholders})")
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let mut insert = Query::new(format!("INSERT INTO {} VALUES {values};", self.name));
+            for param in chunk.iter().flatten() {
+                bind_param(&mut insert, param, stable_types);
+            }
+            insert.execute(&mut **conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Drop the table.
+    pub(crate) async fn drop(&self, conn: &mut PooledConnection<'_>) -> Result<(), Error> {
+        conn.simple_query(self.drop_statement())
+            .await?
+            .into_results()
+            .await?;
+        Ok(())
+    }
+
+    fn drop_statement(&self) -> String {
+        let literal = self.name.replace('\'', "''");
+        format!(
+            "IF OBJECT_ID('tempdb..{literal}') IS NOT NULL DROP TABLE {};",
+            self.name
+        )
+    }
+}
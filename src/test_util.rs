@@ -0,0 +1,467 @@
+use crate::{
+    connection::{PooledConnection, Priority},
+    error::Error,
+    observer::BrokenReason,
+    pool::SqlServerPool,
+    pool::SqlServerPoolBuilder,
+    query::{bind_params, json_query_on, query_rows_on},
+    sql::lexer::{tokenize, Token, TokenKind},
+    TryFromRow,
+};
+use serde::de::DeserializeOwned;
+use std::time::{Duration, Instant};
+use tiberius::{AuthMethod, Config};
+use tokio::process::Command;
+
+/// The image started by [`TestServer::start`], unless overridden with the `MSSQL_TEST_IMAGE` environment variable.
+const DEFAULT_IMAGE: &str = "mcr.microsoft.com/mssql/server:2022-latest";
+
+/// The `sa` password of test servers. It only needs to meet the server's complexity policy,
+/// as the port is bound to localhost.
+const SA_PASSWORD: &str = "MssqlRs-Test-Passw0rd";
+
+/// How long to wait for a new server to accept queries.
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often to check whether a new server is ready.
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A throwaway SQL Server running in a Docker container, for integration tests. Requires the `test-util` feature.
+///
+/// The container is started with the `docker` CLI, which must be on the `PATH`, and is removed when the
+/// `TestServer` is dropped. Set `MSSQL_TEST_IMAGE` to use an image other than SQL Server 2022.
+///
+/// # Example
+///
+/// ```no_run
+/// # use mssql_rs::TestServer;
+/// # async fn example() -> mssql_rs::Result<()> {
+/// let (_server, sql_server) = TestServer::start().await?;
+///
+/// sql_server.ready().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TestServer {
+    container_id: String,
+    config: Config,
+}
+
+impl TestServer {
+    /// Start a server, wait until it accepts queries, and return it with a pool connected to it as `sa`.
+    ///
+    /// Keep the `TestServer` alive for as long as the pool is used.
+    pub async fn start() -> Result<(TestServer, SqlServerPool), Error> {
+        let image = std::env::var("MSSQL_TEST_IMAGE").unwrap_or_else(|_| DEFAULT_IMAGE.to_owned());
+        let container_id = docker(&[
+            "run",
+            "--detach",
+            "--env",
+            "ACCEPT_EULA=Y",
+            "--env",
+            &format!("MSSQL_SA_PASSWORD={SA_PASSWORD}"),
+            "--publish",
+            "127.0.0.1::1433",
+            &image,
+        ])
+        .await?;
+
+        // Construct the server before anything else can fail, so the container is removed on error.
+        let mut server = TestServer {
+            container_id,
+            config: Config::new(),
+        };
+        let port = server.port().await?;
+
+        server.config.host("127.0.0.1");
+        server.config.port(port);
+        server
+            .config
+            .authentication(AuthMethod::sql_server("sa", SA_PASSWORD));
+        server.config.trust_cert();
+
+        let pool = SqlServerPoolBuilder::new()
+            .pool_connection_timeout(READY_POLL_INTERVAL * 5)
+            .build(server.config.clone())
+            .await?;
+        server.wait_until_ready(&pool).await?;
+
+        Ok((server, pool))
+    }
+
+    /// The configuration for connecting to the server as `sa`, e.g. to build a pool with other settings.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The host port the server's port 1433 is published on.
+    async fn port(&self) -> Result<u16, Error> {
+        let mapping = docker(&["port", &self.container_id, "1433/tcp"]).await?;
+        mapping
+            .lines()
+            .next()
+            .and_then(|line| line.rsplit(':').next())
+            .and_then(|port| port.parse().ok())
+            .ok_or_else(|| {
+                std::io::Error::other(format!("Unexpected docker port output: {mapping}")).into()
+            })
+    }
+
+    async fn wait_until_ready(&self, pool: &SqlServerPool) -> Result<(), Error> {
+        let deadline = Instant::now() + READY_TIMEOUT;
+        loop {
+            match pool.ready().await {
+                Ok(()) => return Ok(()),
+                Err(e) if Instant::now() >= deadline => {
+                    return Err(e.context("Test server did not become ready"))
+                }
+                Err(_) => tokio::time::sleep(READY_POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // Drop can't be async, and the container must be gone before the test process exits.
+        let _ = std::process::Command::new("docker")
+            .args(["rm", "--force", "--volumes", &self.container_id])
+            .output();
+    }
+}
+
+/// Run a `docker` command, returning its trimmed standard output.
+async fn docker(args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("docker").args(args).output().await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(std::io::Error::other(format!(
+            "docker {} failed: {}",
+            args.first().unwrap_or(&""),
+            stderr.trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+impl SqlServerPool {
+    /// Begin a transaction that is always rolled back, so a test leaves the database untouched. Requires the
+    /// `test-util` feature.
+    ///
+    /// The transaction is never committed: dropping the [`TestTransaction`] discards its connection, which rolls
+    /// the transaction back on the server, and [`TestTransaction::rollback`] does so explicitly and returns the
+    /// connection to the pool.
+    ///
+    /// Code under test may begin, commit and roll back transactions of its own as usual. They are rewritten to nest
+    /// within the test transaction: `BEGIN TRANSACTION` also sets a savepoint if it is the code's outermost, `COMMIT`
+    /// only ends the nesting, and `ROLLBACK` rolls back to the outermost savepoint, so the code's work is undone
+    /// while the test's is kept. `@@TRANCOUNT` reads as the code's own nesting, without the test transaction.
+    /// A `COMMIT` or `ROLLBACK` with no transaction of the code's to end fails as it would on the server, rather than
+    /// ending the test transaction. Rolling back to a named savepoint works as usual, but rolling back to the name of
+    /// a transaction fails, as it names no savepoint.
+    ///
+    /// Dynamic SQL run with `EXEC` isn't rewritten. If the test transaction is rolled back anyway, e.g. by a
+    /// `ROLLBACK` in dynamic SQL or an error with `XACT_ABORT` on, the query fails with [`Error::InvalidArgument`] and
+    /// a new test transaction is begun, but statements after the rollback in the same batch were committed.
+    ///
+    /// Only queries run through the `TestTransaction` are rolled back. The pool's own helpers, e.g.
+    /// [`SqlServerPool::upsert`], check out other connections and commit as usual, and other connections can't
+    /// see the transaction's uncommitted changes, so cross-connection visibility can't be tested this way.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use mssql_rs::TestServer;
+    /// #[tokio::test]
+    /// async fn inserts_a_person() -> mssql_rs::Result<()> {
+    ///     let (_server, sql_server) = TestServer::start().await?;
+    ///     let mut tx = sql_server.test_transaction().await?;
+    ///
+    ///     tx.execute("INSERT INTO people (name) VALUES (@P1)", &["Alice".to_owned()]).await?;
+    ///     let count: serde_json::Value = tx
+    ///         .json_query("SELECT COUNT(*) AS n FROM people FOR JSON PATH", &[])
+    ///         .await?;
+    ///     assert_eq!(count[0]["n"], 1);
+    ///
+    ///     tx.rollback().await
+    /// }
+    /// ```
+    pub async fn test_transaction(&self) -> Result<TestTransaction<'_>, Error> {
+        let mut conn = self.get_with_priority(Priority::High).await?;
+        conn.mark_broken_because(BrokenReason::SessionState);
+        conn.simple_query(BEGIN_QUERY).await?.into_results().await?;
+        Ok(TestTransaction { conn })
+    }
+}
+
+const BEGIN_QUERY: &str = "BEGIN TRANSACTION;";
+
+/// A transaction that is always rolled back, see [`SqlServerPool::test_transaction`]. Requires the `test-util` feature.
+pub struct TestTransaction<'a> {
+    conn: PooledConnection<'a>,
+}
+
+impl TestTransaction<'_> {
+    /// Run a JSON query in the transaction, see [`SqlServerPool::json_query`].
+    pub async fn json_query<T>(&mut self, query: &str, params: &[String]) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let result = json_query_on(&mut self.conn, &nest_transactions(query), params).await;
+        self.check_open().await?;
+        result
+    }
+
+    /// Run a SQL query in the transaction, see [`SqlServerPool::row_query`].
+    pub async fn row_query<T>(&mut self, query: &str, params: &[String]) -> Result<Vec<T>, Error>
+    where
+        T: TryFromRow,
+    {
+        let result = query_rows_on(&mut self.conn, &nest_transactions(query), params).await;
+        self.check_open().await?;
+        result
+    }
+
+    /// Run a statement in the transaction, returning the number of rows affected.
+    pub async fn execute(&mut self, query: &str, params: &[String]) -> Result<u64, Error> {
+        let query = nest_transactions(query);
+        let statement = bind_params(&query, params)?;
+        let result = match statement.execute(&mut self.conn).await {
+            Ok(result) => Ok(result.total()),
+            Err(e) => Err(e.into()),
+        };
+        self.check_open().await?;
+        result
+    }
+
+    /// Roll the transaction back and return the connection to the pool.
+    pub async fn rollback(mut self) -> Result<(), Error> {
+        self.conn
+            .simple_query("IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION;")
+            .await?
+            .into_results()
+            .await?;
+        self.conn.set_broken(false);
+        Ok(())
+    }
+
+    /// Fail if a query rolled back the transaction, beginning a new one so later queries are still rolled back.
+    async fn check_open(&mut self) -> Result<(), Error> {
+        let count = self
+            .conn
+            .simple_query("SELECT @@TRANCOUNT;")
+            .await?
+            .into_row()
+            .await?
+            .and_then(|row| row.get::<i32, _>(0))
+            .unwrap_or(0);
+        if count > 0 {
+            return Ok(());
+        }
+
+        self.conn
+            .simple_query(BEGIN_QUERY)
+            .await?
+            .into_results()
+            .await?;
+        Err(Error::InvalidArgument(
+            "the test transaction was rolled back, so statements after the rollback in the same batch were committed"
+                .to_owned(),
+        ))
+    }
+}
+
+/// The savepoint set where the outermost transaction of the code under test begins, see
+/// [`SqlServerPool::test_transaction`].
+const SAVEPOINT: &str = "mssql_rs_test";
+
+/// Rewrite the transaction statements of code under test to nest within the test transaction, see
+/// [`SqlServerPool::test_transaction`].
+fn nest_transactions(sql: &str) -> String {
+    let tokens: Vec<_> = tokenize(sql).collect();
+    let mut nested = String::with_capacity(sql.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        if token.kind == TokenKind::Variable && token.text.eq_ignore_ascii_case("@@TRANCOUNT") {
+            nested.push_str("(@@TRANCOUNT - 1)");
+        } else if let Some((statement, end)) = transaction_statement(&tokens, i) {
+            nested.push_str(&statement);
+            i = end;
+            continue;
+        } else {
+            nested.push_str(token.text);
+        }
+        i += 1;
+    }
+    nested
+}
+
+/// If `tokens[start]` begins a `BEGIN TRANSACTION`, `COMMIT` or `ROLLBACK` statement, the statement nesting it in
+/// the test transaction and the index of the token after it.
+fn transaction_statement(tokens: &[Token<'_>], start: usize) -> Option<(String, usize)> {
+    let keyword = tokens[start];
+    if keyword.kind != TokenKind::Word {
+        return None;
+    }
+    let next = next_significant(tokens, start + 1);
+    let tran = next.filter(|&j| is_word(tokens[j], &["TRAN", "TRANSACTION"]));
+    let work = next.filter(|&j| is_word(tokens[j], &["WORK"]));
+    let missing = |statement: &str| {
+        format!(
+            "RAISERROR(N'The {statement} TRANSACTION request has no corresponding BEGIN TRANSACTION.', 16, 1);"
+        )
+    };
+
+    if is_word(keyword, &["BEGIN"]) {
+        let end = skip_name(tokens, tran? + 1);
+        Some((
+            format!(
+                "BEGIN BEGIN TRANSACTION; IF @@TRANCOUNT = 2 SAVE TRANSACTION {SAVEPOINT}; END"
+            ),
+            end,
+        ))
+    } else if is_word(keyword, &["COMMIT"]) {
+        let end = match (tran, work) {
+            (Some(j), _) => skip_name(tokens, j + 1),
+            (_, Some(j)) => j + 1,
+            _ => start + 1,
+        };
+        Some((
+            format!(
+                "BEGIN IF @@TRANCOUNT > 1 BEGIN COMMIT TRANSACTION; END ELSE BEGIN {} END END",
+                missing("COMMIT")
+            ),
+            end,
+        ))
+    } else if is_word(keyword, &["ROLLBACK"]) {
+        let end = match (tran, work) {
+            // Rolling back to a named savepoint needs no rewriting.
+            (Some(j), _) if skip_name(tokens, j + 1) != j + 1 => return None,
+            (Some(j), _) | (_, Some(j)) => j + 1,
+            _ => start + 1,
+        };
+        Some((
+            format!(
+                "BEGIN IF @@TRANCOUNT > 1 BEGIN ROLLBACK TRANSACTION {SAVEPOINT}; \
+                 WHILE @@TRANCOUNT > 1 COMMIT TRANSACTION; END ELSE BEGIN {} END END",
+                missing("ROLLBACK")
+            ),
+            end,
+        ))
+    } else {
+        None
+    }
+}
+
+/// The index of the first token from `start` that isn't whitespace or a comment.
+fn next_significant(tokens: &[Token<'_>], start: usize) -> Option<usize> {
+    (start..tokens.len())
+        .find(|&i| !matches!(tokens[i].kind, TokenKind::Whitespace | TokenKind::Comment))
+}
+
+/// Skip the transaction or savepoint name starting at `start`, if there is one.
+fn skip_name(tokens: &[Token<'_>], start: usize) -> usize {
+    match next_significant(tokens, start) {
+        Some(i) if is_name(tokens[i]) => i + 1,
+        _ => start,
+    }
+}
+
+/// Whether `token` names a transaction, rather than starting the next statement.
+fn is_name(token: Token<'_>) -> bool {
+    match token.kind {
+        TokenKind::Variable | TokenKind::QuotedIdentifier => true,
+        TokenKind::Word => !is_word(token, STATEMENT_KEYWORDS),
+        _ => false,
+    }
+}
+
+/// Keywords that can follow a transaction statement without a semicolon, and so aren't its name.
+const STATEMENT_KEYWORDS: &[&str] = &[
+    "ALTER",
+    "BEGIN",
+    "BREAK",
+    "COMMIT",
+    "CONTINUE",
+    "CREATE",
+    "DECLARE",
+    "DELETE",
+    "DROP",
+    "ELSE",
+    "END",
+    "EXEC",
+    "EXECUTE",
+    "GOTO",
+    "IF",
+    "INSERT",
+    "MERGE",
+    "PRINT",
+    "RAISERROR",
+    "RETURN",
+    "ROLLBACK",
+    "SAVE",
+    "SELECT",
+    "SET",
+    "THROW",
+    "TRUNCATE",
+    "UPDATE",
+    "USE",
+    "WAITFOR",
+    "WHILE",
+    "WITH",
+];
+
+fn is_word(token: Token<'_>, words: &[&str]) -> bool {
+    token.kind == TokenKind::Word
+        && words
+            .iter()
+            .any(|word| token.text.eq_ignore_ascii_case(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transaction_statements_nest_in_the_test_transaction() {
+        let nested = nest_transactions(
+            "BEGIN TRAN outer_tx; INSERT INTO t VALUES (1); IF @@trancount > 0 COMMIT TRANSACTION outer_tx;",
+        );
+        assert_eq!(
+            nested,
+            "BEGIN BEGIN TRANSACTION; IF @@TRANCOUNT = 2 SAVE TRANSACTION mssql_rs_test; END; \
+             INSERT INTO t VALUES (1); IF (@@TRANCOUNT - 1) > 0 BEGIN IF @@TRANCOUNT > 1 BEGIN COMMIT TRANSACTION; END \
+             ELSE BEGIN RAISERROR(N'The COMMIT TRANSACTION request has no corresponding BEGIN TRANSACTION.', 16, 1); \
+             END END;"
+        );
+    }
+
+    #[test]
+    fn rollbacks_return_to_the_outermost_savepoint() {
+        let nested = nest_transactions(
+            "BEGIN TRY SELECT 1; END TRY BEGIN CATCH ROLLBACK\nSELECT 2; END CATCH",
+        );
+        assert!(
+            nested.starts_with("BEGIN TRY SELECT 1; END TRY BEGIN CATCH BEGIN IF @@TRANCOUNT > 1")
+        );
+        assert!(nested.contains(
+            "ROLLBACK TRANSACTION mssql_rs_test; WHILE @@TRANCOUNT > 1 COMMIT TRANSACTION;"
+        ));
+        assert!(nested.ends_with("END END\nSELECT 2; END CATCH"));
+    }
+
+    #[test]
+    fn other_statements_are_left_alone() {
+        for sql in [
+            "SAVE TRANSACTION before_update; ROLLBACK TRANSACTION before_update;",
+            "BEGIN DISTRIBUTED TRANSACTION;",
+            "SELECT 'BEGIN TRAN' AS note /* COMMIT */ -- ROLLBACK",
+            "SELECT [commit] FROM log",
+        ] {
+            assert_eq!(nest_transactions(sql), sql);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The TDS packet type of a tabular result, the only packet type whose payload starts with a token.
+const TABULAR_RESULT: u8 = 0x04;
+
+/// The end of message bit of the TDS packet status.
+const END_OF_MESSAGE: u8 = 0x01;
+
+/// A bounded, shared record of the TDS packet headers sent and received by a pool's connections.
+///
+/// Only packet headers are recorded: the packet type, status and length, plus the type of the first token of each
+/// server response. Payload data is never recorded. Once `capacity` packets are recorded, the oldest are discarded.
+///
+/// When the connection is encrypted, the packets are inside TLS records and only the record headers are visible.
+/// For a token level trace, reproduce the problem with encryption turned off.
+#[derive(Clone)]
+pub struct ProtocolTrace {
+    inner: Arc<TraceInner>,
+}
+
+struct TraceInner {
+    capacity: usize,
+    packets: Mutex<VecDeque<TracePacket>>,
+    next_connection: AtomicU64,
+}
+
+impl ProtocolTrace {
+    /// Create a trace holding at most `capacity` packets.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(TraceInner {
+                capacity,
+                packets: Mutex::new(VecDeque::with_capacity(capacity)),
+                next_connection: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// The recorded packets, oldest first.
+    pub fn packets(&self) -> Vec<TracePacket> {
+        self.lock().iter().copied().collect()
+    }
+
+    /// Discard all recorded packets.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn record(&self, packet: TracePacket) {
+        if self.inner.capacity == 0 {
+            return;
+        }
+        let mut packets = self.lock();
+        if packets.len() == self.inner.capacity {
+            packets.pop_front();
+        }
+        packets.push_back(packet);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<TracePacket>> {
+        self.inner
+            .packets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl fmt::Debug for ProtocolTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProtocolTrace")
+            .field("capacity", &self.inner.capacity)
+            .field("packets", &self.lock().len())
+            .finish()
+    }
+}
+
+/// Dumps the recorded packets, one per line, in a form suitable for a bug report.
+impl fmt::Display for ProtocolTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for packet in self.lock().iter() {
+            writeln!(f, "{packet}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The direction of a traced packet, relative to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceDirection {
+    Sent,
+    Received,
+}
+
+/// The header of a single traced TDS packet or TLS record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TracePacket {
+    /// Identifies the connection within the trace, in the order connections were opened.
+    pub connection: u64,
+    pub direction: TraceDirection,
+    /// Whether this is a TLS record rather than a TDS packet.
+    pub encrypted: bool,
+    /// The TDS packet type, or the TLS record content type.
+    pub packet_type: u8,
+    /// The TDS packet status. Always 0 for TLS records.
+    pub status: u8,
+    /// The length of the packet or record, including its header.
+    pub length: usize,
+    /// The type of the first token of a server response message.
+    pub first_token: Option<u8>,
+}
+
+impl fmt::Display for TracePacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            TraceDirection::Sent => "sent",
+            TraceDirection::Received => "recv",
+        };
+        let protocol = if self.encrypted { "tls" } else { "tds" };
+        write!(
+            f,
+            "#{} {direction} {protocol} type=0x{:02x} status=0x{:02x} length={}",
+            self.connection, self.packet_type, self.status, self.length
+        )?;
+        if let Some(token) = self.first_token {
+            write!(f, " token=0x{token:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Incrementally parses the headers out of one direction of the byte stream.
+struct HeaderParser {
+    direction: TraceDirection,
+    header: [u8; 8],
+    filled: usize,
+    remaining: usize,
+    message_start: bool,
+    awaiting_token: Option<TracePacket>,
+}
+
+impl HeaderParser {
+    fn new(direction: TraceDirection) -> Self {
+        Self {
+            direction,
+            header: [0; 8],
+            filled: 0,
+            remaining: 0,
+            message_start: true,
+            awaiting_token: None,
+        }
+    }
+
+    fn feed(&mut self, trace: &ProtocolTrace, connection: u64, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            if self.remaining > 0 {
+                if let Some(mut packet) = self.awaiting_token.take() {
+                    packet.first_token = Some(bytes[0]);
+                    trace.record(packet);
+                }
+                let consumed = self.remaining.min(bytes.len());
+                self.remaining -= consumed;
+                bytes = &bytes[consumed..];
+                continue;
+            }
+
+            // TLS content types (0x14 to 0x17) never collide with TDS packet types, which stop at 0x12.
+            let lead = if self.filled == 0 {
+                bytes[0]
+            } else {
+                self.header[0]
+            };
+            let encrypted = (0x14..=0x17).contains(&lead);
+            let header_len = if encrypted { 5 } else { 8 };
+            let take = (header_len - self.filled).min(bytes.len());
+            self.header[self.filled..self.filled + take].copy_from_slice(&bytes[..take]);
+            self.filled += take;
+            bytes = &bytes[take..];
+            if self.filled < header_len {
+                continue;
+            }
+
+            let header = self.header;
+            self.header = [0; 8];
+            self.filled = 0;
+
+            let mut packet = TracePacket {
+                connection,
+                direction: self.direction,
+                encrypted,
+                packet_type: header[0],
+                status: 0,
+                length: 0,
+                first_token: None,
+            };
+            if encrypted {
+                let payload = u16::from_be_bytes([header[3], header[4]]) as usize;
+                packet.length = payload + header_len;
+                self.remaining = payload;
+                trace.record(packet);
+                continue;
+            }
+
+            packet.status = header[1];
+            packet.length = u16::from_be_bytes([header[2], header[3]]) as usize;
+            self.remaining = packet.length.saturating_sub(header_len);
+            let wants_token = self.message_start
+                && self.direction == TraceDirection::Received
+                && packet.packet_type == TABULAR_RESULT
+                && self.remaining > 0;
+            self.message_start = packet.status & END_OF_MESSAGE != 0;
+            if wants_token {
+                self.awaiting_token = Some(packet);
+            } else {
+                trace.record(packet);
+            }
+        }
+    }
+}
+
+/// A stream that records the headers of the TDS packets passing through it, the transport of [`Client`](crate::Client)
+/// with the `protocol-debug` feature.
+///
+/// Without a trace, bytes pass straight through.
+pub struct TracedStream<S> {
+    inner: S,
+    trace: Option<ProtocolTrace>,
+    connection: u64,
+    sent: HeaderParser,
+    received: HeaderParser,
+}
+
+impl<S> TracedStream<S> {
+    pub(crate) fn new(inner: S, trace: Option<ProtocolTrace>) -> Self {
+        let connection = trace
+            .as_ref()
+            .map(|trace| trace.inner.next_connection.fetch_add(1, Ordering::Relaxed))
+            .unwrap_or_default();
+        Self {
+            inner,
+            trace,
+            connection,
+            sent: HeaderParser::new(TraceDirection::Sent),
+            received: HeaderParser::new(TraceDirection::Received),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TracedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(())), Some(trace)) = (&poll, &this.trace) {
+            this.received
+                .feed(trace, this.connection, &buf.filled()[before..]);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TracedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let (Poll::Ready(Ok(written)), Some(trace)) = (&poll, &this.trace) {
+            this.sent.feed(trace, this.connection, &buf[..*written]);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
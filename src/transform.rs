@@ -0,0 +1,200 @@
+use crate::value::SqlValue;
+use std::fmt;
+use std::sync::Arc;
+use tiberius::time::{Date, DateTime, DateTime2, SmallDateTime, Time};
+use tiberius::{Column, ColumnData, ColumnType};
+
+/// A transformation applied to every value of a query's rows, e.g. to clean up legacy data.
+///
+/// Register transformers for every query with [`SqlServerPoolBuilder::row_transformer`](crate::SqlServerPoolBuilder::row_transformer),
+/// or for a single query with [`QueryOptions::transformers`](crate::QueryOptions::transformers).
+/// The pool's transformers run first, then the query's, each in the order they were added.
+/// They are applied by [`SqlServerPool::row_query_dynamic`](crate::SqlServerPool::row_query_dynamic).
+pub trait RowTransformer: Send + Sync {
+    /// Transform the value of `column`, returning it unchanged if the transformer doesn't apply.
+    fn transform(&self, column: &Column, value: SqlValue) -> SqlValue;
+
+    /// Whether [`RowTransformer::transform`] may change values of `column`. Values of columns no transformer applies
+    /// to are read in place rather than copied and transformed. Defaults to `true`.
+    fn applies_to(&self, column: &Column) -> bool {
+        let _ = column;
+        true
+    }
+}
+
+impl fmt::Debug for dyn RowTransformer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RowTransformer")
+    }
+}
+
+/// Whether any of `transformers` applies to `column`.
+pub(crate) fn any_applies(transformers: &[Arc<dyn RowTransformer>], column: &Column) -> bool {
+    transformers
+        .iter()
+        .any(|transformer| transformer.applies_to(column))
+}
+
+/// Apply `transformers` to a value of `column`, in order.
+pub(crate) fn transform_value(
+    transformers: &[Arc<dyn RowTransformer>],
+    column: &Column,
+    value: SqlValue,
+) -> SqlValue {
+    transformers.iter().fold(value, |value, transformer| {
+        transformer.transform(column, value)
+    })
+}
+
+/// Trims trailing spaces from `char(n)` and `nchar(n)` columns, which the server pads to their full length.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrimFixedChar;
+
+impl RowTransformer for TrimFixedChar {
+    fn transform(&self, column: &Column, value: SqlValue) -> SqlValue {
+        match (column.column_type(), value) {
+            (ColumnType::BigChar | ColumnType::NChar, ColumnData::String(Some(s))) => {
+                let len = s.trim_end_matches(' ').len();
+                if len == s.len() {
+                    ColumnData::String(Some(s))
+                } else {
+                    // Truncate in place, so a value read from the server isn't copied.
+                    let mut s = s.into_owned();
+                    s.truncate(len);
+                    ColumnData::String(Some(s.into()))
+                }
+            }
+            (_, value) => value,
+        }
+    }
+
+    fn applies_to(&self, column: &Column) -> bool {
+        matches!(
+            column.column_type(),
+            ColumnType::BigChar | ColumnType::NChar
+        )
+    }
+}
+
+/// Converts empty strings to NULL.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmptyToNull;
+
+impl RowTransformer for EmptyToNull {
+    fn transform(&self, _column: &Column, value: SqlValue) -> SqlValue {
+        match value {
+            ColumnData::String(Some(s)) if s.is_empty() => ColumnData::String(None),
+            value => value,
+        }
+    }
+
+    fn applies_to(&self, column: &Column) -> bool {
+        matches!(
+            column.column_type(),
+            ColumnType::BigVarChar
+                | ColumnType::BigChar
+                | ColumnType::NVarchar
+                | ColumnType::NChar
+                | ColumnType::Text
+                | ColumnType::NText
+                | ColumnType::SSVariant
+        )
+    }
+}
+
+/// Converts `datetime`, `smalldatetime` and `datetime2` values from server local time to UTC, using a fixed offset.
+///
+/// The offset is the server's local time minus UTC, e.g. 60 for UTC+01:00. A fixed offset doesn't follow daylight
+/// saving changes. `datetimeoffset` values already carry their offset and are left unchanged.
+/// Values that would fall outside the range of their type are also left unchanged.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalToUtc {
+    offset_minutes: i32,
+}
+
+impl LocalToUtc {
+    /// Create a transformer for a server whose local time is `offset_minutes` ahead of UTC.
+    pub fn from_offset_minutes(offset_minutes: i32) -> Self {
+        Self { offset_minutes }
+    }
+}
+
+impl RowTransformer for LocalToUtc {
+    fn transform(&self, _column: &Column, value: SqlValue) -> SqlValue {
+        let seconds = -i64::from(self.offset_minutes) * 60;
+        match value {
+            // datetime counts time in 1/300 second ticks.
+            ColumnData::DateTime(Some(dt)) => {
+                let shifted = shift(
+                    dt.days().into(),
+                    dt.seconds_fragments().into(),
+                    300 * 86_400,
+                    seconds * 300,
+                )
+                .and_then(|(days, ticks)| {
+                    Some(DateTime::new(days.try_into().ok()?, ticks.try_into().ok()?))
+                });
+                ColumnData::DateTime(Some(shifted.unwrap_or(dt)))
+            }
+            // smalldatetime counts time in minutes.
+            ColumnData::SmallDateTime(Some(dt)) => {
+                let shifted = shift(
+                    dt.days().into(),
+                    dt.seconds_fragments().into(),
+                    1440,
+                    seconds / 60,
+                )
+                .and_then(|(days, ticks)| {
+                    Some(SmallDateTime::new(
+                        days.try_into().ok()?,
+                        ticks.try_into().ok()?,
+                    ))
+                });
+                ColumnData::SmallDateTime(Some(shifted.unwrap_or(dt)))
+            }
+            // datetime2 counts time in increments of 10^-scale seconds.
+            ColumnData::DateTime2(Some(dt)) => {
+                let time = dt.time();
+                let per_second = 10i64.pow(time.scale().into());
+                let shifted = i64::try_from(time.increments())
+                    .ok()
+                    .and_then(|increments| {
+                        shift(
+                            dt.date().days().into(),
+                            increments,
+                            86_400 * per_second,
+                            seconds * per_second,
+                        )
+                    })
+                    .and_then(|(days, increments)| {
+                        Some(DateTime2::new(
+                            Date::new(days.try_into().ok()?),
+                            Time::new(increments.try_into().ok()?, time.scale()),
+                        ))
+                    });
+                ColumnData::DateTime2(Some(shifted.unwrap_or(dt)))
+            }
+            value => value,
+        }
+    }
+
+    fn applies_to(&self, column: &Column) -> bool {
+        matches!(
+            column.column_type(),
+            ColumnType::Datetime
+                | ColumnType::Datetime4
+                | ColumnType::Datetimen
+                | ColumnType::Datetime2
+                | ColumnType::SSVariant
+        )
+    }
+}
+
+/// Shift a day count and time of day by `delta` ticks, returning `None` on overflow.
+fn shift(days: i64, ticks: i64, ticks_per_day: i64, delta: i64) -> Option<(i64, i64)> {
+    let total = ticks.checked_add(delta)?;
+    Some((
+        days.checked_add(total.div_euclid(ticks_per_day))?,
+        total.rem_euclid(ticks_per_day),
+    ))
+}
@@ -0,0 +1,244 @@
+use crate::codec::table_key;
+use crate::connection::Client;
+use crate::error::Error;
+use crate::observer::ConnectionObserver;
+use crate::param::SqlParam;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tiberius::Query;
+
+/// How the upsert and insert helpers handle a string too long for its `nvarchar` or `nchar` column, see
+/// [`SqlServerPoolBuilder::truncation_policy`](crate::SqlServerPoolBuilder::truncation_policy).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TruncationPolicy {
+    /// Send the value as is, so the server fails the statement with "String or binary data would be truncated".
+    #[default]
+    Error,
+    /// Truncate the value to the column's length, as [`SqlParam::nvarchar_truncated`] does, and report the column
+    /// to [`ConnectionObserver::on_truncated`].
+    TruncateAndWarn,
+}
+
+/// Reads the lengths, in UTF-16 code units, of a table's bounded `nvarchar` (231) and `nchar` (239) columns.
+const COLUMN_LENGTHS_QUERY: &str = "SELECT name, max_length / 2 FROM sys.columns \
+    WHERE object_id = OBJECT_ID(@P1) AND system_type_id IN (231, 239) AND max_length > 0;";
+
+/// Truncates the values bound to columns with [`TruncationPolicy::TruncateAndWarn`], caching column lengths per table.
+pub(crate) struct Truncator {
+    /// The columns that are truncated, keyed by unqualified table name and column name, lowercased.
+    columns: HashSet<(String, String)>,
+    /// The lengths of each quoted table's string columns, keyed by lowercased column name.
+    lengths: Mutex<HashMap<String, HashMap<String, usize>>>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+}
+
+impl std::fmt::Debug for Truncator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(&self.columns).finish()
+    }
+}
+
+impl Truncator {
+    /// Returns `None` if no column is truncated, so the helpers skip truncation entirely.
+    pub(crate) fn new(
+        policies: &[(String, String, TruncationPolicy)],
+        observer: Option<Arc<dyn ConnectionObserver>>,
+    ) -> Result<Option<Self>, Error> {
+        let mut columns = HashSet::new();
+        for (table, column, policy) in policies {
+            let key = (table_key(table)?, column.to_lowercase());
+            match policy {
+                TruncationPolicy::TruncateAndWarn => columns.insert(key),
+                TruncationPolicy::Error => columns.remove(&key),
+            };
+        }
+        Ok((!columns.is_empty()).then(|| Self {
+            columns,
+            lengths: Mutex::default(),
+            observer,
+        }))
+    }
+
+    /// Truncate the string values of `params` bound to the quoted `table`'s truncated columns.
+    /// Returns `None` if none of the columns are truncated, so the caller can bind the originals.
+    pub(crate) async fn truncate_params<'a>(
+        &self,
+        conn: &mut Client,
+        table: &str,
+        params: &[(&'a str, SqlParam)],
+    ) -> Result<Option<Vec<(&'a str, SqlParam)>>, Error> {
+        let key = table_key(table)?;
+        let truncated = |column: &str| self.columns.contains(&(key.clone(), column.to_lowercase()));
+        if !params.iter().any(|(column, _)| truncated(column)) {
+            return Ok(None);
+        }
+
+        let lengths = self.lengths(conn, table).await?;
+        Ok(Some(self.truncate(table, &key, &lengths, params)))
+    }
+
+    /// Truncate the string values of `params` bound to truncated columns of `table`, whose key is `key`, to the
+    /// column `lengths`, reporting each truncated column to the observer.
+    fn truncate<'a>(
+        &self,
+        table: &str,
+        key: &str,
+        lengths: &HashMap<String, usize>,
+        params: &[(&'a str, SqlParam)],
+    ) -> Vec<(&'a str, SqlParam)> {
+        params
+            .iter()
+            .map(|(column, param)| {
+                let column_key = column.to_lowercase();
+                let truncated = self.columns.contains(&(key.to_owned(), column_key.clone()));
+                match (param, lengths.get(&column_key)) {
+                    (SqlParam::String(value), Some(&length)) if truncated => {
+                        let (param, was_truncated) = SqlParam::nvarchar_truncated(value, length);
+                        if was_truncated {
+                            if let Some(observer) = &self.observer {
+                                observer.on_truncated(table, column);
+                            }
+                        }
+                        (*column, param)
+                    }
+                    _ => (*column, param.clone()),
+                }
+            })
+            .collect()
+    }
+
+    async fn lengths(
+        &self,
+        conn: &mut Client,
+        table: &str,
+    ) -> Result<HashMap<String, usize>, Error> {
+        let cached = self
+            .lengths
+            .lock()
+            .expect("column length cache poisoned")
+            .get(table)
+            .cloned();
+        if let Some(lengths) = cached {
+            return Ok(lengths);
+        }
+
+        let mut query = Query::new(COLUMN_LENGTHS_QUERY);
+        query.bind(table);
+        let mut lengths = HashMap::new();
+        for row in query.query(conn).await?.into_first_result().await? {
+            if let (Some(name), Some(length)) =
+                (row.try_get::<&str, _>(0)?, row.try_get::<i32, _>(1)?)
+            {
+                lengths.insert(name.to_lowercase(), length as usize);
+            }
+        }
+        self.lengths
+            .lock()
+            .expect("column length cache poisoned")
+            .insert(table.to_owned(), lengths.clone());
+        Ok(lengths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(String, String)>>);
+
+    impl ConnectionObserver for Recorder {
+        fn on_truncated(&self, table: &str, column: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((table.to_owned(), column.to_owned()));
+        }
+    }
+
+    fn policy(
+        table: &str,
+        column: &str,
+        policy: TruncationPolicy,
+    ) -> (String, String, TruncationPolicy) {
+        (table.to_owned(), column.to_owned(), policy)
+    }
+
+    fn string(s: &str) -> SqlParam {
+        SqlParam::String(s.to_owned())
+    }
+
+    #[test]
+    fn no_truncated_columns_disables_truncation() {
+        assert!(Truncator::new(&[], None).unwrap().is_none());
+        let policies = [
+            policy("dbo.posts", "body", TruncationPolicy::TruncateAndWarn),
+            policy("posts", "BODY", TruncationPolicy::Error),
+        ];
+        assert!(Truncator::new(&policies, None).unwrap().is_none());
+        assert!(Truncator::new(
+            &[policy("dbo.", "body", TruncationPolicy::TruncateAndWarn)],
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn only_designated_columns_are_truncated_and_reported() {
+        let recorder = Arc::new(Recorder::default());
+        let policies = [
+            policy("[dbo].[Posts]", "Body", TruncationPolicy::TruncateAndWarn),
+            policy("posts", "summary", TruncationPolicy::TruncateAndWarn),
+        ];
+        let truncator = Truncator::new(&policies, Some(recorder.clone()))
+            .unwrap()
+            .unwrap();
+        let lengths = HashMap::from([
+            ("body".to_owned(), 5),
+            ("summary".to_owned(), 10),
+            ("title".to_owned(), 3),
+        ]);
+
+        let params = [
+            ("BODY", string("héllo wörld")),
+            ("summary", string("short")),
+            ("title", string("too long")),
+            ("views", SqlParam::I32(7)),
+        ];
+        let truncated = truncator.truncate("dbo.posts", "posts", &lengths, &params);
+        assert_eq!(
+            truncated,
+            [
+                ("BODY", string("héllo")),
+                ("summary", string("short")),
+                ("title", string("too long")),
+                ("views", SqlParam::I32(7)),
+            ]
+        );
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [("dbo.posts".to_owned(), "BODY".to_owned())]
+        );
+    }
+
+    #[test]
+    fn truncation_never_splits_a_character() {
+        let truncator =
+            Truncator::new(&[policy("t", "c", TruncationPolicy::TruncateAndWarn)], None)
+                .unwrap()
+                .unwrap();
+        let lengths = HashMap::from([("c".to_owned(), 4)]);
+        let truncate = |value: &str| {
+            truncator
+                .truncate("t", "t", &lengths, &[("c", string(value))])
+                .remove(0)
+                .1
+        };
+
+        // An emoji is two UTF-16 code units, so it is dropped whole rather than split.
+        assert_eq!(truncate("abc🦀"), string("abc"));
+        assert_eq!(truncate("ab🦀"), string("ab🦀"));
+        assert_eq!(truncate("日本語です"), string("日本語で"));
+        assert_eq!(truncate("🦀🦀🦀"), string("🦀🦀"));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::pool::SqlServerPool;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+/// A [`SqlServerPool`] tagged with the database it connects to, so the compiler rejects passing it where a pool for
+/// another database is expected.
+///
+/// The marker is any type the application defines, typically an empty struct per database. `TypedPool` derefs to
+/// the pool, so every query method and [`PooledConnection`](crate::PooledConnection) is available as usual, and it
+/// is the same size as the pool.
+///
+/// # Example
+///
+/// ```no_run
+/// # use mssql_rs::{SqlServerPool, TypedPool};
+/// pub struct Billing;
+/// pub struct Reporting;
+///
+/// async fn monthly_invoices(pool: &TypedPool<Billing>) -> mssql_rs::Result<serde_json::Value> {
+///     pool.json_query("SELECT * FROM invoices FOR JSON PATH", &[]).await
+/// }
+///
+/// # async fn example(cfg: tiberius::Config) -> mssql_rs::Result<()> {
+/// let billing = TypedPool::<Billing>::new(SqlServerPool::new(cfg).await?);
+/// monthly_invoices(&billing).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TypedPool<M> {
+    pool: SqlServerPool,
+    // A function pointer, so the marker doesn't affect auto traits or drop checking.
+    _marker: PhantomData<fn() -> M>,
+}
+
+impl<M> TypedPool<M> {
+    /// Tag `pool` as connecting to the database `M`.
+    pub fn new(pool: SqlServerPool) -> Self {
+        Self {
+            pool,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the untyped pool.
+    pub fn into_inner(self) -> SqlServerPool {
+        self.pool
+    }
+}
+
+impl<M> Deref for TypedPool<M> {
+    type Target = SqlServerPool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+/// Cloning is cheap, see [`SqlServerPool`]. Doesn't require the marker to be `Clone`.
+impl<M> Clone for TypedPool<M> {
+    fn clone(&self) -> Self {
+        Self::new(self.pool.clone())
+    }
+}
+
+impl<M> fmt::Debug for TypedPool<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedPool")
+            .field("database", &std::any::type_name::<M>())
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
@@ -0,0 +1,86 @@
+use crate::clock::Clock;
+use crate::manager::{ConnectionManager, VALIDATION_QUERY};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+
+/// The results of background validation, reported by [`SqlServerPool::status`](crate::SqlServerPool::status).
+#[derive(Debug, Default)]
+pub(crate) struct ValidationStats {
+    last: Mutex<Option<SystemTime>>,
+    failures: AtomicU64,
+}
+
+impl ValidationStats {
+    pub(crate) fn last(&self) -> Option<SystemTime> {
+        *self.last.lock().expect("validation stats poisoned")
+    }
+
+    pub(crate) fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+/// A background task validating a pool's idle connections. The task stops when this is dropped.
+#[derive(Debug)]
+pub(crate) struct Validator {
+    token: CancellationToken,
+}
+
+impl Drop for Validator {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+impl Validator {
+    /// Spawn a task validating the idle connections of `pools`, e.g. a pool and its affinity shards, every `interval`.
+    pub(crate) fn spawn(
+        pools: Vec<bb8::Pool<ConnectionManager>>,
+        interval: Duration,
+        stats: Arc<ValidationStats>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let token = CancellationToken::new();
+        let cancelled = token.clone();
+
+        tokio::spawn(async move {
+            // New connections don't need validating, so wait a full interval first. Each pass is followed by a full
+            // interval, so a slow pass delays the next rather than running them back to back.
+            loop {
+                tokio::select! {
+                    _ = cancelled.cancelled() => break,
+                    _ = clock.sleep(interval) => {
+                        for pool in &pools {
+                            validate_idle(pool, &stats).await;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self { token }
+    }
+}
+
+/// Validate each idle connection once, one at a time, discarding those that fail.
+///
+/// Connections are only checked out while some are idle, so queries never wait on the validator.
+/// Idle connections are queued first in, first out, so each checkout takes the next idle connection.
+async fn validate_idle(pool: &bb8::Pool<ConnectionManager>, stats: &ValidationStats) {
+    let idle = pool.state().idle_connections;
+    for _ in 0..idle {
+        if pool.state().idle_connections == 0 {
+            break;
+        }
+        let Ok(mut conn) = pool.get().await else {
+            break;
+        };
+        if conn.client.simple_query(VALIDATION_QUERY).await.is_err() {
+            conn.broken = true;
+            stats.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    *stats.last.lock().expect("validation stats poisoned") = Some(SystemTime::now());
+}
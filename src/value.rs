@@ -0,0 +1,163 @@
+use crate::columns::ColumnMatching;
+use crate::error::Error;
+use crate::row::FromSqlValue;
+use crate::transform::{any_applies, transform_value, RowTransformer};
+use std::sync::{Arc, OnceLock};
+use tiberius::{Column, ColumnData};
+
+/// The value of a single column, as read from a row.
+pub type SqlValue = ColumnData<'static>;
+
+/// A row read without a target type, see [`SqlServerPool::row_query_dynamic`](crate::SqlServerPool::row_query_dynamic).
+///
+/// Values can be read by column name or position, and converted with [`FromSqlValue`].
+///
+/// Row transformers are applied to a value the first time it is read, and the result kept, so reading a few columns
+/// of a wide row doesn't pay for transforming the rest. Call [`DynamicRow::materialize_all`] up front when every
+/// value will be read anyway, e.g. to export the row.
+#[derive(Debug, Clone)]
+pub struct DynamicRow {
+    columns: Arc<[Column]>,
+    values: Vec<SqlValue>,
+    pending: Option<Pending>,
+}
+
+/// The transformers not yet applied to a row's values, with each value once transformed.
+#[derive(Debug, Clone)]
+struct Pending {
+    transformers: Arc<[Arc<dyn RowTransformer>]>,
+    transformed: Vec<OnceLock<SqlValue>>,
+}
+
+impl DynamicRow {
+    /// A row whose `values` are transformed by `transformers` when read.
+    pub(crate) fn new(
+        columns: Arc<[Column]>,
+        values: Vec<SqlValue>,
+        transformers: Arc<[Arc<dyn RowTransformer>]>,
+    ) -> Self {
+        let pending = (!transformers.is_empty()).then(|| Pending {
+            transformers,
+            transformed: values.iter().map(|_| OnceLock::new()).collect(),
+        });
+        Self {
+            columns,
+            values,
+            pending,
+        }
+    }
+
+    /// Apply the row transformers to every value not read yet, so later reads only borrow.
+    pub fn materialize_all(&mut self) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        for ((column, value), transformed) in self
+            .columns
+            .iter()
+            .zip(&mut self.values)
+            .zip(pending.transformed)
+        {
+            let raw = std::mem::replace(value, ColumnData::I32(None));
+            *value = match transformed.into_inner() {
+                Some(transformed) => transformed,
+                None => transform_value(&pending.transformers, column, raw),
+            };
+        }
+    }
+
+    /// The columns of the row's result set.
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// The number of columns in the row.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Whether the row has no columns.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The value of the column called `name`, matched ignoring case, or `None` if there is no such column or
+    /// several columns match.
+    pub fn value(&self, name: &str) -> Option<&SqlValue> {
+        self.value_with(name, ColumnMatching::default()).ok()
+    }
+
+    /// The value of the column called `name`, matched as `matching` says. Fails if there is no such column or
+    /// several columns match.
+    pub fn value_with(&self, name: &str, matching: ColumnMatching) -> Result<&SqlValue, Error> {
+        let index = matching.resolve(&self.columns, name)?;
+        Ok(self.cell(index))
+    }
+
+    /// The value of the column at `index`, or `None` if it is out of bounds.
+    pub fn value_at(&self, index: usize) -> Option<&SqlValue> {
+        (index < self.values.len()).then(|| self.cell(index))
+    }
+
+    /// The value at `index`, transformed if it hasn't been yet. Values of columns no transformer applies to are
+    /// borrowed as they are.
+    fn cell(&self, index: usize) -> &SqlValue {
+        match &self.pending {
+            Some(pending) if any_applies(&pending.transformers, &self.columns[index]) => {
+                pending.transformed[index].get_or_init(|| {
+                    transform_value(
+                        &pending.transformers,
+                        &self.columns[index],
+                        self.values[index].clone(),
+                    )
+                })
+            }
+            _ => &self.values[index],
+        }
+    }
+
+    /// Convert the value of the column called `name`, matched ignoring case. Fails if there is no such column, if
+    /// several columns match, or if the conversion fails.
+    pub fn get<T: FromSqlValue>(&self, name: &str) -> Result<Option<T>, Error> {
+        self.get_with(name, ColumnMatching::default())
+    }
+
+    /// Convert the value of the column called `name`, matched as `matching` says, see [`DynamicRow::get`].
+    pub fn get_with<T: FromSqlValue>(
+        &self,
+        name: &str,
+        matching: ColumnMatching,
+    ) -> Result<Option<T>, Error> {
+        T::from_sql_value(self.value_with(name, matching)?)
+    }
+
+    /// The values of the row, in column order.
+    pub fn into_values(mut self) -> Vec<SqlValue> {
+        self.materialize_all();
+        self.values
+    }
+}
+
+/// Whether a value is NULL, whatever its type.
+pub(crate) fn is_null(value: &SqlValue) -> bool {
+    match value {
+        ColumnData::U8(v) => v.is_none(),
+        ColumnData::I16(v) => v.is_none(),
+        ColumnData::I32(v) => v.is_none(),
+        ColumnData::I64(v) => v.is_none(),
+        ColumnData::F32(v) => v.is_none(),
+        ColumnData::F64(v) => v.is_none(),
+        ColumnData::Bit(v) => v.is_none(),
+        ColumnData::String(v) => v.is_none(),
+        ColumnData::Guid(v) => v.is_none(),
+        ColumnData::Binary(v) => v.is_none(),
+        ColumnData::Numeric(v) => v.is_none(),
+        ColumnData::Xml(v) => v.is_none(),
+        ColumnData::DateTime(v) => v.is_none(),
+        ColumnData::SmallDateTime(v) => v.is_none(),
+        ColumnData::Time(v) => v.is_none(),
+        ColumnData::Date(v) => v.is_none(),
+        ColumnData::DateTime2(v) => v.is_none(),
+        ColumnData::DateTimeOffset(v) => v.is_none(),
+    }
+}
@@ -0,0 +1,122 @@
+use crate::error::Error;
+use std::fmt;
+
+/// The version of a SQL Server instance, as reported by `SERVERPROPERTY('ProductVersion')`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerVersion {
+    major: u32,
+    minor: u32,
+    build: u32,
+    azure: bool,
+}
+
+impl ServerVersion {
+    pub const SQL_SERVER_2008: ServerVersion = ServerVersion::new(10, 0, 0);
+    pub const SQL_SERVER_2008_R2: ServerVersion = ServerVersion::new(10, 50, 0);
+    pub const SQL_SERVER_2012: ServerVersion = ServerVersion::new(11, 0, 0);
+    pub const SQL_SERVER_2014: ServerVersion = ServerVersion::new(12, 0, 0);
+    pub const SQL_SERVER_2016: ServerVersion = ServerVersion::new(13, 0, 0);
+    pub const SQL_SERVER_2017: ServerVersion = ServerVersion::new(14, 0, 0);
+    pub const SQL_SERVER_2019: ServerVersion = ServerVersion::new(15, 0, 0);
+    pub const SQL_SERVER_2022: ServerVersion = ServerVersion::new(16, 0, 0);
+
+    /// Create a version from its major, minor and build numbers.
+    pub const fn new(major: u32, minor: u32, build: u32) -> Self {
+        Self {
+            major,
+            minor,
+            build,
+            azure: false,
+        }
+    }
+
+    pub fn major(&self) -> u32 {
+        self.major
+    }
+
+    pub fn minor(&self) -> u32 {
+        self.minor
+    }
+
+    pub fn build(&self) -> u32 {
+        self.build
+    }
+
+    /// Returns true for Azure SQL Database and Managed Instance, which report a fixed product version
+    /// but always support the latest features.
+    pub fn is_azure(&self) -> bool {
+        self.azure
+    }
+
+    /// Returns true if this version is the same as or later than `minimum`.
+    pub fn at_least(&self, minimum: ServerVersion) -> bool {
+        self.azure
+            || (self.major, self.minor, self.build) >= (minimum.major, minimum.minor, minimum.build)
+    }
+
+    /// Returns an error naming `feature` if this version is earlier than `required`.
+    pub(crate) fn require(
+        &self,
+        required: ServerVersion,
+        feature: &'static str,
+    ) -> Result<(), Error> {
+        if self.at_least(required) {
+            Ok(())
+        } else {
+            Err(Error::FeatureUnsupported {
+                feature,
+                required,
+                found: *self,
+            })
+        }
+    }
+
+    /// Parse a product version string such as `16.0.1000.6`.
+    pub(crate) fn parse(product_version: &str, engine_edition: Option<i32>) -> Option<Self> {
+        let mut parts = product_version.trim().split('.').map(str::parse::<u32>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().and_then(Result::ok).unwrap_or(0);
+        let build = parts.next().and_then(Result::ok).unwrap_or(0);
+
+        Some(Self {
+            major,
+            minor,
+            build,
+            // 5 = Azure SQL Database, 8 = Azure SQL Managed Instance
+            azure: matches!(engine_edition, Some(5 | 8)),
+        })
+    }
+
+    fn product_name(&self) -> Option<&'static str> {
+        let name = match (self.major, self.minor) {
+            (9, _) => "SQL Server 2005",
+            (10, 50..) => "SQL Server 2008 R2",
+            (10, _) => "SQL Server 2008",
+            (11, _) => "SQL Server 2012",
+            (12, _) => "SQL Server 2014",
+            (13, _) => "SQL Server 2016",
+            (14, _) => "SQL Server 2017",
+            (15, _) => "SQL Server 2019",
+            (16, _) => "SQL Server 2022",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+/// Displays the version in human terms, e.g. `SQL Server 2008 R2 (10.50.1600)`.
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.azure {
+            return write!(
+                f,
+                "Azure SQL ({}.{}.{})",
+                self.major, self.minor, self.build
+            );
+        }
+        match self.product_name() {
+            Some(name) => write!(f, "{name} ({}.{}.{})", self.major, self.minor, self.build),
+            None => write!(f, "SQL Server {}.{}.{}", self.major, self.minor, self.build),
+        }
+    }
+}
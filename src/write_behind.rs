@@ -0,0 +1,438 @@
+use crate::{
+    codec::ColumnCodecs,
+    error::{Error, ErrorKind},
+    param::{bind_param, SqlParam},
+    pool::SqlServerPool,
+    temp_table::{MAX_PARAMS, MAX_VALUES_ROWS},
+};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tiberius::Query;
+use tokio::sync::{watch, Notify};
+
+/// What [`WriteBehindQueue::push`] does when the queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued row to make room, reporting it to [`WriteBehindOptions::on_drop`].
+    #[default]
+    DropOldest,
+    /// Fail the push with [`Error::Overloaded`], keeping the queued rows.
+    Error,
+}
+
+/// Options for [`SqlServerPool::write_behind`].
+#[derive(Clone)]
+pub struct WriteBehindOptions {
+    /// The most rows queued at once, before [`WriteBehindOptions::overflow`] applies. Defaults to 10,000, and is at
+    /// least 1.
+    pub capacity: usize,
+    /// The most rows written by one `INSERT`. Defaults to 500, and is capped by the server's limit of 2100
+    /// parameters per statement.
+    pub batch_rows: usize,
+    /// How long a row may wait for a full batch before it is written anyway. Defaults to 1 second.
+    pub flush_interval: Duration,
+    pub overflow: OverflowPolicy,
+    /// How many times a batch that failed with a transient error (a timeout, connection failure, deadlock or
+    /// overload) is retried, with a doubling delay, before its rows are dropped. Defaults to 3.
+    pub max_retries: u32,
+    /// The longest delay before a retry. A delay suggested by a throttled server, see
+    /// [`Error::throttle_retry_after`], is used instead of the doubling one, up to this. Defaults to 30 seconds.
+    pub max_retry_delay: Duration,
+    /// Called with the number of rows dropped, whether shed by [`OverflowPolicy::DropOldest`] or in a batch that
+    /// couldn't be written. Runs inline, so it should be cheap and must not block.
+    pub on_drop: Option<Arc<dyn Fn(usize) + Send + Sync>>,
+}
+
+impl Default for WriteBehindOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            batch_rows: 500,
+            flush_interval: Duration::from_secs(1),
+            overflow: OverflowPolicy::default(),
+            max_retries: 3,
+            max_retry_delay: Duration::from_secs(30),
+            on_drop: None,
+        }
+    }
+}
+
+impl fmt::Debug for WriteBehindOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteBehindOptions")
+            .field("capacity", &self.capacity)
+            .field("batch_rows", &self.batch_rows)
+            .field("flush_interval", &self.flush_interval)
+            .field("overflow", &self.overflow)
+            .field("max_retries", &self.max_retries)
+            .field("max_retry_delay", &self.max_retry_delay)
+            .field("on_drop", &self.on_drop.is_some())
+            .finish()
+    }
+}
+
+/// The delay before the first retry of a failed batch, doubled for each further retry.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The write-behind counters of a pool, summed over its queues and reported by [`SqlServerPool::status`].
+#[derive(Debug, Default)]
+pub(crate) struct WriteBehindStats {
+    queued: AtomicUsize,
+    batches: AtomicU64,
+    dropped: AtomicU64,
+    queues: Mutex<Vec<Weak<Shared>>>,
+}
+
+impl WriteBehindStats {
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn batches(&self) -> u64 {
+        self.batches.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Close every open queue of the pool, waiting for each to write what it holds.
+    pub(crate) async fn close_all(&self) {
+        let queues: Vec<Arc<Shared>> = self
+            .queues
+            .lock()
+            .expect("write-behind queues poisoned")
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        for shared in queues {
+            shared.close();
+            shared.wait_for(shared.pushed()).await;
+        }
+    }
+}
+
+/// The rows queued for writing, with the number ever pushed.
+#[derive(Debug, Default)]
+struct Queue {
+    rows: VecDeque<Vec<SqlParam>>,
+    pushed: u64,
+    /// Set under the lock, so no row can be pushed after the task has seen the queue closed and empty.
+    closed: bool,
+}
+
+/// The state shared by a queue's handles and its background task.
+#[derive(Debug)]
+struct Shared {
+    /// The table as given, for encryption, and quoted, for the `INSERT`.
+    table: String,
+    quoted_table: String,
+    /// The columns as given, for encryption, and quoted, for the `INSERT`.
+    columns: Vec<String>,
+    quoted_columns: Vec<String>,
+    codecs: Arc<ColumnCodecs>,
+    options: WriteBehindOptions,
+    batch_rows: usize,
+    queue: Mutex<Queue>,
+    /// Wakes the task for a full batch, a flush or closing.
+    wake: Notify,
+    /// The number of pushed rows that have been written or dropped.
+    done: watch::Sender<u64>,
+    stats: Arc<WriteBehindStats>,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().expect("write-behind queue poisoned")
+    }
+
+    fn pushed(&self) -> u64 {
+        self.lock().pushed
+    }
+
+    /// Encrypt the values bound to encrypted columns, so rows are queued as they will be written.
+    fn encrypt(&self, row: Vec<SqlParam>) -> Result<Vec<SqlParam>, Error> {
+        let params: Vec<(&str, SqlParam)> =
+            self.columns.iter().map(String::as_str).zip(row).collect();
+        let encrypted = self.codecs.encrypt_params(&self.table, &params)?;
+        Ok(encrypted
+            .unwrap_or(params)
+            .into_iter()
+            .map(|(_, param)| param)
+            .collect())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.wake.notify_one();
+    }
+
+    /// Count `rows` as done without being written, reporting them to the drop callback.
+    fn dropped(&self, rows: usize) {
+        self.stats.dropped.fetch_add(rows as u64, Ordering::Relaxed);
+        self.done.send_modify(|done| *done += rows as u64);
+        if let Some(on_drop) = &self.options.on_drop {
+            on_drop(rows);
+        }
+    }
+
+    /// Wait until the first `pushed` rows have been written or dropped.
+    async fn wait_for(&self, pushed: u64) {
+        let mut done = self.done.subscribe();
+        // The sender lives as long as `self`, so this only ends once the rows are done.
+        let _ = done.wait_for(|done| *done >= pushed).await;
+    }
+
+    /// Take the next batch, up to `batch_rows` rows, along with whether the queue is closed.
+    fn take_batch(&self) -> (Vec<Vec<SqlParam>>, bool) {
+        let mut queue = self.lock();
+        let len = queue.rows.len().min(self.batch_rows);
+        self.stats.queued.fetch_sub(len, Ordering::Relaxed);
+        (queue.rows.drain(..len).collect(), queue.closed)
+    }
+}
+
+/// Closes the queue once the last handle is dropped, so its task writes what is left and exits.
+#[derive(Debug)]
+struct Handle(Arc<Shared>);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// A buffered queue of rows written to a table in the background, created by [`SqlServerPool::write_behind`].
+///
+/// [`WriteBehindQueue::push`] never waits for the database, so fire-and-forget writes such as telemetry don't add
+/// to request latency. A background task writes the queued rows in batches of multi-row `INSERT`s. Rows may be lost:
+/// when the queue is full under [`OverflowPolicy::DropOldest`], or when a batch keeps failing. Both are reported to
+/// [`WriteBehindOptions::on_drop`] and counted in [`PoolStatus`](crate::PoolStatus).
+///
+/// Cloning is cheap, and every clone pushes to the same queue. Once the last clone is dropped, or the queue is closed
+/// with [`WriteBehindQueue::close`] or [`SqlServerPool::close`], the task writes the remaining rows and exits.
+#[derive(Debug, Clone)]
+pub struct WriteBehindQueue {
+    handle: Arc<Handle>,
+}
+
+impl WriteBehindQueue {
+    /// Queue a row, with one value per column in the order given to [`SqlServerPool::write_behind`].
+    ///
+    /// Fails with [`Error::InvalidArgument`] for a row of the wrong length or a closed queue, and with
+    /// [`Error::Overloaded`] when the queue is full under [`OverflowPolicy::Error`].
+    pub fn push(&self, row: Vec<SqlParam>) -> Result<(), Error> {
+        let shared = &self.handle.0;
+        if row.len() != shared.columns.len() {
+            return Err(Error::InvalidArgument(format!(
+                "write-behind row has {} values, expected {}",
+                row.len(),
+                shared.columns.len()
+            )));
+        }
+        let row = shared.encrypt(row)?;
+
+        let mut queue = shared.lock();
+        if queue.closed {
+            return Err(Error::InvalidArgument(
+                "write-behind queue is closed".to_owned(),
+            ));
+        }
+        let capacity = shared.options.capacity.max(1);
+        let mut shed = 0;
+        if queue.rows.len() >= capacity {
+            match shared.options.overflow {
+                OverflowPolicy::Error => return Err(Error::Overloaded),
+                OverflowPolicy::DropOldest => {
+                    shed = queue.rows.len() + 1 - capacity;
+                    queue.rows.drain(..shed);
+                    shared.stats.queued.fetch_sub(shed, Ordering::Relaxed);
+                }
+            }
+        }
+        queue.rows.push_back(row);
+        queue.pushed += 1;
+        shared.stats.queued.fetch_add(1, Ordering::Relaxed);
+        let full = queue.rows.len() >= shared.batch_rows;
+        drop(queue);
+
+        if shed > 0 {
+            shared.dropped(shed);
+        }
+        if full {
+            shared.wake.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Wait until every row pushed before the call has been written, or dropped.
+    pub async fn flush(&self) {
+        let shared = &self.handle.0;
+        let pushed = shared.pushed();
+        shared.wake.notify_one();
+        shared.wait_for(pushed).await;
+    }
+
+    /// Stop accepting rows, and wait until the queued rows have been written, or dropped.
+    pub async fn close(&self) {
+        let shared = &self.handle.0;
+        shared.close();
+        shared.wait_for(shared.pushed()).await;
+    }
+
+    /// Returns the number of rows waiting to be written.
+    pub fn len(&self) -> usize {
+        self.handle.0.lock().rows.len()
+    }
+
+    /// Returns true if no rows are waiting to be written.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The table and columns a queue writes to, as given and quoted.
+#[derive(Debug)]
+pub(crate) struct Target {
+    pub(crate) table: String,
+    pub(crate) quoted_table: String,
+    pub(crate) columns: Vec<String>,
+    pub(crate) quoted_columns: Vec<String>,
+}
+
+/// Create the queue and spawn the task writing it to `target`.
+pub(crate) fn spawn(
+    pool: SqlServerPool,
+    target: Target,
+    codecs: Arc<ColumnCodecs>,
+    options: WriteBehindOptions,
+    stats: Arc<WriteBehindStats>,
+) -> WriteBehindQueue {
+    let Target {
+        table,
+        quoted_table,
+        columns,
+        quoted_columns,
+    } = target;
+    let batch_rows = options
+        .batch_rows
+        .min(MAX_PARAMS / columns.len().max(1))
+        .clamp(1, MAX_VALUES_ROWS);
+    let shared = Arc::new(Shared {
+        table,
+        quoted_table,
+        columns,
+        quoted_columns,
+        codecs,
+        options,
+        batch_rows,
+        queue: Mutex::default(),
+        wake: Notify::new(),
+        done: watch::Sender::new(0),
+        stats: stats.clone(),
+    });
+    stats
+        .queues
+        .lock()
+        .expect("write-behind queues poisoned")
+        .push(Arc::downgrade(&shared));
+
+    let task = shared.clone();
+    tokio::spawn(async move {
+        loop {
+            let (batch, closed) = task.take_batch();
+            if !batch.is_empty() {
+                write_batch(&pool, &task, batch).await;
+                continue;
+            }
+            if closed {
+                break;
+            }
+            tokio::select! {
+                _ = task.wake.notified() => {}
+                _ = pool.clock().sleep(task.options.flush_interval) => {}
+            }
+        }
+
+        stats
+            .queues
+            .lock()
+            .expect("write-behind queues poisoned")
+            .retain(|queue| !ptr_eq(queue, &task));
+    });
+
+    WriteBehindQueue {
+        handle: Arc::new(Handle(shared)),
+    }
+}
+
+fn ptr_eq(queue: &Weak<Shared>, shared: &Arc<Shared>) -> bool {
+    std::ptr::eq(queue.as_ptr(), Arc::as_ptr(shared))
+}
+
+/// Write a batch, retrying transient errors, and count it as done.
+async fn write_batch(pool: &SqlServerPool, shared: &Shared, batch: Vec<Vec<SqlParam>>) {
+    let mut delay = RETRY_DELAY;
+    let mut retries = 0;
+    loop {
+        match insert_batch(pool, shared, &batch).await {
+            Ok(()) => {
+                shared.stats.batches.fetch_add(1, Ordering::Relaxed);
+                shared.done.send_modify(|done| *done += batch.len() as u64);
+                return;
+            }
+            Err(e) if retries < shared.options.max_retries && is_transient(&e) => {
+                retries += 1;
+                let wait = pool.retry_delay(&e, delay, shared.options.max_retry_delay);
+                pool.clock().sleep(wait).await;
+                delay *= 2;
+            }
+            Err(_) => {
+                shared.dropped(batch.len());
+                return;
+            }
+        }
+    }
+}
+
+fn is_transient(error: &Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::Timeout | ErrorKind::Connection | ErrorKind::Deadlock | ErrorKind::Overloaded
+    )
+}
+
+/// Insert a batch with a single multi-row `INSERT`, so a failed batch writes nothing and can be retried.
+async fn insert_batch(
+    pool: &SqlServerPool,
+    shared: &Shared,
+    batch: &[Vec<SqlParam>],
+) -> Result<(), Error> {
+    let columns = shared.columns.len();
+    let values = (0..batch.len())
+        .map(|row| {
+            let placeholders = (0..columns)
+                .map(|col| format!("@P{}", row * columns + col + 1))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("({placeholders})")
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let statement = format!(
+        "INSERT INTO {} ({}) VALUES {values};",
+        shared.quoted_table,
+        shared.quoted_columns.join(", ")
+    );
+
+    let mut insert = Query::new(statement);
+    for param in batch.iter().flatten() {
+        bind_param(&mut insert, param, pool.stable_param_types());
+    }
+    let mut conn = pool.get().await?;
+    insert.execute(&mut *conn).await?;
+    Ok(())
+}
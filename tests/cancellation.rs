@@ -0,0 +1,115 @@
+//! Queries cancelled or dropped midway against a real server, whose connections must not be reused.
+//! Requires the `test-util` feature and Docker; run with `cargo test --features test-util -- --ignored`.
+#![cfg(feature = "test-util")]
+
+use mssql_rs::{
+    BrokenReason, CancellationToken, ConnectionEvent, ConnectionInfo, ConnectionObserver, Error,
+    SqlServerPool, SqlServerPoolBuilder, TestServer,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A query that takes far longer than any test waits for it.
+const SLOW_QUERY: &str = "WAITFOR DELAY '00:01:00'; SELECT 1;";
+
+/// Records why connections were found broken.
+#[derive(Default)]
+struct BrokenConnections(Mutex<Vec<(u64, BrokenReason)>>);
+
+impl ConnectionObserver for BrokenConnections {
+    fn on_lifecycle(&self, connection: ConnectionInfo, event: ConnectionEvent<'_>) {
+        if let ConnectionEvent::Broken { reason } = event {
+            self.0.lock().unwrap().push((connection.id, reason));
+        }
+    }
+}
+
+/// Start a server with a single connection pool, so a broken connection must be replaced for the next query.
+async fn start() -> (TestServer, SqlServerPool, Arc<BrokenConnections>) {
+    let broken = Arc::new(BrokenConnections::default());
+    let (server, _) = TestServer::start().await.expect("start a test server");
+    let pool = SqlServerPoolBuilder::new()
+        .pool_max_size(1)
+        .observer(broken.clone())
+        .build(server.config().clone())
+        .await
+        .unwrap();
+    (server, pool, broken)
+}
+
+/// Check the pool still answers quickly, replacing the connection if it was discarded.
+async fn assert_recovers(pool: &SqlServerPool) {
+    let start = Instant::now();
+    let rows: Vec<(Option<i32>,)> = pool.row_query("SELECT 1;", &[]).await.unwrap();
+    assert_eq!(rows, [(Some(1),)]);
+    assert!(
+        start.elapsed() < Duration::from_secs(30),
+        "{:?}",
+        start.elapsed()
+    );
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn cancelling_a_query_discards_its_connection() {
+    let (_server, pool, broken) = start().await;
+    let cancel = CancellationToken::new();
+
+    let canceller = {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            cancel.cancel();
+        })
+    };
+    let start = Instant::now();
+    let result = pool
+        .row_query_cancellable::<(Option<i32>,)>(SLOW_QUERY, &[], &cancel)
+        .await;
+    canceller.await.unwrap();
+
+    assert!(matches!(result, Err(Error::Cancelled)), "{result:?}");
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_recovers(&pool).await;
+    assert!(matches!(
+        broken.0.lock().unwrap()[..],
+        [(_, BrokenReason::Interrupted)]
+    ));
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn dropping_a_query_discards_its_connection() {
+    let (_server, pool, broken) = start().await;
+
+    let query = {
+        let pool = pool.clone();
+        tokio::spawn(async move { pool.row_query::<(Option<i32>,)>(SLOW_QUERY, &[]).await })
+    };
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    query.abort();
+    assert!(query.await.unwrap_err().is_cancelled());
+
+    assert_recovers(&pool).await;
+    assert!(matches!(
+        broken.0.lock().unwrap()[..],
+        [(_, BrokenReason::Interrupted)]
+    ));
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn a_query_finishing_before_cancellation_keeps_its_connection() {
+    let (_server, pool, broken) = start().await;
+    let cancel = CancellationToken::new();
+
+    let rows = pool
+        .row_query_cancellable::<(Option<i32>,)>("SELECT 1;", &[], &cancel)
+        .await
+        .unwrap();
+    cancel.cancel();
+
+    assert_eq!(rows, [(Some(1),)]);
+    assert_recovers(&pool).await;
+    assert!(broken.0.lock().unwrap().is_empty());
+}
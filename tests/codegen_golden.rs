@@ -0,0 +1,133 @@
+//! Compare the structs generated for `tests/golden/schema.json` with the checked in output.
+//!
+//! Run with `cargo test --features codegen`, and with `UPDATE_GOLDEN=1` to rewrite the golden files after an intended
+//! change to the output.
+#![cfg(feature = "codegen")]
+
+use mssql_rs::codegen::{generate, CodegenOptions, SchemaSnapshot};
+use std::path::{Path, PathBuf};
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+}
+
+fn snapshot() -> SchemaSnapshot {
+    let json = std::fs::read_to_string(golden_path("schema.json")).unwrap();
+    SchemaSnapshot::from_json(&json).unwrap()
+}
+
+fn assert_golden(name: &str, code: &str) {
+    let path = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, code).unwrap();
+        return;
+    }
+    let golden = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        golden == code,
+        "generated code differs from {}, rerun with UPDATE_GOLDEN=1 if intended:\n{code}",
+        path.display()
+    );
+}
+
+// The golden files must compile against the library as generated.
+#[allow(dead_code)]
+mod default_options {
+    include!("golden/default_options.rs");
+}
+
+#[allow(dead_code)]
+mod serialize {
+    include!("golden/serialize.rs");
+}
+
+#[allow(dead_code)]
+mod custom_types {
+    include!("golden/custom_types.rs");
+}
+
+/// Domain types for [`custom_types`].
+#[derive(Debug)]
+#[allow(dead_code)]
+struct OrderRef(mssql_rs::tiberius::Uuid);
+
+impl mssql_rs::FromSqlValue for OrderRef {
+    fn from_sql_value(
+        value: &mssql_rs::tiberius::ColumnData<'static>,
+    ) -> mssql_rs::Result<Option<Self>> {
+        Ok(mssql_rs::tiberius::Uuid::from_sql_value(value)?.map(OrderRef))
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct TimeOfDay(u64);
+
+impl mssql_rs::FromSqlValue for TimeOfDay {
+    fn from_sql_value(
+        value: &mssql_rs::tiberius::ColumnData<'static>,
+    ) -> mssql_rs::Result<Option<Self>> {
+        match value {
+            mssql_rs::tiberius::ColumnData::Time(time) => {
+                Ok(time.map(|time| TimeOfDay(time.increments())))
+            }
+            _ => Err(mssql_rs::Error::InvalidArgument(format!(
+                "{value:?} is not a time"
+            ))),
+        }
+    }
+}
+
+#[test]
+fn default_options() {
+    let code = generate(
+        &snapshot(),
+        &["dbo.orders", "sales.[Order Lines]", "dbo.events"],
+        &CodegenOptions::default(),
+    )
+    .unwrap();
+    assert_golden("default_options.rs", &code);
+}
+
+#[test]
+fn serialize_derive() {
+    let options = CodegenOptions {
+        derives: vec!["Debug".into(), "serde::Serialize".into()],
+        ..CodegenOptions::default()
+    };
+    let code = generate(&snapshot(), &["Order Lines"], &options).unwrap();
+    assert_golden("serialize.rs", &code);
+}
+
+#[test]
+fn custom_types() {
+    let options = CodegenOptions {
+        derives: vec!["Debug".into()],
+        types: vec![
+            ("uniqueidentifier".into(), "super::OrderRef".into()),
+            ("TIME".into(), "super::TimeOfDay".into()),
+        ],
+    };
+    let code = generate(&snapshot(), &["orders", "shifts"], &options).unwrap();
+    assert_golden("custom_types.rs", &code);
+}
+
+#[test]
+fn output_is_stable() {
+    let snapshot = snapshot();
+    let options = CodegenOptions::default();
+    let first = generate(&snapshot, &["orders"], &options).unwrap();
+    let reparsed = SchemaSnapshot::from_json(&snapshot.to_json()).unwrap();
+    assert_eq!(generate(&reparsed, &["orders"], &options).unwrap(), first);
+}
+
+#[test]
+fn unmapped_types_and_missing_tables_fail() {
+    let snapshot = snapshot();
+    let options = CodegenOptions::default();
+    let error = generate(&snapshot, &["dbo.shifts"], &options).unwrap_err();
+    assert!(error.to_string().contains("time(0)"), "{error}");
+    assert!(generate(&snapshot, &["dbo.missing"], &options).is_err());
+}
@@ -0,0 +1,6 @@
+SELECT 1 AS one
+GO
+-- comment with a CRLF ending
+SELECT 'two
+lines' AS [two]
+GO
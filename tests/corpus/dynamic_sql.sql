@@ -0,0 +1,6 @@
+-- Dynamic SQL with quotes nested three deep, as generated by maintenance scripts.
+DECLARE @sql nvarchar(max) = N'
+SELECT N''it''''s '' + name AS [display name]
+FROM sys.objects WHERE name LIKE N''%[_]log'' ESCAPE ''\''';
+EXEC sp_executesql @sql, N'@P1 int, @P2 nvarchar(50)', @P1 = 1, @P2 = N'O''Brien';
+SELECT 'a -- not a comment', "quoted ""identifier""", [odd]]name], @@ROWCOUNT;
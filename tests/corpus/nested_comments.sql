@@ -0,0 +1,18 @@
+/*
+ * Procedure header.
+ * /* An old version, commented out wholesale:
+ *    CREATE PROCEDURE dbo.legacy AS SELECT 'unterminated? no, inside a comment
+ * */
+ */
+CREATE OR ALTER PROCEDURE [dbo].[usp_Orders ]]v2]
+    @CustomerId int, -- the customer's id, with an apostrophe
+    @From datetime2 = NULL /* defaults to /* nested */ a month ago */
+AS
+BEGIN
+    SET NOCOUNT ON;
+    SELECT o.id, o.total
+    FROM dbo.orders AS o WITH (NOLOCK)
+    WHERE o.customer_id = @CustomerId
+      AND o.created_at >= COALESCE(@From, DATEADD(month, -1, SYSUTCDATETIME()))
+      AND o.note NOT LIKE '%/*%';
+END
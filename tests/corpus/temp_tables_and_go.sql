@@ -0,0 +1,14 @@
+IF OBJECT_ID('tempdb..#staging') IS NOT NULL DROP TABLE #staging;
+GO
+SELECT TOP (0) * INTO #staging FROM dbo.orders;
+-- GO is only a separator on its own line:
+INSERT INTO #staging (id, note) VALUES (1, N'GO'), (2, 'line one
+GO
+line three');
+GO 2
+MERGE INTO dbo.orders WITH (HOLDLOCK) AS t
+USING #staging AS s ON t.id = s.id
+WHEN MATCHED THEN UPDATE SET t.note = s.note
+WHEN NOT MATCHED THEN INSERT (id, note) VALUES (s.id, s.note)
+OUTPUT $action, inserted.id INTO ##audit ([action], id);
+DROP TABLE IF EXISTS #staging, [##audit];
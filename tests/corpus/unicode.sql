@@ -0,0 +1,3 @@
+SELECT N'日本語 ''引用''' AS [列 名], N'emoji 😀 and combining é' AS "naïve", 名前 = @P1
+FROM dbo.[Ünïcödé tåble]
+WHERE note = N'ÿ''' AND email@P2 IS NOT NULL;
@@ -0,0 +1,69 @@
+//! Deadlocks between pools with different deadlock priorities against a real server.
+//! Requires the `test-util` feature and Docker; run with `cargo test --features test-util -- --ignored`.
+#![cfg(feature = "test-util")]
+
+use mssql_rs::{DeadlockPriority, Error, SqlServerPool, SqlServerPoolBuilder, TestServer};
+use std::time::Duration;
+
+/// The error the server returns to the session it chose as the deadlock victim.
+const DEADLOCK_VICTIM: u32 = 1205;
+
+/// Lock `first`, wait for the other session to lock the other table, then lock `second`.
+fn crossing_update(first: &str, second: &str) -> String {
+    format!(
+        "BEGIN TRANSACTION;
+        UPDATE dbo.{first} SET v = v + 1;
+        WAITFOR DELAY '00:00:02';
+        UPDATE dbo.{second} SET v = v + 1;
+        COMMIT;"
+    )
+}
+
+async fn pool_with(server: &TestServer, priority: DeadlockPriority) -> SqlServerPool {
+    SqlServerPoolBuilder::new()
+        .deadlock_priority(priority)
+        .build(server.config().clone())
+        .await
+        .unwrap()
+}
+
+fn victim_code(result: Result<u64, Error>) -> Option<u32> {
+    result.err().and_then(|e| e.into_parts().1)
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn the_low_priority_pool_is_the_deadlock_victim() {
+    let (server, setup) = TestServer::start().await.expect("start a test server");
+    setup
+        .execute_batch(&[
+            "CREATE TABLE dbo.a (v int NOT NULL); INSERT INTO dbo.a VALUES (0);",
+            "CREATE TABLE dbo.b (v int NOT NULL); INSERT INTO dbo.b VALUES (0);",
+        ])
+        .await
+        .unwrap();
+    let high = pool_with(&server, DeadlockPriority::High).await;
+    let low = pool_with(&server, DeadlockPriority::Low).await;
+
+    // Whichever session starts first, and so would otherwise be cheaper to keep, the low priority one loses.
+    for low_starts_first in [true, false] {
+        let high_update = crossing_update("a", "b");
+        let low_update = crossing_update("b", "a");
+        let high_run = async {
+            if low_starts_first {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            high.execute_batch(&[&high_update]).await
+        };
+        let low_run = async {
+            if !low_starts_first {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            low.execute_batch(&[&low_update]).await
+        };
+        let (high_result, low_result) = tokio::join!(high_run, low_run);
+
+        assert!(high_result.is_ok(), "{high_result:?}");
+        assert_eq!(victim_code(low_result), Some(DEADLOCK_VICTIM));
+    }
+}
@@ -0,0 +1,64 @@
+// Generated by mssql_rs::codegen from a schema snapshot. Do not edit.
+
+/// A row of `dbo.orders`.
+#[derive(Debug)]
+pub struct Orders {
+    /// `int NOT NULL`
+    pub id: i32,
+    /// `bigint NOT NULL`
+    pub customer_id: i64,
+    /// `uniqueidentifier NOT NULL`
+    pub reference: super::OrderRef,
+    /// `decimal(19,4) NULL`
+    pub total: Option<mssql_rs::tiberius::numeric::Numeric>,
+    /// `tinyint NOT NULL`
+    pub status: u8,
+    /// `bit NOT NULL`
+    pub is_paid: bool,
+    /// `nvarchar(max) NULL`
+    pub note: Option<String>,
+    /// `varbinary(max) NULL`
+    pub payload: Option<Vec<u8>>,
+    /// `rowversion NOT NULL`
+    pub row_ver: Vec<u8>,
+}
+
+impl mssql_rs::TryFromRow for Orders {
+    fn try_from(row: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {
+        use mssql_rs::RowExt;
+        Ok(Self {
+            id: row.get_named("id")?.ok_or_else(|| null_column("id"))?,
+            customer_id: row.get_named("customer_id")?.ok_or_else(|| null_column("customer_id"))?,
+            reference: row.get_named("Reference")?.ok_or_else(|| null_column("Reference"))?,
+            total: row.get_named("Total")?,
+            status: row.get_named("Status")?.ok_or_else(|| null_column("Status"))?,
+            is_paid: row.get_named("IsPaid")?.ok_or_else(|| null_column("IsPaid"))?,
+            note: row.get_named("Note")?,
+            payload: row.get_named("Payload")?,
+            row_ver: row.get_named("RowVer")?.ok_or_else(|| null_column("RowVer"))?,
+        })
+    }
+}
+
+/// A row of `dbo.shifts`.
+#[derive(Debug)]
+pub struct Shifts {
+    /// `int NOT NULL`
+    pub id: i32,
+    /// `time(0) NOT NULL`
+    pub starts: super::TimeOfDay,
+}
+
+impl mssql_rs::TryFromRow for Shifts {
+    fn try_from(row: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {
+        use mssql_rs::RowExt;
+        Ok(Self {
+            id: row.get_named("id")?.ok_or_else(|| null_column("id"))?,
+            starts: row.get_named("starts")?.ok_or_else(|| null_column("starts"))?,
+        })
+    }
+}
+
+fn null_column(name: &str) -> mssql_rs::Error {
+    mssql_rs::tiberius::error::Error::Conversion(format!("column {name} is NULL").into()).into()
+}
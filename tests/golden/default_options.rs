@@ -0,0 +1,113 @@
+// Generated by mssql_rs::codegen from a schema snapshot. Do not edit.
+
+/// A row of `dbo.orders`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Orders {
+    /// `int NOT NULL`
+    pub id: i32,
+    /// `bigint NOT NULL`
+    pub customer_id: i64,
+    /// `uniqueidentifier NOT NULL`
+    pub reference: mssql_rs::tiberius::Uuid,
+    /// `decimal(19,4) NULL`
+    pub total: Option<mssql_rs::tiberius::numeric::Numeric>,
+    /// `tinyint NOT NULL`
+    pub status: u8,
+    /// `bit NOT NULL`
+    pub is_paid: bool,
+    /// `nvarchar(max) NULL`
+    pub note: Option<String>,
+    /// `varbinary(max) NULL`
+    pub payload: Option<Vec<u8>>,
+    /// `rowversion NOT NULL`
+    pub row_ver: Vec<u8>,
+}
+
+impl mssql_rs::TryFromRow for Orders {
+    fn try_from(row: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {
+        use mssql_rs::RowExt;
+        Ok(Self {
+            id: row.get_named("id")?.ok_or_else(|| null_column("id"))?,
+            customer_id: row.get_named("customer_id")?.ok_or_else(|| null_column("customer_id"))?,
+            reference: row.get_named("Reference")?.ok_or_else(|| null_column("Reference"))?,
+            total: row.get_named("Total")?,
+            status: row.get_named("Status")?.ok_or_else(|| null_column("Status"))?,
+            is_paid: row.get_named("IsPaid")?.ok_or_else(|| null_column("IsPaid"))?,
+            note: row.get_named("Note")?,
+            payload: row.get_named("Payload")?,
+            row_ver: row.get_named("RowVer")?.ok_or_else(|| null_column("RowVer"))?,
+        })
+    }
+}
+
+/// A row of `sales.[Order Lines]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderLines {
+    /// `int NOT NULL`
+    pub order_id: i32,
+    /// `nvarchar(20) NOT NULL`
+    pub r#type: String,
+    /// `bit NULL`
+    pub column_2fa: Option<bool>,
+    /// `int NULL`
+    pub self_: Option<i32>,
+    /// `smallint NOT NULL`
+    pub quantity: i16,
+    /// `money NOT NULL`
+    pub unit_price: f64,
+    /// `real NULL`
+    pub weight: Option<f32>,
+    /// `float NULL`
+    pub ratio: Option<f64>,
+    /// `char(12) NOT NULL`
+    pub sku: String,
+}
+
+impl mssql_rs::TryFromRow for OrderLines {
+    fn try_from(row: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {
+        use mssql_rs::RowExt;
+        Ok(Self {
+            order_id: row.get_named("Order Id")?.ok_or_else(|| null_column("Order Id"))?,
+            r#type: row.get_named("type")?.ok_or_else(|| null_column("type"))?,
+            column_2fa: row.get_named("2fa")?,
+            self_: row.get_named("self")?,
+            quantity: row.get_named("Quantity")?.ok_or_else(|| null_column("Quantity"))?,
+            unit_price: row.get_named("UnitPrice")?.ok_or_else(|| null_column("UnitPrice"))?,
+            weight: row.get_named("Weight")?,
+            ratio: row.get_named("Ratio")?,
+            sku: row.get_named("Sku")?.ok_or_else(|| null_column("Sku"))?,
+        })
+    }
+}
+
+/// A row of `dbo.events`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Events {
+    /// `int NOT NULL`
+    pub id: i32,
+    /// `datetime2(7) NOT NULL`
+    pub at: mssql_rs::SqlDateTime,
+    /// `datetime NULL`
+    pub logged_at: Option<mssql_rs::SqlDateTime>,
+    /// `datetimeoffset(3) NULL`
+    pub received: Option<mssql_rs::SqlDateTime>,
+    /// `date NOT NULL`
+    pub day: mssql_rs::SqlDateTime,
+}
+
+impl mssql_rs::TryFromRow for Events {
+    fn try_from(row: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {
+        use mssql_rs::RowExt;
+        Ok(Self {
+            id: row.get_named("id")?.ok_or_else(|| null_column("id"))?,
+            at: row.get_named("at")?.ok_or_else(|| null_column("at"))?,
+            logged_at: row.get_named("LoggedAt")?,
+            received: row.get_named("received")?,
+            day: row.get_named("day")?.ok_or_else(|| null_column("day"))?,
+        })
+    }
+}
+
+fn null_column(name: &str) -> mssql_rs::Error {
+    mssql_rs::tiberius::error::Error::Conversion(format!("column {name} is NULL").into()).into()
+}
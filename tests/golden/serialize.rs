@@ -0,0 +1,53 @@
+// Generated by mssql_rs::codegen from a schema snapshot. Do not edit.
+
+/// A row of `sales.[Order Lines]`.
+#[derive(Debug, serde::Serialize)]
+pub struct OrderLines {
+    /// `int NOT NULL`
+    #[serde(rename = "Order Id")]
+    pub order_id: i32,
+    /// `nvarchar(20) NOT NULL`
+    pub r#type: String,
+    /// `bit NULL`
+    #[serde(rename = "2fa")]
+    pub column_2fa: Option<bool>,
+    /// `int NULL`
+    #[serde(rename = "self")]
+    pub self_: Option<i32>,
+    /// `smallint NOT NULL`
+    #[serde(rename = "Quantity")]
+    pub quantity: i16,
+    /// `money NOT NULL`
+    #[serde(rename = "UnitPrice")]
+    pub unit_price: f64,
+    /// `real NULL`
+    #[serde(rename = "Weight")]
+    pub weight: Option<f32>,
+    /// `float NULL`
+    #[serde(rename = "Ratio")]
+    pub ratio: Option<f64>,
+    /// `char(12) NOT NULL`
+    #[serde(rename = "Sku")]
+    pub sku: String,
+}
+
+impl mssql_rs::TryFromRow for OrderLines {
+    fn try_from(row: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {
+        use mssql_rs::RowExt;
+        Ok(Self {
+            order_id: row.get_named("Order Id")?.ok_or_else(|| null_column("Order Id"))?,
+            r#type: row.get_named("type")?.ok_or_else(|| null_column("type"))?,
+            column_2fa: row.get_named("2fa")?,
+            self_: row.get_named("self")?,
+            quantity: row.get_named("Quantity")?.ok_or_else(|| null_column("Quantity"))?,
+            unit_price: row.get_named("UnitPrice")?.ok_or_else(|| null_column("UnitPrice"))?,
+            weight: row.get_named("Weight")?,
+            ratio: row.get_named("Ratio")?,
+            sku: row.get_named("Sku")?.ok_or_else(|| null_column("Sku"))?,
+        })
+    }
+}
+
+fn null_column(name: &str) -> mssql_rs::Error {
+    mssql_rs::tiberius::error::Error::Conversion(format!("column {name} is NULL").into()).into()
+}
@@ -0,0 +1,101 @@
+//! Concurrent `insert_or_get` calls for the same key against a real server.
+//! Requires the `test-util` feature and Docker; run with `cargo test --features test-util -- --ignored`.
+#![cfg(feature = "test-util")]
+
+use mssql_rs::tiberius::Row;
+use mssql_rs::{Error, SqlParam, SqlServerPoolBuilder, TestServer, TryFromRow};
+
+/// The number of tasks racing to insert the same customer.
+const TASKS: usize = 32;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Customer {
+    id: i32,
+    email: String,
+    name: String,
+}
+
+impl TryFromRow for Customer {
+    fn try_from(row: Row) -> mssql_rs::Result<Self> {
+        Ok(Customer {
+            id: row.try_get("id")?.unwrap_or_default(),
+            email: row
+                .try_get::<&str, _>("email")?
+                .unwrap_or_default()
+                .to_owned(),
+            name: row
+                .try_get::<&str, _>("name")?
+                .unwrap_or_default()
+                .to_owned(),
+        })
+    }
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn concurrent_calls_insert_exactly_one_row() {
+    let (_server, pool) = TestServer::start().await.expect("start a test server");
+    pool.execute_batch(&["CREATE TABLE dbo.customers (
+            id int IDENTITY PRIMARY KEY,
+            email nvarchar(100) NOT NULL UNIQUE,
+            name nvarchar(100) NOT NULL
+        );"])
+        .await
+        .unwrap();
+
+    let tasks: Vec<_> = (0..TASKS)
+        .map(|task| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                pool.insert_or_get::<Customer>(
+                    "dbo.customers",
+                    &[("email", SqlParam::from("alice@example.com"))],
+                    &[("name", SqlParam::from(format!("Alice {task}")))],
+                )
+                .await
+            })
+        })
+        .collect();
+    let mut results = Vec::new();
+    for task in tasks {
+        results.push(task.await.unwrap().unwrap());
+    }
+
+    let inserted: Vec<_> = results.iter().filter(|(_, inserted)| *inserted).collect();
+    assert_eq!(inserted.len(), 1, "{results:?}");
+    let winner = &inserted[0].0;
+    assert_eq!(winner.email, "alice@example.com");
+    assert!(
+        results.iter().all(|(customer, _)| customer == winner),
+        "{results:?}"
+    );
+
+    let rows: Vec<Customer> = pool
+        .row_query("SELECT * FROM dbo.customers;", &[])
+        .await
+        .unwrap();
+    assert_eq!(rows, std::slice::from_ref(winner));
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn the_statement_is_admitted_like_any_query() {
+    let (server, _) = TestServer::start().await.expect("start a test server");
+    let pool = SqlServerPoolBuilder::new()
+        .max_query_length(64)
+        .build(server.config().clone())
+        .await
+        .unwrap();
+
+    let result = pool
+        .insert_or_get::<Customer>(
+            "dbo.customers",
+            &[("email", SqlParam::from("alice@example.com"))],
+            &[("name", SqlParam::from("Alice"))],
+        )
+        .await;
+    assert!(
+        matches!(result, Err(Error::QueryTooLong { limit: 64, .. })),
+        "{result:?}"
+    );
+}
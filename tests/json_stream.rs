@@ -0,0 +1,76 @@
+//! `json_stream` against a real server, with results that SQL Server splits over many `FOR JSON` rows.
+//! Requires the `test-util` feature and Docker; run with `cargo test --features test-util -- --ignored`.
+#![cfg(feature = "test-util")]
+
+use futures_util::{StreamExt, TryStreamExt};
+use mssql_rs::{SqlServerPool, TestServer};
+use serde::Deserialize;
+
+/// Enough elements for a result of about 2 MB, which the server sends as roughly a thousand 2033 character rows.
+const ELEMENTS: i64 = 20_000;
+
+#[derive(Debug, Deserialize)]
+struct Element {
+    id: i64,
+    text: String,
+}
+
+/// Select `ELEMENTS` elements `FOR JSON PATH`, each with a text long enough to split elements between rows,
+/// and characters outside ASCII to split between the bytes of a character.
+fn query() -> String {
+    format!(
+        "WITH n AS (SELECT TOP ({ELEMENTS}) ROW_NUMBER() OVER (ORDER BY (SELECT NULL)) AS id \
+         FROM sys.all_columns a CROSS JOIN sys.all_columns b) \
+         SELECT id, REPLICATE(N'größe ☃ \"quoted\" ', 4) AS text FROM n ORDER BY id FOR JSON PATH;"
+    )
+}
+
+async fn start() -> (TestServer, SqlServerPool) {
+    TestServer::start().await.expect("start a test server")
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn streams_every_element_of_a_large_result() {
+    let (_server, pool) = start().await;
+
+    let elements: Vec<Element> = pool.json_stream(&query(), &[]).try_collect().await.unwrap();
+
+    assert_eq!(elements.len() as i64, ELEMENTS);
+    for (i, element) in elements.iter().enumerate() {
+        assert_eq!(element.id, i as i64 + 1);
+        assert_eq!(element.text, "größe ☃ \"quoted\" ".repeat(4));
+    }
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn dropping_the_stream_stops_quietly_and_the_pool_recovers() {
+    let (_server, pool) = start().await;
+
+    let stream = pool.json_stream::<Element>(&query(), &[]);
+    let first: Vec<_> = stream.take(10).collect().await;
+    assert_eq!(first.len(), 10);
+    assert!(first.iter().all(Result::is_ok), "{first:?}");
+
+    // The spawned reader notices the dropped stream, and the pool replaces its connection.
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    let ids: Vec<(Option<i32>,)> = pool.row_query("SELECT 1;", &[]).await.unwrap();
+    assert_eq!(ids, [(Some(1),)]);
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn empty_result_is_an_empty_stream() {
+    let (_server, pool) = start().await;
+
+    let elements: Vec<Element> = pool
+        .json_stream(
+            "SELECT 1 AS id, N'' AS text WHERE 1 = 0 FOR JSON PATH;",
+            &[],
+        )
+        .try_collect()
+        .await
+        .unwrap();
+    assert!(elements.is_empty());
+}
@@ -0,0 +1,130 @@
+//! Tokenize a corpus of awkward real-world T-SQL from `tests/corpus`.
+
+use mssql_rs::sql::lexer::{tokenize, Token, TokenKind};
+use std::path::Path;
+
+fn corpus() -> Vec<(String, String)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sql"))
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, std::fs::read_to_string(&path).unwrap())
+        })
+        .collect();
+    files.sort();
+    assert!(!files.is_empty());
+    files
+}
+
+/// Every file in the corpus is well formed, so no quote or comment may run to the end of the input.
+fn is_terminated(token: &Token<'_>) -> bool {
+    let text = token.text;
+    match token.kind {
+        TokenKind::Literal => {
+            let body = text.trim_start_matches(['N', 'n']);
+            body.len() >= 2
+                && body.ends_with('\'')
+                && body[1..body.len() - 1]
+                    .split("''")
+                    .all(|s| !s.contains('\''))
+        }
+        TokenKind::QuotedIdentifier if text.starts_with('[') => {
+            text.len() >= 2
+                && text.ends_with(']')
+                && text[1..text.len() - 1]
+                    .split("]]")
+                    .all(|s| !s.contains(']'))
+        }
+        TokenKind::QuotedIdentifier => {
+            text.len() >= 2
+                && text.ends_with('"')
+                && text[1..text.len() - 1]
+                    .split("\"\"")
+                    .all(|s| !s.contains('"'))
+        }
+        TokenKind::Comment if text.starts_with("/*") => {
+            text.matches("/*").count() == text.matches("*/").count() && text.ends_with("*/")
+        }
+        TokenKind::Comment => !text.contains('\n'),
+        _ => true,
+    }
+}
+
+#[test]
+fn corpus_round_trips() {
+    for (name, sql) in corpus() {
+        let tokens: String = tokenize(&sql).map(|token| token.text).collect();
+        assert_eq!(tokens, sql, "{name}");
+    }
+}
+
+#[test]
+fn corpus_quotes_and_comments_are_terminated() {
+    for (name, sql) in corpus() {
+        for token in tokenize(&sql) {
+            assert!(is_terminated(&token), "{name}: {token:?}");
+        }
+    }
+}
+
+#[test]
+fn corpus_keywords_in_literals_and_comments_are_not_words() {
+    let words = |sql: &str| -> Vec<String> {
+        tokenize(sql)
+            .filter(|token| token.kind == TokenKind::Word)
+            .map(|token| token.text.to_uppercase())
+            .collect()
+    };
+    let counts: Vec<_> = corpus()
+        .iter()
+        .map(|(name, sql)| {
+            let words = words(sql);
+            let count = |word: &str| words.iter().filter(|w| *w == word).count();
+            (
+                name.clone(),
+                count("GO"),
+                count("CREATE"),
+                count("PROCEDURE"),
+            )
+        })
+        .collect();
+    assert_eq!(
+        counts,
+        [
+            ("crlf.sql".to_owned(), 2, 0, 0),
+            ("dynamic_sql.sql".to_owned(), 0, 0, 0),
+            ("nested_comments.sql".to_owned(), 0, 1, 1),
+            ("temp_tables_and_go.sql".to_owned(), 2, 0, 0),
+            ("unicode.sql".to_owned(), 0, 0, 0),
+        ]
+    );
+}
+
+#[test]
+fn corpus_parameters() {
+    let params: Vec<_> = corpus()
+        .iter()
+        .map(|(name, sql)| {
+            let params: Vec<_> = tokenize(sql)
+                .filter_map(|token| match token.kind {
+                    TokenKind::Parameter(n) => Some(n),
+                    _ => None,
+                })
+                .collect();
+            (name.clone(), params)
+        })
+        .collect();
+    assert_eq!(
+        params,
+        [
+            ("crlf.sql".to_owned(), vec![]),
+            ("dynamic_sql.sql".to_owned(), vec![1, 2]),
+            ("nested_comments.sql".to_owned(), vec![]),
+            ("temp_tables_and_go.sql".to_owned(), vec![]),
+            ("unicode.sql".to_owned(), vec![1]),
+        ]
+    );
+}
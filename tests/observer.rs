@@ -0,0 +1,152 @@
+//! The order of connection lifecycle events against a real server.
+//! Requires the `test-util` feature and Docker; run with `cargo test --features test-util -- --ignored`.
+#![cfg(feature = "test-util")]
+
+use mssql_rs::{
+    BrokenReason, ConnectionEvent, ConnectionInfo, ConnectionObserver, Priority, RecycleReason,
+    SqlServerPool, SqlServerPoolBuilder, TestServer,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An event without its borrowed data, to compare across connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recorded {
+    Created,
+    CheckedOut,
+    Validated,
+    ValidationFailed,
+    Broken(BrokenReason),
+    Recycled(RecycleReason),
+    Closed,
+}
+
+/// Records the lifecycle events of each connection, in order.
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<HashMap<u64, Vec<Recorded>>>,
+}
+
+impl Recorder {
+    /// The events of every connection, ordered by connection id.
+    fn connections(&self) -> Vec<Vec<Recorded>> {
+        let events = self.events.lock().unwrap();
+        let mut ids: Vec<_> = events.keys().copied().collect();
+        ids.sort_unstable();
+        ids.iter().map(|id| events[id].clone()).collect()
+    }
+}
+
+impl ConnectionObserver for Recorder {
+    fn on_lifecycle(&self, connection: ConnectionInfo, event: ConnectionEvent<'_>) {
+        let recorded = match event {
+            ConnectionEvent::Created { .. } => Recorded::Created,
+            ConnectionEvent::CheckedOut => Recorded::CheckedOut,
+            ConnectionEvent::Validated => Recorded::Validated,
+            ConnectionEvent::ValidationFailed { .. } => Recorded::ValidationFailed,
+            ConnectionEvent::Broken { reason } => Recorded::Broken(reason),
+            ConnectionEvent::Recycled { reason } => Recorded::Recycled(reason),
+            ConnectionEvent::Closed => Recorded::Closed,
+        };
+        self.events
+            .lock()
+            .unwrap()
+            .entry(connection.id)
+            .or_default()
+            .push(recorded);
+    }
+}
+
+async fn start_with(
+    builder: &mut SqlServerPoolBuilder,
+) -> (TestServer, SqlServerPool, Arc<Recorder>) {
+    let recorder = Arc::new(Recorder::default());
+    let (server, _) = TestServer::start().await.expect("start a test server");
+    let pool = builder
+        .pool_max_size(1)
+        .observer(recorder.clone())
+        .build(server.config().clone())
+        .await
+        .unwrap();
+    (server, pool, recorder)
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn broken_connection_events_are_ordered() {
+    // Without validation on checkout, so every event is the test's doing.
+    let (_server, pool, recorder) =
+        start_with(SqlServerPoolBuilder::new().validate_on_checkout(false)).await;
+
+    // A temp table left behind breaks the connection on return.
+    let mut conn = pool.get_with_priority(Priority::High).await.unwrap();
+    let _: Vec<(Option<i32>,)> = conn
+        .row_query("CREATE TABLE #left_behind (id int); SELECT 1;", &[])
+        .await
+        .unwrap();
+    drop(conn);
+    let _: Vec<(Option<i32>,)> = pool.row_query("SELECT 1;", &[]).await.unwrap();
+    drop(pool);
+
+    use Recorded::*;
+    assert_eq!(
+        recorder.connections(),
+        [
+            vec![
+                Created,
+                CheckedOut,
+                Broken(BrokenReason::SessionState),
+                Closed
+            ],
+            vec![Created, CheckedOut, Closed],
+        ]
+    );
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn recycled_connection_events_are_ordered() {
+    let mut builder = SqlServerPoolBuilder::new();
+    builder
+        .validate_on_checkout(false)
+        .max_lifetime(Some(Duration::from_secs(1)))
+        .reaper_rate(Duration::from_millis(100));
+    let (_server, pool, recorder) = start_with(&mut builder).await;
+
+    let _: Vec<(Option<i32>,)> = pool.row_query("SELECT 1;", &[]).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    use Recorded::*;
+    let connections = recorder.connections();
+    assert_eq!(
+        connections[0],
+        [
+            Created,
+            CheckedOut,
+            Recycled(RecycleReason::MaxLifetime),
+            Closed
+        ]
+    );
+    drop(pool);
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn idle_connections_are_validated_before_checkout() {
+    let (_server, pool, recorder) =
+        start_with(SqlServerPoolBuilder::new().validate_on_checkout(true)).await;
+
+    for _ in 0..2 {
+        let _: Vec<(Option<i32>,)> = pool.row_query("SELECT 1;", &[]).await.unwrap();
+    }
+    drop(pool);
+
+    use Recorded::*;
+    let connections = recorder.connections();
+    assert_eq!(connections.len(), 1, "{connections:?}");
+    assert!(
+        connections[0].ends_with(&[CheckedOut, Validated, CheckedOut, Closed]),
+        "{connections:?}"
+    );
+}
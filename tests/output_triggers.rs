@@ -0,0 +1,85 @@
+//! `OUTPUT` clauses on tables with triggers against a real server, which reject a plain `OUTPUT` with error 334.
+//! Requires the `test-util` feature and Docker; run with `cargo test --features test-util -- --ignored`.
+#![cfg(feature = "test-util")]
+
+use mssql_rs::{SqlServerPool, TestServer};
+
+/// A table with an identity and a trigger, and the audit table the trigger writes to.
+const SCHEMA: &str =
+    "CREATE TABLE dbo.people (id int IDENTITY PRIMARY KEY, name nvarchar(50) NOT NULL);
+CREATE TABLE dbo.people_audit (id int, action nvarchar(10));
+EXEC('CREATE TRIGGER dbo.people_changed ON dbo.people AFTER INSERT, DELETE AS
+    INSERT INTO dbo.people_audit SELECT id, ''insert'' FROM inserted
+    UNION ALL SELECT id, ''delete'' FROM deleted;');";
+
+async fn start() -> (TestServer, SqlServerPool) {
+    let (server, pool) = TestServer::start().await.expect("start a test server");
+    pool.execute(SCHEMA, &[]).await.unwrap();
+    (server, pool)
+}
+
+async fn audited(pool: &SqlServerPool, action: &str) -> usize {
+    let rows: Vec<(Option<i32>,)> = pool
+        .row_query(
+            "SELECT id FROM dbo.people_audit WHERE action = @P1;",
+            &[action.to_owned()],
+        )
+        .await
+        .unwrap();
+    rows.len()
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn insert_returning_ids_falls_back_to_output_into() {
+    let (_server, pool) = start().await;
+    let insert = "INSERT INTO dbo.people (name) OUTPUT inserted.id VALUES (@P1), (@P2);";
+
+    // The first insert discovers the trigger, the second uses OUTPUT ... INTO straight away.
+    for names in [["Alice", "Bob"], ["Carol", "Dave"]] {
+        let mut ids = pool
+            .insert_returning_ids(insert, &names.map(str::to_owned))
+            .await
+            .unwrap();
+        ids.sort_unstable();
+        assert_eq!(ids.len(), 2);
+        assert!(ids[0] < ids[1]);
+    }
+    assert_eq!(audited(&pool, "insert").await, 4);
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn delete_returning_falls_back_to_output_into() {
+    let (_server, pool) = start().await;
+    pool.execute(
+        "INSERT INTO dbo.people (name) VALUES (N'Alice'), (N'Bob'), (N'Carol');",
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let mut deleted: Vec<(Option<i32>, Option<String>)> = pool
+        .delete_returning(
+            "DELETE FROM dbo.people OUTPUT deleted.* WHERE name <> @P1",
+            &["Carol".to_owned()],
+        )
+        .await
+        .unwrap();
+    deleted.sort();
+    assert_eq!(
+        deleted,
+        [
+            (Some(1), Some("Alice".to_owned())),
+            (Some(2), Some("Bob".to_owned()))
+        ]
+    );
+    assert_eq!(audited(&pool, "delete").await, 2);
+
+    // The connection is reusable, with no temp table left behind.
+    let deleted: Vec<(Option<i32>, Option<String>)> = pool
+        .delete_returning("DELETE FROM dbo.people OUTPUT deleted.*", &[])
+        .await
+        .unwrap();
+    assert_eq!(deleted, [(Some(3), Some("Carol".to_owned()))]);
+}
@@ -0,0 +1,52 @@
+//! Pool-wide operations against a real server.
+//! Requires the `test-util` feature and Docker; run with `cargo test --features test-util -- --ignored`.
+#![cfg(feature = "test-util")]
+
+use mssql_rs::{SqlServerPool, SqlServerPoolBuilder, TestServer};
+
+async fn start_with(builder: &SqlServerPoolBuilder) -> (TestServer, SqlServerPool) {
+    let (server, _) = TestServer::start().await.expect("start a test server");
+    let pool = builder.build(server.config().clone()).await.unwrap();
+    (server, pool)
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn validate_all_fills_and_validates_the_whole_pool() {
+    let mut builder = SqlServerPoolBuilder::new();
+    builder.pool_max_size(8).max_in_flight(Some(2));
+    let (_server, pool) = start_with(&builder).await;
+
+    pool.validate_all().await.unwrap();
+
+    let status = pool.status();
+    assert_eq!(status.connections, 8);
+    assert_eq!(status.idle_connections, 8);
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn reconfigure_rebuilds_the_affinity_shards() {
+    let mut builder = SqlServerPoolBuilder::new();
+    builder.pool_max_size(4).affinity_shards(2);
+    let (_server, pool) = start_with(&builder).await;
+
+    // Session state marks the shard connection the key is served by.
+    let marked: Vec<(Option<Vec<u8>>,)> = pool
+        .row_query_affine(3, "SET CONTEXT_INFO 0x01; SELECT CONTEXT_INFO();", &[])
+        .await
+        .unwrap();
+    assert_eq!(marked[0].0.as_deref().and_then(<[u8]>::first), Some(&1));
+
+    pool.reconfigure(8).await.unwrap();
+    assert_eq!(pool.max_size(), 8);
+
+    // Every key, across the doubled shards, is now served by a new connection.
+    for key in 0..4 {
+        let context: Vec<(Option<Vec<u8>>,)> = pool
+            .row_query_affine(key, "SELECT CONTEXT_INFO();", &[])
+            .await
+            .unwrap();
+        assert_eq!(context, [(None,)], "key {key}");
+    }
+}
@@ -0,0 +1,432 @@
+//! Every query method taking [`QueryOptions`] applies them the same way.
+//!
+//! The pool connects to a server that accepts connections and never answers, so every call waits on its checkout
+//! until [`QueryOptions::timeout`] ends it. A method that ignored its options would wait for the pool's much longer
+//! connection timeout instead, and fail with a different error.
+
+use futures_util::future::BoxFuture;
+use futures_util::{FutureExt, StreamExt};
+use mssql_rs::sql::ObjectName;
+use mssql_rs::tiberius::{AuthMethod, Config};
+use mssql_rs::{
+    CancellationToken, CsvImportOptions, Error, QueryOptions, ResumeOptions, ScopedConfigKey,
+    SqlParam, SqlServerPool, SqlServerPoolBuilder, SyncOptions, TempColumn, TryFromRow,
+};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+/// The timeout set on every call.
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long the pool waits for a connection, far past [`TIMEOUT`].
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Id;
+
+impl TryFromRow for Id {
+    fn try_from(_: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {
+        Ok(Id)
+    }
+}
+
+#[derive(PartialEq, serde::Serialize)]
+struct ById {
+    id: i32,
+}
+
+impl TryFromRow for ById {
+    fn try_from(_: mssql_rs::tiberius::Row) -> mssql_rs::Result<Self> {
+        Ok(ById { id: 0 })
+    }
+}
+
+/// Start a listener that accepts connections and holds them open without a word, and a pool connecting to it with
+/// `affinity_shards` shards.
+async fn silent_server(affinity_shards: u32) -> SqlServerPool {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            held.push(socket);
+        }
+    });
+
+    let mut config = Config::new();
+    config.host("127.0.0.1");
+    config.port(port);
+    config.authentication(AuthMethod::sql_server("sa", "unused"));
+    config.trust_cert();
+    SqlServerPoolBuilder::new()
+        .pool_connection_timeout(CONNECTION_TIMEOUT)
+        .affinity_shards(affinity_shards)
+        .build(config)
+        .await
+        .unwrap()
+}
+
+/// A call to every method taking options, by name, with its result reduced to the error.
+fn calls<'a>(
+    pool: &'a SqlServerPool,
+    options: &'a QueryOptions,
+) -> Vec<(&'static str, BoxFuture<'a, Result<(), Error>>)> {
+    let query = "SELECT 1 AS id;";
+    vec![
+        (
+            "row_query_with_options",
+            pool.row_query_with_options::<Id>(query, &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_timed_with_options",
+            pool.row_query_timed_with_options::<Id>(query, &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_named_with_options",
+            pool.row_query_named_with_options::<Id, _>(
+                "SELECT @id AS id;",
+                &ById { id: 1 },
+                options,
+            )
+            .map(|r| r.map(drop))
+            .boxed(),
+        ),
+        (
+            "json_query_with_options",
+            pool.json_query_with_options::<serde_json::Value>(
+                "SELECT 1 AS id FOR JSON PATH;",
+                &[],
+                options,
+            )
+            .map(|r| r.map(drop))
+            .boxed(),
+        ),
+        (
+            "json_stream_with_options",
+            pool.json_stream_with_options::<serde_json::Value>(
+                "SELECT 1 AS id FOR JSON PATH;",
+                &[],
+                options,
+            )
+            .collect::<Vec<_>>()
+            .map(|items| items.into_iter().try_for_each(|item| item.map(drop)))
+            .boxed(),
+        ),
+        (
+            "scalar_opt_with_options",
+            pool.scalar_opt_with_options::<i32>(query, &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "delete_returning_with_options",
+            pool.delete_returning_with_options::<Id>(
+                "DELETE FROM t OUTPUT deleted.id;",
+                &[],
+                options,
+            )
+            .map(|r| r.map(drop))
+            .boxed(),
+        ),
+        (
+            "insert_returning_ids_with_options",
+            pool.insert_returning_ids_with_options(
+                "INSERT INTO t (a) OUTPUT inserted.id VALUES (1);",
+                &[],
+                options,
+            )
+            .map(|r| r.map(drop))
+            .boxed(),
+        ),
+        (
+            "upsert_with_options",
+            async move {
+                let keys = [("id", SqlParam::from(1))];
+                let values = [("a", SqlParam::from("a"))];
+                pool.upsert_with_options("dbo.t", &keys, &values, options)
+                    .await
+                    .map(drop)
+            }
+            .boxed(),
+        ),
+        (
+            "execute_with_options",
+            pool.execute_with_options("UPDATE t SET a = 1;", &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "execute_batch_with_options",
+            pool.execute_batch_with_options(&["UPDATE t SET a = 1;"], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "execute_script_with_options",
+            pool.execute_script_with_options("UPDATE t SET a = 1;\nGO\n", "GO", true, options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_chunked",
+            pool.row_query_chunked::<Id>(query, &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_batched",
+            pool.row_query_batched::<Id, _, _>(query, &[], options, |_| async { Ok(()) })
+                .boxed(),
+        ),
+        (
+            "row_query_find_with_options",
+            pool.row_query_find_with_options::<Id, _>(query, &[], options, |_| true)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_indexed_with_options",
+            pool.row_query_indexed_with_options::<u8, Id>(query, &[], options, |_| 0)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_indexed_allow_dup_with_options",
+            pool.row_query_indexed_allow_dup_with_options::<u8, Id>(query, &[], options, |_| 0)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_dyn_with_options",
+            pool.row_query_dyn_with_options::<dyn std::fmt::Debug + Send, _>(
+                query,
+                &[],
+                options,
+                |_| Ok(Box::new(1)),
+            )
+            .map(|r| r.map(drop))
+            .boxed(),
+        ),
+        (
+            "row_query_dynamic",
+            pool.row_query_dynamic(query, &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_grouped_with_options",
+            pool.row_query_grouped_with_options::<Id, Id, u8>(
+                query,
+                &[],
+                options,
+                |_| Ok(0),
+                |_| Ok(None),
+            )
+            .map(|r| r.map(drop))
+            .boxed(),
+        ),
+        (
+            "row_query_partial_with_options",
+            pool.row_query_partial_with_options::<Id>(query, &[], options)
+                .map(|r| r.map(drop).map_err(|e| e.source))
+                .boxed(),
+        ),
+        (
+            "row_query_counted_with_options",
+            pool.row_query_counted_with_options::<Id>("SELECT 1 AS id, {count};", &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_cancellable_with_options",
+            async move {
+                let cancel = CancellationToken::new();
+                pool.row_query_cancellable_with_options::<Id>(query, &[], options, &cancel)
+                    .await
+                    .map(drop)
+            }
+            .boxed(),
+        ),
+        (
+            "row_query_affine_with_options",
+            pool.row_query_affine_with_options::<Id>(7, query, &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_numeric_with_options",
+            pool.row_query_numeric_with_options::<1>(query, &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "row_query_in_temp_with_options",
+            async move {
+                let keys = [SqlParam::from(1)];
+                pool.row_query_in_temp_with_options::<Id>(
+                    "SELECT id FROM #keys;",
+                    "id",
+                    &keys,
+                    options,
+                )
+                .await
+                .map(drop)
+            }
+            .boxed(),
+        ),
+        (
+            "resumable_stream_with_options",
+            pool.resumable_stream_with_options::<Id>(
+                "SELECT 1 AS id WHERE @P1 IS NULL;",
+                &[],
+                "id",
+                ResumeOptions::default(),
+                options,
+            )
+            .collect::<Vec<_>>()
+            .map(|items| items.into_iter().try_for_each(|item| item.map(drop)))
+            .boxed(),
+        ),
+        (
+            "execute_counts_with_options",
+            pool.execute_counts_with_options("UPDATE t SET a = 1;", &[], options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "insert_or_get_with_options",
+            async move {
+                let keys = [("id", SqlParam::from(1))];
+                pool.insert_or_get_with_options::<Id>("dbo.t", &keys, &[], options)
+                    .await
+                    .map(drop)
+            }
+            .boxed(),
+        ),
+        (
+            "sync_table_with_options",
+            async move {
+                let desired = [ById { id: 1 }];
+                pool.sync_table_with_options(
+                    "dbo.t",
+                    &["id"],
+                    &desired,
+                    &SyncOptions::default(),
+                    options,
+                )
+                .await
+                .map(drop)
+            }
+            .boxed(),
+        ),
+        (
+            "csv_import_with_options",
+            async move {
+                let import = CsvImportOptions::default();
+                pool.csv_import_with_options(&b"id\n1\n"[..], "dbo.t", &import, options)
+                    .await
+                    .map(drop)
+            }
+            .boxed(),
+        ),
+        (
+            "with_temp_table_with_options",
+            async move {
+                let columns = [TempColumn::new("id", "int")];
+                let rows = [vec![SqlParam::from(1)]];
+                pool.with_temp_table_with_options("#ids", &columns, &rows, options, |_| {
+                    async { Ok(()) }.boxed()
+                })
+                .await
+            }
+            .boxed(),
+        ),
+        (
+            "with_identity_insert_with_options",
+            pool.with_identity_insert_with_options("dbo.t", options, |_| async { Ok(()) }.boxed())
+                .boxed(),
+        ),
+        (
+            "read_snapshot_with_options",
+            pool.read_snapshot_with_options(options, |_| async { Ok(()) }.boxed())
+                .boxed(),
+        ),
+        (
+            "ready_with_options",
+            pool.ready_with_options(options).boxed(),
+        ),
+        (
+            "can_access_with_options",
+            async move {
+                let object = ObjectName::new().schema("dbo").object("t");
+                pool.can_access_with_options(&object, options)
+                    .await
+                    .map(drop)
+            }
+            .boxed(),
+        ),
+        (
+            "session_options_with_options",
+            pool.session_options_with_options(options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "database_scoped_config_with_options",
+            pool.database_scoped_config_with_options(options)
+                .map(|r| r.map(drop))
+                .boxed(),
+        ),
+        (
+            "set_database_scoped_config_with_options",
+            pool.set_database_scoped_config_with_options(ScopedConfigKey::MaxDop, "4", options)
+                .boxed(),
+        ),
+    ]
+}
+
+#[tokio::test]
+async fn every_method_honours_the_timeout() {
+    let pool = silent_server(0).await;
+    let options = QueryOptions {
+        timeout: Some(TIMEOUT),
+        ..Default::default()
+    };
+
+    let calls = calls(&pool, &options);
+    assert_eq!(calls.len(), 39);
+    assert_times_out(calls).await;
+}
+
+#[tokio::test]
+async fn affine_queries_honour_the_timeout_on_a_shard() {
+    let pool = silent_server(2).await;
+    let options = QueryOptions {
+        timeout: Some(TIMEOUT),
+        ..Default::default()
+    };
+
+    let calls = calls(&pool, &options)
+        .into_iter()
+        .filter(|(name, _)| name.starts_with("row_query_affine"))
+        .collect();
+    assert_times_out(calls).await;
+}
+
+/// Run each call, asserting it fails with [`Error::QueryTimeout`] soon after [`TIMEOUT`].
+async fn assert_times_out(calls: Vec<(&str, BoxFuture<'_, Result<(), Error>>)>) {
+    for (name, call) in calls {
+        let start = Instant::now();
+        let result = tokio::time::timeout(CONNECTION_TIMEOUT / 2, call)
+            .await
+            .unwrap_or_else(|_| panic!("{name} ignored QueryOptions::timeout"));
+        assert!(
+            matches!(result, Err(Error::QueryTimeout { timeout }) if timeout == TIMEOUT),
+            "{name}: {result:?}"
+        );
+        assert!(start.elapsed() < Duration::from_secs(5), "{name}");
+    }
+}
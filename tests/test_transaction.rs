@@ -0,0 +1,64 @@
+//! Code under test that manages its own transactions inside a rolled back test transaction, against a real server.
+//! Requires the `test-util` feature and Docker; run with `cargo test --features test-util -- --ignored`.
+#![cfg(feature = "test-util")]
+
+use mssql_rs::{Error, SqlServerPool, TestServer, TestTransaction};
+
+/// The number of rows in `dbo.people`, as seen from `tx`.
+async fn count(tx: &mut TestTransaction<'_>) -> Option<i32> {
+    let rows: Vec<(Option<i32>,)> = tx
+        .row_query("SELECT COUNT(*) FROM dbo.people;", &[])
+        .await
+        .unwrap();
+    rows[0].0
+}
+
+async fn start() -> (TestServer, SqlServerPool) {
+    let (server, pool) = TestServer::start().await.expect("start a test server");
+    pool.execute_batch(&["CREATE TABLE dbo.people (name nvarchar(100) NOT NULL);"])
+        .await
+        .unwrap();
+    (server, pool)
+}
+
+#[tokio::test]
+#[ignore = "starts a SQL Server container with docker"]
+async fn nested_transactions_behave_as_usual_and_are_rolled_back() {
+    let (_server, pool) = start().await;
+    let mut tx = pool.test_transaction().await.unwrap();
+    tx.execute("INSERT INTO dbo.people VALUES (N'fixture');", &[])
+        .await
+        .unwrap();
+
+    // The code's rollback undoes its own work, not the test's.
+    tx.execute(
+        "BEGIN TRANSACTION; INSERT INTO dbo.people VALUES (N'rolled back'); ROLLBACK;",
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(count(&mut tx).await, Some(1));
+
+    tx.execute(
+        "BEGIN TRAN; BEGIN TRAN; INSERT INTO dbo.people VALUES (N'committed'); COMMIT; COMMIT;",
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(count(&mut tx).await, Some(2));
+
+    let depth: Vec<(Option<i32>,)> = tx.row_query("SELECT @@TRANCOUNT;", &[]).await.unwrap();
+    assert_eq!(depth[0].0, Some(0));
+
+    // A commit without a transaction of the code's fails rather than committing the test's.
+    let result = tx.execute("COMMIT;", &[]).await;
+    assert!(matches!(result, Err(Error::Tiberius(_))), "{result:?}");
+    assert_eq!(count(&mut tx).await, Some(2));
+
+    tx.rollback().await.unwrap();
+    let rows: Vec<(Option<i32>,)> = pool
+        .row_query("SELECT COUNT(*) FROM dbo.people;", &[])
+        .await
+        .unwrap();
+    assert_eq!(rows[0].0, Some(0));
+}